@@ -1,47 +1,573 @@
 use std::{
-    io::{Read, Write},
-    net::{TcpStream, ToSocketAddrs},
+    collections::{HashSet, VecDeque},
+    io::ErrorKind,
+    net::ToSocketAddrs,
+    time::{Duration, Instant},
 };
 
+use my_stp_core::{codec::Message, handshake::ClientHandshake};
+
 use crate::{
-    errors::{ConnectError, RequestError},
-    recv_string, send_string,
+    auth::Credentials,
+    errors::{ConnectError, RecvError, RequestError, SendError},
+    limits::Limits,
+    message::{Encoding, Push, Request, Response, StreamStatus},
+    protocol::{Features, Protocol},
+    recv_message, recv_string, run_handshake, send_message, send_string,
+    transport::Stream,
 };
 
+#[cfg(unix)]
+use std::path::Path;
+
 pub struct StpClient;
 
 impl StpClient {
+    /// Open a session with the latest protocol version and all features
     pub fn connect<Addrs>(addrs: Addrs) -> Result<StpConnection, ConnectError>
     where
         Addrs: ToSocketAddrs,
     {
-        let tcp = TcpStream::connect(addrs)?;
-        Self::try_handshake(tcp)
+        Self::connect_with(addrs, Protocol::default())
     }
 
-    fn try_handshake(mut stream: TcpStream) -> Result<StpConnection, ConnectError> {
-        stream.write_all(b"clnt")?;
-        let mut buf = [0u8; 4];
-        stream.read_exact(&mut buf)?;
-        if &buf != b"serv" {
-            return Err(ConnectError::BadHandshake);
-        }
-        Ok(StpConnection { stream })
+    /// Open a connection offering at most `protocol` to the server
+    pub fn connect_with<Addrs>(
+        addrs: Addrs,
+        protocol: Protocol,
+    ) -> Result<StpConnection, ConnectError>
+    where
+        Addrs: ToSocketAddrs,
+    {
+        Self::connect_with_limits(addrs, protocol, Limits::default())
+    }
+
+    /// Same as [`StpClient::connect_with`], with custom frame size limit and deadlines
+    pub fn connect_with_limits<Addrs>(
+        addrs: Addrs,
+        protocol: Protocol,
+        limits: Limits,
+    ) -> Result<StpConnection, ConnectError>
+    where
+        Addrs: ToSocketAddrs,
+    {
+        let stream = Stream::connect_tcp(addrs)?;
+        Self::try_handshake(stream, protocol, limits, None)
+    }
+
+    /// Open a session with a server that requires authentication
+    pub fn connect_authenticated<Addrs>(
+        addrs: Addrs,
+        credentials: &Credentials,
+    ) -> Result<StpConnection, ConnectError>
+    where
+        Addrs: ToSocketAddrs,
+    {
+        let stream = Stream::connect_tcp(addrs)?;
+        Self::try_handshake(
+            stream,
+            Protocol::default(),
+            Limits::default(),
+            Some(credentials),
+        )
     }
+
+    /// Open a session over the Unix socket at `path`
+    /// with the latest protocol version and all features
+    #[cfg(unix)]
+    pub fn connect_unix<P>(path: P) -> Result<StpConnection, ConnectError>
+    where
+        P: AsRef<Path>,
+    {
+        Self::connect_unix_with(path, Protocol::default(), Limits::default())
+    }
+
+    /// Same as [`StpClient::connect_with_limits`], over a Unix socket
+    #[cfg(unix)]
+    pub fn connect_unix_with<P>(
+        path: P,
+        protocol: Protocol,
+        limits: Limits,
+    ) -> Result<StpConnection, ConnectError>
+    where
+        P: AsRef<Path>,
+    {
+        let stream = Stream::connect_unix(path.as_ref())?;
+        Self::try_handshake(stream, protocol, limits, None)
+    }
+
+    /// Same as [`StpClient::connect_authenticated`], over a Unix socket
+    #[cfg(unix)]
+    pub fn connect_unix_authenticated<P>(
+        path: P,
+        credentials: &Credentials,
+    ) -> Result<StpConnection, ConnectError>
+    where
+        P: AsRef<Path>,
+    {
+        let stream = Stream::connect_unix(path.as_ref())?;
+        Self::try_handshake(
+            stream,
+            Protocol::default(),
+            Limits::default(),
+            Some(credentials),
+        )
+    }
+
+    fn try_handshake(
+        mut stream: Stream,
+        protocol: Protocol,
+        limits: Limits,
+        credentials: Option<&Credentials>,
+    ) -> Result<StpConnection, ConnectError> {
+        let deadline = limits
+            .handshake_timeout
+            .map(|timeout| Instant::now() + timeout);
+        stream.set_write_timeout(limits.handshake_timeout)?;
+        let mut handshake = ClientHandshake::new(protocol, credentials);
+        run_handshake(&mut handshake, &mut stream, deadline)?;
+        Ok(StpConnection {
+            stream,
+            protocol: handshake.outcome().ok_or(ConnectError::BadHandshake)?,
+            limits,
+            next_request_id: 1,
+            pending_requests: 0,
+            responses: VecDeque::new(),
+            pushes: VecDeque::new(),
+            discarded: HashSet::new(),
+            last_heartbeat: 0,
+            heartbeat_reply: 0,
+            closed: false,
+        })
+    }
+}
+
+/// Response or a part of a streamed one
+#[derive(Debug)]
+enum Incoming {
+    Response(Response),
+    Chunk(String),
+    End(StreamStatus),
 }
 
+/// Client side of a connection.
+/// In a session the server answers requests in the order they were sent,
+/// every response carries the id of its request.
+/// Push messages may arrive between responses, they are kept until [`StpConnection::recv_push`].
+/// A v1 connection is closed after the first request
 #[derive(Debug)]
 pub struct StpConnection {
-    stream: TcpStream,
+    stream: Stream,
+    protocol: Protocol,
+    limits: Limits,
+    next_request_id: u64,
+    pending_requests: usize,
+    /// Responses and chunks read while waiting for something else
+    responses: VecDeque<(u64, Incoming)>,
+    /// Pushes read while waiting for a response
+    pushes: VecDeque<Push>,
+    /// Cancelled streams whose remaining chunks are skipped
+    discarded: HashSet<u64>,
+    /// Id of the last heartbeat sent and of the last one the server sent back
+    last_heartbeat: u64,
+    heartbeat_reply: u64,
+    closed: bool,
 }
 
 impl StpConnection {
+    /// Version and features negotiated with the server
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Send a request and wait for its response
     pub fn send_request<T>(&mut self, request: T) -> Result<String, RequestError>
     where
         T: ToString,
     {
-        send_string(request.to_string().as_str(), &mut self.stream)?;
-        let response = recv_string(&mut self.stream)?;
+        if !self.protocol.is_session() {
+            return self.send_single_request(request.to_string());
+        }
+        let id = self.send(request)?;
+        let (response_id, response) = self.recv()?;
+        Self::check_response_id(id, response_id)?;
+        Ok(response)
+    }
+
+    /// Send a request and read its response chunk by chunk as the server produces it.
+    /// A response that is not streamed is yielded as a single chunk.
+    /// Dropping the stream before its end cancels the rest
+    pub fn send_streaming<T>(&mut self, request: T) -> Result<ResponseStream<'_>, RequestError>
+    where
+        T: ToString,
+    {
+        if !self.protocol.is_session() {
+            let response = self.send_single_request(request.to_string())?;
+            return Ok(ResponseStream::single(self, response));
+        }
+        let id = self.send(request)?;
+        Ok(ResponseStream::new(self, id))
+    }
+
+    /// Send a typed request in the most compact negotiated encoding and wait for its response
+    pub fn send_typed(&mut self, request: &Request) -> Result<Response, RequestError> {
+        let encoding = Encoding::preferred(self.protocol.features)
+            .ok_or(RequestError::EncodingNotNegotiated)?;
+        let data = encoding.encode(request).map_err(SendError::Encode)?;
+        let id = self.send_message(|id| Message::Typed { id, encoding, data })?;
+        let (response_id, response) = self.recv_response()?;
+        Self::check_response_id(id, response_id)?;
         Ok(response)
     }
+
+    /// Send a request without waiting for the response. Returns the request id
+    pub fn send<T>(&mut self, request: T) -> Result<u64, RequestError>
+    where
+        T: ToString,
+    {
+        let body = request.to_string();
+        self.send_message(|id| Message::Text { id, body })
+    }
+
+    /// Receive the next response together with the id of its request.
+    /// Returns [`RequestError::Closed`] if the server has closed the session, e.g. after idle timeout
+    pub fn recv(&mut self) -> Result<(u64, String), RequestError> {
+        let (id, response) = self.recv_response()?;
+        Ok((id, response.into_text()))
+    }
+
+    /// Next push message, waiting at most `timeout` for it, `None` waits forever.
+    /// Returns `Ok(None)` if nothing arrived in time
+    pub fn recv_push(&mut self, timeout: Option<Duration>) -> Result<Option<Push>, RequestError> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some(push) = self.pushes.pop_front() {
+                return Ok(Some(push));
+            }
+            if self.closed || !self.protocol.is_session() {
+                return Err(RequestError::Closed);
+            }
+            let timeout = match deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        return Ok(None);
+                    }
+                    Some(remaining)
+                }
+                None => None,
+            };
+            self.stream
+                .set_read_timeout(timeout)
+                .map_err(RecvError::Io)?;
+            match self.stream.peek() {
+                Ok(0) => {
+                    self.closed = true;
+                    return Err(RequestError::Closed);
+                }
+                Ok(_) => self.recv_next()?,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(RecvError::Io(e).into()),
+            }
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Check without waiting whether the server has closed the session, e.g. after idle timeout.
+    /// Only a session with no responses outstanding can be checked, others count as open
+    pub fn is_open(&mut self) -> bool {
+        self.protocol.is_session() && !self.closed && !self.is_closed_by_server()
+    }
+
+    /// Send a heartbeat and wait for the server to send it back. Returns the round trip time.
+    /// A server that does not answer within the I/O timeout of the limits is considered dead,
+    /// responses and pushes that arrive meanwhile are kept
+    pub fn heartbeat(&mut self) -> Result<Duration, RequestError> {
+        if !self.protocol.features.contains(Features::HEARTBEAT) {
+            return Err(RequestError::HeartbeatNotNegotiated);
+        }
+        if self.closed || self.is_closed_by_server() {
+            return Err(RequestError::Closed);
+        }
+        self.last_heartbeat += 1;
+        let id = self.last_heartbeat;
+        let started = Instant::now();
+        send_message(&Message::Heartbeat { id }, &mut self.stream, &self.limits)?;
+        while self.heartbeat_reply != id {
+            if self.closed {
+                return Err(RequestError::Closed);
+            }
+            self.recv_next()?;
+        }
+        Ok(started.elapsed())
+    }
+
+    /// Tell the server that the session is over
+    pub fn close(mut self) -> Result<(), RequestError> {
+        self.closed = true;
+        if self.protocol.is_session() {
+            send_message(&Message::Close, &mut self.stream, &self.limits)?;
+        }
+        Ok(())
+    }
+
+    fn send_single_request(&mut self, request: String) -> Result<String, RequestError> {
+        if self.closed {
+            return Err(RequestError::Closed);
+        }
+        self.closed = true;
+        send_string(request, &mut self.stream, &self.limits)?;
+        Ok(recv_string(&mut self.stream, &self.limits)?)
+    }
+
+    fn send_message<F>(&mut self, message: F) -> Result<u64, RequestError>
+    where
+        F: FnOnce(u64) -> Message,
+    {
+        if !self.protocol.is_session() || self.closed || self.is_closed_by_server() {
+            return Err(RequestError::Closed);
+        }
+        let id = self.next_request_id;
+        self.next_request_id += 1;
+        send_message(&message(id), &mut self.stream, &self.limits)?;
+        self.pending_requests += 1;
+        Ok(id)
+    }
+
+    /// Next response of any request, the chunks of a streamed one are joined
+    fn recv_response(&mut self) -> Result<(u64, Response), RequestError> {
+        let (id, first) = loop {
+            if let Some(incoming) = self.responses.pop_front() {
+                break incoming;
+            }
+            if self.closed {
+                return Err(RequestError::Closed);
+            }
+            self.recv_next()?;
+        };
+        let mut body = match first {
+            Incoming::Response(response) => return Ok((id, response)),
+            Incoming::Chunk(chunk) => chunk,
+            Incoming::End(status) => return Self::joined(id, String::new(), status),
+        };
+        loop {
+            match self.recv_incoming(id)? {
+                Incoming::Chunk(chunk) => body.push_str(&chunk),
+                Incoming::End(status) => return Self::joined(id, body, status),
+                Incoming::Response(response) => return Ok((id, response)),
+            }
+        }
+    }
+
+    fn joined(
+        id: u64,
+        body: String,
+        status: StreamStatus,
+    ) -> Result<(u64, Response), RequestError> {
+        match status {
+            StreamStatus::Complete => Ok((id, Response::Ok(body))),
+            StreamStatus::Failed(message) => Err(RequestError::StreamFailed(message)),
+            StreamStatus::Cancelled => Err(RequestError::StreamFailed("cancelled".to_string())),
+        }
+    }
+
+    /// Next response or chunk of request `id`, others stay queued
+    fn recv_incoming(&mut self, id: u64) -> Result<Incoming, RequestError> {
+        loop {
+            if let Some(index) = self.responses.iter().position(|(queued, _)| *queued == id) {
+                return Ok(self.responses.remove(index).unwrap().1);
+            }
+            if self.closed {
+                return Err(RequestError::Closed);
+            }
+            self.recv_next()?;
+        }
+    }
+
+    /// Read one message and queue it as a response, a chunk or a push
+    fn recv_next(&mut self) -> Result<(), RequestError> {
+        let (id, incoming) = match recv_message(&mut self.stream, &self.limits)? {
+            Message::Text { id, body } => (id, Incoming::Response(Response::Ok(body))),
+            Message::Typed { id, encoding, data } => {
+                let response = encoding.decode(&data).map_err(RecvError::Decode)?;
+                (id, Incoming::Response(response))
+            }
+            Message::Chunk { id, body } => (id, Incoming::Chunk(body)),
+            Message::End { id, status } => (id, Incoming::End(status)),
+            Message::Push(push) => {
+                self.pushes.push_back(push);
+                return Ok(());
+            }
+            Message::Close => {
+                self.closed = true;
+                return Err(RequestError::Closed);
+            }
+            Message::Heartbeat { id } => {
+                self.heartbeat_reply = id;
+                return Ok(());
+            }
+            Message::Cancel { .. } => return Err(RecvError::BadMessage.into()),
+        };
+        if !matches!(incoming, Incoming::Chunk(_)) {
+            self.pending_requests = self.pending_requests.saturating_sub(1);
+        }
+        if self.discarded.contains(&id) {
+            if !matches!(incoming, Incoming::Chunk(_)) {
+                self.discarded.remove(&id);
+            }
+            return Ok(());
+        }
+        self.responses.push_back((id, incoming));
+        Ok(())
+    }
+
+    /// Ask the server to stop stream `id`, chunks already on the way are skipped
+    fn cancel_stream(&mut self, id: u64) -> Result<(), RequestError> {
+        let ended = self
+            .responses
+            .iter()
+            .any(|(queued, incoming)| *queued == id && !matches!(incoming, Incoming::Chunk(_)));
+        self.responses.retain(|(queued, _)| *queued != id);
+        if !ended {
+            send_message(&Message::Cancel { id }, &mut self.stream, &self.limits)?;
+            self.discarded.insert(id);
+        }
+        Ok(())
+    }
+
+    fn check_response_id(expected: u64, received: u64) -> Result<(), RequestError> {
+        if expected != received {
+            return Err(RequestError::UnexpectedResponseId { expected, received });
+        }
+        Ok(())
+    }
+
+    /// With no responses outstanding anything readable on the socket besides pushes
+    /// is a close message or EOF. Checking it before sending keeps the request from
+    /// being written into a connection the server has already dropped
+    fn is_closed_by_server(&mut self) -> bool {
+        if self.pending_requests > 0 {
+            return false;
+        }
+        loop {
+            if self.stream.set_nonblocking(true).is_err() {
+                return false;
+            }
+            let peeked = self.stream.peek();
+            let _ = self.stream.set_nonblocking(false);
+            match peeked {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return false,
+                Ok(0) | Err(_) => break,
+                Ok(_) => match recv_message(&mut self.stream, &self.limits) {
+                    Ok(Message::Push(push)) => self.pushes.push_back(push),
+                    _ => break,
+                },
+            }
+        }
+        self.closed = true;
+        true
+    }
+}
+
+impl Drop for StpConnection {
+    fn drop(&mut self) {
+        if !self.closed && self.protocol.is_session() {
+            let _ = send_message(&Message::Close, &mut self.stream, &self.limits);
+        }
+    }
+}
+
+/// Chunks of a streamed response, see [`StpConnection::send_streaming`].
+/// Items are the chunks in order, a failed stream ends with [`RequestError::StreamFailed`]
+#[derive(Debug)]
+pub struct ResponseStream<'a> {
+    connection: &'a mut StpConnection,
+    id: u64,
+    /// Response of a v1 connection, it is read before the stream is created
+    single: Option<String>,
+    done: bool,
+}
+
+impl<'a> ResponseStream<'a> {
+    fn new(connection: &'a mut StpConnection, id: u64) -> Self {
+        Self {
+            connection,
+            id,
+            single: None,
+            done: false,
+        }
+    }
+
+    fn single(connection: &'a mut StpConnection, response: String) -> Self {
+        Self {
+            connection,
+            id: 0,
+            single: Some(response),
+            done: false,
+        }
+    }
+
+    /// Id of the request
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Stop the stream. The server stops producing chunks and the connection
+    /// is ready for the next request, chunks that were on the way are dropped
+    pub fn cancel(mut self) -> Result<(), RequestError> {
+        if self.done {
+            return Ok(());
+        }
+        self.done = true;
+        self.connection.cancel_stream(self.id)
+    }
+}
+
+impl Iterator for ResponseStream<'_> {
+    type Item = Result<String, RequestError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if let Some(response) = self.single.take() {
+            self.done = true;
+            return Some(Ok(response));
+        }
+        let incoming = match self.connection.recv_incoming(self.id) {
+            Ok(incoming) => incoming,
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e));
+            }
+        };
+        match incoming {
+            Incoming::Chunk(chunk) => Some(Ok(chunk)),
+            Incoming::Response(response) => {
+                self.done = true;
+                Some(Ok(response.into_text()))
+            }
+            Incoming::End(StreamStatus::Failed(message)) => {
+                self.done = true;
+                Some(Err(RequestError::StreamFailed(message)))
+            }
+            Incoming::End(_) => {
+                self.done = true;
+                None
+            }
+        }
+    }
+}
+
+impl Drop for ResponseStream<'_> {
+    fn drop(&mut self) {
+        if !self.done && !self.connection.closed {
+            let _ = self.connection.cancel_stream(self.id);
+        }
+    }
 }
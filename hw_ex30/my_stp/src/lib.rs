@@ -1,33 +1,126 @@
-use std::io::{Read, Write};
+use std::{
+    io::{ErrorKind, Read, Write},
+    time::Instant,
+};
 
-use errors::{RecvError, SendError};
+use my_stp_core::{
+    codec::{encode_frame, FrameDecoder, Message},
+    handshake::Handshake,
+};
+
+#[cfg(unix)]
+pub use my_stp_core::socket_file;
+pub use my_stp_core::{
+    auth, command_schema, custom_parser, errors, jsonrpc, limits, message, protocol,
+};
+
+use errors::{ConnectError, RecvError, SendError};
+use limits::Limits;
+use transport::Stream;
 
 pub mod client;
-pub mod custom_parser;
-pub mod errors;
+pub mod push;
+pub mod reconnect;
 pub mod server;
+mod transport;
 
-fn send_string<Data, Writer>(data: Data, mut writer: Writer) -> Result<(), SendError>
+/// Drive a handshake state machine over the stream, reads give up at `deadline`
+fn run_handshake<H: Handshake>(
+    handshake: &mut H,
+    stream: &mut Stream,
+    deadline: Option<Instant>,
+) -> Result<(), ConnectError> {
+    loop {
+        write_all(stream, &handshake.take_output())?;
+        let needed = handshake.bytes_needed();
+        if needed == 0 {
+            return Ok(());
+        }
+        let mut buf = vec![0u8; needed];
+        read_exact_until(stream, &mut buf, deadline)?;
+        if let Err(e) = handshake.receive(&buf) {
+            // The peer still learns why, e.g. that its credentials were rejected
+            let _ = write_all(stream, &handshake.take_output());
+            return Err(e);
+        }
+    }
+}
+
+fn send_string<Data>(data: Data, stream: &mut Stream, limits: &Limits) -> Result<(), SendError>
 where
     Data: AsRef<str>,
-    Writer: Write,
 {
-    let data_bytes = data.as_ref().as_bytes();
-    let len_bytes = (data_bytes.len() as u32).to_be_bytes();
-    writer.write_all(&len_bytes)?;
-    writer.write_all(data_bytes)?;
+    send_frame(data.as_ref().as_bytes(), stream, limits)
+}
+
+fn recv_string(stream: &mut Stream, limits: &Limits) -> Result<String, RecvError> {
+    let buf = recv_frame(stream, limits)?;
+    String::from_utf8(buf).map_err(|_| RecvError::BadEncoding)
+}
+
+fn send_frame(data: &[u8], stream: &mut Stream, limits: &Limits) -> Result<(), SendError> {
+    let frame = encode_frame(data, limits)?;
+    stream.set_write_timeout(limits.io_timeout)?;
+    write_all(stream, &frame)
+}
+
+/// Reads exactly one frame, bytes of the next one stay in the socket
+fn recv_frame(stream: &mut Stream, limits: &Limits) -> Result<Vec<u8>, RecvError> {
+    let deadline = limits.io_timeout.map(|timeout| Instant::now() + timeout);
+    let mut decoder = FrameDecoder::new(limits);
+    loop {
+        if let Some(frame) = decoder.decode()? {
+            return Ok(frame);
+        }
+        let mut buf = vec![0u8; decoder.bytes_needed()];
+        read_exact_until(stream, &mut buf, deadline)?;
+        decoder.extend(&buf);
+    }
+}
+
+fn write_all(stream: &mut Stream, data: &[u8]) -> Result<(), SendError> {
+    stream.write_all(data).map_err(|e| match e.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => SendError::Timeout,
+        _ => SendError::Io(e),
+    })
+}
+
+/// `read_exact` that gives up once `deadline` has passed, however the data trickles in
+fn read_exact_until(
+    stream: &mut Stream,
+    buf: &mut [u8],
+    deadline: Option<Instant>,
+) -> Result<(), RecvError> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let timeout = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(RecvError::Timeout);
+                }
+                Some(remaining)
+            }
+            None => None,
+        };
+        stream.set_read_timeout(timeout)?;
+        match stream.read(&mut buf[filled..]) {
+            Ok(0) => return Err(RecvError::ConnectionClosed),
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err(RecvError::Timeout)
+            }
+            Err(e) => return Err(RecvError::Io(e)),
+        }
+    }
     Ok(())
 }
 
-fn recv_string<Reader>(mut reader: Reader) -> Result<String, RecvError>
-where
-    Reader: Read,
-{
-    let mut len_buf = [0u8; 4];
-    reader.read_exact(&mut len_buf)?;
-    let len = u32::from_be_bytes(len_buf);
+fn send_message(message: &Message, stream: &mut Stream, limits: &Limits) -> Result<(), SendError> {
+    send_frame(&message.encode(), stream, limits)
+}
 
-    let mut buf = vec![0; len as usize];
-    reader.read_exact(&mut buf)?;
-    String::from_utf8(buf).map_err(|_| RecvError::BadEncoding)
+fn recv_message(stream: &mut Stream, limits: &Limits) -> Result<Message, RecvError> {
+    Message::decode(recv_frame(stream, limits)?)
 }
@@ -0,0 +1,112 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Instant,
+};
+
+use my_stp_core::codec::Message;
+
+use crate::{errors::PushError, limits::Limits, message::Push, send_message, transport::Stream};
+
+/// Pushes waiting for the writer before new ones are dropped
+pub const DEFAULT_PUSH_QUEUE_LEN: usize = 64;
+
+/// Handle for sending push messages to the client of one connection.
+/// Clones share the queue and the sequence numbers.
+/// Pushing never blocks: a slow client loses messages instead of stalling the server
+#[derive(Debug, Clone)]
+pub struct Pusher {
+    queue: SyncSender<Push>,
+    state: Arc<PushState>,
+}
+
+#[derive(Debug)]
+pub(crate) struct PushState {
+    next_seq: AtomicU64,
+    dropped: AtomicU64,
+    closed: AtomicBool,
+    last_push: Mutex<Option<Instant>>,
+}
+
+impl Pusher {
+    /// Start the writer thread. Responses and pushes share `writer`,
+    /// so frames of both never interleave
+    pub(crate) fn start(writer: Arc<Mutex<Stream>>, limits: Limits, queue_len: usize) -> Self {
+        let (queue, receiver) = mpsc::sync_channel(queue_len.max(1));
+        let state = Arc::new(PushState {
+            next_seq: AtomicU64::new(1),
+            dropped: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+            last_push: Mutex::new(None),
+        });
+        let writer_state = state.clone();
+        thread::spawn(move || Self::write_pushes(receiver, writer, limits, writer_state));
+        Self { queue, state }
+    }
+
+    /// Queue a message for the client. Returns its sequence number
+    pub fn push(&self, topic: &str, body: &str) -> Result<u64, PushError> {
+        if self.is_closed() {
+            return Err(PushError::Closed);
+        }
+        // The number is taken even if the message is dropped, the client sees the gap
+        let seq = self.state.next_seq.fetch_add(1, Ordering::Relaxed);
+        let push = Push {
+            seq,
+            topic: topic.to_string(),
+            body: body.to_string(),
+        };
+        match self.queue.try_send(push) {
+            Ok(()) => {
+                *self.state.last_push.lock().unwrap() = Some(Instant::now());
+                Ok(seq)
+            }
+            Err(TrySendError::Full(_)) => {
+                self.state.dropped.fetch_add(1, Ordering::Relaxed);
+                Err(PushError::QueueFull)
+            }
+            Err(TrySendError::Disconnected(_)) => Err(PushError::Closed),
+        }
+    }
+
+    /// The connection is gone, all further pushes fail with [`PushError::Closed`]
+    pub fn is_closed(&self) -> bool {
+        self.state.closed.load(Ordering::Relaxed)
+    }
+
+    /// Messages dropped because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.state.dropped.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn close(&self) {
+        self.state.closed.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn last_push(&self) -> Option<Instant> {
+        *self.state.last_push.lock().unwrap()
+    }
+
+    /// Runs until every pusher is dropped or the client can't be written to
+    fn write_pushes(
+        receiver: Receiver<Push>,
+        writer: Arc<Mutex<Stream>>,
+        limits: Limits,
+        state: Arc<PushState>,
+    ) {
+        for push in receiver {
+            if state.closed.load(Ordering::Relaxed) {
+                break;
+            }
+            let mut stream = writer.lock().unwrap();
+            if send_message(&Message::Push(push), &mut stream, &limits).is_err() {
+                state.closed.store(true, Ordering::Relaxed);
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    net::ToSocketAddrs,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+pub use my_stp_core::reconnect::{Backoff, ConnectionState, ReconnectPolicy};
+
+use crate::{
+    client::{StpClient, StpConnection},
+    errors::{ConnectError, RequestError},
+    message::{Push, Request, Response},
+};
+
+type Connect = Box<dyn Fn() -> Result<StpConnection, ConnectError> + Send + Sync>;
+type StateListener = Box<dyn Fn(ConnectionState) + Send + Sync>;
+
+/// Client that keeps one session open across server restarts and network failures.
+/// A lost session is opened again with exponential backoff, requests the policy marks
+/// as idempotent are sent again on the new one. Idle sessions are checked with heartbeats
+/// from a background thread. Server side state of a session, e.g. pushed report streams,
+/// does not survive a reconnect, watch [`ReconnectingClient::on_state_change`] to restore it
+pub struct ReconnectingClient {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    connect: Connect,
+    policy: ReconnectPolicy,
+    connection: Mutex<Option<StpConnection>>,
+    state: Mutex<ConnectionState>,
+    listener: Mutex<Option<StateListener>>,
+    stopped: Mutex<bool>,
+    wake: Condvar,
+}
+
+impl ReconnectingClient {
+    /// Client that opens sessions with `connect`. Nothing is opened until the first request
+    pub fn new<F>(connect: F, policy: ReconnectPolicy) -> Self
+    where
+        F: Fn() -> Result<StpConnection, ConnectError> + Send + Sync + 'static,
+    {
+        let heartbeat_interval = policy.heartbeat_interval;
+        let shared = Arc::new(Shared {
+            connect: Box::new(connect),
+            policy,
+            connection: Mutex::new(None),
+            state: Mutex::new(ConnectionState::Disconnected),
+            listener: Mutex::new(None),
+            stopped: Mutex::new(false),
+            wake: Condvar::new(),
+        });
+        if let Some(interval) = heartbeat_interval {
+            let shared = shared.clone();
+            thread::spawn(move || shared.keep_alive(interval));
+        }
+        Self { shared }
+    }
+
+    /// Open a session with the latest protocol version and all features right away
+    pub fn connect<Addrs>(addrs: Addrs, policy: ReconnectPolicy) -> Result<Self, RequestError>
+    where
+        Addrs: ToSocketAddrs + Clone + Send + Sync + 'static,
+    {
+        let client = Self::new(move || StpClient::connect(addrs.clone()), policy);
+        client.with_connection(|_| Ok(()))?;
+        Ok(client)
+    }
+
+    /// `listener` is called on every state change, from the thread that noticed it.
+    /// It must not use the client
+    pub fn on_state_change<F>(&self, listener: F)
+    where
+        F: Fn(ConnectionState) + Send + Sync + 'static,
+    {
+        *self.shared.listener.lock().unwrap() = Some(Box::new(listener));
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        *self.shared.state.lock().unwrap()
+    }
+
+    /// Send a request and wait for its response, see [`StpConnection::send_request`]
+    pub fn send_request<T>(&self, request: T) -> Result<String, RequestError>
+    where
+        T: ToString,
+    {
+        let request = request.to_string();
+        let idempotent = self.shared.policy.is_idempotent(&request);
+        self.shared
+            .run(idempotent, |connection| connection.send_request(&request))
+    }
+
+    /// Send a typed request and wait for its response, see [`StpConnection::send_typed`]
+    pub fn send_typed(&self, request: &Request) -> Result<Response, RequestError> {
+        let idempotent = self.shared.policy.is_idempotent_request(request);
+        self.shared
+            .run(idempotent, |connection| connection.send_typed(request))
+    }
+
+    /// Next push of the current session, see [`StpConnection::recv_push`].
+    /// Returns `Ok(None)` without waiting while there is no session
+    pub fn recv_push(&self, timeout: Option<Duration>) -> Result<Option<Push>, RequestError> {
+        let mut connection = self.shared.connection.lock().unwrap();
+        let Some(session) = connection.as_mut() else {
+            return Ok(None);
+        };
+        let result = session.recv_push(timeout);
+        if let Err(e) = &result {
+            if e.is_connection_lost() {
+                self.shared.lose(&mut connection);
+            }
+        }
+        result
+    }
+
+    /// Run `f` on the session, opening it first if needed. Nothing is retried
+    pub fn with_connection<F, R>(&self, f: F) -> Result<R, RequestError>
+    where
+        F: FnOnce(&mut StpConnection) -> Result<R, RequestError>,
+    {
+        let mut f = Some(f);
+        self.shared
+            .run(false, |connection| (f.take().unwrap())(connection))
+    }
+
+    /// Close the session, the next request opens a new one
+    pub fn disconnect(&self) {
+        let mut connection = self.shared.connection.lock().unwrap();
+        *connection = None;
+        self.shared.set_state(ConnectionState::Disconnected);
+    }
+}
+
+impl Drop for ReconnectingClient {
+    fn drop(&mut self) {
+        *self.shared.stopped.lock().unwrap() = true;
+        self.shared.wake.notify_all();
+    }
+}
+
+impl Shared {
+    /// Run `f` on an open session. A lost session is replaced,
+    /// `f` runs again on the new one only for idempotent requests
+    fn run<F, R>(&self, idempotent: bool, mut f: F) -> Result<R, RequestError>
+    where
+        F: FnMut(&mut StpConnection) -> Result<R, RequestError>,
+    {
+        let mut connection = self.connection.lock().unwrap();
+        let mut retries = 0;
+        loop {
+            // A session closed while idle has not seen the request yet, so any request may use the next one
+            if !connection.as_mut().is_some_and(StpConnection::is_open) {
+                if connection.take().is_some() {
+                    self.set_state(ConnectionState::Reconnecting { attempt: 1 });
+                }
+                self.reconnect(&mut connection)?;
+            }
+            match f(connection.as_mut().unwrap()) {
+                Err(e) if e.is_connection_lost() => {
+                    self.lose(&mut connection);
+                    if !idempotent || retries == self.policy.max_retries {
+                        return Err(e);
+                    }
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Heartbeat idle sessions and bring lost ones back until the client is dropped
+    fn keep_alive(&self, interval: Duration) {
+        while self.sleep(interval) {
+            let mut connection = self.connection.lock().unwrap();
+            let lost = match connection.as_mut() {
+                Some(session) => session.heartbeat().is_err_and(|e| e.is_connection_lost()),
+                None => self.state() != ConnectionState::Disconnected,
+            };
+            if lost {
+                self.lose(&mut connection);
+                let _ = self.reconnect(&mut connection);
+            }
+        }
+    }
+
+    /// Open a session, trying again with backoff while the failure is transient
+    fn reconnect(&self, connection: &mut Option<StpConnection>) -> Result<(), RequestError> {
+        let mut attempt = 1;
+        loop {
+            if attempt > 1 || self.state() != ConnectionState::Disconnected {
+                self.set_state(ConnectionState::Reconnecting { attempt });
+            }
+            if attempt > 1 && !self.sleep(self.policy.backoff.next_delay(attempt)) {
+                return Err(RequestError::Closed);
+            }
+            match (self.connect)() {
+                Ok(session) => {
+                    *connection = Some(session);
+                    self.set_state(ConnectionState::Connected);
+                    return Ok(());
+                }
+                Err(e)
+                    if !e.is_transient()
+                        || self.policy.max_attempts.is_some_and(|max| attempt >= max) =>
+                {
+                    self.set_state(ConnectionState::Disconnected);
+                    return Err(e.into());
+                }
+                Err(_) => attempt += 1,
+            }
+        }
+    }
+
+    /// Drop a broken session, it is opened again by the next request or heartbeat
+    fn lose(&self, connection: &mut Option<StpConnection>) {
+        *connection = None;
+        self.set_state(ConnectionState::Reconnecting { attempt: 1 });
+    }
+
+    fn state(&self) -> ConnectionState {
+        *self.state.lock().unwrap()
+    }
+
+    fn set_state(&self, state: ConnectionState) {
+        let previous = std::mem::replace(&mut *self.state.lock().unwrap(), state);
+        if previous != state {
+            if let Some(listener) = self.listener.lock().unwrap().as_ref() {
+                listener(state);
+            }
+        }
+    }
+
+    /// `false` if the client was dropped meanwhile
+    fn sleep(&self, duration: Duration) -> bool {
+        let stopped = self.stopped.lock().unwrap();
+        let (stopped, _) = self
+            .wake
+            .wait_timeout_while(stopped, duration, |stopped| !*stopped)
+            .unwrap();
+        !*stopped
+    }
+}
@@ -1,15 +1,86 @@
 use std::{
-    io::{self, Read, Write},
-    net::{TcpListener, TcpStream, ToSocketAddrs},
+    collections::VecDeque,
+    io::{self, ErrorKind},
+    net::{Shutdown, SocketAddr, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
+use my_stp_core::{codec::Message, handshake::ServerHandshake};
+
 use crate::{
-    errors::{ConnectError, RequestError},
-    recv_string, send_string,
+    auth::KeyStore,
+    errors::{ConnectError, RecvError, RequestError, SendError},
+    limits::Limits,
+    message::{Encoding, Request, Response, StreamStatus, STREAM_FAILED_CODE},
+    protocol::{Features, Protocol},
+    push::{Pusher, DEFAULT_PUSH_QUEUE_LEN},
+    recv_message, recv_string, run_handshake, send_message, send_string,
+    transport::{Listener, Stream},
 };
 
+#[cfg(unix)]
+use crate::socket_file::UnixSocketOptions;
+#[cfg(unix)]
+use std::path::Path;
+
+/// Sessions without requests for this long are closed by the server
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Chunks of a streamed response. An `Err` ends the stream with that message
+pub type Chunks = Box<dyn Iterator<Item = Result<String, String>>>;
+
+/// Answer of a handler: a single response or a stream of chunks
+pub enum Reply {
+    Response(Response),
+    Stream(Chunks),
+}
+
+impl Reply {
+    /// Stream the items of `chunks`, each one is produced right before it is sent
+    pub fn stream<I>(chunks: I) -> Self
+    where
+        I: IntoIterator<Item = Result<String, String>>,
+        I::IntoIter: 'static,
+    {
+        Reply::Stream(Box::new(chunks.into_iter()))
+    }
+
+    /// The whole reply as one response, for clients without [`Features::STREAM`]
+    fn into_response(self) -> Response {
+        let chunks = match self {
+            Reply::Response(response) => return response,
+            Reply::Stream(chunks) => chunks,
+        };
+        let mut body = String::new();
+        for chunk in chunks {
+            match chunk {
+                Ok(chunk) => body.push_str(&chunk),
+                Err(message) => {
+                    return Response::Error {
+                        code: STREAM_FAILED_CODE.to_string(),
+                        message,
+                    }
+                }
+            }
+        }
+        Response::Ok(body)
+    }
+}
+
+impl From<Response> for Reply {
+    fn from(response: Response) -> Self {
+        Reply::Response(response)
+    }
+}
+
 pub struct StpServer {
-    tcp: TcpListener,
+    listener: Listener,
+    protocol: Protocol,
+    limits: Limits,
+    idle_timeout: Option<Duration>,
+    key_store: Option<KeyStore>,
+    push_queue_len: usize,
 }
 
 impl StpServer {
@@ -17,39 +88,409 @@ impl StpServer {
     where
         Addrs: ToSocketAddrs,
     {
-        let tcp = TcpListener::bind(addrs)?;
-        Ok(Self { tcp })
+        Ok(Self::with_listener(Listener::bind_tcp(addrs)?))
     }
 
-    pub fn accept(&self) -> Result<StpConnection, ConnectError> {
-        let (stream, _) = self.tcp.accept()?;
-        Self::try_handshake(stream)
+    /// Listen on a Unix socket at `path`, the socket file is removed when the server is dropped.
+    /// Only users allowed by the file permissions can connect
+    #[cfg(unix)]
+    pub fn bind_unix<P>(path: P, options: UnixSocketOptions) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self::with_listener(Listener::bind_unix(
+            path.as_ref(),
+            &options,
+        )?))
     }
 
-    fn try_handshake(mut stream: TcpStream) -> Result<StpConnection, ConnectError> {
-        let mut buf = [0u8; 4];
-        stream.read_exact(&mut buf)?;
-        if &buf != b"clnt" {
-            return Err(ConnectError::BadHandshake);
+    fn with_listener(listener: Listener) -> Self {
+        Self {
+            listener,
+            protocol: Protocol::default(),
+            limits: Limits::default(),
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+            key_store: None,
+            push_queue_len: DEFAULT_PUSH_QUEUE_LEN,
         }
-        stream.write_all(b"serv")?;
-        Ok(StpConnection { stream })
     }
+
+    /// Fails for a server listening on a Unix socket
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Path of the Unix socket, `None` for a TCP server
+    #[cfg(unix)]
+    pub fn socket_path(&self) -> Option<&Path> {
+        self.listener.socket_path()
+    }
+
+    /// Highest version and features offered to clients
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
+    /// Frame size limit and deadlines for connections accepted after this call
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Idle timeout for sessions accepted after this call, `None` keeps sessions open forever
+    pub fn set_idle_timeout(&mut self, idle_timeout: Option<Duration>) {
+        self.idle_timeout = idle_timeout;
+    }
+
+    /// Require clients to prove they know one of the keys, `None` accepts everyone.
+    /// v1 clients can't authenticate, so they are rejected once keys are set
+    pub fn set_key_store(&mut self, key_store: Option<KeyStore>) {
+        self.key_store = key_store;
+    }
+
+    /// Pushes a connection holds for a slow client before dropping new ones
+    pub fn set_push_queue_len(&mut self, push_queue_len: usize) {
+        self.push_queue_len = push_queue_len;
+    }
+
+    /// Accept a connection and run its handshake on the calling thread
+    pub fn accept(&self) -> Result<StpConnection, ConnectError> {
+        let pending = self.accept_pending()?;
+        self.handshake(pending)
+    }
+
+    /// Accept a connection without waiting for the client. Finish it with
+    /// [`StpServer::handshake`] on another thread, so a silent client can't block the accept loop
+    pub fn accept_pending(&self) -> Result<PendingConnection, ConnectError> {
+        Ok(PendingConnection {
+            stream: self.listener.accept()?,
+        })
+    }
+
+    /// A client that does not finish the handshake in time is dropped
+    pub fn handshake(&self, pending: PendingConnection) -> Result<StpConnection, ConnectError> {
+        let mut stream = pending.stream;
+        let deadline = self
+            .limits
+            .handshake_timeout
+            .map(|timeout| Instant::now() + timeout);
+        stream.set_write_timeout(self.limits.handshake_timeout)?;
+        let mut handshake = ServerHandshake::new(self.protocol, self.key_store.as_ref());
+        run_handshake(&mut handshake, &mut stream, deadline)?;
+        let (protocol, identity) = handshake.outcome().ok_or(ConnectError::BadHandshake)?;
+        Ok(StpConnection {
+            stream,
+            protocol,
+            identity: identity.map(str::to_string),
+            limits: self.limits,
+            idle_timeout: self.idle_timeout,
+            push_queue_len: self.push_queue_len,
+            writer: None,
+            pusher: None,
+            pending: VecDeque::new(),
+        })
+    }
+}
+
+/// Connection accepted by [`StpServer::accept_pending`] whose handshake has not run yet
+#[derive(Debug)]
+pub struct PendingConnection {
+    stream: Stream,
 }
 
+impl PendingConnection {
+    /// Handle to end the connection from another thread, also once the handshake is done.
+    /// A handshake in progress fails right away
+    pub fn closer(&self) -> io::Result<SessionCloser> {
+        Ok(SessionCloser {
+            stream: self.stream.try_clone()?,
+        })
+    }
+}
+
+/// Server side of a connection. Depending on the negotiated protocol it is either
+/// a single request connection or a session with many requests
 #[derive(Debug)]
 pub struct StpConnection {
-    stream: TcpStream,
+    stream: Stream,
+    protocol: Protocol,
+    identity: Option<String>,
+    limits: Limits,
+    idle_timeout: Option<Duration>,
+    push_queue_len: usize,
+    /// Shared with the push writer once pushes are used
+    writer: Option<Arc<Mutex<Stream>>>,
+    pusher: Option<Pusher>,
+    /// Messages the client sent while a response was streamed
+    pending: VecDeque<Message>,
 }
 
 impl StpConnection {
+    /// Version and features negotiated with the client
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    pub fn is_session(&self) -> bool {
+        self.protocol.is_session()
+    }
+
+    /// Id of the authenticated client, `None` if the server does not require authentication
+    pub fn identity(&self) -> Option<&str> {
+        self.identity.as_deref()
+    }
+
+    /// Fails for a connection over a Unix socket
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    /// Handle for pushing messages to the client while requests are served.
+    /// `None` if the client has not negotiated [`Features::PUSH`].
+    /// Pushers report [`crate::errors::PushError::Closed`] once the connection is dropped
+    pub fn pusher(&mut self) -> Option<Pusher> {
+        if !self.is_session() || !self.protocol.features.contains(Features::PUSH) {
+            return None;
+        }
+        if let Some(pusher) = &self.pusher {
+            return Some(pusher.clone());
+        }
+        let writer = Arc::new(Mutex::new(self.stream.try_clone().ok()?));
+        let pusher = Pusher::start(writer.clone(), self.limits, self.push_queue_len);
+        self.writer = Some(writer);
+        self.pusher = Some(pusher.clone());
+        Some(pusher)
+    }
+
+    /// Handle to end the session from another thread, e.g. on server shutdown
+    pub fn closer(&self) -> io::Result<SessionCloser> {
+        Ok(SessionCloser {
+            stream: self.stream.try_clone()?,
+        })
+    }
+
+    /// Answer one request and close the connection
     pub fn proccess_request<F>(mut self, handler: F) -> Result<(), RequestError>
     where
         F: FnOnce(String) -> String,
     {
-        let request = recv_string(&mut self.stream)?;
-        let response = handler(request);
-        send_string(response, &mut self.stream)?;
+        if !self.is_session() {
+            let request = recv_string(&mut self.stream, &self.limits)?;
+            let response = handler(request);
+            send_string(response, &mut self.stream, &self.limits)?;
+            return Ok(());
+        }
+
+        if let Some((id, request, encoding)) = self.recv_request()? {
+            let response = Response::Ok(handler(request.into_command_line()));
+            self.send_response(id, response, encoding)?;
+            self.send(&Message::Close)?;
+        }
+        Ok(())
+    }
+
+    /// Answer requests until the client closes the session, disconnects
+    /// or stays idle for longer than the idle timeout.
+    /// Typed requests are passed to `handler` as command lines
+    pub fn serve<F>(self, mut handler: F) -> Result<(), RequestError>
+    where
+        F: FnMut(String) -> String,
+    {
+        self.serve_typed(|request| Response::Ok(handler(request.into_command_line())))
+    }
+
+    /// Same as [`StpConnection::serve`], but with typed requests and responses.
+    /// String requests arrive as [`Request::Text`] and get [`Response::into_text`] back
+    pub fn serve_typed<F>(self, mut handler: F) -> Result<(), RequestError>
+    where
+        F: FnMut(Request) -> Response,
+    {
+        self.serve_streaming(|request| Reply::Response(handler(request)))
+    }
+
+    /// Same as [`StpConnection::serve_typed`], but large responses may be streamed.
+    /// Clients that have negotiated [`Features::STREAM`] get the chunks as they are produced
+    /// and can cancel the rest, other clients get all chunks joined into one response
+    pub fn serve_streaming<F>(mut self, mut handler: F) -> Result<(), RequestError>
+    where
+        F: FnMut(Request) -> Reply,
+    {
+        if !self.is_session() {
+            let request = recv_string(&mut self.stream, &self.limits)?;
+            let response = handler(Request::Text(request)).into_response();
+            send_string(response.into_text(), &mut self.stream, &self.limits)?;
+            return Ok(());
+        }
+
+        while let Some((id, request, encoding)) = self.recv_request()? {
+            match handler(request) {
+                Reply::Stream(chunks) if self.protocol.features.contains(Features::STREAM) => {
+                    self.send_stream(id, chunks)?
+                }
+                reply => self.send_response(id, reply.into_response(), encoding)?,
+            }
+        }
         Ok(())
     }
+
+    /// Chunks are sent until the stream ends or the client cancels it
+    fn send_stream(&mut self, id: u64, mut chunks: Chunks) -> Result<(), RequestError> {
+        let status = loop {
+            if self.cancel_requested(id)? {
+                break StreamStatus::Cancelled;
+            }
+            match chunks.next() {
+                Some(Ok(body)) => self.send(&Message::Chunk { id, body })?,
+                Some(Err(message)) => break StreamStatus::Failed(message),
+                None => break StreamStatus::Complete,
+            }
+        };
+        self.send(&Message::End { id, status })?;
+        Ok(())
+    }
+
+    /// Reads whatever the client has sent without waiting for more.
+    /// `true` if it cancelled stream `id` or is closing the session.
+    /// Requests are kept for [`StpConnection::recv_request`]
+    fn cancel_requested(&mut self, id: u64) -> Result<bool, RequestError> {
+        loop {
+            let peeked = {
+                // The push writer's clone shares the blocking mode, keep it off the socket
+                let _writer = self.writer.as_ref().map(|writer| writer.lock().unwrap());
+                self.stream.set_nonblocking(true).map_err(RecvError::Io)?;
+                let peeked = self.stream.peek();
+                self.stream.set_nonblocking(false).map_err(RecvError::Io)?;
+                peeked
+            };
+            match peeked {
+                Ok(0) => {
+                    self.pending.push_back(Message::Close);
+                    return Ok(true);
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => return Err(RecvError::Io(e).into()),
+            }
+            match recv_message(&mut self.stream, &self.limits)? {
+                Message::Cancel { id: cancelled } if cancelled == id => return Ok(true),
+                Message::Close => {
+                    self.pending.push_back(Message::Close);
+                    return Ok(true);
+                }
+                // Answered right away, a long stream must not look like a dead server
+                Message::Heartbeat { id } => self.send(&Message::Heartbeat { id })?,
+                message => self.pending.push_back(message),
+            }
+        }
+    }
+
+    /// Responses use the encoding of their request, `None` means a string request
+    fn send_response(
+        &mut self,
+        id: u64,
+        response: Response,
+        encoding: Option<Encoding>,
+    ) -> Result<(), RequestError> {
+        let message = match encoding {
+            Some(encoding) => Message::Typed {
+                id,
+                encoding,
+                data: encoding.encode(&response).map_err(SendError::Encode)?,
+            },
+            None => Message::Text {
+                id,
+                body: response.into_text(),
+            },
+        };
+        self.send(&message)?;
+        Ok(())
+    }
+
+    fn send(&mut self, message: &Message) -> Result<(), SendError> {
+        match &self.writer {
+            Some(writer) => send_message(message, &mut writer.lock().unwrap(), &self.limits),
+            None => send_message(message, &mut self.stream, &self.limits),
+        }
+    }
+
+    /// Pushes keep a session alive like requests do
+    fn pushed_recently(&self) -> bool {
+        let last_push = self.pusher.as_ref().and_then(Pusher::last_push);
+        match (last_push, self.idle_timeout) {
+            (Some(last_push), Some(idle_timeout)) => last_push.elapsed() < idle_timeout,
+            _ => false,
+        }
+    }
+
+    /// `None` means the session is over.
+    /// Waiting for the next request is bounded by the idle timeout,
+    /// receiving it once it has started by the I/O deadline
+    fn recv_request(&mut self) -> Result<Option<(u64, Request, Option<Encoding>)>, RequestError> {
+        loop {
+            let message = match self.pending.pop_front() {
+                Some(message) => message,
+                None if self.wait_for_message()? => recv_message(&mut self.stream, &self.limits)?,
+                None => return Ok(None),
+            };
+            match message {
+                Message::Text { id, body } => return Ok(Some((id, Request::Text(body), None))),
+                Message::Typed { id, encoding, data } => {
+                    let request = encoding.decode(&data).map_err(RecvError::Decode)?;
+                    return Ok(Some((id, request, Some(encoding))));
+                }
+                Message::Close => return Ok(None),
+                // The stream has ended before the cancel arrived
+                Message::Cancel { .. } => continue,
+                Message::Heartbeat { id } => self.send(&Message::Heartbeat { id })?,
+                Message::Push(_) | Message::Chunk { .. } | Message::End { .. } => {
+                    return Err(RecvError::BadMessage.into())
+                }
+            }
+        }
+    }
+
+    /// `false` if the client has disconnected or the session has been idle for too long
+    fn wait_for_message(&mut self) -> Result<bool, RequestError> {
+        self.stream
+            .set_read_timeout(self.idle_timeout)
+            .map_err(RecvError::Io)?;
+        loop {
+            match self.stream.peek() {
+                Ok(0) => return Ok(false),
+                Ok(_) => return Ok(true),
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    if self.pushed_recently() {
+                        continue;
+                    }
+                    let _ = self.send(&Message::Close);
+                    return Ok(false);
+                }
+                Err(e) => return Err(RecvError::Io(e).into()),
+            }
+        }
+    }
+}
+
+/// Ends a session from outside of the thread serving it
+#[derive(Debug)]
+pub struct SessionCloser {
+    stream: Stream,
+}
+
+impl SessionCloser {
+    /// Stops reading from the client. A request in flight is still answered,
+    /// a session waiting for the next request ends right away
+    pub fn close(&self) {
+        let _ = self.stream.shutdown(Shutdown::Read);
+    }
+}
+
+impl Drop for StpConnection {
+    /// The push writer holds a clone of the socket, shut it down
+    /// so the client sees the end of the connection
+    fn drop(&mut self) {
+        if let Some(pusher) = &self.pusher {
+            pusher.close();
+            let _ = self.stream.shutdown(Shutdown::Both);
+        }
+    }
 }
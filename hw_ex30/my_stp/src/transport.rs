@@ -0,0 +1,202 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::{
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+};
+
+#[cfg(unix)]
+use my_stp_core::socket_file::{self, UnixSocketOptions};
+
+/// Call the same method on whichever socket is inside
+macro_rules! on_socket {
+    ($socket:expr, $s:ident => $call:expr) => {
+        match $socket {
+            Socket::Tcp($s) => $call,
+            #[cfg(unix)]
+            Socket::Unix($s) => $call,
+        }
+    };
+}
+
+#[derive(Debug)]
+enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+/// Connected TCP or Unix socket.
+/// Peeking reads one byte ahead and keeps it for the next read,
+/// std Unix sockets can't peek
+#[derive(Debug)]
+pub(crate) struct Stream {
+    socket: Socket,
+    lookahead: Option<u8>,
+}
+
+impl Stream {
+    fn new(socket: Socket) -> Self {
+        Self {
+            socket,
+            lookahead: None,
+        }
+    }
+
+    pub(crate) fn connect_tcp(addr: impl std::net::ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self::new(Socket::Tcp(TcpStream::connect(addr)?)))
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn connect_unix(path: &Path) -> io::Result<Self> {
+        Ok(Self::new(Socket::Unix(UnixStream::connect(path)?)))
+    }
+
+    /// Wait for data without consuming it, bounded by the read timeout.
+    /// `0` means the peer has closed the connection
+    pub(crate) fn peek(&mut self) -> io::Result<usize> {
+        if self.lookahead.is_some() {
+            return Ok(1);
+        }
+        let mut byte = [0u8; 1];
+        let read = on_socket!(&mut self.socket, s => s.read(&mut byte))?;
+        if read == 1 {
+            self.lookahead = Some(byte[0]);
+        }
+        Ok(read)
+    }
+
+    pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        on_socket!(&self.socket, s => s.set_read_timeout(timeout))
+    }
+
+    pub(crate) fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        on_socket!(&self.socket, s => s.set_write_timeout(timeout))
+    }
+
+    /// Shared with every clone of the socket
+    pub(crate) fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        on_socket!(&self.socket, s => s.set_nonblocking(nonblocking))
+    }
+
+    /// Second handle to the socket for writing, it does not see the lookahead
+    pub(crate) fn try_clone(&self) -> io::Result<Self> {
+        let socket = match &self.socket {
+            Socket::Tcp(s) => Socket::Tcp(s.try_clone()?),
+            #[cfg(unix)]
+            Socket::Unix(s) => Socket::Unix(s.try_clone()?),
+        };
+        Ok(Self::new(socket))
+    }
+
+    pub(crate) fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        on_socket!(&self.socket, s => s.shutdown(how))
+    }
+
+    /// Unix sockets have no IP address
+    pub(crate) fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match &self.socket {
+            Socket::Tcp(s) => s.peer_addr(),
+            #[cfg(unix)]
+            Socket::Unix(_) => Err(no_ip_address()),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if let Some(byte) = self.lookahead.take() {
+            buf[0] = byte;
+            return Ok(1);
+        }
+        on_socket!(&mut self.socket, s => s.read(buf))
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        on_socket!(&mut self.socket, s => s.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        on_socket!(&mut self.socket, s => s.flush())
+    }
+}
+
+/// TCP listener or Unix listener together with its socket file
+#[derive(Debug)]
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        path: PathBuf,
+    },
+}
+
+impl Listener {
+    pub(crate) fn bind_tcp(addrs: impl std::net::ToSocketAddrs) -> io::Result<Self> {
+        Ok(Listener::Tcp(TcpListener::bind(addrs)?))
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn bind_unix(path: &Path, options: &UnixSocketOptions) -> io::Result<Self> {
+        socket_file::prepare(path, options)?;
+        let listener = UnixListener::bind(path)?;
+        let listener = Listener::Unix {
+            listener,
+            path: path.to_path_buf(),
+        };
+        // Dropping the listener on error removes the file again
+        socket_file::restrict(path, options)?;
+        Ok(listener)
+    }
+
+    pub(crate) fn accept(&self) -> io::Result<Stream> {
+        let socket = match self {
+            Listener::Tcp(listener) => Socket::Tcp(listener.accept()?.0),
+            #[cfg(unix)]
+            Listener::Unix { listener, .. } => Socket::Unix(listener.accept()?.0),
+        };
+        Ok(Stream::new(socket))
+    }
+
+    /// Unix listeners have no IP address
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr(),
+            #[cfg(unix)]
+            Listener::Unix { .. } => Err(no_ip_address()),
+        }
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn socket_path(&self) -> Option<&Path> {
+        match self {
+            Listener::Tcp(_) => None,
+            Listener::Unix { path, .. } => Some(path),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix { path, .. } = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn no_ip_address() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "unix socket has no IP address")
+}
@@ -0,0 +1,88 @@
+use std::{
+    net::SocketAddr,
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use my_stp::{
+    auth::{Credentials, KeyStore},
+    client::StpClient,
+    errors::ConnectError,
+    server::StpServer,
+};
+
+/// Echo server answering with the client identity.
+/// Handshake errors are forwarded to the returned receiver
+fn start_server(key_store: Option<KeyStore>) -> (SocketAddr, Receiver<ConnectError>) {
+    let mut server = StpServer::bind("127.0.0.1:0").unwrap();
+    server.set_key_store(key_store);
+    let addr = server.local_addr().unwrap();
+    let (errors_tx, errors_rx) = mpsc::channel();
+    thread::spawn(move || loop {
+        match server.accept() {
+            Ok(connection) => {
+                let identity = connection.identity().unwrap_or("anonymous").to_string();
+                thread::spawn(move || connection.serve(|request| format!("{identity}: {request}")));
+            }
+            Err(e) => {
+                let _ = errors_tx.send(e);
+            }
+        }
+    });
+    (addr, errors_rx)
+}
+
+fn key_store() -> KeyStore {
+    KeyStore::parse("alice:secret\nbob:other\n").unwrap()
+}
+
+#[test]
+fn authenticated_identity_reaches_the_handler() {
+    let (addr, _) = start_server(Some(key_store()));
+    let mut connection =
+        StpClient::connect_authenticated(addr, &Credentials::new("alice", b"secret")).unwrap();
+    assert_eq!(connection.send_request("ping").unwrap(), "alice: ping");
+}
+
+#[test]
+fn wrong_key_is_rejected() {
+    let (addr, errors) = start_server(Some(key_store()));
+    assert!(matches!(
+        StpClient::connect_authenticated(addr, &Credentials::new("alice", b"other")),
+        Err(ConnectError::AuthenticationRejected)
+    ));
+    assert!(matches!(
+        errors.recv().unwrap(),
+        ConnectError::BadSignature(client_id) if client_id == "alice"
+    ));
+}
+
+#[test]
+fn unknown_client_is_rejected() {
+    let (addr, errors) = start_server(Some(key_store()));
+    assert!(matches!(
+        StpClient::connect_authenticated(addr, &Credentials::new("eve", b"secret")),
+        Err(ConnectError::AuthenticationRejected)
+    ));
+    assert!(matches!(
+        errors.recv().unwrap(),
+        ConnectError::UnknownClient(client_id) if client_id == "eve"
+    ));
+}
+
+#[test]
+fn client_without_credentials_is_told_to_authenticate() {
+    let (addr, _) = start_server(Some(key_store()));
+    assert!(matches!(
+        StpClient::connect(addr),
+        Err(ConnectError::AuthenticationRequired)
+    ));
+}
+
+#[test]
+fn credentials_are_not_needed_without_key_store() {
+    let (addr, _) = start_server(None);
+    let mut connection =
+        StpClient::connect_authenticated(addr, &Credentials::new("alice", b"secret")).unwrap();
+    assert_eq!(connection.send_request("ping").unwrap(), "anonymous: ping");
+}
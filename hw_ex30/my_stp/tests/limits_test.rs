@@ -0,0 +1,98 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use my_stp::{
+    client::StpClient,
+    errors::{RecvError, RequestError, SendError},
+    limits::Limits,
+    protocol::Protocol,
+    server::StpServer,
+};
+
+fn start_echo_server(limits: Limits) -> SocketAddr {
+    let mut server = StpServer::bind("127.0.0.1:0").unwrap();
+    server.set_limits(limits);
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || loop {
+        if let Ok(connection) = server.accept() {
+            thread::spawn(move || connection.serve(|request| format!("echo {request}")));
+        }
+    });
+    addr
+}
+
+/// Server that completes the handshake and reads a request,
+/// then sends only the beginning of the response frame
+fn start_stalling_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut handshake = [0u8; 10];
+        stream.read_exact(&mut handshake).unwrap();
+        stream.write_all(b"serv\x00\x02\x00\x00\x00\x00").unwrap();
+        let mut len = [0u8; 4];
+        stream.read_exact(&mut len).unwrap();
+        let mut request = vec![0u8; u32::from_be_bytes(len) as usize];
+        stream.read_exact(&mut request).unwrap();
+        stream.write_all(&20u32.to_be_bytes()).unwrap();
+        stream.write_all(b"D\x00\x00").unwrap();
+        thread::sleep(Duration::from_secs(5));
+    });
+    addr
+}
+
+fn small_limits() -> Limits {
+    Limits {
+        max_frame_size: 64,
+        handshake_timeout: Some(Duration::from_millis(100)),
+        io_timeout: Some(Duration::from_millis(100)),
+    }
+}
+
+#[test]
+fn oversized_request_is_not_sent() {
+    let addr = start_echo_server(Limits::default());
+    let mut connection =
+        StpClient::connect_with_limits(addr, Protocol::default(), small_limits()).unwrap();
+    assert!(matches!(
+        connection.send_request("x".repeat(100)),
+        Err(RequestError::Send(SendError::FrameTooLarge {
+            size: 109,
+            max: 64
+        }))
+    ));
+}
+
+#[test]
+fn server_drops_connection_with_oversized_frame() {
+    let addr = start_echo_server(small_limits());
+    let mut connection = StpClient::connect(addr).unwrap();
+    assert!(connection.send_request("x".repeat(100)).is_err());
+
+    let mut connection = StpClient::connect(addr).unwrap();
+    assert_eq!(connection.send_request("ping").unwrap(), "echo ping");
+}
+
+#[test]
+fn stalled_handshake_does_not_block_other_clients() {
+    let addr = start_echo_server(small_limits());
+    let _stalled = TcpStream::connect(addr).unwrap();
+    let mut connection = StpClient::connect(addr).unwrap();
+    assert_eq!(connection.send_request("ping").unwrap(), "echo ping");
+}
+
+#[test]
+fn stalled_response_times_out() {
+    let addr = start_stalling_server();
+    let mut connection =
+        StpClient::connect_with_limits(addr, Protocol::default(), small_limits()).unwrap();
+    assert!(matches!(
+        connection.send_request("ping"),
+        Err(RequestError::Recv(RecvError::Timeout))
+    ));
+}
@@ -0,0 +1,171 @@
+use std::{
+    net::SocketAddr,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use my_stp::{
+    client::StpClient,
+    errors::RequestError,
+    limits::Limits,
+    protocol::{Features, Protocol, V2},
+    reconnect::{Backoff, ConnectionState, ReconnectPolicy, ReconnectingClient},
+    server::StpServer,
+};
+
+/// The first `broken` connections are accepted and left unanswered for `hold`,
+/// then dropped. Later ones are served
+fn start_flaky_server(broken: usize, hold: Duration) -> SocketAddr {
+    let server = StpServer::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || {
+        for _ in 0..broken {
+            if let Ok(connection) = server.accept() {
+                thread::spawn(move || {
+                    thread::sleep(hold);
+                    drop(connection);
+                });
+            }
+        }
+        loop {
+            if let Ok(connection) = server.accept() {
+                thread::spawn(move || connection.serve(|request| format!("echo {request}")));
+            }
+        }
+    });
+    addr
+}
+
+fn policy() -> ReconnectPolicy {
+    ReconnectPolicy {
+        backoff: Backoff {
+            initial: Duration::from_millis(20),
+            max: Duration::from_millis(100),
+            ..Backoff::default()
+        },
+        heartbeat_interval: None,
+        ..ReconnectPolicy::default()
+    }
+    .idempotent(["rooms_list"])
+}
+
+/// States reported by the client, in order
+fn record_states(client: &ReconnectingClient) -> mpsc::Receiver<ConnectionState> {
+    let (sender, receiver) = mpsc::channel();
+    let sender = Mutex::new(sender);
+    client.on_state_change(move |state| {
+        let _ = sender.lock().unwrap().send(state);
+    });
+    receiver
+}
+
+#[test]
+fn heartbeat_is_answered() {
+    let addr = start_flaky_server(0, Duration::ZERO);
+    let mut connection = StpClient::connect(addr).unwrap();
+    connection.heartbeat().unwrap();
+    assert_eq!(connection.send_request("ping").unwrap(), "echo ping");
+
+    let mut without_heartbeats = StpClient::connect_with(
+        addr,
+        Protocol {
+            version: V2,
+            features: Features::JSON,
+        },
+    )
+    .unwrap();
+    assert!(matches!(
+        without_heartbeats.heartbeat(),
+        Err(RequestError::HeartbeatNotNegotiated)
+    ));
+}
+
+#[test]
+fn idempotent_request_is_sent_again_after_the_connection_is_lost() {
+    let addr = start_flaky_server(1, Duration::from_millis(100));
+    let client = ReconnectingClient::connect(addr, policy()).unwrap();
+    let states = record_states(&client);
+    assert_eq!(
+        client.send_request("rooms_list").unwrap(),
+        "echo rooms_list"
+    );
+    assert_eq!(
+        states.try_iter().collect::<Vec<_>>(),
+        [
+            ConnectionState::Reconnecting { attempt: 1 },
+            ConnectionState::Connected
+        ]
+    );
+}
+
+#[test]
+fn other_requests_fail_and_the_next_one_reconnects() {
+    let addr = start_flaky_server(1, Duration::from_millis(100));
+    let client = ReconnectingClient::connect(addr, policy()).unwrap();
+    let error = client.send_request("set_device_power_state").unwrap_err();
+    assert!(error.is_connection_lost(), "{error:?}");
+    assert_eq!(client.state(), ConnectionState::Reconnecting { attempt: 1 });
+    assert_eq!(client.send_request("hello").unwrap(), "echo hello");
+    assert_eq!(client.state(), ConnectionState::Connected);
+}
+
+#[test]
+fn client_gives_up_after_max_attempts_with_backoff() {
+    let addr = StpServer::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let client = ReconnectingClient::new(
+        move || StpClient::connect(addr),
+        ReconnectPolicy {
+            max_attempts: Some(3),
+            ..policy()
+        },
+    );
+    let states = record_states(&client);
+    let started = std::time::Instant::now();
+    let error = client.send_request("rooms_list").unwrap_err();
+    assert!(matches!(error, RequestError::Connect(_)), "{error:?}");
+    // Two delays of at least half of 20 and 40 ms
+    assert!(started.elapsed() >= Duration::from_millis(30));
+    assert_eq!(
+        states.try_iter().collect::<Vec<_>>(),
+        [
+            ConnectionState::Reconnecting { attempt: 2 },
+            ConnectionState::Reconnecting { attempt: 3 },
+            ConnectionState::Disconnected
+        ]
+    );
+}
+
+#[test]
+fn heartbeat_detects_a_hung_server_and_reconnects() {
+    let addr = start_flaky_server(1, Duration::from_secs(5));
+    let limits = Limits {
+        io_timeout: Some(Duration::from_millis(200)),
+        ..Limits::default()
+    };
+    let client = ReconnectingClient::new(
+        move || StpClient::connect_with_limits(addr, Protocol::default(), limits),
+        ReconnectPolicy {
+            heartbeat_interval: Some(Duration::from_millis(50)),
+            ..policy()
+        },
+    );
+    let states = Arc::new(Mutex::new(Vec::new()));
+    let recorded = states.clone();
+    client.on_state_change(move |state| recorded.lock().unwrap().push(state));
+    client.with_connection(|_| Ok(())).unwrap();
+
+    thread::sleep(Duration::from_millis(600));
+    assert_eq!(
+        *states.lock().unwrap(),
+        [
+            ConnectionState::Connected,
+            ConnectionState::Reconnecting { attempt: 1 },
+            ConnectionState::Connected
+        ]
+    );
+    assert_eq!(client.send_request("ping").unwrap(), "echo ping");
+}
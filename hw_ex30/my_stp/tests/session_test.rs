@@ -0,0 +1,177 @@
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    thread,
+    time::Duration,
+};
+
+use my_stp::{
+    client::StpClient,
+    errors::{ConnectError, RequestError},
+    message::{Request, Response},
+    protocol::{Features, Protocol, V1, V2},
+    server::StpServer,
+};
+
+fn start_echo_server(idle_timeout: Option<Duration>) -> std::net::SocketAddr {
+    start_server(idle_timeout, Protocol::default())
+}
+
+fn start_server(idle_timeout: Option<Duration>, protocol: Protocol) -> std::net::SocketAddr {
+    let mut server = StpServer::bind("127.0.0.1:0").unwrap();
+    server.set_idle_timeout(idle_timeout);
+    server.set_protocol(protocol);
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || loop {
+        if let Ok(connection) = server.accept() {
+            thread::spawn(move || connection.serve(|request| format!("echo {request}")));
+        }
+    });
+    addr
+}
+
+#[test]
+fn many_requests_over_one_session() {
+    let addr = start_echo_server(None);
+    let mut connection = StpClient::connect(addr).unwrap();
+    for i in 0..3 {
+        assert_eq!(
+            connection.send_request(format!("ping {i}")).unwrap(),
+            format!("echo ping {i}")
+        );
+    }
+    connection.close().unwrap();
+}
+
+#[test]
+fn pipelined_responses_carry_request_ids() {
+    let addr = start_echo_server(None);
+    let mut connection = StpClient::connect(addr).unwrap();
+    let first = connection.send("first").unwrap();
+    let second = connection.send("second").unwrap();
+    assert_ne!(first, second);
+    assert_eq!(
+        connection.recv().unwrap(),
+        (first, "echo first".to_string())
+    );
+    assert_eq!(
+        connection.recv().unwrap(),
+        (second, "echo second".to_string())
+    );
+}
+
+#[test]
+fn idle_session_is_closed_by_server() {
+    let addr = start_echo_server(Some(Duration::from_millis(100)));
+    let mut connection = StpClient::connect(addr).unwrap();
+    assert_eq!(connection.send_request("ping").unwrap(), "echo ping");
+    thread::sleep(Duration::from_millis(300));
+    assert!(matches!(
+        connection.send_request("ping"),
+        Err(RequestError::Closed)
+    ));
+    assert!(connection.is_closed());
+}
+
+#[test]
+fn single_request_handshake_is_still_served() {
+    let addr = start_echo_server(None);
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"clnt").unwrap();
+    let mut handshake = [0u8; 4];
+    stream.read_exact(&mut handshake).unwrap();
+    assert_eq!(&handshake, b"serv");
+
+    stream.write_all(&4u32.to_be_bytes()).unwrap();
+    stream.write_all(b"ping").unwrap();
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).unwrap();
+    let mut response = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut response).unwrap();
+    assert_eq!(response, b"echo ping");
+}
+
+#[test]
+fn typed_requests_in_both_encodings() {
+    let addr = start_echo_server(None);
+    let request = Request::command("device_report")
+        .param("room_name", "Kitchen")
+        .param("device_name", "Socket");
+    for features in [Features::JSON, Features::BINARY] {
+        let protocol = Protocol {
+            version: V2,
+            features,
+        };
+        let mut connection = StpClient::connect_with(addr, protocol).unwrap();
+        assert_eq!(connection.protocol(), protocol);
+        assert_eq!(
+            connection.send_typed(&request).unwrap(),
+            Response::Ok("echo device_report room_name=Kitchen device_name=Socket".to_string())
+        );
+    }
+}
+
+#[test]
+fn negotiation_falls_back_to_common_version_and_features() {
+    let addr = start_server(
+        None,
+        Protocol {
+            version: V1,
+            features: Features::JSON,
+        },
+    );
+    let mut connection = StpClient::connect(addr).unwrap();
+    assert_eq!(connection.protocol(), Protocol::v1());
+    assert!(matches!(
+        connection.send_typed(&Request::command("hello")),
+        Err(RequestError::EncodingNotNegotiated)
+    ));
+    assert_eq!(connection.send_request("hello").unwrap(), "echo hello");
+    assert!(matches!(
+        connection.send_request("hello"),
+        Err(RequestError::Closed)
+    ));
+}
+
+#[test]
+fn unsupported_version_is_rejected() {
+    let addr = start_echo_server(None);
+    let result = StpClient::connect_with(
+        addr,
+        Protocol {
+            version: 0,
+            features: Features::NONE,
+        },
+    );
+    assert!(matches!(result, Err(ConnectError::UnsupportedVersion(0))));
+}
+
+#[test]
+fn closed_session_ends_after_the_request_in_flight() {
+    let server = StpServer::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let (closer_sender, closer) = std::sync::mpsc::channel();
+    let served = thread::spawn(move || {
+        let connection = server.accept().unwrap();
+        let mut closer_sender = Some(closer_sender);
+        let mut closer = Some(connection.closer().unwrap());
+        connection.serve(|request| {
+            // The session is closed while the first request is processed
+            if let Some(closer) = closer.take() {
+                closer_sender.take().unwrap().send(closer).unwrap();
+                thread::sleep(Duration::from_millis(100));
+            }
+            format!("echo {request}")
+        })
+    });
+    let mut connection = StpClient::connect(addr).unwrap();
+    let request = thread::spawn(move || {
+        let response = connection.send_request("ping").unwrap();
+        (connection, response)
+    });
+    closer.recv().unwrap().close();
+    let (mut connection, response) = request.join().unwrap();
+    assert_eq!(response, "echo ping");
+    served.join().unwrap().unwrap();
+    assert!(connection.send_request("ping").is_err());
+}
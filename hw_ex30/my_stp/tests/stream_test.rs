@@ -0,0 +1,163 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use my_stp::{
+    client::StpClient,
+    errors::RequestError,
+    message::{Request, Response, STREAM_FAILED_CODE},
+    protocol::{Features, Protocol, V1, V2},
+    server::{Reply, StpServer},
+};
+
+/// `count <n>` streams n numbered lines, `fail` fails after two of them,
+/// `endless` streams until it is cancelled. Returns the number of chunks produced
+fn start_stream_server() -> (SocketAddr, Arc<AtomicUsize>) {
+    let server = StpServer::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let produced = Arc::new(AtomicUsize::new(0));
+    let counter = produced.clone();
+    thread::spawn(move || loop {
+        if let Ok(connection) = server.accept() {
+            let counter = counter.clone();
+            thread::spawn(move || {
+                connection.serve_streaming(|request| stream_reply(request, counter.clone()))
+            });
+        }
+    });
+    (addr, produced)
+}
+
+fn stream_reply(request: Request, produced: Arc<AtomicUsize>) -> Reply {
+    let request = request.into_command_line();
+    let lines = (0..).map(move |i| {
+        produced.fetch_add(1, Ordering::Relaxed);
+        Ok(format!("line {i}\n"))
+    });
+    match request.split_once(' ') {
+        Some(("count", n)) => Reply::stream(lines.take(n.parse().unwrap())),
+        _ if request == "fail" => {
+            Reply::stream(lines.take(2).chain([Err("device is gone".to_string())]))
+        }
+        _ if request == "endless" => Reply::stream(lines.inspect(|_| {
+            thread::sleep(Duration::from_millis(5));
+        })),
+        _ => Reply::Response(Response::Ok(format!("echo {request}"))),
+    }
+}
+
+#[test]
+fn chunks_arrive_in_order() {
+    let (addr, _) = start_stream_server();
+    let mut connection = StpClient::connect(addr).unwrap();
+    let chunks: Vec<String> = connection
+        .send_streaming("count 3")
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(chunks, ["line 0\n", "line 1\n", "line 2\n"]);
+    assert_eq!(connection.send_request("ping").unwrap(), "echo ping");
+}
+
+#[test]
+fn plain_response_is_a_single_chunk() {
+    let (addr, _) = start_stream_server();
+    let mut connection = StpClient::connect(addr).unwrap();
+    let chunks: Vec<_> = connection.send_streaming("ping").unwrap().collect();
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(chunks[0].as_ref().unwrap(), "echo ping");
+}
+
+#[test]
+fn send_request_joins_the_chunks() {
+    let (addr, _) = start_stream_server();
+    let mut connection = StpClient::connect(addr).unwrap();
+    assert_eq!(
+        connection.send_request("count 2").unwrap(),
+        "line 0\nline 1\n"
+    );
+    assert!(matches!(
+        connection.send_request("fail"),
+        Err(RequestError::StreamFailed(message)) if message == "device is gone"
+    ));
+    assert_eq!(connection.send_request("ping").unwrap(), "echo ping");
+}
+
+#[test]
+fn failed_stream_ends_with_an_error() {
+    let (addr, _) = start_stream_server();
+    let mut connection = StpClient::connect(addr).unwrap();
+    let mut stream = connection.send_streaming("fail").unwrap();
+    assert_eq!(stream.next().unwrap().unwrap(), "line 0\n");
+    assert_eq!(stream.next().unwrap().unwrap(), "line 1\n");
+    assert!(matches!(
+        stream.next(),
+        Some(Err(RequestError::StreamFailed(_)))
+    ));
+    assert!(stream.next().is_none());
+}
+
+#[test]
+fn cancel_stops_the_stream_and_keeps_the_session() {
+    let (addr, produced) = start_stream_server();
+    let mut connection = StpClient::connect(addr).unwrap();
+    let mut stream = connection.send_streaming("endless").unwrap();
+    for i in 0..3 {
+        assert_eq!(stream.next().unwrap().unwrap(), format!("line {i}\n"));
+    }
+    stream.cancel().unwrap();
+    assert_eq!(connection.send_request("ping").unwrap(), "echo ping");
+
+    let after_cancel = produced.load(Ordering::Relaxed);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(produced.load(Ordering::Relaxed), after_cancel);
+}
+
+#[test]
+fn dropped_stream_is_cancelled() {
+    let (addr, _) = start_stream_server();
+    let mut connection = StpClient::connect(addr).unwrap();
+    let first = connection.send_streaming("endless").unwrap().next();
+    assert_eq!(first.unwrap().unwrap(), "line 0\n");
+    assert_eq!(connection.send_request("count 1").unwrap(), "line 0\n");
+}
+
+#[test]
+fn clients_without_streams_get_one_response() {
+    let (addr, _) = start_stream_server();
+    let mut connection = StpClient::connect_with(
+        addr,
+        Protocol {
+            version: V2,
+            features: Features::JSON,
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        connection.send_request("count 2").unwrap(),
+        "line 0\nline 1\n"
+    );
+    let response = connection.send_typed(&Request::command("fail")).unwrap();
+    assert!(matches!(
+        response,
+        Response::Error { code, message } if code == STREAM_FAILED_CODE && message == "device is gone"
+    ));
+
+    let mut v1 = StpClient::connect_with(
+        addr,
+        Protocol {
+            version: V1,
+            features: Features::NONE,
+        },
+    )
+    .unwrap();
+    let chunks: Vec<_> = v1.send_streaming("count 2").unwrap().collect();
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(chunks[0].as_ref().unwrap(), "line 0\nline 1\n");
+}
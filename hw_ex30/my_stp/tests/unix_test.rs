@@ -0,0 +1,94 @@
+#![cfg(unix)]
+
+use std::{
+    fs, io,
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use my_stp::{
+    client::StpClient, errors::RequestError, server::StpServer, socket_file::UnixSocketOptions,
+};
+
+fn socket_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("stp-test-{}-{name}.sock", std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn session_over_unix_socket() {
+    let path = socket_path("session");
+    let server = StpServer::bind_unix(&path, UnixSocketOptions::default()).unwrap();
+    assert_eq!(server.socket_path(), Some(path.as_path()));
+    assert!(server.local_addr().is_err());
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let connection = server.accept().unwrap();
+        sender.send(connection.peer_addr().is_err()).unwrap();
+        connection.serve(|request| format!("echo {request}"))
+    });
+
+    let mut connection = StpClient::connect_unix(&path).unwrap();
+    assert!(receiver.recv().unwrap());
+    for i in 0..3 {
+        assert_eq!(
+            connection.send_request(format!("ping {i}")).unwrap(),
+            format!("echo ping {i}")
+        );
+    }
+    connection.close().unwrap();
+}
+
+#[test]
+fn socket_file_follows_the_options() {
+    let path = socket_path("mode");
+    let options = UnixSocketOptions {
+        mode: Some(0o600),
+        ..UnixSocketOptions::default()
+    };
+    let server = StpServer::bind_unix(&path, options).unwrap();
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    // A live server is never replaced
+    let error = StpServer::bind_unix(&path, options).err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+
+    drop(server);
+    assert!(!path.exists());
+}
+
+#[test]
+fn pushes_and_idle_timeout_over_unix_socket() {
+    let path = socket_path("push");
+    let mut server = StpServer::bind_unix(&path, UnixSocketOptions::default()).unwrap();
+    server.set_idle_timeout(Some(Duration::from_millis(200)));
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut connection = server.accept().unwrap();
+        sender.send(connection.pusher().unwrap()).unwrap();
+        connection.serve(|request| format!("echo {request}"))
+    });
+
+    let mut connection = StpClient::connect_unix(&path).unwrap();
+    let pusher = receiver.recv().unwrap();
+    pusher.push("report", "tick").unwrap();
+    let push = connection.recv_push(None).unwrap().unwrap();
+    assert_eq!((push.seq, push.body.as_str()), (1, "tick"));
+    assert_eq!(connection.send_request("ping").unwrap(), "echo ping");
+
+    thread::sleep(Duration::from_millis(400));
+    assert!(matches!(
+        connection.send_request("ping"),
+        Err(RequestError::Closed)
+    ));
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while !pusher.is_closed() {
+        assert!(Instant::now() < deadline, "pusher is still open");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
@@ -1,47 +1,699 @@
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpStream, ToSocketAddrs},
+use std::{
+    collections::{HashSet, VecDeque},
+    future::{self, Future},
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
 };
 
+use futures::Stream;
+use tokio::net::ToSocketAddrs;
+
+use my_stp_core::{codec::Message, handshake::ClientHandshake};
+
 use crate::{
-    errors::{ConnectError, RequestError},
-    recv_string, send_string,
+    auth::Credentials,
+    errors::{ConnectError, RecvError, RequestError, SendError},
+    limits::Limits,
+    message::{Encoding, Push, Request, Response, StreamStatus},
+    protocol::{Features, Protocol},
+    recv_message, recv_string, run_handshake, send_message, send_string, transport, with_deadline,
 };
 
+#[cfg(unix)]
+use std::path::Path;
+
 pub struct StpClient;
 
 impl StpClient {
+    /// Open a session with the latest protocol version and all features
     pub async fn connect<Addrs>(addrs: Addrs) -> Result<StpConnection, ConnectError>
     where
         Addrs: ToSocketAddrs,
     {
-        let tcp = TcpStream::connect(addrs).await?;
-        Self::try_handshake(tcp).await
+        Self::connect_with(addrs, Protocol::default()).await
     }
 
-    async fn try_handshake(mut stream: TcpStream) -> Result<StpConnection, ConnectError> {
-        stream.write_all(b"clnt").await?;
-        let mut buf = [0u8; 4];
-        stream.read_exact(&mut buf).await?;
-        if &buf != b"serv" {
-            return Err(ConnectError::BadHandshake);
-        }
-        Ok(StpConnection { stream })
+    /// Open a connection offering at most `protocol` to the server
+    pub async fn connect_with<Addrs>(
+        addrs: Addrs,
+        protocol: Protocol,
+    ) -> Result<StpConnection, ConnectError>
+    where
+        Addrs: ToSocketAddrs,
+    {
+        Self::connect_with_limits(addrs, protocol, Limits::default()).await
+    }
+
+    /// Same as [`StpClient::connect_with`], with custom frame size limit and deadlines
+    pub async fn connect_with_limits<Addrs>(
+        addrs: Addrs,
+        protocol: Protocol,
+        limits: Limits,
+    ) -> Result<StpConnection, ConnectError>
+    where
+        Addrs: ToSocketAddrs,
+    {
+        let stream = transport::Stream::connect_tcp(addrs).await?;
+        Self::try_handshake(stream, protocol, limits, None).await
+    }
+
+    /// Open a session with a server that requires authentication
+    pub async fn connect_authenticated<Addrs>(
+        addrs: Addrs,
+        credentials: &Credentials,
+    ) -> Result<StpConnection, ConnectError>
+    where
+        Addrs: ToSocketAddrs,
+    {
+        let stream = transport::Stream::connect_tcp(addrs).await?;
+        Self::try_handshake(
+            stream,
+            Protocol::default(),
+            Limits::default(),
+            Some(credentials),
+        )
+        .await
+    }
+
+    /// Open a session over the Unix socket at `path`
+    /// with the latest protocol version and all features
+    #[cfg(unix)]
+    pub async fn connect_unix<P>(path: P) -> Result<StpConnection, ConnectError>
+    where
+        P: AsRef<Path>,
+    {
+        Self::connect_unix_with(path, Protocol::default(), Limits::default()).await
+    }
+
+    /// Same as [`StpClient::connect_with_limits`], over a Unix socket
+    #[cfg(unix)]
+    pub async fn connect_unix_with<P>(
+        path: P,
+        protocol: Protocol,
+        limits: Limits,
+    ) -> Result<StpConnection, ConnectError>
+    where
+        P: AsRef<Path>,
+    {
+        let stream = transport::Stream::connect_unix(path.as_ref()).await?;
+        Self::try_handshake(stream, protocol, limits, None).await
+    }
+
+    /// Same as [`StpClient::connect_authenticated`], over a Unix socket
+    #[cfg(unix)]
+    pub async fn connect_unix_authenticated<P>(
+        path: P,
+        credentials: &Credentials,
+    ) -> Result<StpConnection, ConnectError>
+    where
+        P: AsRef<Path>,
+    {
+        let stream = transport::Stream::connect_unix(path.as_ref()).await?;
+        Self::try_handshake(
+            stream,
+            Protocol::default(),
+            Limits::default(),
+            Some(credentials),
+        )
+        .await
     }
+
+    /// The whole handshake is bounded by the handshake timeout
+    async fn try_handshake(
+        mut stream: transport::Stream,
+        protocol: Protocol,
+        limits: Limits,
+        credentials: Option<&Credentials>,
+    ) -> Result<StpConnection, ConnectError> {
+        let mut handshake = ClientHandshake::new(protocol, credentials);
+        with_deadline(
+            limits.handshake_timeout,
+            run_handshake(&mut handshake, &mut stream),
+        )
+        .await
+        .ok_or(ConnectError::HandshakeTimeout)??;
+        Ok(StpConnection {
+            stream,
+            protocol: handshake.outcome().ok_or(ConnectError::BadHandshake)?,
+            limits,
+            next_request_id: 1,
+            pending_requests: 0,
+            responses: VecDeque::new(),
+            pushes: VecDeque::new(),
+            discarded: HashSet::new(),
+            last_heartbeat: 0,
+            heartbeat_reply: 0,
+            unsent_cancels: Vec::new(),
+            closed: false,
+        })
+    }
+}
+
+/// Response or a part of a streamed one
+#[derive(Debug)]
+enum Incoming {
+    Response(Response),
+    Chunk(String),
+    End(StreamStatus),
 }
 
+/// Client side of a connection.
+/// In a session the server answers requests in the order they were sent,
+/// every response carries the id of its request.
+/// Push messages may arrive between responses, they are kept until [`StpConnection::recv_push`].
+/// A v1 connection is closed after the first request
 #[derive(Debug)]
 pub struct StpConnection {
-    stream: TcpStream,
+    stream: transport::Stream,
+    protocol: Protocol,
+    limits: Limits,
+    next_request_id: u64,
+    pending_requests: usize,
+    /// Responses and chunks read while waiting for something else
+    responses: VecDeque<(u64, Incoming)>,
+    /// Pushes read while waiting for a response
+    pushes: VecDeque<Push>,
+    /// Cancelled streams whose remaining chunks are skipped
+    discarded: HashSet<u64>,
+    /// Id of the last heartbeat sent and of the last one the server sent back
+    last_heartbeat: u64,
+    heartbeat_reply: u64,
+    /// Cancels of dropped streams, sent before the next read or write
+    unsent_cancels: Vec<u64>,
+    closed: bool,
 }
 
 impl StpConnection {
+    /// Version and features negotiated with the server
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Send a request and wait for its response
     pub async fn send_request<T>(&mut self, request: T) -> Result<String, RequestError>
     where
         T: ToString,
     {
-        send_string(request.to_string().as_str(), &mut self.stream).await?;
-        let response = recv_string(&mut self.stream).await?;
+        if !self.protocol.is_session() {
+            return self.send_single_request(request.to_string()).await;
+        }
+        let id = self.send(request).await?;
+        let (response_id, response) = self.recv().await?;
+        Self::check_response_id(id, response_id)?;
         Ok(response)
     }
+
+    /// Send a request and read its response chunk by chunk as the server produces it.
+    /// A response that is not streamed is yielded as a single chunk.
+    /// Dropping the stream before its end cancels the rest
+    pub async fn send_streaming<T>(
+        &mut self,
+        request: T,
+    ) -> Result<ResponseStream<'_>, RequestError>
+    where
+        T: ToString,
+    {
+        if !self.protocol.is_session() {
+            let response = self.send_single_request(request.to_string()).await?;
+            return Ok(ResponseStream::single(self, response));
+        }
+        let id = self.send(request).await?;
+        Ok(ResponseStream::new(self, id))
+    }
+
+    /// Send a typed request in the most compact negotiated encoding and wait for its response
+    pub async fn send_typed(&mut self, request: &Request) -> Result<Response, RequestError> {
+        let id = self.send_typed_request(request).await?;
+        let (response_id, response) = self.recv_response().await?;
+        Self::check_response_id(id, response_id)?;
+        Ok(response)
+    }
+
+    /// Send a request without waiting for the response. Returns the request id
+    pub async fn send<T>(&mut self, request: T) -> Result<u64, RequestError>
+    where
+        T: ToString,
+    {
+        let body = request.to_string();
+        self.send_message(|id| Message::Text { id, body }).await
+    }
+
+    /// Send a typed request without waiting for the response. Returns the request id
+    pub(crate) async fn send_typed_request(
+        &mut self,
+        request: &Request,
+    ) -> Result<u64, RequestError> {
+        let encoding = Encoding::preferred(self.protocol.features)
+            .ok_or(RequestError::EncodingNotNegotiated)?;
+        let data = encoding.encode(request).map_err(SendError::Encode)?;
+        self.send_message(|id| Message::Typed { id, encoding, data })
+            .await
+    }
+
+    /// Receive the next response together with the id of its request.
+    /// Returns [`RequestError::Closed`] if the server has closed the session, e.g. after idle timeout
+    pub async fn recv(&mut self) -> Result<(u64, String), RequestError> {
+        let (id, response) = self.recv_response().await?;
+        Ok((id, response.into_text()))
+    }
+
+    /// Next push message, waiting at most `timeout` for it, `None` waits forever.
+    /// Returns `Ok(None)` if nothing arrived in time
+    pub async fn recv_push(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Push>, RequestError> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some(push) = self.pushes.pop_front() {
+                return Ok(Some(push));
+            }
+            if self.closed || !self.protocol.is_session() {
+                return Err(RequestError::Closed);
+            }
+            let timeout =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            // Only the peek is cut short, a message that has started arriving is read whole
+            match with_deadline(timeout, self.stream.peek()).await {
+                Some(Ok(0)) => {
+                    self.closed = true;
+                    return Err(RequestError::Closed);
+                }
+                Some(Ok(_)) => self.recv_next().await?,
+                Some(Err(e)) => return Err(RecvError::Io(e).into()),
+                None => return Ok(None),
+            }
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    pub(crate) fn is_session(&self) -> bool {
+        self.protocol.is_session()
+    }
+
+    /// Wait until the next message starts arriving. Cancel safe, nothing is consumed
+    pub(crate) async fn readable(&mut self) -> Result<(), RequestError> {
+        match self.stream.peek().await {
+            Ok(0) => {
+                self.closed = true;
+                Err(RequestError::Closed)
+            }
+            Ok(_) => Ok(()),
+            Err(e) => Err(RecvError::Io(e).into()),
+        }
+    }
+
+    /// Read one message. Returns the response it completes together with the id of its request,
+    /// chunks of a streamed response are kept until its end and joined.
+    /// Pushes are dropped, nobody reads them from a pipelined session
+    pub(crate) async fn recv_completed(
+        &mut self,
+    ) -> Result<Option<(u64, Result<Response, RequestError>)>, RequestError> {
+        self.recv_next().await?;
+        self.pushes.clear();
+        let Some(index) = self
+            .responses
+            .iter()
+            .position(|(_, incoming)| !matches!(incoming, Incoming::Chunk(_)))
+        else {
+            return Ok(None);
+        };
+        let (id, last) = self.responses.remove(index).unwrap();
+        let mut body = String::new();
+        self.responses.retain(|(queued, incoming)| match incoming {
+            Incoming::Chunk(chunk) if *queued == id => {
+                body.push_str(chunk);
+                false
+            }
+            _ => true,
+        });
+        let response = match last {
+            Incoming::Response(response) => Ok(response),
+            Incoming::End(status) => Self::joined(id, body, status).map(|(_, response)| response),
+            Incoming::Chunk(_) => unreachable!("chunks are never the last part"),
+        };
+        Ok(Some((id, response)))
+    }
+
+    /// Drop the response of request `id` when it arrives, a streamed one is stopped on the server
+    pub(crate) async fn cancel_request(&mut self, id: u64) -> Result<(), SendError> {
+        self.cancel_stream(id);
+        self.flush_cancels().await
+    }
+
+    /// Check without waiting whether the server has closed the session, e.g. after idle timeout.
+    /// Only a session with no responses outstanding can be checked, others count as open
+    pub async fn is_open(&mut self) -> bool {
+        self.protocol.is_session() && !self.closed && !self.is_closed_by_server().await
+    }
+
+    /// Send a heartbeat and wait for the server to send it back. Returns the round trip time.
+    /// A server that does not answer within the I/O timeout of the limits is considered dead,
+    /// responses and pushes that arrive meanwhile are kept
+    pub async fn heartbeat(&mut self) -> Result<Duration, RequestError> {
+        if !self.protocol.features.contains(Features::HEARTBEAT) {
+            return Err(RequestError::HeartbeatNotNegotiated);
+        }
+        if self.closed || self.is_closed_by_server().await {
+            return Err(RequestError::Closed);
+        }
+        self.last_heartbeat += 1;
+        let id = self.last_heartbeat;
+        let started = Instant::now();
+        send_message(&Message::Heartbeat { id }, &mut self.stream, &self.limits).await?;
+        while self.heartbeat_reply != id {
+            if self.closed {
+                return Err(RequestError::Closed);
+            }
+            self.recv_next().await?;
+        }
+        Ok(started.elapsed())
+    }
+
+    /// Tell the server that the session is over
+    pub async fn close(mut self) -> Result<(), RequestError> {
+        self.closed = true;
+        if self.protocol.is_session() {
+            send_message(&Message::Close, &mut self.stream, &self.limits).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_single_request(&mut self, request: String) -> Result<String, RequestError> {
+        if self.closed {
+            return Err(RequestError::Closed);
+        }
+        self.closed = true;
+        send_string(request, &mut self.stream, &self.limits).await?;
+        Ok(recv_string(&mut self.stream, &self.limits).await?)
+    }
+
+    async fn send_message<F>(&mut self, message: F) -> Result<u64, RequestError>
+    where
+        F: FnOnce(u64) -> Message,
+    {
+        if !self.protocol.is_session() || self.closed || self.is_closed_by_server().await {
+            return Err(RequestError::Closed);
+        }
+        self.flush_cancels().await?;
+        let id = self.next_request_id;
+        self.next_request_id += 1;
+        send_message(&message(id), &mut self.stream, &self.limits).await?;
+        self.pending_requests += 1;
+        Ok(id)
+    }
+
+    /// Next response of any request, the chunks of a streamed one are joined
+    async fn recv_response(&mut self) -> Result<(u64, Response), RequestError> {
+        let (id, first) = loop {
+            if let Some(incoming) = self.responses.pop_front() {
+                break incoming;
+            }
+            if self.closed {
+                return Err(RequestError::Closed);
+            }
+            self.recv_next().await?;
+        };
+        let mut body = match first {
+            Incoming::Response(response) => return Ok((id, response)),
+            Incoming::Chunk(chunk) => chunk,
+            Incoming::End(status) => return Self::joined(id, String::new(), status),
+        };
+        loop {
+            match self.recv_incoming(id).await? {
+                Incoming::Chunk(chunk) => body.push_str(&chunk),
+                Incoming::End(status) => return Self::joined(id, body, status),
+                Incoming::Response(response) => return Ok((id, response)),
+            }
+        }
+    }
+
+    fn joined(
+        id: u64,
+        body: String,
+        status: StreamStatus,
+    ) -> Result<(u64, Response), RequestError> {
+        match status {
+            StreamStatus::Complete => Ok((id, Response::Ok(body))),
+            StreamStatus::Failed(message) => Err(RequestError::StreamFailed(message)),
+            StreamStatus::Cancelled => Err(RequestError::StreamFailed("cancelled".to_string())),
+        }
+    }
+
+    /// Next response or chunk of request `id`, others stay queued
+    async fn recv_incoming(&mut self, id: u64) -> Result<Incoming, RequestError> {
+        loop {
+            if let Some(index) = self.responses.iter().position(|(queued, _)| *queued == id) {
+                return Ok(self.responses.remove(index).unwrap().1);
+            }
+            if self.closed {
+                return Err(RequestError::Closed);
+            }
+            self.recv_next().await?;
+        }
+    }
+
+    /// Read one message and queue it as a response, a chunk or a push
+    async fn recv_next(&mut self) -> Result<(), RequestError> {
+        self.flush_cancels().await?;
+        let (id, incoming) = match recv_message(&mut self.stream, &self.limits).await? {
+            Message::Text { id, body } => (id, Incoming::Response(Response::Ok(body))),
+            Message::Typed { id, encoding, data } => {
+                let response = encoding.decode(&data).map_err(RecvError::Decode)?;
+                (id, Incoming::Response(response))
+            }
+            Message::Chunk { id, body } => (id, Incoming::Chunk(body)),
+            Message::End { id, status } => (id, Incoming::End(status)),
+            Message::Push(push) => {
+                self.pushes.push_back(push);
+                return Ok(());
+            }
+            Message::Close => {
+                self.closed = true;
+                return Err(RequestError::Closed);
+            }
+            Message::Heartbeat { id } => {
+                self.heartbeat_reply = id;
+                return Ok(());
+            }
+            Message::Cancel { .. } => return Err(RecvError::BadMessage.into()),
+        };
+        if !matches!(incoming, Incoming::Chunk(_)) {
+            self.pending_requests = self.pending_requests.saturating_sub(1);
+        }
+        if self.discarded.contains(&id) {
+            if !matches!(incoming, Incoming::Chunk(_)) {
+                self.discarded.remove(&id);
+            }
+            return Ok(());
+        }
+        self.responses.push_back((id, incoming));
+        Ok(())
+    }
+
+    /// Skip the rest of stream `id` and ask the server to stop it.
+    /// The cancel itself goes out with the next read or write
+    fn cancel_stream(&mut self, id: u64) {
+        let ended = self
+            .responses
+            .iter()
+            .any(|(queued, incoming)| *queued == id && !matches!(incoming, Incoming::Chunk(_)));
+        self.responses.retain(|(queued, _)| *queued != id);
+        if !ended {
+            self.discarded.insert(id);
+            self.unsent_cancels.push(id);
+        }
+    }
+
+    async fn flush_cancels(&mut self) -> Result<(), SendError> {
+        while let Some(&id) = self.unsent_cancels.first() {
+            send_message(&Message::Cancel { id }, &mut self.stream, &self.limits).await?;
+            self.unsent_cancels.remove(0);
+        }
+        Ok(())
+    }
+
+    fn check_response_id(expected: u64, received: u64) -> Result<(), RequestError> {
+        if expected != received {
+            return Err(RequestError::UnexpectedResponseId { expected, received });
+        }
+        Ok(())
+    }
+
+    /// With no responses outstanding anything readable on the socket besides pushes
+    /// is a close message or EOF. Checking it before sending keeps the request from
+    /// being written into a connection the server has already dropped
+    async fn is_closed_by_server(&mut self) -> bool {
+        if self.pending_requests > 0 {
+            return false;
+        }
+        loop {
+            let peeked = future::poll_fn(|cx| match self.stream.poll_peek(cx) {
+                Poll::Pending => Poll::Ready(None),
+                Poll::Ready(peeked) => Poll::Ready(Some(peeked)),
+            })
+            .await;
+            match peeked {
+                None => return false,
+                Some(Ok(0)) | Some(Err(_)) => break,
+                Some(Ok(_)) => match recv_message(&mut self.stream, &self.limits).await {
+                    Ok(Message::Push(push)) => self.pushes.push_back(push),
+                    _ => break,
+                },
+            }
+        }
+        self.closed = true;
+        true
+    }
+}
+
+impl Drop for StpConnection {
+    fn drop(&mut self) {
+        if !self.closed && self.protocol.is_session() {
+            // Best effort: the close frame is tiny, so it fits into the socket buffer
+            if let Ok(frame) = Message::Close.encode_frame(&self.limits) {
+                let _ = self.stream.try_write(&frame);
+            }
+        }
+    }
+}
+
+type NextIncoming<'a> = Pin<
+    Box<dyn Future<Output = (&'a mut StpConnection, Result<Incoming, RequestError>)> + Send + 'a>,
+>;
+
+/// Chunks of a streamed response, see [`StpConnection::send_streaming`].
+/// Items are the chunks in order, a failed stream ends with [`RequestError::StreamFailed`].
+/// Like any other read, a chunk that is half read when the stream is dropped
+/// leaves the connection unusable
+pub struct ResponseStream<'a> {
+    /// Taken by the read in progress
+    connection: Option<&'a mut StpConnection>,
+    reading: Option<NextIncoming<'a>>,
+    id: u64,
+    /// Response of a v1 connection, it is read before the stream is created
+    single: Option<String>,
+    done: bool,
+}
+
+impl<'a> ResponseStream<'a> {
+    fn new(connection: &'a mut StpConnection, id: u64) -> Self {
+        Self {
+            connection: Some(connection),
+            reading: None,
+            id,
+            single: None,
+            done: false,
+        }
+    }
+
+    fn single(connection: &'a mut StpConnection, response: String) -> Self {
+        Self {
+            connection: Some(connection),
+            reading: None,
+            id: 0,
+            single: Some(response),
+            done: false,
+        }
+    }
+
+    /// Id of the request
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Stop the stream. The server stops producing chunks and the connection
+    /// is ready for the next request, chunks that were on the way are dropped
+    pub async fn cancel(mut self) -> Result<(), RequestError> {
+        if let Some(reading) = self.reading.take() {
+            let (connection, incoming) = reading.await;
+            self.connection = Some(connection);
+            self.item(incoming);
+        }
+        if self.done {
+            return Ok(());
+        }
+        self.done = true;
+        let id = self.id;
+        if let Some(connection) = self.connection.as_deref_mut() {
+            connection.cancel_stream(id);
+            connection.flush_cancels().await?;
+        }
+        Ok(())
+    }
+
+    fn item(
+        &mut self,
+        incoming: Result<Incoming, RequestError>,
+    ) -> Option<Result<String, RequestError>> {
+        let incoming = match incoming {
+            Ok(incoming) => incoming,
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e));
+            }
+        };
+        match incoming {
+            Incoming::Chunk(chunk) => Some(Ok(chunk)),
+            Incoming::Response(response) => {
+                self.done = true;
+                Some(Ok(response.into_text()))
+            }
+            Incoming::End(StreamStatus::Failed(message)) => {
+                self.done = true;
+                Some(Err(RequestError::StreamFailed(message)))
+            }
+            Incoming::End(_) => {
+                self.done = true;
+                None
+            }
+        }
+    }
+}
+
+impl Stream for ResponseStream<'_> {
+    type Item = Result<String, RequestError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if this.done {
+            return Poll::Ready(None);
+        }
+        if let Some(response) = this.single.take() {
+            this.done = true;
+            return Poll::Ready(Some(Ok(response)));
+        }
+        if this.reading.is_none() {
+            let Some(connection) = this.connection.take() else {
+                return Poll::Ready(None);
+            };
+            let id = this.id;
+            this.reading = Some(Box::pin(async move {
+                let incoming = connection.recv_incoming(id).await;
+                (connection, incoming)
+            }));
+        }
+        let reading = this.reading.as_mut().expect("read is in progress");
+        let (connection, incoming) = ready!(reading.as_mut().poll(cx));
+        this.reading = None;
+        this.connection = Some(connection);
+        Poll::Ready(this.item(incoming))
+    }
+}
+
+impl Drop for ResponseStream<'_> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        if let Some(connection) = self.connection.as_deref_mut() {
+            if !connection.closed {
+                connection.cancel_stream(self.id);
+            }
+        }
+    }
 }
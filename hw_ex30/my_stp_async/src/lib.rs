@@ -1,32 +1,145 @@
-use errors::{RecvError, SendError};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::{future::Future, io::ErrorKind, time::Duration};
+
+use my_stp_core::{
+    codec::{encode_frame, FrameDecoder, Message},
+    handshake::Handshake,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time,
+};
+
+#[cfg(unix)]
+pub use my_stp_core::socket_file;
+pub use my_stp_core::{
+    auth, command_schema, custom_parser, errors, jsonrpc, limits, message, protocol,
+};
+
+use errors::{ConnectError, RecvError, SendError};
+use limits::Limits;
 
 pub mod client;
-pub mod custom_parser;
-pub mod errors;
+pub mod pipeline;
+pub mod push;
+pub mod reconnect;
 pub mod server;
+mod transport;
 
-async fn send_string<Data, Writer>(data: Data, mut writer: Writer) -> Result<(), SendError>
+/// Drive a handshake state machine over the stream.
+/// Callers bound the whole handshake with [`with_deadline`]
+async fn run_handshake<H, Stream>(
+    handshake: &mut H,
+    stream: &mut Stream,
+) -> Result<(), ConnectError>
+where
+    H: Handshake,
+    Stream: AsyncReadExt + AsyncWriteExt + Unpin,
+{
+    loop {
+        stream.write_all(&handshake.take_output()).await?;
+        let needed = handshake.bytes_needed();
+        if needed == 0 {
+            return Ok(());
+        }
+        let mut buf = vec![0u8; needed];
+        read_exact(stream, &mut buf).await?;
+        if let Err(e) = handshake.receive(&buf) {
+            // The peer still learns why, e.g. that its credentials were rejected
+            let _ = stream.write_all(&handshake.take_output()).await;
+            return Err(e);
+        }
+    }
+}
+
+async fn send_string<Data, Writer>(
+    data: Data,
+    writer: Writer,
+    limits: &Limits,
+) -> Result<(), SendError>
 where
     Data: AsRef<str>,
     Writer: AsyncWriteExt + Unpin,
 {
-    let data_bytes = data.as_ref().as_bytes();
-    let len_bytes = (data_bytes.len() as u32).to_be_bytes();
-    writer.write_all(&len_bytes).await?;
-    writer.write_all(data_bytes).await?;
+    send_frame(data.as_ref().as_bytes(), writer, limits).await
+}
+
+async fn recv_string<Reader>(reader: Reader, limits: &Limits) -> Result<String, RecvError>
+where
+    Reader: AsyncReadExt + Unpin,
+{
+    let buf = recv_frame(reader, limits).await?;
+    String::from_utf8(buf).map_err(|_| RecvError::BadEncoding)
+}
+
+async fn send_frame<Writer>(
+    data: &[u8],
+    mut writer: Writer,
+    limits: &Limits,
+) -> Result<(), SendError>
+where
+    Writer: AsyncWriteExt + Unpin,
+{
+    let frame = encode_frame(data, limits)?;
+    with_deadline(limits.io_timeout, writer.write_all(&frame))
+        .await
+        .ok_or(SendError::Timeout)??;
     Ok(())
 }
 
-async fn recv_string<Reader>(mut reader: Reader) -> Result<String, RecvError>
+/// Reads exactly one frame, bytes of the next one stay in the socket
+async fn recv_frame<Reader>(mut reader: Reader, limits: &Limits) -> Result<Vec<u8>, RecvError>
 where
     Reader: AsyncReadExt + Unpin,
 {
-    let mut len_buf = [0u8; 4];
-    reader.read_exact(&mut len_buf).await?;
-    let len = u32::from_be_bytes(len_buf);
+    let recv = async {
+        let mut decoder = FrameDecoder::new(limits);
+        loop {
+            if let Some(frame) = decoder.decode()? {
+                return Ok(frame);
+            }
+            let mut buf = vec![0u8; decoder.bytes_needed()];
+            read_exact(&mut reader, &mut buf).await?;
+            decoder.extend(&buf);
+        }
+    };
+    with_deadline(limits.io_timeout, recv)
+        .await
+        .ok_or(RecvError::Timeout)?
+}
 
-    let mut buf = vec![0; len as usize];
-    reader.read_exact(&mut buf).await?;
-    String::from_utf8(buf).map_err(|_| RecvError::BadEncoding)
+async fn read_exact<Reader>(reader: &mut Reader, buf: &mut [u8]) -> Result<(), RecvError>
+where
+    Reader: AsyncReadExt + Unpin,
+{
+    match reader.read_exact(buf).await {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Err(RecvError::ConnectionClosed),
+        Err(e) => Err(RecvError::Io(e)),
+    }
+}
+
+/// `None` if `timeout` has elapsed before `future` completed
+async fn with_deadline<F: Future>(timeout: Option<Duration>, future: F) -> Option<F::Output> {
+    match timeout {
+        Some(timeout) => time::timeout(timeout, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+async fn send_message<Writer>(
+    message: &Message,
+    writer: Writer,
+    limits: &Limits,
+) -> Result<(), SendError>
+where
+    Writer: AsyncWriteExt + Unpin,
+{
+    send_frame(&message.encode(), writer, limits).await
+}
+
+async fn recv_message<Reader>(reader: Reader, limits: &Limits) -> Result<Message, RecvError>
+where
+    Reader: AsyncReadExt + Unpin,
+{
+    Message::decode(recv_frame(reader, limits).await?)
 }
@@ -0,0 +1,254 @@
+use std::{
+    collections::HashMap,
+    future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::sync::{mpsc, oneshot, watch};
+
+use crate::{
+    client::StpConnection,
+    errors::RequestError,
+    message::{Request, Response},
+    protocol::Protocol,
+    with_deadline,
+};
+
+type Reply = oneshot::Sender<Result<Response, RequestError>>;
+
+/// How long a single call may take and when it is given up
+#[derive(Debug, Clone, Default)]
+pub struct CallOptions {
+    /// Fails the call with [`RequestError::DeadlineExceeded`], `None` waits forever
+    pub deadline: Option<Duration>,
+    /// Sending `true` fails the call with [`RequestError::Cancelled`]
+    pub cancel: Option<watch::Receiver<bool>>,
+}
+
+impl CallOptions {
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn cancel_on(mut self, cancel: watch::Receiver<bool>) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+}
+
+enum Call {
+    Text(String),
+    Typed(Request),
+}
+
+enum Command {
+    Send {
+        call: Call,
+        reply: Reply,
+    },
+    /// A caller stopped waiting, the server is told to drop its request
+    Abandoned,
+}
+
+/// Session shared by concurrent callers. Requests are written as soon as they are made,
+/// without waiting for the responses of earlier ones, and responses are matched to their
+/// callers by request id. A call that is cancelled or runs past its deadline is cancelled
+/// on the server too, a streamed response stops being produced.
+/// Pushes are not delivered, use a separate session for report streams
+#[derive(Debug)]
+pub struct PipelinedConnection {
+    commands: mpsc::UnboundedSender<Command>,
+    protocol: Protocol,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl PipelinedConnection {
+    /// Hand `connection` over to a background task, it is closed when this is dropped.
+    /// Requires a session, a v1 connection fails with [`RequestError::Closed`]
+    pub fn new(connection: StpConnection) -> Result<Self, RequestError> {
+        if !connection.is_session() || connection.is_closed() {
+            return Err(RequestError::Closed);
+        }
+        let protocol = connection.protocol();
+        let (commands, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(drive(connection, receiver));
+        Ok(Self {
+            commands,
+            protocol,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Version and features negotiated with the server
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Calls waiting for their responses
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// The session was closed or lost, calls fail with [`RequestError::Closed`]
+    pub fn is_closed(&self) -> bool {
+        self.commands.is_closed()
+    }
+
+    /// Send a request and wait for its response
+    pub async fn send_request<T>(&self, request: T) -> Result<String, RequestError>
+    where
+        T: ToString,
+    {
+        self.send_request_with(request, CallOptions::default())
+            .await
+    }
+
+    /// Same as [`PipelinedConnection::send_request`], bounded by `options`
+    pub async fn send_request_with<T>(
+        &self,
+        request: T,
+        options: CallOptions,
+    ) -> Result<String, RequestError>
+    where
+        T: ToString,
+    {
+        let response = self.call(Call::Text(request.to_string()), options).await?;
+        Ok(response.into_text())
+    }
+
+    /// Send a typed request in the most compact negotiated encoding and wait for its response
+    pub async fn send_typed(&self, request: &Request) -> Result<Response, RequestError> {
+        self.send_typed_with(request, CallOptions::default()).await
+    }
+
+    /// Same as [`PipelinedConnection::send_typed`], bounded by `options`
+    pub async fn send_typed_with(
+        &self,
+        request: &Request,
+        options: CallOptions,
+    ) -> Result<Response, RequestError> {
+        self.call(Call::Typed(request.clone()), options).await
+    }
+
+    async fn call(&self, call: Call, options: CallOptions) -> Result<Response, RequestError> {
+        let (reply, response) = oneshot::channel();
+        self.commands
+            .send(Command::Send { call, reply })
+            .map_err(|_| RequestError::Closed)?;
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        let mut waiting = Waiting {
+            commands: &self.commands,
+            in_flight: &self.in_flight,
+            answered: false,
+        };
+        let cancelled = async {
+            if let Some(mut cancel) = options.cancel {
+                if cancel.wait_for(|cancel| *cancel).await.is_ok() {
+                    return;
+                }
+            }
+            // A dropped sender never cancels
+            future::pending().await
+        };
+        let result = tokio::select! {
+            response = with_deadline(options.deadline, response) => match response {
+                Some(response) => response.unwrap_or(Err(RequestError::Closed)),
+                None => return Err(RequestError::DeadlineExceeded),
+            },
+            _ = cancelled => return Err(RequestError::Cancelled),
+        };
+        waiting.answered = true;
+        result
+    }
+}
+
+/// Tells the background task about a call that was given up before its response arrived
+struct Waiting<'a> {
+    commands: &'a mpsc::UnboundedSender<Command>,
+    in_flight: &'a AtomicUsize,
+    answered: bool,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+        if !self.answered {
+            let _ = self.commands.send(Command::Abandoned);
+        }
+    }
+}
+
+/// Write requests as they come and hand every response to its caller,
+/// until the session is lost or every handle is dropped
+async fn drive(mut connection: StpConnection, mut commands: mpsc::UnboundedReceiver<Command>) {
+    let mut pending: HashMap<u64, Reply> = HashMap::new();
+    loop {
+        // Both branches are cancel safe: a message is only read once it has started arriving
+        let result = tokio::select! {
+            command = commands.recv() => match command {
+                Some(Command::Send { call, reply }) => {
+                    let sent = match &call {
+                        Call::Text(body) => connection.send(body).await,
+                        Call::Typed(request) => connection.send_typed_request(request).await,
+                    };
+                    match sent {
+                        Ok(id) => {
+                            pending.insert(id, reply);
+                            Ok(())
+                        }
+                        Err(e) if e.is_connection_lost() => Err(e),
+                        Err(e) => {
+                            let _ = reply.send(Err(e));
+                            Ok(())
+                        }
+                    }
+                }
+                Some(Command::Abandoned) => abandon(&mut connection, &mut pending).await,
+                None => break,
+            },
+            readable = connection.readable() => match readable {
+                Ok(()) => match connection.recv_completed().await {
+                    Ok(Some((id, response))) => {
+                        if let Some(reply) = pending.remove(&id) {
+                            let _ = reply.send(response);
+                        }
+                        Ok(())
+                    }
+                    Ok(None) => Ok(()),
+                    Err(e) => Err(e),
+                },
+                Err(e) => Err(e),
+            },
+        };
+        if result.is_err() {
+            break;
+        }
+    }
+    // Callers see the session closing, the handle reports it right away
+    commands.close();
+    for (_, reply) in pending {
+        let _ = reply.send(Err(RequestError::Closed));
+    }
+}
+
+/// Cancel the requests of callers that stopped waiting
+async fn abandon(
+    connection: &mut StpConnection,
+    pending: &mut HashMap<u64, Reply>,
+) -> Result<(), RequestError> {
+    let abandoned: Vec<u64> = pending
+        .iter()
+        .filter(|(_, reply)| reply.is_closed())
+        .map(|(&id, _)| id)
+        .collect();
+    for id in abandoned {
+        pending.remove(&id);
+        connection.cancel_request(id).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,262 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use futures::future::BoxFuture;
+use tokio::{
+    net::ToSocketAddrs,
+    sync::{watch, Mutex},
+    time,
+};
+
+pub use my_stp_core::reconnect::{Backoff, ConnectionState, ReconnectPolicy};
+
+use crate::{
+    client::{StpClient, StpConnection},
+    errors::{ConnectError, RequestError},
+    message::{Push, Request, Response},
+};
+
+type Connect =
+    Box<dyn Fn() -> BoxFuture<'static, Result<StpConnection, ConnectError>> + Send + Sync>;
+type StateListener = Box<dyn Fn(ConnectionState) + Send + Sync>;
+
+/// Client that keeps one session open across server restarts and network failures.
+/// A lost session is opened again with exponential backoff, requests the policy marks
+/// as idempotent are sent again on the new one. Idle sessions are checked with heartbeats
+/// from a background task. Server side state of a session, e.g. pushed report streams,
+/// does not survive a reconnect, watch [`ReconnectingClient::on_state_change`] to restore it
+pub struct ReconnectingClient {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    connect: Connect,
+    policy: ReconnectPolicy,
+    connection: Mutex<Option<StpConnection>>,
+    state: std::sync::Mutex<ConnectionState>,
+    listener: std::sync::Mutex<Option<StateListener>>,
+    stopped: watch::Sender<bool>,
+}
+
+impl ReconnectingClient {
+    /// Client that opens sessions with `connect`. Nothing is opened until the first request.
+    /// Has to be called inside the runtime when heartbeats are on
+    pub fn new<F, Fut>(connect: F, policy: ReconnectPolicy) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<StpConnection, ConnectError>> + Send + 'static,
+    {
+        let heartbeat_interval = policy.heartbeat_interval;
+        let shared = Arc::new(Shared {
+            connect: Box::new(move || Box::pin(connect())),
+            policy,
+            connection: Mutex::new(None),
+            state: std::sync::Mutex::new(ConnectionState::Disconnected),
+            listener: std::sync::Mutex::new(None),
+            stopped: watch::channel(false).0,
+        });
+        if let Some(interval) = heartbeat_interval {
+            tokio::spawn(shared.clone().keep_alive(interval));
+        }
+        Self { shared }
+    }
+
+    /// Open a session with the latest protocol version and all features right away
+    pub async fn connect<Addrs>(addrs: Addrs, policy: ReconnectPolicy) -> Result<Self, RequestError>
+    where
+        Addrs: ToSocketAddrs + Clone + Send + Sync + 'static,
+    {
+        let client = Self::new(move || StpClient::connect(addrs.clone()), policy);
+        client
+            .with_connection(|_| Box::pin(async { Ok(()) }))
+            .await?;
+        Ok(client)
+    }
+
+    /// `listener` is called on every state change, from the task that noticed it.
+    /// It must not use the client
+    pub fn on_state_change<F>(&self, listener: F)
+    where
+        F: Fn(ConnectionState) + Send + Sync + 'static,
+    {
+        *self.shared.listener.lock().unwrap() = Some(Box::new(listener));
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        *self.shared.state.lock().unwrap()
+    }
+
+    /// Send a request and wait for its response, see [`StpConnection::send_request`]
+    pub async fn send_request<T>(&self, request: T) -> Result<String, RequestError>
+    where
+        T: ToString,
+    {
+        let request = request.to_string();
+        let idempotent = self.shared.policy.is_idempotent(&request);
+        self.shared
+            .run(idempotent, |connection| {
+                Box::pin(connection.send_request(request.clone()))
+            })
+            .await
+    }
+
+    /// Send a typed request and wait for its response, see [`StpConnection::send_typed`]
+    pub async fn send_typed(&self, request: &Request) -> Result<Response, RequestError> {
+        let idempotent = self.shared.policy.is_idempotent_request(request);
+        self.shared
+            .run(idempotent, |connection| {
+                let request = request.clone();
+                Box::pin(async move { connection.send_typed(&request).await })
+            })
+            .await
+    }
+
+    /// Next push of the current session, see [`StpConnection::recv_push`].
+    /// Returns `Ok(None)` without waiting while there is no session
+    pub async fn recv_push(&self, timeout: Option<Duration>) -> Result<Option<Push>, RequestError> {
+        let mut connection = self.shared.connection.lock().await;
+        let Some(session) = connection.as_mut() else {
+            return Ok(None);
+        };
+        let result = session.recv_push(timeout).await;
+        if let Err(e) = &result {
+            if e.is_connection_lost() {
+                self.shared.lose(&mut connection);
+            }
+        }
+        result
+    }
+
+    /// Run `f` on the session, opening it first if needed. Nothing is retried
+    pub async fn with_connection<F, R>(&self, f: F) -> Result<R, RequestError>
+    where
+        F: for<'c> FnOnce(&'c mut StpConnection) -> BoxFuture<'c, Result<R, RequestError>>,
+    {
+        let mut f = Some(f);
+        self.shared
+            .run(false, |connection| (f.take().unwrap())(connection))
+            .await
+    }
+
+    /// Close the session, the next request opens a new one
+    pub async fn disconnect(&self) {
+        let mut connection = self.shared.connection.lock().await;
+        *connection = None;
+        self.shared.set_state(ConnectionState::Disconnected);
+    }
+}
+
+impl Drop for ReconnectingClient {
+    fn drop(&mut self) {
+        self.shared.stopped.send_replace(true);
+    }
+}
+
+impl Shared {
+    /// Run `f` on an open session. A lost session is replaced,
+    /// `f` runs again on the new one only for idempotent requests
+    async fn run<F, R>(&self, idempotent: bool, mut f: F) -> Result<R, RequestError>
+    where
+        F: for<'c> FnMut(&'c mut StpConnection) -> BoxFuture<'c, Result<R, RequestError>>,
+    {
+        let mut connection = self.connection.lock().await;
+        let mut retries = 0;
+        loop {
+            // A session closed while idle has not seen the request yet, so any request may use the next one
+            let open = match connection.as_mut() {
+                Some(session) => session.is_open().await,
+                None => false,
+            };
+            if !open {
+                if connection.take().is_some() {
+                    self.set_state(ConnectionState::Reconnecting { attempt: 1 });
+                }
+                self.reconnect(&mut connection).await?;
+            }
+            match f(connection.as_mut().unwrap()).await {
+                Err(e) if e.is_connection_lost() => {
+                    self.lose(&mut connection);
+                    if !idempotent || retries == self.policy.max_retries {
+                        return Err(e);
+                    }
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Heartbeat idle sessions and bring lost ones back until the client is dropped
+    async fn keep_alive(self: Arc<Self>, interval: Duration) {
+        while self.sleep(interval).await {
+            let mut connection = self.connection.lock().await;
+            let lost = match connection.as_mut() {
+                Some(session) => session
+                    .heartbeat()
+                    .await
+                    .is_err_and(|e| e.is_connection_lost()),
+                None => self.state() != ConnectionState::Disconnected,
+            };
+            if lost {
+                self.lose(&mut connection);
+                let _ = self.reconnect(&mut connection).await;
+            }
+        }
+    }
+
+    /// Open a session, trying again with backoff while the failure is transient
+    async fn reconnect(&self, connection: &mut Option<StpConnection>) -> Result<(), RequestError> {
+        let mut attempt = 1;
+        loop {
+            if attempt > 1 || self.state() != ConnectionState::Disconnected {
+                self.set_state(ConnectionState::Reconnecting { attempt });
+            }
+            if attempt > 1 && !self.sleep(self.policy.backoff.next_delay(attempt)).await {
+                return Err(RequestError::Closed);
+            }
+            match (self.connect)().await {
+                Ok(session) => {
+                    *connection = Some(session);
+                    self.set_state(ConnectionState::Connected);
+                    return Ok(());
+                }
+                Err(e)
+                    if !e.is_transient()
+                        || self.policy.max_attempts.is_some_and(|max| attempt >= max) =>
+                {
+                    self.set_state(ConnectionState::Disconnected);
+                    return Err(e.into());
+                }
+                Err(_) => attempt += 1,
+            }
+        }
+    }
+
+    /// Drop a broken session, it is opened again by the next request or heartbeat
+    fn lose(&self, connection: &mut Option<StpConnection>) {
+        *connection = None;
+        self.set_state(ConnectionState::Reconnecting { attempt: 1 });
+    }
+
+    fn state(&self) -> ConnectionState {
+        *self.state.lock().unwrap()
+    }
+
+    fn set_state(&self, state: ConnectionState) {
+        let previous = std::mem::replace(&mut *self.state.lock().unwrap(), state);
+        if previous != state {
+            if let Some(listener) = self.listener.lock().unwrap().as_ref() {
+                listener(state);
+            }
+        }
+    }
+
+    /// `false` if the client was dropped meanwhile
+    async fn sleep(&self, duration: Duration) -> bool {
+        let mut stopped = self.stopped.subscribe();
+        tokio::select! {
+            _ = time::sleep(duration) => {}
+            _ = stopped.wait_for(|stopped| *stopped) => return false,
+        }
+        !*self.stopped.borrow()
+    }
+}
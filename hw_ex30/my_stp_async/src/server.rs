@@ -1,15 +1,95 @@
+#[cfg(unix)]
+use std::path::Path;
+use std::{
+    collections::VecDeque,
+    future::{self, Future},
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::Poll,
+    time::Duration,
+};
+
+use futures::{Stream, StreamExt};
 use tokio::{
-    io::{self, AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpStream, ToSocketAddrs},
+    io,
+    net::ToSocketAddrs,
+    sync::{watch, Mutex},
 };
 
+use my_stp_core::{codec::Message, handshake::ServerHandshake};
+
 use crate::{
-    errors::{ConnectError, RequestError},
-    recv_string, send_string,
+    auth::KeyStore,
+    errors::{ConnectError, RecvError, RequestError, SendError},
+    limits::Limits,
+    message::{Encoding, Request, Response, StreamStatus, STREAM_FAILED_CODE},
+    protocol::{Features, Protocol},
+    push::{Pusher, DEFAULT_PUSH_QUEUE_LEN},
+    recv_message, recv_string, run_handshake, send_message, send_string,
+    transport::{self, Listener, ReadHalf, WriteHalf},
+    with_deadline,
 };
 
+#[cfg(unix)]
+use crate::socket_file::UnixSocketOptions;
+
+/// Sessions without requests for this long are closed by the server
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Chunks of a streamed response. An `Err` ends the stream with that message
+pub type Chunks = Pin<Box<dyn Stream<Item = Result<String, String>> + Send>>;
+
+/// Answer of a handler: a single response or a stream of chunks
+pub enum Reply {
+    Response(Response),
+    Stream(Chunks),
+}
+
+impl Reply {
+    /// Stream the items of `chunks`, each one is produced right before it is sent
+    pub fn stream<S>(chunks: S) -> Self
+    where
+        S: Stream<Item = Result<String, String>> + Send + 'static,
+    {
+        Reply::Stream(Box::pin(chunks))
+    }
+
+    /// The whole reply as one response, for clients without [`Features::STREAM`]
+    async fn into_response(self) -> Response {
+        let mut chunks = match self {
+            Reply::Response(response) => return response,
+            Reply::Stream(chunks) => chunks,
+        };
+        let mut body = String::new();
+        while let Some(chunk) = chunks.next().await {
+            match chunk {
+                Ok(chunk) => body.push_str(&chunk),
+                Err(message) => {
+                    return Response::Error {
+                        code: STREAM_FAILED_CODE.to_string(),
+                        message,
+                    }
+                }
+            }
+        }
+        Response::Ok(body)
+    }
+}
+
+impl From<Response> for Reply {
+    fn from(response: Response) -> Self {
+        Reply::Response(response)
+    }
+}
+
 pub struct StpServer {
-    tcp: TcpListener,
+    listener: Listener,
+    protocol: Protocol,
+    limits: Limits,
+    idle_timeout: Option<Duration>,
+    key_store: Option<KeyStore>,
+    push_queue_len: usize,
 }
 
 impl StpServer {
@@ -17,39 +97,423 @@ impl StpServer {
     where
         Addrs: ToSocketAddrs,
     {
-        let tcp = TcpListener::bind(addrs).await?;
-        Ok(Self { tcp })
+        Ok(Self::with_listener(Listener::bind_tcp(addrs).await?))
     }
 
-    pub async fn accept(&self) -> Result<StpConnection, ConnectError> {
-        let (stream, _) = self.tcp.accept().await?;
-        Self::try_handshake(stream).await
+    /// Listen on a Unix socket at `path`, the socket file is removed when the server is dropped.
+    /// Only users allowed by the file permissions can connect
+    #[cfg(unix)]
+    pub async fn bind_unix<P>(path: P, options: UnixSocketOptions) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self::with_listener(Listener::bind_unix(
+            path.as_ref(),
+            &options,
+        )?))
     }
 
-    async fn try_handshake(mut stream: TcpStream) -> Result<StpConnection, ConnectError> {
-        let mut buf = [0u8; 4];
-        stream.read_exact(&mut buf).await?;
-        if &buf != b"clnt" {
-            return Err(ConnectError::BadHandshake);
+    fn with_listener(listener: Listener) -> Self {
+        Self {
+            listener,
+            protocol: Protocol::default(),
+            limits: Limits::default(),
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+            key_store: None,
+            push_queue_len: DEFAULT_PUSH_QUEUE_LEN,
         }
-        stream.write_all(b"serv").await?;
-        Ok(StpConnection { stream })
+    }
+
+    /// Fails for a server listening on a Unix socket
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Path of the Unix socket, `None` for a TCP server
+    #[cfg(unix)]
+    pub fn socket_path(&self) -> Option<&Path> {
+        self.listener.socket_path()
+    }
+
+    /// Highest version and features offered to clients
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
+    /// Frame size limit and deadlines for connections accepted after this call
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Idle timeout for sessions accepted after this call, `None` keeps sessions open forever
+    pub fn set_idle_timeout(&mut self, idle_timeout: Option<Duration>) {
+        self.idle_timeout = idle_timeout;
+    }
+
+    /// Require clients to prove they know one of the keys, `None` accepts everyone.
+    /// v1 clients can't authenticate, so they are rejected once keys are set
+    pub fn set_key_store(&mut self, key_store: Option<KeyStore>) {
+        self.key_store = key_store;
+    }
+
+    /// Pushes a connection holds for a slow client before dropping new ones
+    pub fn set_push_queue_len(&mut self, push_queue_len: usize) {
+        self.push_queue_len = push_queue_len;
+    }
+
+    /// Accept a connection and run its handshake in the calling task
+    pub async fn accept(&self) -> Result<StpConnection, ConnectError> {
+        let pending = self.accept_pending().await?;
+        self.handshake(pending).await
+    }
+
+    /// Accept a connection without waiting for the client. Finish it with
+    /// [`StpServer::handshake`] in another task, so a silent client can't block the accept loop
+    pub async fn accept_pending(&self) -> Result<PendingConnection, ConnectError> {
+        Ok(PendingConnection {
+            stream: self.listener.accept().await?,
+        })
+    }
+
+    /// A client that does not finish the handshake in time is dropped
+    pub async fn handshake(
+        &self,
+        pending: PendingConnection,
+    ) -> Result<StpConnection, ConnectError> {
+        with_deadline(
+            self.limits.handshake_timeout,
+            self.try_handshake(pending.stream),
+        )
+        .await
+        .ok_or(ConnectError::HandshakeTimeout)?
+    }
+
+    async fn try_handshake(
+        &self,
+        mut stream: transport::Stream,
+    ) -> Result<StpConnection, ConnectError> {
+        let mut handshake = ServerHandshake::new(self.protocol, self.key_store.as_ref());
+        run_handshake(&mut handshake, &mut stream).await?;
+        let (protocol, identity) = handshake.outcome().ok_or(ConnectError::BadHandshake)?;
+        let identity = identity.map(str::to_string);
+        let (reader, writer) = stream.into_split();
+        Ok(StpConnection {
+            reader,
+            writer: Arc::new(Mutex::new(writer)),
+            protocol,
+            identity,
+            limits: self.limits,
+            idle_timeout: self.idle_timeout,
+            push_queue_len: self.push_queue_len,
+            pusher: None,
+            pending: VecDeque::new(),
+            closed: Arc::new(watch::Sender::new(false)),
+        })
     }
 }
 
+/// Connection accepted by [`StpServer::accept_pending`] whose handshake has not run yet
+#[derive(Debug)]
+pub struct PendingConnection {
+    stream: transport::Stream,
+}
+
+/// Server side of a connection. Depending on the negotiated protocol it is either
+/// a single request connection or a session with many requests
 #[derive(Debug)]
 pub struct StpConnection {
-    stream: TcpStream,
+    reader: ReadHalf,
+    /// Shared with the push writer task once pushes are used
+    writer: Arc<Mutex<WriteHalf>>,
+    protocol: Protocol,
+    identity: Option<String>,
+    limits: Limits,
+    idle_timeout: Option<Duration>,
+    push_queue_len: usize,
+    pusher: Option<Pusher>,
+    /// Messages the client sent while a response was streamed
+    pending: VecDeque<Message>,
+    /// Set by [`SessionCloser::close`]
+    closed: Arc<watch::Sender<bool>>,
 }
 
 impl StpConnection {
+    /// Version and features negotiated with the client
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    pub fn is_session(&self) -> bool {
+        self.protocol.is_session()
+    }
+
+    /// Id of the authenticated client, `None` if the server does not require authentication
+    pub fn identity(&self) -> Option<&str> {
+        self.identity.as_deref()
+    }
+
+    /// Fails for a connection over a Unix socket
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.reader.get_ref().peer_addr()
+    }
+
+    /// Handle for pushing messages to the client while requests are served.
+    /// `None` if the client has not negotiated [`Features::PUSH`].
+    /// Pushers report [`crate::errors::PushError::Closed`] once the connection is dropped
+    pub fn pusher(&mut self) -> Option<Pusher> {
+        if !self.is_session() || !self.protocol.features.contains(Features::PUSH) {
+            return None;
+        }
+        let pusher = self.pusher.get_or_insert_with(|| {
+            Pusher::start(self.writer.clone(), self.limits, self.push_queue_len)
+        });
+        Some(pusher.clone())
+    }
+
+    /// Handle to end the session from another task, e.g. on server shutdown
+    pub fn closer(&self) -> SessionCloser {
+        SessionCloser {
+            closed: self.closed.clone(),
+        }
+    }
+
+    /// Answer one request and close the connection
     pub async fn proccess_request<F>(mut self, handler: F) -> Result<(), RequestError>
     where
         F: FnOnce(String) -> String,
     {
-        let request = recv_string(&mut self.stream).await?;
-        let response = handler(request);
-        send_string(response, &mut self.stream).await?;
+        if !self.is_session() {
+            let request = recv_string(&mut self.reader, &self.limits).await?;
+            let response = handler(request);
+            let mut writer = self.writer.lock().await;
+            send_string(response, &mut *writer, &self.limits).await?;
+            return Ok(());
+        }
+
+        if let Some((id, request, encoding)) = self.recv_request().await? {
+            let response = Response::Ok(handler(request.into_command_line()));
+            self.send_response(id, response, encoding).await?;
+            self.send(&Message::Close).await?;
+        }
+        Ok(())
+    }
+
+    /// Answer requests until the client closes the session, disconnects
+    /// or stays idle for longer than the idle timeout.
+    /// Typed requests are passed to `handler` as command lines
+    pub async fn serve<F, Fut>(self, mut handler: F) -> Result<(), RequestError>
+    where
+        F: FnMut(String) -> Fut,
+        Fut: Future<Output = String>,
+    {
+        self.serve_typed(|request| {
+            let response = handler(request.into_command_line());
+            async move { Response::Ok(response.await) }
+        })
+        .await
+    }
+
+    /// Same as [`StpConnection::serve`], but with typed requests and responses.
+    /// String requests arrive as [`Request::Text`] and get [`Response::into_text`] back
+    pub async fn serve_typed<F, Fut>(self, mut handler: F) -> Result<(), RequestError>
+    where
+        F: FnMut(Request) -> Fut,
+        Fut: Future<Output = Response>,
+    {
+        self.serve_streaming(|request| {
+            let response = handler(request);
+            async move { Reply::Response(response.await) }
+        })
+        .await
+    }
+
+    /// Same as [`StpConnection::serve_typed`], but large responses may be streamed.
+    /// Clients that have negotiated [`Features::STREAM`] get the chunks as they are produced
+    /// and can cancel the rest, other clients get all chunks joined into one response
+    pub async fn serve_streaming<F, Fut>(mut self, mut handler: F) -> Result<(), RequestError>
+    where
+        F: FnMut(Request) -> Fut,
+        Fut: Future<Output = Reply>,
+    {
+        if !self.is_session() {
+            let request = recv_string(&mut self.reader, &self.limits).await?;
+            let response = handler(Request::Text(request)).await.into_response().await;
+            let mut writer = self.writer.lock().await;
+            send_string(response.into_text(), &mut *writer, &self.limits).await?;
+            return Ok(());
+        }
+
+        while let Some((id, request, encoding)) = self.recv_request().await? {
+            match handler(request).await {
+                Reply::Stream(chunks) if self.protocol.features.contains(Features::STREAM) => {
+                    self.send_stream(id, chunks).await?
+                }
+                reply => {
+                    let response = reply.into_response().await;
+                    self.send_response(id, response, encoding).await?
+                }
+            }
+        }
         Ok(())
     }
+
+    /// Chunks are sent until the stream ends or the client cancels it
+    async fn send_stream(&mut self, id: u64, mut chunks: Chunks) -> Result<(), RequestError> {
+        let status = loop {
+            if self.cancel_requested(id).await? {
+                break StreamStatus::Cancelled;
+            }
+            match chunks.next().await {
+                Some(Ok(body)) => self.send(&Message::Chunk { id, body }).await?,
+                Some(Err(message)) => break StreamStatus::Failed(message),
+                None => break StreamStatus::Complete,
+            }
+        };
+        self.send(&Message::End { id, status }).await?;
+        Ok(())
+    }
+
+    /// Reads whatever the client has sent without waiting for more.
+    /// `true` if it cancelled stream `id` or is closing the session.
+    /// Requests are kept for [`StpConnection::recv_request`]
+    async fn cancel_requested(&mut self, id: u64) -> Result<bool, RequestError> {
+        loop {
+            let peeked = future::poll_fn(|cx| match self.reader.poll_peek(cx) {
+                Poll::Pending => Poll::Ready(None),
+                Poll::Ready(peeked) => Poll::Ready(Some(peeked)),
+            })
+            .await;
+            match peeked {
+                None => return Ok(false),
+                Some(Ok(0)) => {
+                    self.pending.push_back(Message::Close);
+                    return Ok(true);
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(RecvError::Io(e).into()),
+            }
+            match recv_message(&mut self.reader, &self.limits).await? {
+                Message::Cancel { id: cancelled } if cancelled == id => return Ok(true),
+                Message::Close => {
+                    self.pending.push_back(Message::Close);
+                    return Ok(true);
+                }
+                // Answered right away, a long stream must not look like a dead server
+                Message::Heartbeat { id } => self.send(&Message::Heartbeat { id }).await?,
+                message => self.pending.push_back(message),
+            }
+        }
+    }
+
+    /// Responses use the encoding of their request, `None` means a string request
+    async fn send_response(
+        &mut self,
+        id: u64,
+        response: Response,
+        encoding: Option<Encoding>,
+    ) -> Result<(), RequestError> {
+        let message = match encoding {
+            Some(encoding) => Message::Typed {
+                id,
+                encoding,
+                data: encoding.encode(&response).map_err(SendError::Encode)?,
+            },
+            None => Message::Text {
+                id,
+                body: response.into_text(),
+            },
+        };
+        self.send(&message).await?;
+        Ok(())
+    }
+
+    async fn send(&self, message: &Message) -> Result<(), SendError> {
+        let mut writer = self.writer.lock().await;
+        send_message(message, &mut *writer, &self.limits).await
+    }
+
+    /// Pushes keep a session alive like requests do
+    fn pushed_recently(&self) -> bool {
+        let last_push = self.pusher.as_ref().and_then(Pusher::last_push);
+        match (last_push, self.idle_timeout) {
+            (Some(last_push), Some(idle_timeout)) => last_push.elapsed() < idle_timeout,
+            _ => false,
+        }
+    }
+
+    /// `None` means the session is over.
+    /// Waiting for the next request is bounded by the idle timeout,
+    /// receiving it once it has started by the I/O deadline
+    async fn recv_request(
+        &mut self,
+    ) -> Result<Option<(u64, Request, Option<Encoding>)>, RequestError> {
+        loop {
+            let message = match self.pending.pop_front() {
+                Some(message) => message,
+                None if self.wait_for_message().await? => {
+                    recv_message(&mut self.reader, &self.limits).await?
+                }
+                None => return Ok(None),
+            };
+            match message {
+                Message::Text { id, body } => return Ok(Some((id, Request::Text(body), None))),
+                Message::Typed { id, encoding, data } => {
+                    let request = encoding.decode(&data).map_err(RecvError::Decode)?;
+                    return Ok(Some((id, request, Some(encoding))));
+                }
+                Message::Close => return Ok(None),
+                // The stream has ended before the cancel arrived
+                Message::Cancel { .. } => continue,
+                Message::Heartbeat { id } => self.send(&Message::Heartbeat { id }).await?,
+                Message::Push(_) | Message::Chunk { .. } | Message::End { .. } => {
+                    return Err(RecvError::BadMessage.into())
+                }
+            }
+        }
+    }
+
+    /// `false` if the client has disconnected, the session has been idle for too long
+    /// or it has been closed by a [`SessionCloser`]
+    async fn wait_for_message(&mut self) -> Result<bool, RequestError> {
+        let mut closed = self.closed.subscribe();
+        loop {
+            let peeked = tokio::select! {
+                peeked = with_deadline(self.idle_timeout, self.reader.peek()) => peeked,
+                _ = closed.wait_for(|closed| *closed) => None,
+            };
+            match peeked {
+                Some(Ok(0)) => return Ok(false),
+                Some(Ok(_)) => return Ok(true),
+                Some(Err(e)) => return Err(RecvError::Io(e).into()),
+                None if !*closed.borrow() && self.pushed_recently() => continue,
+                None => {
+                    let _ = self.send(&Message::Close).await;
+                    return Ok(false);
+                }
+            }
+        }
+    }
+}
+
+/// Ends a session from outside of the task serving it
+#[derive(Debug, Clone)]
+pub struct SessionCloser {
+    closed: Arc<watch::Sender<bool>>,
+}
+
+impl SessionCloser {
+    /// A request in flight is still answered, then the client is told the session is closed.
+    /// A session waiting for the next request is closed right away
+    pub fn close(&self) {
+        self.closed.send_replace(true);
+    }
+}
+
+impl Drop for StpConnection {
+    /// Stops the push writer task, which releases the write half and shuts the socket down
+    fn drop(&mut self) {
+        if let Some(pusher) = &self.pusher {
+            pusher.close();
+        }
+    }
 }
@@ -0,0 +1,302 @@
+use std::{
+    future, io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{tcp, TcpListener, TcpStream, ToSocketAddrs},
+};
+
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+
+#[cfg(unix)]
+use my_stp_core::socket_file::{self, UnixSocketOptions};
+#[cfg(unix)]
+use tokio::net::{unix, UnixListener, UnixStream};
+
+/// Call the same method on whichever socket half is inside
+macro_rules! on_socket {
+    ($enum:ident, $socket:expr, $s:ident => $call:expr) => {
+        match $socket {
+            $enum::Tcp($s) => $call,
+            #[cfg(unix)]
+            $enum::Unix($s) => $call,
+        }
+    };
+}
+
+/// Reader that can peek by reading one byte ahead,
+/// tokio Unix sockets can't peek
+#[derive(Debug)]
+pub(crate) struct Lookahead<Inner> {
+    inner: Inner,
+    byte: Option<u8>,
+}
+
+impl<Inner: AsyncRead + Unpin> Lookahead<Inner> {
+    fn new(inner: Inner) -> Self {
+        Self { inner, byte: None }
+    }
+
+    /// `0` means the peer has closed the connection.
+    /// Cancel safe, a byte that has been read is kept
+    pub(crate) fn poll_peek(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        if self.byte.is_some() {
+            return Poll::Ready(Ok(1));
+        }
+        let mut byte = [0u8; 1];
+        let mut buf = ReadBuf::new(&mut byte);
+        ready!(Pin::new(&mut self.inner).poll_read(cx, &mut buf))?;
+        if buf.filled().is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        self.byte = Some(byte[0]);
+        Poll::Ready(Ok(1))
+    }
+
+    pub(crate) async fn peek(&mut self) -> io::Result<usize> {
+        future::poll_fn(|cx| self.poll_peek(cx)).await
+    }
+
+    pub(crate) fn get_ref(&self) -> &Inner {
+        &self.inner
+    }
+}
+
+impl<Inner: AsyncRead + Unpin> AsyncRead for Lookahead<Inner> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        if let Some(byte) = this.byte.take() {
+            buf.put_slice(&[byte]);
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl<Inner: AsyncWrite + Unpin> AsyncWrite for Lookahead<Inner> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Connected TCP or Unix socket
+#[derive(Debug)]
+pub(crate) enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+pub(crate) type Stream = Lookahead<Socket>;
+pub(crate) type ReadHalf = Lookahead<SocketReadHalf>;
+
+impl Stream {
+    pub(crate) async fn connect_tcp<Addrs: ToSocketAddrs>(addrs: Addrs) -> io::Result<Self> {
+        Ok(Self::new(Socket::Tcp(TcpStream::connect(addrs).await?)))
+    }
+
+    #[cfg(unix)]
+    pub(crate) async fn connect_unix(path: &Path) -> io::Result<Self> {
+        Ok(Self::new(Socket::Unix(UnixStream::connect(path).await?)))
+    }
+
+    /// Write without waiting, for best effort messages from `Drop`
+    pub(crate) fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+        on_socket!(Socket, &self.inner, s => s.try_write(buf))
+    }
+
+    /// The peeked byte, if any, stays with the read half
+    pub(crate) fn into_split(self) -> (ReadHalf, WriteHalf) {
+        let (reader, writer) = match self.inner {
+            Socket::Tcp(s) => {
+                let (reader, writer) = s.into_split();
+                (SocketReadHalf::Tcp(reader), WriteHalf::Tcp(writer))
+            }
+            #[cfg(unix)]
+            Socket::Unix(s) => {
+                let (reader, writer) = s.into_split();
+                (SocketReadHalf::Unix(reader), WriteHalf::Unix(writer))
+            }
+        };
+        let reader = Lookahead {
+            inner: reader,
+            byte: self.byte,
+        };
+        (reader, writer)
+    }
+}
+
+impl AsyncRead for Socket {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        on_socket!(Socket, self.get_mut(), s => Pin::new(s).poll_read(cx, buf))
+    }
+}
+
+impl AsyncWrite for Socket {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        on_socket!(Socket, self.get_mut(), s => Pin::new(s).poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        on_socket!(Socket, self.get_mut(), s => Pin::new(s).poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        on_socket!(Socket, self.get_mut(), s => Pin::new(s).poll_shutdown(cx))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum SocketReadHalf {
+    Tcp(tcp::OwnedReadHalf),
+    #[cfg(unix)]
+    Unix(unix::OwnedReadHalf),
+}
+
+impl SocketReadHalf {
+    /// Unix sockets have no IP address
+    pub(crate) fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            SocketReadHalf::Tcp(s) => s.peer_addr(),
+            #[cfg(unix)]
+            SocketReadHalf::Unix(_) => Err(no_ip_address()),
+        }
+    }
+}
+
+impl AsyncRead for SocketReadHalf {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        on_socket!(SocketReadHalf, self.get_mut(), s => Pin::new(s).poll_read(cx, buf))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum WriteHalf {
+    Tcp(tcp::OwnedWriteHalf),
+    #[cfg(unix)]
+    Unix(unix::OwnedWriteHalf),
+}
+
+impl AsyncWrite for WriteHalf {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        on_socket!(WriteHalf, self.get_mut(), s => Pin::new(s).poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        on_socket!(WriteHalf, self.get_mut(), s => Pin::new(s).poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        on_socket!(WriteHalf, self.get_mut(), s => Pin::new(s).poll_shutdown(cx))
+    }
+}
+
+/// TCP listener or Unix listener together with its socket file
+#[derive(Debug)]
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix {
+        listener: UnixListener,
+        path: PathBuf,
+    },
+}
+
+impl Listener {
+    pub(crate) async fn bind_tcp<Addrs: ToSocketAddrs>(addrs: Addrs) -> io::Result<Self> {
+        Ok(Listener::Tcp(TcpListener::bind(addrs).await?))
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn bind_unix(path: &Path, options: &UnixSocketOptions) -> io::Result<Self> {
+        socket_file::prepare(path, options)?;
+        let listener = UnixListener::bind(path)?;
+        let listener = Listener::Unix {
+            listener,
+            path: path.to_path_buf(),
+        };
+        // Dropping the listener on error removes the file again
+        socket_file::restrict(path, options)?;
+        Ok(listener)
+    }
+
+    pub(crate) async fn accept(&self) -> io::Result<Stream> {
+        let socket = match self {
+            Listener::Tcp(listener) => Socket::Tcp(listener.accept().await?.0),
+            #[cfg(unix)]
+            Listener::Unix { listener, .. } => Socket::Unix(listener.accept().await?.0),
+        };
+        Ok(Stream::new(socket))
+    }
+
+    /// Unix listeners have no IP address
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr(),
+            #[cfg(unix)]
+            Listener::Unix { .. } => Err(no_ip_address()),
+        }
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn socket_path(&self) -> Option<&Path> {
+        match self {
+            Listener::Tcp(_) => None,
+            Listener::Unix { path, .. } => Some(path),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix { path, .. } = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn no_ip_address() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "unix socket has no IP address")
+}
@@ -0,0 +1,101 @@
+use std::net::SocketAddr;
+
+use my_stp_async::{
+    auth::{Credentials, KeyStore},
+    client::StpClient,
+    errors::ConnectError,
+    server::StpServer,
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+/// Echo server answering with the client identity.
+/// Handshake errors are forwarded to the returned receiver
+async fn start_server(
+    key_store: Option<KeyStore>,
+) -> (SocketAddr, UnboundedReceiver<ConnectError>) {
+    let mut server = StpServer::bind("127.0.0.1:0").await.unwrap();
+    server.set_key_store(key_store);
+    let addr = server.local_addr().unwrap();
+    let (errors_tx, errors_rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            match server.accept().await {
+                Ok(connection) => {
+                    let identity = connection.identity().unwrap_or("anonymous").to_string();
+                    tokio::spawn(connection.serve(move |request| {
+                        std::future::ready(format!("{identity}: {request}"))
+                    }));
+                }
+                Err(e) => {
+                    let _ = errors_tx.send(e);
+                }
+            }
+        }
+    });
+    (addr, errors_rx)
+}
+
+fn key_store() -> KeyStore {
+    KeyStore::parse("alice:secret\nbob:other\n").unwrap()
+}
+
+#[tokio::test]
+async fn authenticated_identity_reaches_the_handler() {
+    let (addr, _) = start_server(Some(key_store())).await;
+    let mut connection =
+        StpClient::connect_authenticated(addr, &Credentials::new("alice", b"secret"))
+            .await
+            .unwrap();
+    assert_eq!(
+        connection.send_request("ping").await.unwrap(),
+        "alice: ping"
+    );
+}
+
+#[tokio::test]
+async fn wrong_key_is_rejected() {
+    let (addr, mut errors) = start_server(Some(key_store())).await;
+    assert!(matches!(
+        StpClient::connect_authenticated(addr, &Credentials::new("alice", b"other")).await,
+        Err(ConnectError::AuthenticationRejected)
+    ));
+    assert!(matches!(
+        errors.recv().await.unwrap(),
+        ConnectError::BadSignature(client_id) if client_id == "alice"
+    ));
+}
+
+#[tokio::test]
+async fn unknown_client_is_rejected() {
+    let (addr, mut errors) = start_server(Some(key_store())).await;
+    assert!(matches!(
+        StpClient::connect_authenticated(addr, &Credentials::new("eve", b"secret")).await,
+        Err(ConnectError::AuthenticationRejected)
+    ));
+    assert!(matches!(
+        errors.recv().await.unwrap(),
+        ConnectError::UnknownClient(client_id) if client_id == "eve"
+    ));
+}
+
+#[tokio::test]
+async fn client_without_credentials_is_told_to_authenticate() {
+    let (addr, _) = start_server(Some(key_store())).await;
+    assert!(matches!(
+        StpClient::connect(addr).await,
+        Err(ConnectError::AuthenticationRequired)
+    ));
+}
+
+#[tokio::test]
+async fn credentials_are_not_needed_without_key_store() {
+    let (addr, _) = start_server(None).await;
+    let mut connection =
+        StpClient::connect_authenticated(addr, &Credentials::new("alice", b"secret"))
+            .await
+            .unwrap();
+    assert_eq!(
+        connection.send_request("ping").await.unwrap(),
+        "anonymous: ping"
+    );
+}
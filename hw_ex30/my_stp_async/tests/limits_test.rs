@@ -0,0 +1,105 @@
+use std::{net::SocketAddr, time::Duration};
+
+use my_stp_async::{
+    client::StpClient,
+    errors::{RecvError, RequestError, SendError},
+    limits::Limits,
+    protocol::Protocol,
+    server::StpServer,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time,
+};
+
+async fn start_echo_server(limits: Limits) -> SocketAddr {
+    let mut server = StpServer::bind("127.0.0.1:0").await.unwrap();
+    server.set_limits(limits);
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            if let Ok(connection) = server.accept().await {
+                tokio::spawn(connection.serve(|request| async move { format!("echo {request}") }));
+            }
+        }
+    });
+    addr
+}
+
+/// Server that completes the handshake and reads a request,
+/// then sends only the beginning of the response frame
+async fn start_stalling_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut handshake = [0u8; 10];
+        stream.read_exact(&mut handshake).await.unwrap();
+        stream
+            .write_all(b"serv\x00\x02\x00\x00\x00\x00")
+            .await
+            .unwrap();
+        let mut len = [0u8; 4];
+        stream.read_exact(&mut len).await.unwrap();
+        let mut request = vec![0u8; u32::from_be_bytes(len) as usize];
+        stream.read_exact(&mut request).await.unwrap();
+        stream.write_all(&20u32.to_be_bytes()).await.unwrap();
+        stream.write_all(b"D\x00\x00").await.unwrap();
+        time::sleep(Duration::from_secs(5)).await;
+    });
+    addr
+}
+
+fn small_limits() -> Limits {
+    Limits {
+        max_frame_size: 64,
+        handshake_timeout: Some(Duration::from_millis(100)),
+        io_timeout: Some(Duration::from_millis(100)),
+    }
+}
+
+#[tokio::test]
+async fn oversized_request_is_not_sent() {
+    let addr = start_echo_server(Limits::default()).await;
+    let mut connection = StpClient::connect_with_limits(addr, Protocol::default(), small_limits())
+        .await
+        .unwrap();
+    assert!(matches!(
+        connection.send_request("x".repeat(100)).await,
+        Err(RequestError::Send(SendError::FrameTooLarge {
+            size: 109,
+            max: 64
+        }))
+    ));
+}
+
+#[tokio::test]
+async fn server_drops_connection_with_oversized_frame() {
+    let addr = start_echo_server(small_limits()).await;
+    let mut connection = StpClient::connect(addr).await.unwrap();
+    assert!(connection.send_request("x".repeat(100)).await.is_err());
+
+    let mut connection = StpClient::connect(addr).await.unwrap();
+    assert_eq!(connection.send_request("ping").await.unwrap(), "echo ping");
+}
+
+#[tokio::test]
+async fn stalled_handshake_does_not_block_other_clients() {
+    let addr = start_echo_server(small_limits()).await;
+    let _stalled = TcpStream::connect(addr).await.unwrap();
+    let mut connection = StpClient::connect(addr).await.unwrap();
+    assert_eq!(connection.send_request("ping").await.unwrap(), "echo ping");
+}
+
+#[tokio::test]
+async fn stalled_response_times_out() {
+    let addr = start_stalling_server().await;
+    let mut connection = StpClient::connect_with_limits(addr, Protocol::default(), small_limits())
+        .await
+        .unwrap();
+    assert!(matches!(
+        connection.send_request("ping").await,
+        Err(RequestError::Recv(RecvError::Timeout))
+    ));
+}
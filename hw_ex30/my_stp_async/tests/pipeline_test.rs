@@ -0,0 +1,171 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::{future, stream, StreamExt};
+use my_stp_async::{
+    client::StpClient,
+    errors::RequestError,
+    message::{Request, Response},
+    pipeline::{CallOptions, PipelinedConnection},
+    protocol::{Features, Protocol, V1},
+    server::{Reply, StpServer},
+};
+use tokio::{sync::watch, time};
+
+/// `sleep <ms>` answers after a while, `count <n>` streams n lines,
+/// `endless` streams until it is cancelled. Returns the number of accepted connections
+/// and of chunks produced
+async fn start_server() -> (SocketAddr, Arc<AtomicUsize>, Arc<AtomicUsize>) {
+    let server = StpServer::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let produced = Arc::new(AtomicUsize::new(0));
+    let (connections, chunks) = (accepted.clone(), produced.clone());
+    tokio::spawn(async move {
+        loop {
+            if let Ok(connection) = server.accept().await {
+                connections.fetch_add(1, Ordering::Relaxed);
+                let chunks = chunks.clone();
+                tokio::spawn(connection.serve_streaming(move |request| {
+                    reply(request.into_command_line(), chunks.clone())
+                }));
+            }
+        }
+    });
+    (addr, accepted, produced)
+}
+
+async fn reply(request: String, produced: Arc<AtomicUsize>) -> Reply {
+    let lines = stream::iter(0..).then(move |i| {
+        let produced = produced.clone();
+        async move {
+            time::sleep(Duration::from_millis(5)).await;
+            produced.fetch_add(1, Ordering::Relaxed);
+            Ok(format!("line {i}\n"))
+        }
+    });
+    match request.split_once(' ') {
+        Some(("sleep", millis)) => {
+            time::sleep(Duration::from_millis(millis.parse().unwrap())).await;
+            Reply::Response(Response::Ok(format!("slept {millis}")))
+        }
+        Some(("count", n)) => Reply::stream(lines.take(n.parse().unwrap())),
+        _ if request == "endless" => Reply::stream(lines),
+        _ => Reply::Response(Response::Ok(format!("echo {request}"))),
+    }
+}
+
+async fn connect(addr: SocketAddr) -> PipelinedConnection {
+    PipelinedConnection::new(StpClient::connect(addr).await.unwrap()).unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_requests_share_one_session() {
+    let (addr, accepted, _) = start_server().await;
+    let connection = connect(addr).await;
+    let responses =
+        future::join_all((0..50).map(|i| connection.send_request(format!("ping {i}")))).await;
+    for (i, response) in responses.into_iter().enumerate() {
+        assert_eq!(response.unwrap(), format!("echo ping {i}"));
+    }
+    let typed = connection
+        .send_typed(&Request::command("ping"))
+        .await
+        .unwrap();
+    assert_eq!(typed, Response::Ok("echo ping".to_string()));
+    assert_eq!(accepted.load(Ordering::Relaxed), 1);
+    assert_eq!(connection.in_flight(), 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn streamed_responses_are_joined() {
+    let (addr, _, _) = start_server().await;
+    let connection = connect(addr).await;
+    let (streamed, echoed) = tokio::join!(
+        connection.send_request("count 3"),
+        connection.send_request("ping")
+    );
+    assert_eq!(streamed.unwrap(), "line 0\nline 1\nline 2\n");
+    assert_eq!(echoed.unwrap(), "echo ping");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn deadline_fails_the_call_and_the_session_stays_usable() {
+    let (addr, accepted, _) = start_server().await;
+    let connection = connect(addr).await;
+    let options = CallOptions::default().deadline(Duration::from_millis(50));
+    assert!(matches!(
+        connection.send_request_with("sleep 300", options).await,
+        Err(RequestError::DeadlineExceeded)
+    ));
+    // The late response of the abandoned call is skipped
+    assert_eq!(connection.send_request("ping").await.unwrap(), "echo ping");
+    assert_eq!(accepted.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cancelled_call_stops_the_stream_on_the_server() {
+    let (addr, _, produced) = start_server().await;
+    let connection = connect(addr).await;
+    let (cancel, cancelled) = watch::channel(false);
+    tokio::spawn(async move {
+        time::sleep(Duration::from_millis(50)).await;
+        let _ = cancel.send(true);
+    });
+    let options = CallOptions::default().cancel_on(cancelled);
+    assert!(matches!(
+        connection.send_request_with("endless", options).await,
+        Err(RequestError::Cancelled)
+    ));
+    assert_eq!(connection.send_request("ping").await.unwrap(), "echo ping");
+    let stopped_at = produced.load(Ordering::Relaxed);
+    time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(produced.load(Ordering::Relaxed), stopped_at);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lost_session_fails_pending_calls() {
+    let server = StpServer::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(async move {
+        let connection = server.accept().await.unwrap();
+        time::sleep(Duration::from_millis(50)).await;
+        drop(connection);
+    });
+    let connection = connect(addr).await;
+    assert!(connection
+        .send_request("ping")
+        .await
+        .unwrap_err()
+        .is_connection_lost());
+    time::sleep(Duration::from_millis(10)).await;
+    assert!(connection.is_closed());
+    assert!(matches!(
+        connection.send_request("ping").await,
+        Err(RequestError::Closed)
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn v1_connection_is_not_pipelined() {
+    let (addr, _, _) = start_server().await;
+    let connection = StpClient::connect_with(
+        addr,
+        Protocol {
+            version: V1,
+            features: Features::NONE,
+        },
+    )
+    .await
+    .unwrap();
+    assert!(matches!(
+        PipelinedConnection::new(connection),
+        Err(RequestError::Closed)
+    ));
+}
@@ -0,0 +1,139 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use my_stp_async::{
+    client::StpClient,
+    errors::PushError,
+    protocol::{Features, Protocol, V2},
+    push::Pusher,
+    server::StpServer,
+};
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver},
+    time,
+};
+
+/// Echo server handing the pusher of every connection to the test
+async fn start_push_server(
+    queue_len: usize,
+    idle_timeout: Option<Duration>,
+) -> (SocketAddr, UnboundedReceiver<Option<Pusher>>) {
+    let mut server = StpServer::bind("127.0.0.1:0").await.unwrap();
+    server.set_push_queue_len(queue_len);
+    server.set_idle_timeout(idle_timeout);
+    let addr = server.local_addr().unwrap();
+    let (sender, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            if let Ok(mut connection) = server.accept().await {
+                let _ = sender.send(connection.pusher());
+                tokio::spawn(connection.serve(|request| async move { format!("echo {request}") }));
+            }
+        }
+    });
+    (addr, receiver)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pushes_arrive_in_order_between_responses() {
+    let (addr, mut pushers) = start_push_server(8, None).await;
+    let mut connection = StpClient::connect(addr).await.unwrap();
+    let pusher = pushers.recv().await.unwrap().unwrap();
+
+    assert_eq!(pusher.push("report", "first").unwrap(), 1);
+    assert_eq!(connection.send_request("ping").await.unwrap(), "echo ping");
+    assert_eq!(pusher.push("report", "second").unwrap(), 2);
+
+    let first = connection.recv_push(None).await.unwrap().unwrap();
+    assert_eq!((first.seq, first.topic.as_str()), (1, "report"));
+    assert_eq!(first.body, "first");
+    let second = connection.recv_push(None).await.unwrap().unwrap();
+    assert_eq!((second.seq, second.body.as_str()), (2, "second"));
+    assert!(connection
+        .recv_push(Some(Duration::from_millis(50)))
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(connection.send_request("pong").await.unwrap(), "echo pong");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn slow_client_loses_pushes_and_sees_gaps() {
+    let (addr, mut pushers) = start_push_server(1, None).await;
+    let mut connection = StpClient::connect(addr).await.unwrap();
+    let pusher = pushers.recv().await.unwrap().unwrap();
+
+    // Large bodies fill the socket buffers, so the writer stalls and the queue overflows
+    let body = "x".repeat(64 * 1024);
+    let sent: Vec<u64> = (0..200)
+        .filter_map(|_| pusher.push("report", &body).ok())
+        .collect();
+    assert!(pusher.dropped() > 0);
+    assert_eq!(sent.len() as u64 + pusher.dropped(), 200);
+
+    let mut received = Vec::new();
+    while let Some(push) = connection
+        .recv_push(Some(Duration::from_millis(500)))
+        .await
+        .unwrap()
+    {
+        received.push(push.seq);
+    }
+    assert_eq!(received, sent);
+
+    // Once the client has caught up, pushes go through again after a gap
+    assert_eq!(pusher.push("report", "last").unwrap(), 201);
+    let last = connection.recv_push(None).await.unwrap().unwrap();
+    assert_eq!(last.seq, 201);
+    assert!(last.seq - received.last().unwrap() > 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pusher_is_closed_when_client_disconnects() {
+    let (addr, mut pushers) = start_push_server(8, None).await;
+    let connection = StpClient::connect(addr).await.unwrap();
+    let pusher = pushers.recv().await.unwrap().unwrap();
+    connection.close().await.unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(2);
+    loop {
+        match pusher.push("report", "tick") {
+            Err(PushError::Closed) => break,
+            _ if Instant::now() > deadline => panic!("pusher is still open"),
+            _ => time::sleep(Duration::from_millis(10)).await,
+        }
+    }
+    assert!(pusher.is_closed());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pushes_keep_session_open() {
+    let (addr, mut pushers) = start_push_server(8, Some(Duration::from_millis(200))).await;
+    let mut connection = StpClient::connect(addr).await.unwrap();
+    let pusher = pushers.recv().await.unwrap().unwrap();
+    for _ in 0..10 {
+        pusher.push("report", "tick").unwrap();
+        time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(connection.send_request("ping").await.unwrap(), "echo ping");
+    for seq in 1..=10 {
+        assert_eq!(connection.recv_push(None).await.unwrap().unwrap().seq, seq);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn no_pusher_without_push_feature() {
+    let (addr, mut pushers) = start_push_server(8, None).await;
+    let _connection = StpClient::connect_with(
+        addr,
+        Protocol {
+            version: V2,
+            features: Features::JSON,
+        },
+    )
+    .await
+    .unwrap();
+    assert!(pushers.recv().await.unwrap().is_none());
+}
@@ -0,0 +1,187 @@
+use std::{net::SocketAddr, time::Duration};
+
+use my_stp_async::{
+    client::StpClient,
+    errors::{ConnectError, RequestError},
+    message::{Request, Response},
+    protocol::{Features, Protocol, V1, V2},
+    server::StpServer,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+async fn start_echo_server(idle_timeout: Option<Duration>) -> SocketAddr {
+    start_server(idle_timeout, Protocol::default()).await
+}
+
+async fn start_server(idle_timeout: Option<Duration>, protocol: Protocol) -> SocketAddr {
+    let mut server = StpServer::bind("127.0.0.1:0").await.unwrap();
+    server.set_idle_timeout(idle_timeout);
+    server.set_protocol(protocol);
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            if let Ok(connection) = server.accept().await {
+                tokio::spawn(connection.serve(|request| async move { format!("echo {request}") }));
+            }
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn many_requests_over_one_session() {
+    let addr = start_echo_server(None).await;
+    let mut connection = StpClient::connect(addr).await.unwrap();
+    for i in 0..3 {
+        assert_eq!(
+            connection.send_request(format!("ping {i}")).await.unwrap(),
+            format!("echo ping {i}")
+        );
+    }
+    connection.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn pipelined_responses_carry_request_ids() {
+    let addr = start_echo_server(None).await;
+    let mut connection = StpClient::connect(addr).await.unwrap();
+    let first = connection.send("first").await.unwrap();
+    let second = connection.send("second").await.unwrap();
+    assert_ne!(first, second);
+    assert_eq!(
+        connection.recv().await.unwrap(),
+        (first, "echo first".to_string())
+    );
+    assert_eq!(
+        connection.recv().await.unwrap(),
+        (second, "echo second".to_string())
+    );
+}
+
+#[tokio::test]
+async fn idle_session_is_closed_by_server() {
+    let addr = start_echo_server(Some(Duration::from_millis(100))).await;
+    let mut connection = StpClient::connect(addr).await.unwrap();
+    assert_eq!(connection.send_request("ping").await.unwrap(), "echo ping");
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert!(matches!(
+        connection.send_request("ping").await,
+        Err(RequestError::Closed)
+    ));
+    assert!(connection.is_closed());
+}
+
+#[tokio::test]
+async fn single_request_handshake_is_still_served() {
+    let addr = start_echo_server(None).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"clnt").await.unwrap();
+    let mut handshake = [0u8; 4];
+    stream.read_exact(&mut handshake).await.unwrap();
+    assert_eq!(&handshake, b"serv");
+
+    stream.write_all(&4u32.to_be_bytes()).await.unwrap();
+    stream.write_all(b"ping").await.unwrap();
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).await.unwrap();
+    let mut response = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut response).await.unwrap();
+    assert_eq!(response, b"echo ping");
+}
+
+#[tokio::test]
+async fn typed_requests_in_both_encodings() {
+    let addr = start_echo_server(None).await;
+    let request = Request::command("device_report")
+        .param("room_name", "Kitchen")
+        .param("device_name", "Socket");
+    for features in [Features::JSON, Features::BINARY] {
+        let protocol = Protocol {
+            version: V2,
+            features,
+        };
+        let mut connection = StpClient::connect_with(addr, protocol).await.unwrap();
+        assert_eq!(connection.protocol(), protocol);
+        assert_eq!(
+            connection.send_typed(&request).await.unwrap(),
+            Response::Ok("echo device_report room_name=Kitchen device_name=Socket".to_string())
+        );
+    }
+}
+
+#[tokio::test]
+async fn negotiation_falls_back_to_common_version_and_features() {
+    let addr = start_server(
+        None,
+        Protocol {
+            version: V1,
+            features: Features::JSON,
+        },
+    )
+    .await;
+    let mut connection = StpClient::connect(addr).await.unwrap();
+    assert_eq!(connection.protocol(), Protocol::v1());
+    assert!(matches!(
+        connection.send_typed(&Request::command("hello")).await,
+        Err(RequestError::EncodingNotNegotiated)
+    ));
+    assert_eq!(
+        connection.send_request("hello").await.unwrap(),
+        "echo hello"
+    );
+    assert!(matches!(
+        connection.send_request("hello").await,
+        Err(RequestError::Closed)
+    ));
+}
+
+#[tokio::test]
+async fn unsupported_version_is_rejected() {
+    let addr = start_echo_server(None).await;
+    let result = StpClient::connect_with(
+        addr,
+        Protocol {
+            version: 0,
+            features: Features::NONE,
+        },
+    )
+    .await;
+    assert!(matches!(result, Err(ConnectError::UnsupportedVersion(0))));
+}
+
+#[tokio::test]
+async fn closed_session_ends_after_the_request_in_flight() {
+    let server = StpServer::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let (closer_sender, closer) = tokio::sync::oneshot::channel();
+    let served = tokio::spawn(async move {
+        let connection = server.accept().await.unwrap();
+        let mut closer_sender = Some((closer_sender, connection.closer()));
+        connection
+            .serve(|request| {
+                // The session is closed while the first request is processed
+                let closer = closer_sender.take();
+                async move {
+                    if let Some((closer_sender, closer)) = closer {
+                        closer_sender.send(closer).unwrap();
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                    }
+                    format!("echo {request}")
+                }
+            })
+            .await
+    });
+    let mut connection = StpClient::connect(addr).await.unwrap();
+    let request = tokio::spawn(async move {
+        let response = connection.send_request("ping").await.unwrap();
+        (connection, response)
+    });
+    closer.await.unwrap().close();
+    let (mut connection, response) = request.await.unwrap();
+    assert_eq!(response, "echo ping");
+    served.await.unwrap().unwrap();
+    assert!(connection.send_request("ping").await.is_err());
+}
@@ -0,0 +1,188 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::{stream, StreamExt, TryStreamExt};
+use my_stp_async::{
+    client::StpClient,
+    errors::RequestError,
+    message::{Request, Response, STREAM_FAILED_CODE},
+    protocol::{Features, Protocol, V1, V2},
+    server::{Reply, StpServer},
+};
+use tokio::time;
+
+/// `count <n>` streams n numbered lines, `fail` fails after two of them,
+/// `endless` streams until it is cancelled. Returns the number of chunks produced
+async fn start_stream_server() -> (SocketAddr, Arc<AtomicUsize>) {
+    let server = StpServer::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let produced = Arc::new(AtomicUsize::new(0));
+    let counter = produced.clone();
+    tokio::spawn(async move {
+        loop {
+            if let Ok(connection) = server.accept().await {
+                let counter = counter.clone();
+                tokio::spawn(connection.serve_streaming(move |request| {
+                    let reply = stream_reply(request, counter.clone());
+                    async move { reply }
+                }));
+            }
+        }
+    });
+    (addr, produced)
+}
+
+fn stream_reply(request: Request, produced: Arc<AtomicUsize>) -> Reply {
+    let request = request.into_command_line();
+    let lines = (0..).map(move |i| {
+        produced.fetch_add(1, Ordering::Relaxed);
+        Ok(format!("line {i}\n"))
+    });
+    match request.split_once(' ') {
+        Some(("count", n)) => Reply::stream(stream::iter(lines.take(n.parse().unwrap()))),
+        _ if request == "fail" => Reply::stream(stream::iter(
+            lines.take(2).chain([Err("device is gone".to_string())]),
+        )),
+        _ if request == "endless" => Reply::stream(stream::iter(lines).then(|line| async {
+            time::sleep(Duration::from_millis(5)).await;
+            line
+        })),
+        _ => Reply::Response(Response::Ok(format!("echo {request}"))),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn chunks_arrive_in_order() {
+    let (addr, _) = start_stream_server().await;
+    let mut connection = StpClient::connect(addr).await.unwrap();
+    let chunks: Vec<String> = connection
+        .send_streaming("count 3")
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(chunks, ["line 0\n", "line 1\n", "line 2\n"]);
+    assert_eq!(connection.send_request("ping").await.unwrap(), "echo ping");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn plain_response_is_a_single_chunk() {
+    let (addr, _) = start_stream_server().await;
+    let mut connection = StpClient::connect(addr).await.unwrap();
+    let chunks: Vec<_> = connection
+        .send_streaming("ping")
+        .await
+        .unwrap()
+        .collect()
+        .await;
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(chunks[0].as_ref().unwrap(), "echo ping");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn send_request_joins_the_chunks() {
+    let (addr, _) = start_stream_server().await;
+    let mut connection = StpClient::connect(addr).await.unwrap();
+    assert_eq!(
+        connection.send_request("count 2").await.unwrap(),
+        "line 0\nline 1\n"
+    );
+    assert!(matches!(
+        connection.send_request("fail").await,
+        Err(RequestError::StreamFailed(message)) if message == "device is gone"
+    ));
+    assert_eq!(connection.send_request("ping").await.unwrap(), "echo ping");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failed_stream_ends_with_an_error() {
+    let (addr, _) = start_stream_server().await;
+    let mut connection = StpClient::connect(addr).await.unwrap();
+    let mut stream = connection.send_streaming("fail").await.unwrap();
+    assert_eq!(stream.next().await.unwrap().unwrap(), "line 0\n");
+    assert_eq!(stream.next().await.unwrap().unwrap(), "line 1\n");
+    assert!(matches!(
+        stream.next().await,
+        Some(Err(RequestError::StreamFailed(_)))
+    ));
+    assert!(stream.next().await.is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cancel_stops_the_stream_and_keeps_the_session() {
+    let (addr, produced) = start_stream_server().await;
+    let mut connection = StpClient::connect(addr).await.unwrap();
+    let mut stream = connection.send_streaming("endless").await.unwrap();
+    for i in 0..3 {
+        assert_eq!(stream.next().await.unwrap().unwrap(), format!("line {i}\n"));
+    }
+    stream.cancel().await.unwrap();
+    assert_eq!(connection.send_request("ping").await.unwrap(), "echo ping");
+
+    let after_cancel = produced.load(Ordering::Relaxed);
+    time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(produced.load(Ordering::Relaxed), after_cancel);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dropped_stream_is_cancelled() {
+    let (addr, _) = start_stream_server().await;
+    let mut connection = StpClient::connect(addr).await.unwrap();
+    let first = connection
+        .send_streaming("endless")
+        .await
+        .unwrap()
+        .next()
+        .await;
+    assert_eq!(first.unwrap().unwrap(), "line 0\n");
+    assert_eq!(
+        connection.send_request("count 1").await.unwrap(),
+        "line 0\n"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn clients_without_streams_get_one_response() {
+    let (addr, _) = start_stream_server().await;
+    let mut connection = StpClient::connect_with(
+        addr,
+        Protocol {
+            version: V2,
+            features: Features::JSON,
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        connection.send_request("count 2").await.unwrap(),
+        "line 0\nline 1\n"
+    );
+    let response = connection
+        .send_typed(&Request::command("fail"))
+        .await
+        .unwrap();
+    assert!(matches!(
+        response,
+        Response::Error { code, message } if code == STREAM_FAILED_CODE && message == "device is gone"
+    ));
+
+    let mut v1 = StpClient::connect_with(
+        addr,
+        Protocol {
+            version: V1,
+            features: Features::NONE,
+        },
+    )
+    .await
+    .unwrap();
+    let chunks: Vec<_> = v1.send_streaming("count 2").await.unwrap().collect().await;
+    assert_eq!(chunks.len(), 1);
+    assert_eq!(chunks[0].as_ref().unwrap(), "line 0\nline 1\n");
+}
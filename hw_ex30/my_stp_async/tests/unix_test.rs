@@ -0,0 +1,102 @@
+#![cfg(unix)]
+
+use std::{
+    fs, io,
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use my_stp_async::{
+    client::StpClient, errors::RequestError, server::StpServer, socket_file::UnixSocketOptions,
+};
+use tokio::{sync::oneshot, time};
+
+fn socket_path(name: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("stp-async-test-{}-{name}.sock", std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn session_over_unix_socket() {
+    let path = socket_path("session");
+    let server = StpServer::bind_unix(&path, UnixSocketOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(server.socket_path(), Some(path.as_path()));
+    assert!(server.local_addr().is_err());
+    let (sender, receiver) = oneshot::channel();
+    tokio::spawn(async move {
+        let connection = server.accept().await.unwrap();
+        sender.send(connection.peer_addr().is_err()).unwrap();
+        connection
+            .serve(|request| async move { format!("echo {request}") })
+            .await
+    });
+
+    let mut connection = StpClient::connect_unix(&path).await.unwrap();
+    assert!(receiver.await.unwrap());
+    for i in 0..3 {
+        assert_eq!(
+            connection.send_request(format!("ping {i}")).await.unwrap(),
+            format!("echo ping {i}")
+        );
+    }
+    connection.close().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn socket_file_follows_the_options() {
+    let path = socket_path("mode");
+    let options = UnixSocketOptions {
+        mode: Some(0o600),
+        ..UnixSocketOptions::default()
+    };
+    let server = StpServer::bind_unix(&path, options).await.unwrap();
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    // A live server is never replaced
+    let error = StpServer::bind_unix(&path, options).await.err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+
+    drop(server);
+    assert!(!path.exists());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pushes_and_idle_timeout_over_unix_socket() {
+    let path = socket_path("push");
+    let mut server = StpServer::bind_unix(&path, UnixSocketOptions::default())
+        .await
+        .unwrap();
+    server.set_idle_timeout(Some(Duration::from_millis(200)));
+    let (sender, receiver) = oneshot::channel();
+    tokio::spawn(async move {
+        let mut connection = server.accept().await.unwrap();
+        sender.send(connection.pusher().unwrap()).unwrap();
+        connection
+            .serve(|request| async move { format!("echo {request}") })
+            .await
+    });
+
+    let mut connection = StpClient::connect_unix(&path).await.unwrap();
+    let pusher = receiver.await.unwrap();
+    pusher.push("report", "tick").unwrap();
+    let push = connection.recv_push(None).await.unwrap().unwrap();
+    assert_eq!((push.seq, push.body.as_str()), (1, "tick"));
+    assert_eq!(connection.send_request("ping").await.unwrap(), "echo ping");
+
+    time::sleep(Duration::from_millis(400)).await;
+    assert!(matches!(
+        connection.send_request("ping").await,
+        Err(RequestError::Closed)
+    ));
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while !pusher.is_closed() {
+        assert!(Instant::now() < deadline, "pusher is still open");
+        time::sleep(Duration::from_millis(10)).await;
+    }
+}
@@ -0,0 +1,136 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::errors::KeyFileError;
+
+pub const NONCE_LEN: usize = 32;
+pub const SIGNATURE_LEN: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Identity and shared secret a client proves during the handshake
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub client_id: String,
+    pub key: Vec<u8>,
+}
+
+impl Credentials {
+    pub fn new(client_id: &str, key: &[u8]) -> Self {
+        Self {
+            client_id: client_id.to_string(),
+            key: key.to_vec(),
+        }
+    }
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("client_id", &self.client_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Keys of the clients a server accepts
+#[derive(Default, Clone)]
+pub struct KeyStore {
+    keys: HashMap<String, Vec<u8>>,
+}
+
+impl KeyStore {
+    /// Load keys from a file with one `client_id:key` pair per line.
+    /// Empty lines and lines starting with `#` are skipped
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, KeyFileError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, KeyFileError> {
+        let mut store = Self::default();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (client_id, key) = line
+                .split_once(':')
+                .filter(|(client_id, key)| !client_id.is_empty() && !key.is_empty())
+                .ok_or(KeyFileError::BadLine(index + 1))?;
+            if store.contains(client_id) {
+                return Err(KeyFileError::DuplicateClient(client_id.to_string()));
+            }
+            store.insert(client_id, key.as_bytes());
+        }
+        Ok(store)
+    }
+
+    pub fn insert(&mut self, client_id: &str, key: &[u8]) {
+        self.keys.insert(client_id.to_string(), key.to_vec());
+    }
+
+    pub fn contains(&self, client_id: &str) -> bool {
+        self.keys.contains_key(client_id)
+    }
+
+    pub fn key(&self, client_id: &str) -> Option<&[u8]> {
+        self.keys.get(client_id).map(Vec::as_slice)
+    }
+}
+
+pub(crate) fn new_nonce() -> [u8; NONCE_LEN] {
+    rand::random()
+}
+
+fn mac(key: &[u8], nonce: &[u8], client_id: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(nonce);
+    mac.update(client_id.as_bytes());
+    mac
+}
+
+/// HMAC-SHA256 over the server nonce and the client id
+pub(crate) fn sign(key: &[u8], nonce: &[u8], client_id: &str) -> [u8; SIGNATURE_LEN] {
+    mac(key, nonce, client_id).finalize().into_bytes().into()
+}
+
+/// Constant time comparison with the expected signature
+pub(crate) fn verify(key: &[u8], nonce: &[u8], client_id: &str, signature: &[u8]) -> bool {
+    mac(key, nonce, client_id).verify_slice(signature).is_ok()
+}
+
+#[cfg(test)]
+mod auth_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key_file() {
+        let store = KeyStore::parse("# clients\nalice:secret\n\nbob:other:key\n").unwrap();
+        assert_eq!(store.key("alice"), Some(b"secret".as_slice()));
+        assert_eq!(store.key("bob"), Some(b"other:key".as_slice()));
+        assert_eq!(store.key("eve"), None);
+    }
+
+    #[test]
+    fn test_parse_bad_key_file() {
+        assert!(matches!(
+            KeyStore::parse("alice:secret\nbob\n"),
+            Err(KeyFileError::BadLine(2))
+        ));
+        assert!(matches!(
+            KeyStore::parse("alice:secret\nalice:other\n"),
+            Err(KeyFileError::DuplicateClient(client_id)) if client_id == "alice"
+        ));
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let nonce = new_nonce();
+        let signature = sign(b"secret", &nonce, "alice");
+        assert!(verify(b"secret", &nonce, "alice", &signature));
+        assert!(!verify(b"wrong", &nonce, "alice", &signature));
+        assert!(!verify(b"secret", &nonce, "bob", &signature));
+        assert!(!verify(b"secret", &new_nonce(), "alice", &signature));
+    }
+}
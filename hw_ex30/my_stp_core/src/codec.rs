@@ -0,0 +1,390 @@
+use crate::{
+    errors::{RecvError, SendError},
+    limits::Limits,
+    message::{Encoding, Push, StreamStatus},
+};
+
+const TEXT_MESSAGE_TAG: u8 = b'D';
+const JSON_MESSAGE_TAG: u8 = b'J';
+const BINARY_MESSAGE_TAG: u8 = b'B';
+const CLOSE_MESSAGE_TAG: u8 = b'C';
+const PUSH_MESSAGE_TAG: u8 = b'P';
+const CHUNK_MESSAGE_TAG: u8 = b'K';
+const END_MESSAGE_TAG: u8 = b'E';
+const CANCEL_MESSAGE_TAG: u8 = b'X';
+const HEARTBEAT_MESSAGE_TAG: u8 = b'H';
+
+const STREAM_COMPLETE: u8 = 0;
+const STREAM_FAILED: u8 = 1;
+const STREAM_CANCELLED: u8 = 2;
+
+/// Length of the big endian `u32` in front of every frame
+pub const FRAME_PREFIX_LEN: usize = 4;
+
+/// Frame payload exchanged inside a session
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    /// Request or response string, responses carry the id of their request
+    Text { id: u64, body: String },
+    /// Encoded [`crate::message::Request`] or [`crate::message::Response`]
+    Typed {
+        id: u64,
+        encoding: Encoding,
+        data: Vec<u8>,
+    },
+    /// Sender is going to close the connection
+    Close,
+    /// Server initiated message, the id is the sequence number
+    Push(Push),
+    /// Part of a streamed response, chunks of one response are never interleaved
+    /// with other responses
+    Chunk { id: u64, body: String },
+    /// Last frame of a streamed response
+    End { id: u64, status: StreamStatus },
+    /// Client no longer wants the rest of a streamed response
+    Cancel { id: u64 },
+    /// Liveness check, the server sends it back with the same id
+    Heartbeat { id: u64 },
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let (tag, id, body) = match self {
+            Message::Text { id, body } => (TEXT_MESSAGE_TAG, id, body.as_bytes()),
+            Message::Typed {
+                id,
+                encoding: Encoding::Json,
+                data,
+            } => (JSON_MESSAGE_TAG, id, data.as_slice()),
+            Message::Typed {
+                id,
+                encoding: Encoding::Binary,
+                data,
+            } => (BINARY_MESSAGE_TAG, id, data.as_slice()),
+            Message::Chunk { id, body } => (CHUNK_MESSAGE_TAG, id, body.as_bytes()),
+            Message::Cancel { id } => (CANCEL_MESSAGE_TAG, id, [].as_slice()),
+            Message::Heartbeat { id } => (HEARTBEAT_MESSAGE_TAG, id, [].as_slice()),
+            Message::Close => return vec![CLOSE_MESSAGE_TAG],
+            Message::Push(push) => return encode_push(push),
+            Message::End { id, status } => return encode_end(*id, status),
+        };
+        let mut data = Vec::with_capacity(1 + 8 + body.len());
+        data.push(tag);
+        data.extend_from_slice(&id.to_be_bytes());
+        data.extend_from_slice(body);
+        data
+    }
+
+    pub fn decode(mut data: Vec<u8>) -> Result<Self, RecvError> {
+        let tag = match data.first() {
+            Some(&CLOSE_MESSAGE_TAG) if data.len() == 1 => return Ok(Message::Close),
+            Some(&tag) if data.len() >= 9 => tag,
+            _ => return Err(RecvError::BadMessage),
+        };
+        let id = u64::from_be_bytes(data[1..9].try_into().unwrap());
+        let body = data.split_off(9);
+        match tag {
+            TEXT_MESSAGE_TAG => {
+                let body = String::from_utf8(body).map_err(|_| RecvError::BadEncoding)?;
+                Ok(Message::Text { id, body })
+            }
+            JSON_MESSAGE_TAG => Ok(Message::Typed {
+                id,
+                encoding: Encoding::Json,
+                data: body,
+            }),
+            BINARY_MESSAGE_TAG => Ok(Message::Typed {
+                id,
+                encoding: Encoding::Binary,
+                data: body,
+            }),
+            PUSH_MESSAGE_TAG => Ok(Message::Push(decode_push(id, body)?)),
+            CHUNK_MESSAGE_TAG => {
+                let body = String::from_utf8(body).map_err(|_| RecvError::BadEncoding)?;
+                Ok(Message::Chunk { id, body })
+            }
+            END_MESSAGE_TAG => Ok(Message::End {
+                id,
+                status: decode_stream_status(body)?,
+            }),
+            CANCEL_MESSAGE_TAG if body.is_empty() => Ok(Message::Cancel { id }),
+            HEARTBEAT_MESSAGE_TAG if body.is_empty() => Ok(Message::Heartbeat { id }),
+            _ => Err(RecvError::BadMessage),
+        }
+    }
+
+    /// The message as one length prefixed frame
+    pub fn encode_frame(&self, limits: &Limits) -> Result<Vec<u8>, SendError> {
+        encode_frame(&self.encode(), limits)
+    }
+}
+
+/// Tag, sequence number, u16 length of the topic, topic and body
+fn encode_push(push: &Push) -> Vec<u8> {
+    let topic = push.topic.as_bytes();
+    let topic_len = u16::try_from(topic.len()).unwrap_or(u16::MAX);
+    let topic = &topic[..topic_len as usize];
+    let mut data = Vec::with_capacity(1 + 8 + 2 + topic.len() + push.body.len());
+    data.push(PUSH_MESSAGE_TAG);
+    data.extend_from_slice(&push.seq.to_be_bytes());
+    data.extend_from_slice(&topic_len.to_be_bytes());
+    data.extend_from_slice(topic);
+    data.extend_from_slice(push.body.as_bytes());
+    data
+}
+
+fn decode_push(seq: u64, mut data: Vec<u8>) -> Result<Push, RecvError> {
+    if data.len() < 2 {
+        return Err(RecvError::BadMessage);
+    }
+    let topic_len = u16::from_be_bytes([data[0], data[1]]) as usize;
+    if data.len() < 2 + topic_len {
+        return Err(RecvError::BadMessage);
+    }
+    let body = data.split_off(2 + topic_len);
+    let topic = data.split_off(2);
+    Ok(Push {
+        seq,
+        topic: String::from_utf8(topic).map_err(|_| RecvError::BadEncoding)?,
+        body: String::from_utf8(body).map_err(|_| RecvError::BadEncoding)?,
+    })
+}
+
+/// Tag, request id, status byte and the error message of a failed stream
+fn encode_end(id: u64, status: &StreamStatus) -> Vec<u8> {
+    let (code, message) = match status {
+        StreamStatus::Complete => (STREAM_COMPLETE, ""),
+        StreamStatus::Failed(message) => (STREAM_FAILED, message.as_str()),
+        StreamStatus::Cancelled => (STREAM_CANCELLED, ""),
+    };
+    let mut data = Vec::with_capacity(1 + 8 + 1 + message.len());
+    data.push(END_MESSAGE_TAG);
+    data.extend_from_slice(&id.to_be_bytes());
+    data.push(code);
+    data.extend_from_slice(message.as_bytes());
+    data
+}
+
+fn decode_stream_status(mut data: Vec<u8>) -> Result<StreamStatus, RecvError> {
+    let Some(&code) = data.first() else {
+        return Err(RecvError::BadMessage);
+    };
+    let message = data.split_off(1);
+    match code {
+        STREAM_COMPLETE if message.is_empty() => Ok(StreamStatus::Complete),
+        STREAM_FAILED => {
+            let message = String::from_utf8(message).map_err(|_| RecvError::BadEncoding)?;
+            Ok(StreamStatus::Failed(message))
+        }
+        STREAM_CANCELLED if message.is_empty() => Ok(StreamStatus::Cancelled),
+        _ => Err(RecvError::BadMessage),
+    }
+}
+
+/// Length prefixed frame, ready to be written in one call
+pub fn encode_frame(data: &[u8], limits: &Limits) -> Result<Vec<u8>, SendError> {
+    let len = u32::try_from(data.len())
+        .ok()
+        .filter(|len| *len <= limits.max_frame_size)
+        .ok_or(SendError::FrameTooLarge {
+            size: data.len(),
+            max: limits.max_frame_size,
+        })?;
+    let mut frame = Vec::with_capacity(FRAME_PREFIX_LEN + data.len());
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(data);
+    Ok(frame)
+}
+
+/// Splits a byte stream into frames.
+/// Transports that must not read past the end of a frame read exactly
+/// [`FrameDecoder::bytes_needed`] bytes before every [`FrameDecoder::decode`]
+#[derive(Debug)]
+pub struct FrameDecoder {
+    max_frame_size: u32,
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new(limits: &Limits) -> Self {
+        Self {
+            max_frame_size: limits.max_frame_size,
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Bytes missing to complete the length prefix or, once it is known, the frame
+    pub fn bytes_needed(&self) -> usize {
+        match self.frame_len() {
+            Some(len) => (FRAME_PREFIX_LEN + len as usize).saturating_sub(self.buf.len()),
+            None => FRAME_PREFIX_LEN - self.buf.len(),
+        }
+    }
+
+    /// Next complete frame, `None` if more bytes are needed.
+    /// The length prefix is checked against the limit before the frame is buffered
+    pub fn decode(&mut self) -> Result<Option<Vec<u8>>, RecvError> {
+        let Some(len) = self.frame_len() else {
+            return Ok(None);
+        };
+        if len > self.max_frame_size {
+            return Err(RecvError::FrameTooLarge {
+                size: len,
+                max: self.max_frame_size,
+            });
+        }
+        let end = FRAME_PREFIX_LEN + len as usize;
+        if self.buf.len() < end {
+            return Ok(None);
+        }
+        let rest = self.buf.split_off(end);
+        let frame = std::mem::replace(&mut self.buf, rest).split_off(FRAME_PREFIX_LEN);
+        Ok(Some(frame))
+    }
+
+    /// Bytes received after the last complete frame
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    fn frame_len(&self) -> Option<u32> {
+        let prefix = self.buf.get(..FRAME_PREFIX_LEN)?;
+        Some(u32::from_be_bytes(prefix.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod codec_tests {
+    use super::*;
+    use crate::message::Encoding;
+
+    fn limits(max_frame_size: u32) -> Limits {
+        Limits {
+            max_frame_size,
+            ..Limits::default()
+        }
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let messages = [
+            Message::Text {
+                id: 7,
+                body: "rooms_list".to_string(),
+            },
+            Message::Typed {
+                id: u64::MAX,
+                encoding: Encoding::Binary,
+                data: vec![0, 1, 2],
+            },
+            Message::Close,
+            Message::Push(Push {
+                seq: 3,
+                topic: "Кухня-Розетка1".to_string(),
+                body: "report".to_string(),
+            }),
+            Message::Chunk {
+                id: 4,
+                body: "part".to_string(),
+            },
+            Message::End {
+                id: 4,
+                status: StreamStatus::Complete,
+            },
+            Message::End {
+                id: 5,
+                status: StreamStatus::Failed("device is gone".to_string()),
+            },
+            Message::End {
+                id: 6,
+                status: StreamStatus::Cancelled,
+            },
+            Message::Cancel { id: 6 },
+            Message::Heartbeat { id: 1 },
+        ];
+        for message in messages {
+            assert_eq!(Message::decode(message.encode()).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn bad_messages_are_rejected() {
+        assert!(matches!(
+            Message::decode(vec![]),
+            Err(RecvError::BadMessage)
+        ));
+        assert!(matches!(
+            Message::decode(b"Z00000000".to_vec()),
+            Err(RecvError::BadMessage)
+        ));
+        let mut text = Message::Text {
+            id: 1,
+            body: String::new(),
+        }
+        .encode();
+        text.push(0xff);
+        assert!(matches!(Message::decode(text), Err(RecvError::BadEncoding)));
+        // Topic length points past the end of the push
+        let mut push = vec![b'P'];
+        push.extend_from_slice(&1u64.to_be_bytes());
+        push.extend_from_slice(&10u16.to_be_bytes());
+        assert!(matches!(Message::decode(push), Err(RecvError::BadMessage)));
+    }
+
+    #[test]
+    fn frames_are_split_from_a_byte_stream() {
+        let limits = limits(64);
+        let mut stream = encode_frame(b"first", &limits).unwrap();
+        stream.extend(encode_frame(b"", &limits).unwrap());
+        stream.extend(encode_frame(b"second", &limits).unwrap());
+
+        let mut decoder = FrameDecoder::new(&limits);
+        let mut frames = Vec::new();
+        // One byte at a time, the worst case for a stream transport
+        for byte in stream {
+            decoder.extend(&[byte]);
+            while let Some(frame) = decoder.decode().unwrap() {
+                frames.push(frame);
+            }
+        }
+        assert_eq!(frames, [b"first".to_vec(), vec![], b"second".to_vec()]);
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn bytes_needed_allows_exact_reads() {
+        let limits = limits(64);
+        let frame = encode_frame(b"ping", &limits).unwrap();
+        let mut decoder = FrameDecoder::new(&limits);
+        assert_eq!(decoder.bytes_needed(), FRAME_PREFIX_LEN);
+        decoder.extend(&frame[..2]);
+        assert_eq!(decoder.bytes_needed(), 2);
+        decoder.extend(&frame[2..FRAME_PREFIX_LEN]);
+        assert_eq!(decoder.bytes_needed(), 4);
+        assert_eq!(decoder.decode().unwrap(), None);
+        decoder.extend(&frame[FRAME_PREFIX_LEN..]);
+        assert_eq!(decoder.decode().unwrap(), Some(b"ping".to_vec()));
+    }
+
+    #[test]
+    fn frame_limit_is_checked_on_both_sides() {
+        let limits = limits(4);
+        assert!(matches!(
+            encode_frame(b"12345", &limits),
+            Err(SendError::FrameTooLarge { size: 5, max: 4 })
+        ));
+        let mut decoder = FrameDecoder::new(&limits);
+        decoder.extend(&u32::MAX.to_be_bytes());
+        assert!(matches!(
+            decoder.decode(),
+            Err(RecvError::FrameTooLarge {
+                size: u32::MAX,
+                max: 4
+            })
+        ));
+    }
+}
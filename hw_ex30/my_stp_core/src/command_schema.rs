@@ -0,0 +1,260 @@
+use std::fmt::Write;
+
+use crate::custom_parser::RequestParams;
+use crate::errors::SchemaError;
+
+/// What a parameter value has to look like
+#[derive(Debug, Clone, Copy)]
+pub enum ParamType {
+    Text,
+    /// `true` or `false`
+    Bool,
+    UnsignedInteger,
+    /// Finite, `NaN` and infinities are rejected
+    Number,
+    /// One of the listed words
+    OneOf(&'static [&'static str]),
+    /// Anything `check` accepts, `expected` describes it for errors and help
+    Custom {
+        expected: &'static str,
+        check: fn(&str) -> bool,
+    },
+}
+
+impl ParamType {
+    /// Description used in help and validation errors
+    pub fn expected(&self) -> String {
+        match self {
+            ParamType::Text => "string".to_string(),
+            ParamType::Bool => "true|false".to_string(),
+            ParamType::UnsignedInteger => "unsigned integer".to_string(),
+            ParamType::Number => "number".to_string(),
+            ParamType::OneOf(values) => values.join("|"),
+            ParamType::Custom { expected, .. } => expected.to_string(),
+        }
+    }
+
+    pub fn accepts(&self, value: &str) -> bool {
+        match self {
+            ParamType::Text => true,
+            ParamType::Bool => value.parse::<bool>().is_ok(),
+            ParamType::UnsignedInteger => value.parse::<u64>().is_ok(),
+            ParamType::Number => value.parse::<f32>().is_ok_and(f32::is_finite),
+            ParamType::OneOf(values) => values.contains(&value),
+            ParamType::Custom { check, .. } => check(value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ParamSchema {
+    pub name: &'static str,
+    pub ty: ParamType,
+    pub required: bool,
+    /// Value used when the parameter is not given
+    pub default: Option<&'static str>,
+    pub description: &'static str,
+}
+
+impl ParamSchema {
+    pub const fn required(name: &'static str, ty: ParamType, description: &'static str) -> Self {
+        Self {
+            name,
+            ty,
+            required: true,
+            default: None,
+            description,
+        }
+    }
+
+    pub const fn optional(name: &'static str, ty: ParamType, description: &'static str) -> Self {
+        Self {
+            name,
+            ty,
+            required: false,
+            default: None,
+            description,
+        }
+    }
+
+    /// Optional parameter that takes `default` when it is not given
+    pub const fn with_default(mut self, default: &'static str) -> Self {
+        self.required = false;
+        self.default = Some(default);
+        self
+    }
+}
+
+/// Name and parameters of a command, requests are validated against it and help is made of it.
+/// Parameters that are not declared are passed through as they are
+#[derive(Debug, Clone, Copy)]
+pub struct CommandSchema {
+    pub name: &'static str,
+    pub summary: &'static str,
+    pub params: &'static [ParamSchema],
+}
+
+impl CommandSchema {
+    pub const fn new(
+        name: &'static str,
+        summary: &'static str,
+        params: &'static [ParamSchema],
+    ) -> Self {
+        Self {
+            name,
+            summary,
+            params,
+        }
+    }
+
+    /// Check required parameters and value types, missing optional parameters get their defaults
+    pub fn validate(&self, mut params: RequestParams) -> Result<RequestParams, SchemaError> {
+        for param in self.params {
+            match params.get(param.name) {
+                Some(value) if !param.ty.accepts(value) => {
+                    return Err(SchemaError::InvalidParam {
+                        name: param.name.to_string(),
+                        value: value.to_string(),
+                        expected: param.ty.expected(),
+                    })
+                }
+                Some(_) => {}
+                None if param.required => {
+                    return Err(SchemaError::MissingParam(param.name.to_string()))
+                }
+                None => {
+                    if let Some(default) = param.default {
+                        params.push_param(param.name, default);
+                    }
+                }
+            }
+        }
+        Ok(params)
+    }
+
+    /// `command key=<type> [optional=<type>]`
+    pub fn usage(&self) -> String {
+        let mut usage = self.name.to_string();
+        for param in self.params {
+            let pair = format!("{}=<{}>", param.name, param.ty.expected());
+            match param.required {
+                true => write!(usage, " {pair}"),
+                false => write!(usage, " [{pair}]"),
+            }
+            .unwrap();
+        }
+        usage
+    }
+
+    /// Usage, summary and a line per parameter
+    pub fn describe(&self) -> String {
+        let mut description = format!("{}\n  {}", self.usage(), self.summary);
+        for param in self.params {
+            let presence = match (param.required, param.default) {
+                (true, _) => "required".to_string(),
+                (false, Some(default)) => format!("default {default}"),
+                (false, None) => "optional".to_string(),
+            };
+            write!(
+                description,
+                "\n  {}: {}, {presence}",
+                param.name, param.description
+            )
+            .unwrap();
+        }
+        description
+    }
+}
+
+/// Usage line and summary of every command
+pub fn help<'a, I>(schemas: I) -> String
+where
+    I: IntoIterator<Item = &'a CommandSchema>,
+{
+    schemas
+        .into_iter()
+        .map(|schema| format!("{}\n  {}", schema.usage(), schema.summary))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod command_schema_tests {
+    use super::*;
+    use crate::custom_parser::parse_request_parameters;
+
+    const STREAM: CommandSchema = CommandSchema::new(
+        "stream",
+        "Stream reports of a device",
+        &[
+            ParamSchema::required("room_name", ParamType::Text, "room of the device"),
+            ParamSchema::optional(
+                "delay",
+                ParamType::UnsignedInteger,
+                "seconds between reports",
+            )
+            .with_default("5"),
+            ParamSchema::optional(
+                "delivery",
+                ParamType::OneOf(&["udp", "push"]),
+                "how reports are sent",
+            ),
+        ],
+    );
+
+    fn validate(line: &str) -> Result<RequestParams, SchemaError> {
+        STREAM.validate(parse_request_parameters(line).unwrap())
+    }
+
+    #[test]
+    fn defaults_are_filled_in() {
+        let params = validate("stream room_name=Кухня extra=1").unwrap();
+        assert_eq!(params.get("delay"), Some("5"));
+        assert_eq!(params.get("delivery"), None);
+        assert_eq!(params.get("extra"), Some("1"));
+        let params = validate("stream room_name=Кухня delay=1").unwrap();
+        assert_eq!(params.get("delay"), Some("1"));
+    }
+
+    #[test]
+    fn missing_and_invalid_params() {
+        assert_eq!(
+            validate("stream delay=1").unwrap_err(),
+            SchemaError::MissingParam("room_name".to_string())
+        );
+        assert_eq!(
+            validate("stream room_name=Кухня delivery=mail").unwrap_err(),
+            SchemaError::InvalidParam {
+                name: "delivery".to_string(),
+                value: "mail".to_string(),
+                expected: "udp|push".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn numbers_are_finite() {
+        assert!(ParamType::Number.accepts("-1.5"));
+        assert!(ParamType::Number.accepts("100"));
+        for value in ["NaN", "inf", "-inf", "infinity", "1e39"] {
+            assert!(!ParamType::Number.accepts(value), "{value}");
+        }
+    }
+
+    #[test]
+    fn usage_and_description() {
+        assert_eq!(
+            STREAM.usage(),
+            "stream room_name=<string> [delay=<unsigned integer>] [delivery=<udp|push>]"
+        );
+        assert_eq!(
+            STREAM.describe().lines().skip(1).collect::<Vec<_>>(),
+            [
+                "  Stream reports of a device",
+                "  room_name: room of the device, required",
+                "  delay: seconds between reports, default 5",
+                "  delivery: how reports are sent, optional",
+            ]
+        );
+    }
+}
@@ -0,0 +1,311 @@
+use std::{borrow::Cow, iter::Peekable, str::CharIndices, str::FromStr};
+
+use crate::errors::ParseError;
+
+/// Parameter of a request together with the byte offset of its value
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub key: String,
+    pub value: String,
+    pub position: usize,
+}
+
+/// Parsed `command key=value key="quoted value"` line
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestParams {
+    command: String,
+    params: Vec<Param>,
+}
+
+impl RequestParams {
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    pub fn params(&self) -> &[Param] {
+        &self.params
+    }
+
+    /// Value of the key. If the key is repeated, the last value wins
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.param(key).map(|param| param.value.as_str())
+    }
+
+    /// Values of a repeated key in request order
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.params
+            .iter()
+            .filter(|param| param.key == key)
+            .map(|param| param.value.as_str())
+            .collect()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.param(key).is_some()
+    }
+
+    pub fn get_u64(&self, key: &str) -> Result<Option<u64>, ParseError> {
+        self.get_parsed(key, "unsigned integer")
+    }
+
+    pub fn get_f32(&self, key: &str) -> Result<Option<f32>, ParseError> {
+        self.get_parsed(key, "number")
+    }
+
+    /// `true` or `false`
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>, ParseError> {
+        self.get_parsed(key, "true or false")
+    }
+
+    /// Value parsed with the `FromStr` of `T`, e.g. one of the names of an enum
+    pub fn get_enum<T: FromStr>(&self, key: &str) -> Result<Option<T>, ParseError> {
+        let expected = std::any::type_name::<T>().rsplit("::").next().unwrap_or("");
+        self.get_parsed(key, expected)
+    }
+
+    fn get_parsed<T: FromStr>(
+        &self,
+        key: &str,
+        expected: &'static str,
+    ) -> Result<Option<T>, ParseError> {
+        let Some(param) = self.param(key) else {
+            return Ok(None);
+        };
+        let value = param.value.parse().map_err(|_| ParseError::BadValue {
+            key: param.key.clone(),
+            value: param.value.clone(),
+            position: param.position,
+            expected,
+        })?;
+        Ok(Some(value))
+    }
+
+    /// Add a parameter that was not in the line, e.g. a default value
+    pub(crate) fn push_param(&mut self, key: &str, value: &str) {
+        self.params.push(Param {
+            key: key.to_string(),
+            value: value.to_string(),
+            position: 0,
+        });
+    }
+
+    fn param(&self, key: &str) -> Option<&Param> {
+        self.params.iter().rev().find(|param| param.key == key)
+    }
+}
+
+/// Parse `command key=value ...`.
+/// Values may be quoted with `"` to contain spaces, both quoted and plain values
+/// support the escapes `\\`, `\"`, `\n`, `\t`, `\r`, `\ ` and `\=`.
+/// Everything after the first `=` of a pair belongs to the value
+pub fn parse_request_parameters(target: &str) -> Result<RequestParams, ParseError> {
+    let mut chars = target.char_indices().peekable();
+    skip_whitespace(&mut chars);
+    let command = read_word(&mut chars, |c| c.is_whitespace())?;
+    if command.is_empty() {
+        return Err(ParseError::EmptyRequest);
+    }
+
+    let mut params = vec![];
+    loop {
+        skip_whitespace(&mut chars);
+        let Some(&(key_position, _)) = chars.peek() else {
+            break;
+        };
+        let key = read_word(&mut chars, |c| c == '=' || c.is_whitespace())?;
+        match chars.next() {
+            Some((_, '=')) => {}
+            _ => {
+                return Err(ParseError::MissingSeparator {
+                    position: key_position,
+                })
+            }
+        }
+        if key.is_empty() {
+            return Err(ParseError::EmptyKey {
+                position: key_position,
+            });
+        }
+        let position = chars.peek().map_or(target.len(), |&(position, _)| position);
+        let value = read_value(&mut chars)?;
+        params.push(Param {
+            key,
+            value,
+            position,
+        });
+    }
+
+    Ok(RequestParams { command, params })
+}
+
+/// Quote the value if it can't be sent as is
+pub fn quote(value: &str) -> Cow<'_, str> {
+    let is_plain = !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || c == '"' || c == '\\');
+    if is_plain {
+        return Cow::Borrowed(value);
+    }
+
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    Cow::Owned(quoted)
+}
+
+type Chars<'a> = Peekable<CharIndices<'a>>;
+
+fn skip_whitespace(chars: &mut Chars<'_>) {
+    while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+}
+
+/// Command name or key, quotes are not allowed there
+fn read_word(chars: &mut Chars<'_>, is_end: impl Fn(char) -> bool) -> Result<String, ParseError> {
+    let mut word = String::new();
+    while let Some((position, c)) = chars.next_if(|&(_, c)| !is_end(c)) {
+        if c == '"' {
+            return Err(ParseError::UnexpectedQuote { position });
+        }
+        word.push(c);
+    }
+    Ok(word)
+}
+
+fn read_value(chars: &mut Chars<'_>) -> Result<String, ParseError> {
+    let mut value = String::new();
+    while let Some((position, c)) = chars.next_if(|(_, c)| !c.is_whitespace()) {
+        match c {
+            '"' => loop {
+                match chars.next() {
+                    None => return Err(ParseError::UnterminatedQuote { position }),
+                    Some((_, '"')) => break,
+                    Some((position, '\\')) => value.push(read_escape(chars, position)?),
+                    Some((_, c)) => value.push(c),
+                }
+            },
+            '\\' => value.push(read_escape(chars, position)?),
+            c => value.push(c),
+        }
+    }
+    Ok(value)
+}
+
+fn read_escape(chars: &mut Chars<'_>, position: usize) -> Result<char, ParseError> {
+    match chars.next() {
+        None => Err(ParseError::DanglingEscape { position }),
+        Some((_, 'n')) => Ok('\n'),
+        Some((_, 't')) => Ok('\t'),
+        Some((_, 'r')) => Ok('\r'),
+        Some((_, c @ ('\\' | '"' | ' ' | '='))) => Ok(c),
+        Some((_, found)) => Err(ParseError::UnknownEscape { position, found }),
+    }
+}
+
+#[cfg(test)]
+mod custom_parser_tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_params() {
+        let params =
+            parse_request_parameters("device_report room_name=Кухня device_name=Розетка1").unwrap();
+        assert_eq!(params.command(), "device_report");
+        assert_eq!(params.get("room_name"), Some("Кухня"));
+        assert_eq!(params.get("device_name"), Some("Розетка1"));
+        assert_eq!(params.get("power_state"), None);
+    }
+
+    #[test]
+    fn test_quotes_and_escapes() {
+        let params = parse_request_parameters(
+            r#"rename room_name="Living room" new_name=a\ b note="say \"hi\"\n" formula=x=1"#,
+        )
+        .unwrap();
+        assert_eq!(params.get("room_name"), Some("Living room"));
+        assert_eq!(params.get("new_name"), Some("a b"));
+        assert_eq!(params.get("note"), Some("say \"hi\"\n"));
+        assert_eq!(params.get("formula"), Some("x=1"));
+    }
+
+    #[test]
+    fn test_empty_and_repeated_values() {
+        let params = parse_request_parameters("cmd a= b=\"\" a=2").unwrap();
+        assert_eq!(params.get("a"), Some("2"));
+        assert_eq!(params.get_all("a"), ["", "2"]);
+        assert_eq!(params.get("b"), Some(""));
+    }
+
+    #[test]
+    fn test_typed_values() {
+        let params = parse_request_parameters("cmd delay=5 on=true threshold=2.5 bad=x").unwrap();
+        assert_eq!(params.get_u64("delay").unwrap(), Some(5));
+        assert_eq!(params.get_bool("on").unwrap(), Some(true));
+        assert_eq!(params.get_f32("threshold").unwrap(), Some(2.5));
+        assert_eq!(params.get_u64("missing").unwrap(), None);
+        assert_eq!(
+            params.get_bool("bad"),
+            Err(ParseError::BadValue {
+                key: "bad".to_string(),
+                value: "x".to_string(),
+                position: 38,
+                expected: "true or false",
+            })
+        );
+    }
+
+    #[test]
+    fn test_errors_have_positions() {
+        assert_eq!(
+            parse_request_parameters("   "),
+            Err(ParseError::EmptyRequest)
+        );
+        assert_eq!(
+            parse_request_parameters("cmd room_name"),
+            Err(ParseError::MissingSeparator { position: 4 })
+        );
+        assert_eq!(
+            parse_request_parameters("cmd =x"),
+            Err(ParseError::EmptyKey { position: 4 })
+        );
+        assert_eq!(
+            parse_request_parameters("cmd a=\"open"),
+            Err(ParseError::UnterminatedQuote { position: 6 })
+        );
+        assert_eq!(
+            parse_request_parameters("cmd a=\\q"),
+            Err(ParseError::UnknownEscape {
+                position: 6,
+                found: 'q'
+            })
+        );
+        assert_eq!(
+            parse_request_parameters("cmd a=x\\"),
+            Err(ParseError::DanglingEscape { position: 7 })
+        );
+        assert_eq!(
+            parse_request_parameters("cmd \"a\"=x"),
+            Err(ParseError::UnexpectedQuote { position: 4 })
+        );
+    }
+
+    #[test]
+    fn test_quote_round_trip() {
+        for value in ["Кухня", "Living room", "", "say \"hi\"\\", "x=1", "a\tb\n"] {
+            let line = format!("cmd value={}", quote(value));
+            let params = parse_request_parameters(&line).unwrap();
+            assert_eq!(params.get("value"), Some(value), "{line}");
+        }
+        assert_eq!(quote("Кухня"), "Кухня");
+    }
+}
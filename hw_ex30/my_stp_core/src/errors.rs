@@ -0,0 +1,179 @@
+#[derive(Debug, thiserror::Error)]
+pub enum ConnectError {
+    #[error("bad handshake")]
+    BadHandshake,
+    #[error("unsupported protocol version {0}")]
+    UnsupportedVersion(u16),
+    #[error("handshake timed out")]
+    HandshakeTimeout,
+    #[error("server requires authentication")]
+    AuthenticationRequired,
+    #[error("server rejected the credentials")]
+    AuthenticationRejected,
+    #[error("unknown client {0}")]
+    UnknownClient(String),
+    #[error("bad signature from client {0}")]
+    BadSignature(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl ConnectError {
+    /// Failures that may go away on their own, e.g. a server that is restarting.
+    /// Rejected credentials or an unsupported version are not worth another try
+    pub fn is_transient(&self) -> bool {
+        matches!(self, ConnectError::Io(_) | ConnectError::HandshakeTimeout)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum KeyFileError {
+    #[error("line {0} is not a client_id:key pair")]
+    BadLine(usize),
+    #[error("client {0} is listed twice")]
+    DuplicateClient(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RequestError {
+    #[error(transparent)]
+    Connect(#[from] ConnectError),
+    #[error("failed to send request: {0}")]
+    Send(#[from] SendError),
+    #[error("failed to receive response: {0}")]
+    Recv(#[from] RecvError),
+    #[error("connection closed by peer")]
+    Closed,
+    #[error("typed messages were not negotiated for this connection")]
+    EncodingNotNegotiated,
+    #[error("response id {received} does not match request id {expected}")]
+    UnexpectedResponseId { expected: u64, received: u64 },
+    #[error("streamed response failed: {0}")]
+    StreamFailed(String),
+    #[error("heartbeats were not negotiated for this connection")]
+    HeartbeatNotNegotiated,
+    #[error("no response before the deadline")]
+    DeadlineExceeded,
+    #[error("request cancelled")]
+    Cancelled,
+}
+
+impl RequestError {
+    /// The connection can't be used any more, a new one has to be opened
+    pub fn is_connection_lost(&self) -> bool {
+        match self {
+            RequestError::Connect(e) => e.is_transient(),
+            RequestError::Send(e) => matches!(e, SendError::Io(_) | SendError::Timeout),
+            RequestError::Recv(e) => matches!(
+                e,
+                RecvError::Io(_) | RecvError::Timeout | RecvError::ConnectionClosed
+            ),
+            RequestError::Closed => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum PushError {
+    /// The client reads slower than messages are pushed, this one is dropped.
+    /// The client sees a gap in sequence numbers
+    #[error("push queue is full, message dropped")]
+    QueueFull,
+    #[error("connection closed")]
+    Closed,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SendError {
+    #[error("failed to encode message: {0}")]
+    Encode(String),
+    #[error("frame of {size} bytes exceeds the limit of {max} bytes")]
+    FrameTooLarge { size: usize, max: u32 },
+    #[error("send timed out")]
+    Timeout,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RecvError {
+    #[error("bad encoding")]
+    BadEncoding,
+    #[error("bad message")]
+    BadMessage,
+    #[error("failed to decode message: {0}")]
+    Decode(String),
+    #[error("frame of {size} bytes exceeds the limit of {max} bytes")]
+    FrameTooLarge { size: u32, max: u32 },
+    #[error("receive timed out")]
+    Timeout,
+    #[error("connection closed in the middle of a frame")]
+    ConnectionClosed,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl From<RecvError> for ConnectError {
+    fn from(e: RecvError) -> Self {
+        match e {
+            RecvError::Timeout => ConnectError::HandshakeTimeout,
+            RecvError::Io(e) => ConnectError::Io(e),
+            RecvError::ConnectionClosed => {
+                ConnectError::Io(std::io::ErrorKind::UnexpectedEof.into())
+            }
+            _ => ConnectError::BadHandshake,
+        }
+    }
+}
+
+impl From<SendError> for ConnectError {
+    fn from(e: SendError) -> Self {
+        match e {
+            SendError::Timeout => ConnectError::HandshakeTimeout,
+            SendError::Io(e) => ConnectError::Io(e),
+            _ => ConnectError::BadHandshake,
+        }
+    }
+}
+
+/// Malformed request line. Positions are byte offsets into the line
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum ParseError {
+    #[error("empty request")]
+    EmptyRequest,
+    #[error("expected key=value at {position}")]
+    MissingSeparator { position: usize },
+    #[error("empty key at {position}")]
+    EmptyKey { position: usize },
+    #[error("quote outside of a value at {position}")]
+    UnexpectedQuote { position: usize },
+    #[error("quote opened at {position} is not closed")]
+    UnterminatedQuote { position: usize },
+    #[error("unknown escape sequence \\{found} at {position}")]
+    UnknownEscape { position: usize, found: char },
+    #[error("escape at the end of the request at {position}")]
+    DanglingEscape { position: usize },
+    #[error("bad value {value:?} of {key} at {position}, expected {expected}")]
+    BadValue {
+        key: String,
+        value: String,
+        position: usize,
+        expected: &'static str,
+    },
+}
+
+/// Request that does not match the parameters its command declares
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum SchemaError {
+    #[error("missing parameter {0}")]
+    MissingParam(String),
+    #[error("invalid parameter {name}={value:?}, expected {expected}")]
+    InvalidParam {
+        name: String,
+        value: String,
+        expected: String,
+    },
+}
@@ -0,0 +1,466 @@
+use crate::{
+    auth::{self, Credentials, KeyStore, NONCE_LEN, SIGNATURE_LEN},
+    errors::ConnectError,
+    protocol::{Features, Protocol},
+};
+
+/// Client greeting of v1 clients, the connection serves exactly one string request
+pub const SINGLE_REQUEST_HANDSHAKE: &[u8; 4] = b"clnt";
+/// Client greeting followed by the highest supported version and features
+pub const NEGOTIATE_HANDSHAKE: &[u8; 4] = b"stpv";
+pub const SERVER_HANDSHAKE: &[u8; 4] = b"serv";
+/// Last byte of the authentication exchange, sent by the server
+pub const AUTH_ACCEPTED: u8 = 1;
+pub const AUTH_REJECTED: u8 = 0;
+
+const GREETING_LEN: usize = 4;
+const PROTOCOL_LEN: usize = 6;
+
+/// Version and features as sent during the handshake
+fn encode_protocol(protocol: Protocol) -> [u8; PROTOCOL_LEN] {
+    let mut buf = [0u8; PROTOCOL_LEN];
+    buf[..2].copy_from_slice(&protocol.version.to_be_bytes());
+    buf[2..].copy_from_slice(&protocol.features.bits().to_be_bytes());
+    buf
+}
+
+fn decode_protocol(buf: &[u8]) -> Protocol {
+    Protocol {
+        version: u16::from_be_bytes([buf[0], buf[1]]),
+        features: Features::from_bits(u32::from_be_bytes([buf[2], buf[3], buf[4], buf[5]])),
+    }
+}
+
+/// One side of the handshake without any I/O.
+///
+/// The transport writes [`Handshake::take_output`], reads exactly
+/// [`Handshake::bytes_needed`] bytes and passes them to [`Handshake::receive`]
+/// until no more bytes are needed. Output left after an error, e.g. the
+/// rejection of bad credentials, should still be written before giving up
+pub trait Handshake {
+    /// Bytes to send to the peer, empty if there is nothing to send
+    fn take_output(&mut self) -> Vec<u8>;
+    /// Bytes the next step waits for, 0 once the handshake is over
+    fn bytes_needed(&self) -> usize;
+    /// Feed exactly [`Handshake::bytes_needed`] bytes
+    fn receive(&mut self, data: &[u8]) -> Result<(), ConnectError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ServerState {
+    Greeting,
+    Version,
+    ClientIdLen,
+    ClientId { len: usize },
+    Done,
+}
+
+/// Server side: answers the greeting, negotiates the protocol
+/// and checks the client signature when keys are set
+pub struct ServerHandshake<'a> {
+    protocol: Protocol,
+    key_store: Option<&'a KeyStore>,
+    state: ServerState,
+    output: Vec<u8>,
+    negotiated: Option<Protocol>,
+    nonce: [u8; NONCE_LEN],
+    identity: Option<String>,
+}
+
+impl<'a> ServerHandshake<'a> {
+    /// `protocol` is the highest version and the features the server offers
+    pub fn new(protocol: Protocol, key_store: Option<&'a KeyStore>) -> Self {
+        Self {
+            protocol,
+            key_store,
+            state: ServerState::Greeting,
+            output: Vec::new(),
+            negotiated: None,
+            nonce: [0u8; NONCE_LEN],
+            identity: None,
+        }
+    }
+
+    /// Negotiated protocol and the id of the authenticated client.
+    /// `None` until the handshake is over
+    pub fn outcome(&self) -> Option<(Protocol, Option<&str>)> {
+        if self.state != ServerState::Done {
+            return None;
+        }
+        Some((self.negotiated?, self.identity.as_deref()))
+    }
+
+    fn receive_greeting(&mut self, greeting: &[u8]) -> Result<(), ConnectError> {
+        if greeting == NEGOTIATE_HANDSHAKE {
+            self.state = ServerState::Version;
+            return Ok(());
+        }
+        if greeting != SINGLE_REQUEST_HANDSHAKE {
+            return Err(ConnectError::BadHandshake);
+        }
+        // v1 clients can't authenticate
+        if self.key_store.is_some() {
+            return Err(ConnectError::AuthenticationRequired);
+        }
+        self.output.extend_from_slice(SERVER_HANDSHAKE);
+        self.negotiated = Some(Protocol::v1());
+        self.state = ServerState::Done;
+        Ok(())
+    }
+
+    fn receive_version(&mut self, data: &[u8]) -> Result<(), ConnectError> {
+        let requested = decode_protocol(data);
+        let negotiated = Protocol::negotiate(self.protocol, requested);
+        // Version 0 tells the client there is no common version
+        let mut reply = negotiated.unwrap_or(Protocol {
+            version: 0,
+            features: Features::NONE,
+        });
+        if negotiated.is_some() && self.key_store.is_some() {
+            reply.features = reply.features | Features::AUTH;
+        }
+        self.output.extend_from_slice(SERVER_HANDSHAKE);
+        self.output.extend_from_slice(&encode_protocol(reply));
+        self.negotiated =
+            Some(negotiated.ok_or(ConnectError::UnsupportedVersion(requested.version))?);
+
+        if self.key_store.is_some() {
+            // Challenge-response: the client signs a fresh nonce with its key
+            self.nonce = auth::new_nonce();
+            self.output.extend_from_slice(&self.nonce);
+            self.state = ServerState::ClientIdLen;
+        } else {
+            self.state = ServerState::Done;
+        }
+        Ok(())
+    }
+
+    fn receive_client_id(&mut self, data: &[u8]) -> Result<(), ConnectError> {
+        let (client_id, signature) = data.split_at(data.len() - SIGNATURE_LEN);
+        let client_id =
+            String::from_utf8(client_id.to_vec()).map_err(|_| ConnectError::BadHandshake)?;
+        let key_store = self.key_store.ok_or(ConnectError::BadHandshake)?;
+
+        // The client gets the same answer for unknown ids and wrong keys
+        let result = match key_store.key(&client_id) {
+            None => Err(ConnectError::UnknownClient(client_id)),
+            Some(key) if !auth::verify(key, &self.nonce, &client_id, signature) => {
+                Err(ConnectError::BadSignature(client_id))
+            }
+            Some(_) => Ok(client_id),
+        };
+        match result {
+            Ok(client_id) => {
+                self.output.push(AUTH_ACCEPTED);
+                self.identity = Some(client_id);
+                self.state = ServerState::Done;
+                Ok(())
+            }
+            Err(e) => {
+                self.output.push(AUTH_REJECTED);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Handshake for ServerHandshake<'_> {
+    fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    fn bytes_needed(&self) -> usize {
+        match self.state {
+            ServerState::Greeting => GREETING_LEN,
+            ServerState::Version => PROTOCOL_LEN,
+            ServerState::ClientIdLen => 2,
+            ServerState::ClientId { len } => len + SIGNATURE_LEN,
+            ServerState::Done => 0,
+        }
+    }
+
+    fn receive(&mut self, data: &[u8]) -> Result<(), ConnectError> {
+        if data.len() != self.bytes_needed() {
+            return Err(ConnectError::BadHandshake);
+        }
+        match self.state {
+            ServerState::Greeting => self.receive_greeting(data),
+            ServerState::Version => self.receive_version(data),
+            ServerState::ClientIdLen => {
+                let len = u16::from_be_bytes([data[0], data[1]]) as usize;
+                self.state = ServerState::ClientId { len };
+                Ok(())
+            }
+            ServerState::ClientId { .. } => self.receive_client_id(data),
+            ServerState::Done => Err(ConnectError::BadHandshake),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ClientState {
+    Greeting,
+    Reply,
+    Nonce,
+    AuthReply,
+    Done,
+}
+
+/// Client side: offers a protocol and signs the server nonce if asked to
+#[derive(Debug)]
+pub struct ClientHandshake<'a> {
+    offered: Protocol,
+    credentials: Option<&'a Credentials>,
+    state: ClientState,
+    output: Vec<u8>,
+    negotiated: Option<Protocol>,
+}
+
+impl<'a> ClientHandshake<'a> {
+    /// Open a session offering at most `offered` to the server
+    pub fn new(offered: Protocol, credentials: Option<&'a Credentials>) -> Self {
+        let mut output = NEGOTIATE_HANDSHAKE.to_vec();
+        output.extend_from_slice(&encode_protocol(offered));
+        Self {
+            offered,
+            credentials,
+            state: ClientState::Greeting,
+            output,
+            negotiated: None,
+        }
+    }
+
+    /// Version and features both sides support, `None` until the handshake is over
+    pub fn outcome(&self) -> Option<Protocol> {
+        if self.state != ClientState::Done {
+            return None;
+        }
+        self.negotiated
+    }
+
+    fn receive_reply(&mut self, data: &[u8]) -> Result<(), ConnectError> {
+        let reply = decode_protocol(data);
+        let negotiated = Protocol {
+            version: reply.version,
+            features: reply.features.difference(Features::AUTH),
+        };
+        if Protocol::negotiate(self.offered, negotiated) != Some(negotiated) {
+            return Err(ConnectError::UnsupportedVersion(negotiated.version));
+        }
+        self.negotiated = Some(negotiated);
+        self.state = if reply.features.contains(Features::AUTH) {
+            if self.credentials.is_none() {
+                return Err(ConnectError::AuthenticationRequired);
+            }
+            ClientState::Nonce
+        } else {
+            ClientState::Done
+        };
+        Ok(())
+    }
+
+    /// Sign the server nonce with the client key
+    fn receive_nonce(&mut self, nonce: &[u8]) -> Result<(), ConnectError> {
+        let credentials = self
+            .credentials
+            .ok_or(ConnectError::AuthenticationRequired)?;
+        let client_id = credentials.client_id.as_bytes();
+        let client_id_len =
+            u16::try_from(client_id.len()).map_err(|_| ConnectError::AuthenticationRejected)?;
+        let signature = auth::sign(&credentials.key, nonce, &credentials.client_id);
+
+        self.output.extend_from_slice(&client_id_len.to_be_bytes());
+        self.output.extend_from_slice(client_id);
+        self.output.extend_from_slice(&signature);
+        self.state = ClientState::AuthReply;
+        Ok(())
+    }
+}
+
+impl Handshake for ClientHandshake<'_> {
+    fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    fn bytes_needed(&self) -> usize {
+        match self.state {
+            ClientState::Greeting => GREETING_LEN,
+            ClientState::Reply => PROTOCOL_LEN,
+            ClientState::Nonce => NONCE_LEN,
+            ClientState::AuthReply => 1,
+            ClientState::Done => 0,
+        }
+    }
+
+    fn receive(&mut self, data: &[u8]) -> Result<(), ConnectError> {
+        if data.len() != self.bytes_needed() {
+            return Err(ConnectError::BadHandshake);
+        }
+        match self.state {
+            ClientState::Greeting if data == SERVER_HANDSHAKE => {
+                self.state = ClientState::Reply;
+                Ok(())
+            }
+            ClientState::Greeting => Err(ConnectError::BadHandshake),
+            ClientState::Reply => self.receive_reply(data),
+            ClientState::Nonce => self.receive_nonce(data),
+            ClientState::AuthReply if data[0] == AUTH_ACCEPTED => {
+                self.state = ClientState::Done;
+                Ok(())
+            }
+            ClientState::AuthReply => Err(ConnectError::AuthenticationRejected),
+            ClientState::Done => Err(ConnectError::BadHandshake),
+        }
+    }
+}
+
+#[cfg(test)]
+mod handshake_tests {
+    use super::*;
+    use crate::protocol::{V1, V2};
+
+    /// Pass bytes between both sides until neither has anything left to say
+    fn run(
+        client: &mut ClientHandshake,
+        server: &mut ServerHandshake,
+    ) -> (Result<(), ConnectError>, Result<(), ConnectError>) {
+        let mut to_server = Vec::new();
+        let mut to_client = Vec::new();
+        loop {
+            to_server.extend(client.take_output());
+            let server_needed = server.bytes_needed();
+            if server_needed > 0 && to_server.len() >= server_needed {
+                let data: Vec<u8> = to_server.drain(..server_needed).collect();
+                if let Err(e) = server.receive(&data) {
+                    to_client.extend(server.take_output());
+                    return (feed(client, &to_client), Err(e));
+                }
+                continue;
+            }
+            to_client.extend(server.take_output());
+            let client_needed = client.bytes_needed();
+            if client_needed > 0 && to_client.len() >= client_needed {
+                let data: Vec<u8> = to_client.drain(..client_needed).collect();
+                if let Err(e) = client.receive(&data) {
+                    return (Err(e), Ok(()));
+                }
+                continue;
+            }
+            return (Ok(()), Ok(()));
+        }
+    }
+
+    fn feed(client: &mut ClientHandshake, mut data: &[u8]) -> Result<(), ConnectError> {
+        while client.bytes_needed() > 0 && data.len() >= client.bytes_needed() {
+            let (step, rest) = data.split_at(client.bytes_needed());
+            client.receive(step)?;
+            data = rest;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn negotiates_common_features() {
+        let mut client = ClientHandshake::new(
+            Protocol {
+                version: V2,
+                features: Features::JSON | Features::PUSH,
+            },
+            None,
+        );
+        let mut server = ServerHandshake::new(Protocol::default(), None);
+        let (client_result, server_result) = run(&mut client, &mut server);
+        client_result.unwrap();
+        server_result.unwrap();
+        let expected = Protocol {
+            version: V2,
+            features: Features::JSON | Features::PUSH,
+        };
+        assert_eq!(client.outcome(), Some(expected));
+        assert_eq!(server.outcome(), Some((expected, None)));
+    }
+
+    #[test]
+    fn v1_greeting_is_answered() {
+        let mut server = ServerHandshake::new(Protocol::default(), None);
+        server.receive(SINGLE_REQUEST_HANDSHAKE).unwrap();
+        assert_eq!(server.take_output(), SERVER_HANDSHAKE);
+        assert_eq!(server.bytes_needed(), 0);
+        let (protocol, _) = server.outcome().unwrap();
+        assert_eq!(protocol.version, V1);
+    }
+
+    #[test]
+    fn bad_greeting_is_rejected() {
+        let mut server = ServerHandshake::new(Protocol::default(), None);
+        assert!(matches!(
+            server.receive(b"http"),
+            Err(ConnectError::BadHandshake)
+        ));
+        assert!(matches!(
+            server.receive(b"stp"),
+            Err(ConnectError::BadHandshake)
+        ));
+    }
+
+    #[test]
+    fn authenticated_client_is_identified() {
+        let key_store = KeyStore::parse("alice:secret").unwrap();
+        let credentials = Credentials::new("alice", b"secret");
+        let mut client = ClientHandshake::new(Protocol::default(), Some(&credentials));
+        let mut server = ServerHandshake::new(Protocol::default(), Some(&key_store));
+        let (client_result, server_result) = run(&mut client, &mut server);
+        client_result.unwrap();
+        server_result.unwrap();
+        assert_eq!(server.outcome().unwrap().1, Some("alice"));
+        assert!(client.outcome().is_some());
+    }
+
+    #[test]
+    fn wrong_key_is_rejected_on_both_sides() {
+        let key_store = KeyStore::parse("alice:secret").unwrap();
+        let credentials = Credentials::new("alice", b"guess");
+        let mut client = ClientHandshake::new(Protocol::default(), Some(&credentials));
+        let mut server = ServerHandshake::new(Protocol::default(), Some(&key_store));
+        let (client_result, server_result) = run(&mut client, &mut server);
+        assert!(matches!(
+            client_result,
+            Err(ConnectError::AuthenticationRejected)
+        ));
+        assert!(matches!(server_result, Err(ConnectError::BadSignature(id)) if id == "alice"));
+        assert!(server.outcome().is_none());
+    }
+
+    #[test]
+    fn client_without_credentials_stops_at_auth() {
+        let key_store = KeyStore::parse("alice:secret").unwrap();
+        let mut client = ClientHandshake::new(Protocol::default(), None);
+        let mut server = ServerHandshake::new(Protocol::default(), Some(&key_store));
+        let (client_result, _) = run(&mut client, &mut server);
+        assert!(matches!(
+            client_result,
+            Err(ConnectError::AuthenticationRequired)
+        ));
+    }
+
+    #[test]
+    fn no_common_version() {
+        let mut client = ClientHandshake::new(
+            Protocol {
+                version: 0,
+                features: Features::NONE,
+            },
+            None,
+        );
+        let mut server = ServerHandshake::new(Protocol::default(), None);
+        let (client_result, server_result) = run(&mut client, &mut server);
+        assert!(matches!(
+            server_result,
+            Err(ConnectError::UnsupportedVersion(0))
+        ));
+        assert!(matches!(
+            client_result,
+            Err(ConnectError::UnsupportedVersion(0))
+        ));
+    }
+}
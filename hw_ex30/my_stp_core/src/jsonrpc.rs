@@ -0,0 +1,302 @@
+//! JSON-RPC 2.0 requests carried in text frames, next to `command key=value` lines.
+//! A call is turned into a [`Request::Command`] with its named params,
+//! the server answers it like any other command
+
+use std::future::Future;
+
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::message::Request;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+/// Codes from here down to -32099 are left to the server for its own errors
+pub const SERVER_ERROR: i64 = -32000;
+
+/// Error object of a response
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+    /// Name of the failure, e.g. `CantFindRoom`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+}
+
+impl JsonRpcError {
+    pub fn new(code: i64, message: &str) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+            data: None,
+        }
+    }
+
+    pub fn with_data(mut self, data: &str) -> Self {
+        self.data = Some(data.to_string());
+        self
+    }
+}
+
+/// Text frames starting with an object or an array are JSON-RPC, anything else is a command line
+pub fn is_json_rpc(frame: &str) -> bool {
+    matches!(frame.trim_start().chars().next(), Some('{' | '['))
+}
+
+/// Answer a single call or a batch with `call`, in order. Returns the response frame,
+/// `None` if there is nothing to answer because every call was a notification
+pub fn handle_frame<F>(frame: &str, mut call: F) -> Option<String>
+where
+    F: FnMut(Request) -> Result<String, JsonRpcError>,
+{
+    let (calls, batch) = match read_frame(frame) {
+        Ok(calls) => calls,
+        Err(response) => return Some(response),
+    };
+    let responses = calls.into_iter().map(|value| match read_call(value) {
+        Call::Invalid(response) => Some(response),
+        Call::Valid { id, request } => respond(id, request.and_then(&mut call)),
+    });
+    write_frame(responses.collect(), batch)
+}
+
+/// [`handle_frame`] for a `call` that has to be awaited, calls of a batch are still answered in order
+pub async fn handle_frame_async<F, Fut>(frame: &str, mut call: F) -> Option<String>
+where
+    F: FnMut(Request) -> Fut,
+    Fut: Future<Output = Result<String, JsonRpcError>>,
+{
+    let (calls, batch) = match read_frame(frame) {
+        Ok(calls) => calls,
+        Err(response) => return Some(response),
+    };
+    let mut responses = Vec::with_capacity(calls.len());
+    for value in calls {
+        responses.push(match read_call(value) {
+            Call::Invalid(response) => Some(response),
+            Call::Valid { id, request } => {
+                let result = match request {
+                    Ok(request) => call(request).await,
+                    Err(error) => Err(error),
+                };
+                respond(id, result)
+            }
+        });
+    }
+    write_frame(responses, batch)
+}
+
+/// Calls of the frame and whether they came in a batch.
+/// A frame that is not JSON or an empty batch is answered right away
+fn read_frame(frame: &str) -> Result<(Vec<Value>, bool), String> {
+    match serde_json::from_str(frame) {
+        Ok(Value::Array(calls)) if calls.is_empty() => {
+            let error = JsonRpcError::new(INVALID_REQUEST, "Invalid Request");
+            Err(error_response(Value::Null, error).to_string())
+        }
+        Ok(Value::Array(calls)) => Ok((calls, true)),
+        Ok(value) => Ok((vec![value], false)),
+        Err(_) => {
+            let error = JsonRpcError::new(PARSE_ERROR, "Parse error");
+            Err(error_response(Value::Null, error).to_string())
+        }
+    }
+}
+
+/// Responses of a batch go into an array, the response of a single call is sent as it is
+fn write_frame(responses: Vec<Option<Value>>, batch: bool) -> Option<String> {
+    let mut responses: Vec<Value> = responses.into_iter().flatten().collect();
+    if batch {
+        (!responses.is_empty()).then(|| Value::Array(responses).to_string())
+    } else {
+        responses.pop().map(|response| response.to_string())
+    }
+}
+
+/// A call of a frame before it is answered
+enum Call {
+    /// Not a call object or a bad id, answered with this response
+    Invalid(Value),
+    /// `id` is `None` for a notification, it is carried out but never answered
+    Valid {
+        id: Option<Value>,
+        request: Result<Request, JsonRpcError>,
+    },
+}
+
+fn read_call(value: Value) -> Call {
+    let Value::Object(mut object) = value else {
+        let error = JsonRpcError::new(INVALID_REQUEST, "Invalid Request");
+        return Call::Invalid(error_response(Value::Null, error));
+    };
+    let id = object.remove("id");
+    if id
+        .as_ref()
+        .is_some_and(|id| !matches!(id, Value::Null | Value::Number(_) | Value::String(_)))
+    {
+        let error = JsonRpcError::new(INVALID_REQUEST, "Invalid Request");
+        return Call::Invalid(error_response(Value::Null, error));
+    }
+    Call::Valid {
+        id,
+        request: parse_call(object),
+    }
+}
+
+/// Response of one call, `None` for a notification
+fn respond(id: Option<Value>, result: Result<String, JsonRpcError>) -> Option<Value> {
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+        Err(error) => error_response(id, error),
+    })
+}
+
+fn parse_call(mut object: Map<String, Value>) -> Result<Request, JsonRpcError> {
+    if object.get("jsonrpc") != Some(&json!("2.0")) {
+        return Err(JsonRpcError::new(INVALID_REQUEST, "Invalid Request"));
+    }
+    let Some(Value::String(method)) = object.remove("method") else {
+        return Err(JsonRpcError::new(INVALID_REQUEST, "Invalid Request"));
+    };
+    if method.is_empty() || method.contains(char::is_whitespace) {
+        return Err(JsonRpcError::new(METHOD_NOT_FOUND, "Method not found"));
+    }
+    let params = match object.remove("params") {
+        None => Map::new(),
+        Some(Value::Object(params)) => params,
+        Some(Value::Array(params)) if params.is_empty() => Map::new(),
+        Some(Value::Array(_)) => {
+            return Err(JsonRpcError::new(INVALID_PARAMS, "Params must be named"));
+        }
+        Some(_) => return Err(JsonRpcError::new(INVALID_REQUEST, "Invalid Request")),
+    };
+    let mut request = Request::command(&method);
+    for (key, value) in params {
+        request = request.param(&key, param_value(&key, value)?);
+    }
+    Ok(request)
+}
+
+/// Params are passed on as command parameter strings, `null` becomes `none`
+fn param_value(key: &str, value: Value) -> Result<String, JsonRpcError> {
+    match value {
+        Value::String(value) => Ok(value),
+        Value::Number(value) => Ok(value.to_string()),
+        Value::Bool(value) => Ok(value.to_string()),
+        Value::Null => Ok("none".to_string()),
+        Value::Array(_) | Value::Object(_) => Err(JsonRpcError::new(
+            INVALID_PARAMS,
+            &format!("Param {key} must be a string, a number, a boolean or null"),
+        )),
+    }
+}
+
+fn error_response(id: Value, error: JsonRpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "error": error, "id": id })
+}
+
+#[cfg(test)]
+mod jsonrpc_tests {
+    use super::*;
+
+    /// Echoes the command line, `fail` fails with a server error
+    fn echo(request: Request) -> Result<String, JsonRpcError> {
+        match request.into_command_line() {
+            line if line == "fail" => {
+                Err(JsonRpcError::new(SERVER_ERROR, "failed").with_data("Fail"))
+            }
+            line => Ok(line),
+        }
+    }
+
+    fn handle(frame: &str) -> Option<Value> {
+        handle_frame(frame, echo).map(|response| serde_json::from_str(&response).unwrap())
+    }
+
+    #[test]
+    fn frames_are_told_apart_by_their_first_character() {
+        assert!(is_json_rpc(r#" {"jsonrpc":"2.0"}"#));
+        assert!(is_json_rpc("[]"));
+        assert!(!is_json_rpc("rooms_list"));
+        assert!(!is_json_rpc(""));
+    }
+
+    #[test]
+    fn named_params_become_command_parameters() {
+        let response = handle(
+            r#"{"jsonrpc":"2.0","method":"set_device_power_state",
+                "params":{"room_name":"Кухня","device_name":"Розетка 1","power_state":true},"id":7}"#,
+        );
+        assert_eq!(
+            response,
+            Some(json!({
+                "jsonrpc": "2.0",
+                "result": "set_device_power_state device_name=\"Розетка 1\" power_state=true room_name=Кухня",
+                "id": 7
+            }))
+        );
+    }
+
+    #[test]
+    fn errors_carry_the_code_and_the_id() {
+        assert_eq!(
+            handle(r#"{"jsonrpc":"2.0","method":"fail","id":"a"}"#),
+            Some(json!({
+                "jsonrpc": "2.0",
+                "error": { "code": SERVER_ERROR, "message": "failed", "data": "Fail" },
+                "id": "a"
+            }))
+        );
+        assert_eq!(
+            handle(r#"{"jsonrpc":"2.0","method":"rooms_list","params":[1],"id":1}"#).unwrap()
+                ["error"]["code"],
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            handle(r#"{"jsonrpc":"1.0","method":"rooms_list","id":1}"#).unwrap()["error"]["code"],
+            INVALID_REQUEST
+        );
+        assert_eq!(
+            handle(r#"{"jsonrpc":"2.0","method":"#).unwrap(),
+            json!({
+                "jsonrpc": "2.0",
+                "error": { "code": PARSE_ERROR, "message": "Parse error" },
+                "id": null
+            })
+        );
+    }
+
+    #[test]
+    fn batches_skip_notifications() {
+        let mut calls = Vec::new();
+        let response = handle_frame(
+            r#"[{"jsonrpc":"2.0","method":"hello","id":1},
+                {"jsonrpc":"2.0","method":"rooms_list"},
+                42,
+                {"jsonrpc":"2.0","method":"fail","id":2}]"#,
+            |request| {
+                calls.push(request.clone().into_command_line());
+                echo(request)
+            },
+        );
+        let response: Value = serde_json::from_str(&response.unwrap()).unwrap();
+        assert_eq!(calls, ["hello", "rooms_list", "fail"]);
+        let responses = response.as_array().unwrap();
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0]["result"], "hello");
+        assert_eq!(responses[1]["error"]["code"], INVALID_REQUEST);
+        assert_eq!(responses[2]["id"], 2);
+    }
+
+    #[test]
+    fn notifications_alone_are_not_answered() {
+        assert_eq!(handle(r#"{"jsonrpc":"2.0","method":"hello"}"#), None);
+        assert_eq!(handle(r#"[{"jsonrpc":"2.0","method":"hello"}]"#), None);
+        assert_eq!(handle("[]").unwrap()["error"]["code"], INVALID_REQUEST);
+    }
+}
@@ -0,0 +1,18 @@
+//! Protocol core shared by the std and tokio transports of STP.
+//! Apart from preparing Unix socket files nothing here touches a socket:
+//! the handshake is a state machine fed with bytes and frames are encoded into
+//! and decoded from byte buffers
+
+pub mod auth;
+pub mod codec;
+pub mod command_schema;
+pub mod custom_parser;
+pub mod errors;
+pub mod handshake;
+pub mod jsonrpc;
+pub mod limits;
+pub mod message;
+pub mod protocol;
+pub mod reconnect;
+#[cfg(unix)]
+pub mod socket_file;
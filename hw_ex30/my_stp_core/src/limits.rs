@@ -0,0 +1,27 @@
+use std::time::Duration;
+
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16 * 1024 * 1024;
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Protection against peers that send garbage or stop sending in the middle of a message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Frames with a larger length prefix are rejected before anything is allocated
+    pub max_frame_size: u32,
+    /// Deadline for the whole handshake
+    pub handshake_timeout: Option<Duration>,
+    /// Deadline for sending or receiving one frame once it has started.
+    /// Clients also use it as the deadline for a response
+    pub io_timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            handshake_timeout: Some(DEFAULT_HANDSHAKE_TIMEOUT),
+            io_timeout: Some(DEFAULT_IO_TIMEOUT),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::fmt::Display;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{custom_parser::quote, protocol::Features};
+
+/// Encoding of typed messages inside frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Json,
+    Binary,
+}
+
+impl Encoding {
+    /// Most compact encoding supported by the negotiated features
+    pub fn preferred(features: Features) -> Option<Self> {
+        if features.contains(Features::BINARY) {
+            Some(Encoding::Binary)
+        } else if features.contains(Features::JSON) {
+            Some(Encoding::Json)
+        } else {
+            None
+        }
+    }
+
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Encoding::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            Encoding::Binary => bincode::serialize(value).map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, String> {
+        match self {
+            Encoding::Json => serde_json::from_slice(data).map_err(|e| e.to_string()),
+            Encoding::Binary => bincode::deserialize(data).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Request {
+    /// Ad-hoc command string, the only request a v1 client can send
+    Text(String),
+    /// Command with named parameters
+    Command {
+        name: String,
+        params: Vec<(String, String)>,
+    },
+}
+
+impl Request {
+    pub fn command(name: &str) -> Self {
+        Request::Command {
+            name: name.to_string(),
+            params: vec![],
+        }
+    }
+
+    /// Add a parameter to a command, text requests are left unchanged
+    pub fn param<T: ToString>(mut self, key: &str, value: T) -> Self {
+        if let Request::Command { params, .. } = &mut self {
+            params.push((key.to_string(), value.to_string()));
+        }
+        self
+    }
+
+    /// Request as a command line: `name key=value key="quoted value"`
+    pub fn into_command_line(self) -> String {
+        match self {
+            Request::Text(text) => text,
+            Request::Command { name, params } => {
+                let mut line = name;
+                for (key, value) in params {
+                    line.push_str(&format!(" {key}={}", quote(&value)));
+                }
+                line
+            }
+        }
+    }
+}
+
+/// Code of the error response a failed stream turns into
+/// for clients that have not negotiated [`Features::STREAM`]
+pub const STREAM_FAILED_CODE: &str = "StreamFailed";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Response {
+    Ok(String),
+    Error { code: String, message: String },
+}
+
+impl Response {
+    /// Response as a plain string for v1 clients
+    pub fn into_text(self) -> String {
+        match self {
+            Response::Ok(text) => text,
+            Response::Error { message, .. } => message,
+        }
+    }
+}
+
+impl Display for Response {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Response::Ok(text) => write!(f, "{text}"),
+            Response::Error { code, message } => write!(f, "{code}: {message}"),
+        }
+    }
+}
+
+/// Message the server sends on its own, e.g. a report of a subscribed stream.
+/// Sequence numbers grow by one for every push of the connection
+#[derive(Debug, Clone, PartialEq)]
+pub struct Push {
+    pub seq: u64,
+    pub topic: String,
+    pub body: String,
+}
+
+/// How a streamed response ended
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamStatus {
+    /// Every chunk has been sent
+    Complete,
+    /// The server could not produce the rest of the response
+    Failed(String),
+    /// The client cancelled the stream, chunks may be missing
+    Cancelled,
+}
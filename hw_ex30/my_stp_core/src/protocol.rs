@@ -0,0 +1,104 @@
+use std::ops::BitOr;
+
+/// Single request per connection, plain string payloads
+pub const V1: u16 = 1;
+/// Sessions with request ids, close message and typed messages
+pub const V2: u16 = 2;
+pub const LATEST_VERSION: u16 = V2;
+
+/// Optional capabilities negotiated during the handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Features(u32);
+
+impl Features {
+    pub const NONE: Features = Features(0);
+    /// Typed messages encoded as JSON
+    pub const JSON: Features = Features(1);
+    /// Typed messages in the compact binary encoding
+    pub const BINARY: Features = Features(1 << 1);
+    /// Set by the server in its reply when clients have to authenticate.
+    /// It is not negotiated, so it is never part of [`Features::all`]
+    pub const AUTH: Features = Features(1 << 2);
+    /// Server initiated push messages inside the session
+    pub const PUSH: Features = Features(1 << 3);
+    /// Responses split into chunk frames followed by an end marker
+    pub const STREAM: Features = Features(1 << 4);
+    /// Heartbeat frames the server answers, used to detect dead connections
+    pub const HEARTBEAT: Features = Features(1 << 5);
+
+    pub fn all() -> Self {
+        Self::JSON | Self::BINARY | Self::PUSH | Self::STREAM | Self::HEARTBEAT
+    }
+
+    pub fn from_bits(bits: u32) -> Self {
+        Self(bits & (Self::all() | Self::AUTH).0)
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    pub fn contains(&self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersection(&self, other: Features) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    pub fn difference(&self, other: Features) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+impl BitOr for Features {
+    type Output = Features;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Features(self.0 | rhs.0)
+    }
+}
+
+/// Protocol agreed on by both sides of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Protocol {
+    pub version: u16,
+    pub features: Features,
+}
+
+impl Protocol {
+    pub fn v1() -> Self {
+        Self {
+            version: V1,
+            features: Features::NONE,
+        }
+    }
+
+    /// Negotiate the highest version and the common features of both sides.
+    /// Features need sessions, so v1 never has any.
+    /// Returns `None` if there is no common version
+    pub fn negotiate(local: Protocol, remote: Protocol) -> Option<Self> {
+        let version = local.version.min(remote.version);
+        if version < V1 {
+            return None;
+        }
+        let features = match version {
+            V1 => Features::NONE,
+            _ => local.features.intersection(remote.features),
+        };
+        Some(Self { version, features })
+    }
+
+    pub fn is_session(&self) -> bool {
+        self.version >= V2
+    }
+}
+
+impl Default for Protocol {
+    fn default() -> Self {
+        Self {
+            version: LATEST_VERSION,
+            features: Features::all(),
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use std::{collections::HashSet, time::Duration};
+
+use crate::message::Request;
+
+pub const DEFAULT_INITIAL_DELAY: Duration = Duration::from_millis(100);
+pub const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(10);
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// Exponential delays between reconnect attempts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    /// Delay before the second attempt, the first one is made right away
+    pub initial: Duration,
+    /// Delays stop growing here
+    pub max: Duration,
+    pub multiplier: f64,
+    /// Share of the delay taken off at random, from `0.0` to `1.0`,
+    /// so clients that lost the same server don't come back all at once
+    pub jitter: f64,
+}
+
+impl Backoff {
+    /// Delay before attempt number `attempt`, counted from 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        if attempt <= 1 {
+            return Duration::ZERO;
+        }
+        let factor = self.multiplier.powi(attempt as i32 - 2);
+        self.initial.mul_f64(factor).min(self.max)
+    }
+
+    /// [`Backoff::delay`] shortened by the jitter share of `random`, which is in `0.0..1.0`
+    pub fn jittered(&self, attempt: u32, random: f64) -> Duration {
+        let jitter = self.jitter.clamp(0.0, 1.0) * random.clamp(0.0, 1.0);
+        self.delay(attempt).mul_f64(1.0 - jitter)
+    }
+
+    /// [`Backoff::jittered`] with a random jitter
+    pub fn next_delay(&self, attempt: u32) -> Duration {
+        self.jittered(attempt, rand::random())
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: DEFAULT_INITIAL_DELAY,
+            max: DEFAULT_MAX_DELAY,
+            multiplier: 2.0,
+            jitter: 0.5,
+        }
+    }
+}
+
+/// How a reconnecting client keeps its session alive
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    pub backoff: Backoff,
+    /// Attempts before the client gives up until the next request, `None` never gives up
+    pub max_attempts: Option<u32>,
+    /// Idle sessions are checked this often, `None` turns heartbeats off.
+    /// A reply has to arrive within the I/O timeout of the connection limits
+    pub heartbeat_interval: Option<Duration>,
+    /// How many times a request that is safe to repeat is sent again after the connection was lost
+    pub max_retries: u32,
+    /// Names of the commands that are safe to repeat, e.g. `rooms_list`
+    pub idempotent: HashSet<String>,
+}
+
+impl ReconnectPolicy {
+    /// Commands with these names are sent again after the connection was lost
+    pub fn idempotent<I, S>(mut self, commands: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.idempotent.extend(commands.into_iter().map(Into::into));
+        self
+    }
+
+    /// Command lines are matched by their first word
+    pub fn is_idempotent(&self, request: &str) -> bool {
+        request
+            .split_whitespace()
+            .next()
+            .is_some_and(|name| self.idempotent.contains(name))
+    }
+
+    pub fn is_idempotent_request(&self, request: &Request) -> bool {
+        match request {
+            Request::Text(text) => self.is_idempotent(text),
+            Request::Command { name, .. } => self.idempotent.contains(name),
+        }
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            backoff: Backoff::default(),
+            max_attempts: None,
+            heartbeat_interval: Some(DEFAULT_HEARTBEAT_INTERVAL),
+            max_retries: DEFAULT_MAX_RETRIES,
+            idempotent: HashSet::new(),
+        }
+    }
+}
+
+/// Reported to the state listener of a reconnecting client whenever it changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// A session is open
+    Connected,
+    /// The session was lost, `attempt` is the number of the next try, counted from 1
+    Reconnecting { attempt: u32 },
+    /// No session, the client has given up or was never connected.
+    /// The next request starts over
+    Disconnected,
+}
+
+#[cfg(test)]
+mod reconnect_tests {
+    use super::*;
+
+    fn backoff() -> Backoff {
+        Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+            multiplier: 2.0,
+            jitter: 0.5,
+        }
+    }
+
+    #[test]
+    fn delays_grow_up_to_the_limit() {
+        let delays: Vec<_> = (1..=7).map(|attempt| backoff().delay(attempt)).collect();
+        let millis = [0, 100, 200, 400, 800, 1000, 1000].map(Duration::from_millis);
+        assert_eq!(delays, millis);
+    }
+
+    #[test]
+    fn jitter_only_shortens_the_delay() {
+        let backoff = backoff();
+        assert_eq!(backoff.jittered(3, 0.0), Duration::from_millis(200));
+        assert_eq!(backoff.jittered(3, 0.5), Duration::from_millis(150));
+        for _ in 0..100 {
+            let delay = backoff.next_delay(3);
+            assert!(delay > Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        }
+    }
+
+    #[test]
+    fn idempotent_commands_are_matched_by_name() {
+        let policy = ReconnectPolicy::default().idempotent(["rooms_list", "device_report"]);
+        assert!(policy.is_idempotent("rooms_list"));
+        assert!(policy.is_idempotent("device_report room_name=Кухня device_name=Розетка1"));
+        assert!(!policy.is_idempotent("set_device_power_state power_state=false"));
+        assert!(!policy.is_idempotent(""));
+        assert!(policy.is_idempotent_request(&Request::command("device_report")));
+        assert!(!policy.is_idempotent_request(&Request::command("add_alert")));
+    }
+}
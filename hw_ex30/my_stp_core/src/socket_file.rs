@@ -0,0 +1,112 @@
+use std::{
+    fs, io,
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::UnixStream,
+    },
+    path::Path,
+};
+
+/// Owner and group may connect, access control relies on file ownership
+pub const DEFAULT_SOCKET_MODE: u32 = 0o660;
+
+/// How a server creates the file of its Unix socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnixSocketOptions {
+    /// Permission bits of the socket file, `None` keeps what the umask gives.
+    /// Connecting needs write permission
+    pub mode: Option<u32>,
+    /// Replace a socket file left behind by a server that was not shut down cleanly.
+    /// A socket that still accepts connections is never replaced
+    pub remove_stale: bool,
+}
+
+impl Default for UnixSocketOptions {
+    fn default() -> Self {
+        Self {
+            mode: Some(DEFAULT_SOCKET_MODE),
+            remove_stale: true,
+        }
+    }
+}
+
+/// Make room for a new socket at `path`
+pub fn prepare(path: &Path, options: &UnixSocketOptions) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+    if !options.remove_stale || UnixStream::connect(path).is_ok() {
+        return Err(io::ErrorKind::AddrInUse.into());
+    }
+    fs::remove_file(path)
+}
+
+/// Apply the permissions of `options` to a freshly bound socket
+pub fn restrict(path: &Path, options: &UnixSocketOptions) -> io::Result<()> {
+    match options.mode {
+        Some(mode) => fs::set_permissions(path, fs::Permissions::from_mode(mode)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod socket_file_tests {
+    use std::os::unix::net::UnixListener;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("stp-{}-{name}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn stale_socket_is_removed() {
+        let path = temp_path("stale.sock");
+        drop(UnixListener::bind(&path).unwrap());
+        prepare(&path, &UnixSocketOptions::default()).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn live_socket_is_kept() {
+        let path = temp_path("live.sock");
+        let _listener = UnixListener::bind(&path).unwrap();
+        let error = prepare(&path, &UnixSocketOptions::default()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+        assert!(path.exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn other_files_are_never_removed() {
+        let path = temp_path("regular");
+        fs::write(&path, "data").unwrap();
+        assert!(prepare(&path, &UnixSocketOptions::default()).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "data");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mode_is_applied() {
+        let path = temp_path("mode.sock");
+        let _listener = UnixListener::bind(&path).unwrap();
+        let options = UnixSocketOptions {
+            mode: Some(0o600),
+            ..UnixSocketOptions::default()
+        };
+        restrict(&path, &options).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,291 @@
+use std::{
+    fmt::Display,
+    sync::{Arc, RwLock},
+};
+
+use async_trait::async_trait;
+
+use crate::async_device::{AsyncDevice, AsyncReporter};
+use crate::device::Device;
+use crate::reporter::Reporter;
+use crate::smart_tools::smart_socket::{AsyncSmartSocketInfoProvider, SmartSocketInfoProvider};
+use crate::smart_tools::thermomener::{AsyncThermometerInfoProvider, ThermometerInfoProvider};
+use crate::temperature::{Temperature, TemperatureMeasureUnits};
+
+/// Exposes a synchronous provider through the async provider traits.
+/// The wrapped provider is called inline, so it must not block for long
+pub struct AsyncProviderAdapter<T: ?Sized> {
+    provider: Arc<T>,
+}
+
+impl<T: ?Sized> AsyncProviderAdapter<T> {
+    pub fn new(provider: Arc<T>) -> Self {
+        Self { provider }
+    }
+}
+
+#[async_trait]
+impl<T: ThermometerInfoProvider + ?Sized> AsyncThermometerInfoProvider for AsyncProviderAdapter<T> {
+    async fn get_temperature(
+        &self,
+    ) -> Result<Temperature, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self.provider.get_temperature())
+    }
+}
+
+#[async_trait]
+impl<T: SmartSocketInfoProvider + ?Sized> AsyncSmartSocketInfoProvider for AsyncProviderAdapter<T> {
+    async fn get_current_power_consumption(
+        &self,
+    ) -> Result<f32, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self.provider.get_current_power_consumption())
+    }
+}
+
+/// Exposes an async provider through the synchronous provider traits.
+/// Every call blocks the current thread on `futures::executor::block_on` until the provider answers.
+/// It must not be used from async context: inside a tokio runtime it stalls the worker
+/// and deadlocks if the provider waits on that runtime. Wrap such calls in `spawn_blocking`.
+/// Failed readings are reported as NaN, which devices already treat as unparsable
+pub struct BlockingProviderAdapter<T: ?Sized> {
+    provider: Arc<T>,
+}
+
+impl<T: ?Sized> BlockingProviderAdapter<T> {
+    pub fn new(provider: Arc<T>) -> Self {
+        Self { provider }
+    }
+}
+
+impl<T: AsyncThermometerInfoProvider + ?Sized> ThermometerInfoProvider
+    for BlockingProviderAdapter<T>
+{
+    fn get_temperature(&self) -> Temperature {
+        futures::executor::block_on(self.provider.get_temperature())
+            .unwrap_or(Temperature::new(f32::NAN, TemperatureMeasureUnits::Celsius))
+    }
+}
+
+impl<T: AsyncSmartSocketInfoProvider + ?Sized> SmartSocketInfoProvider
+    for BlockingProviderAdapter<T>
+{
+    fn get_current_power_consumption(&self) -> f32 {
+        futures::executor::block_on(self.provider.get_current_power_consumption())
+            .unwrap_or(f32::NAN)
+    }
+}
+
+/// The blocking pool dropped a device call before it ran,
+/// which happens when the runtime is shutting down
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceCallCancelled {
+    pub device_name: String,
+}
+
+impl Display for DeviceCallCancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "device call of {} was cancelled", self.device_name)
+    }
+}
+
+impl std::error::Error for DeviceCallCancelled {}
+
+/// Exposes a device stored in [`crate::Room`] through [`AsyncDevice`].
+/// Device calls run on the tokio blocking pool, so a slow device only holds up
+/// the caller waiting for it. Must be awaited inside a tokio runtime
+pub struct AsyncDeviceAdapter {
+    name: String,
+    device: Arc<RwLock<Box<dyn Device>>>,
+}
+
+impl AsyncDeviceAdapter {
+    /// `name` is the name the device has. It is passed in because reading it
+    /// would wait for the device lock on the caller's task
+    pub fn new(name: &str, device: Arc<RwLock<Box<dyn Device>>>) -> Self {
+        Self {
+            name: name.to_string(),
+            device,
+        }
+    }
+
+    /// Run a blocking device call on the blocking pool, a panic in it is resumed here
+    async fn blocking<R, F>(&self, call: F) -> Result<R, DeviceCallCancelled>
+    where
+        R: Send + 'static,
+        F: FnOnce(&RwLock<Box<dyn Device>>) -> R + Send + 'static,
+    {
+        let device = self.device.clone();
+        match tokio::task::spawn_blocking(move || call(&device)).await {
+            Ok(result) => Ok(result),
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(_) => Err(DeviceCallCancelled {
+                device_name: self.name.clone(),
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncReporter for AsyncDeviceAdapter {
+    async fn create_report(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        self.blocking(|device| device.read().unwrap().create_report())
+            .await?
+    }
+}
+
+/// A cancelled call leaves the device as it was, its state is reported as unknown,
+/// i.e. neither on nor off
+#[async_trait]
+impl AsyncDevice for AsyncDeviceAdapter {
+    async fn turn_on(&mut self) {
+        let _ = self
+            .blocking(|device| device.write().unwrap().turn_on())
+            .await;
+    }
+
+    async fn turn_off(&mut self) {
+        let _ = self
+            .blocking(|device| device.write().unwrap().turn_off())
+            .await;
+    }
+
+    async fn is_on(&self) -> bool {
+        self.blocking(|device| device.read().unwrap().is_on())
+            .await
+            .unwrap_or(false)
+    }
+
+    async fn is_off(&self) -> bool {
+        self.blocking(|device| device.read().unwrap().is_off())
+            .await
+            .unwrap_or(false)
+    }
+
+    fn get_device_name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_device_name(&mut self, name: &str) {
+        self.device.write().unwrap().set_device_name(name);
+        self.name = name.to_string();
+    }
+}
+
+/// Exposes an [`AsyncDevice`] through [`Device`] so it can be placed in a [`crate::Room`].
+/// Every call blocks the current thread on `futures::executor::block_on` until the device answers,
+/// so like [`BlockingProviderAdapter`] it must not be used from async context
+pub struct BlockingDeviceAdapter<D: AsyncDevice> {
+    device: D,
+}
+
+impl<D: AsyncDevice> BlockingDeviceAdapter<D> {
+    pub fn new(device: D) -> Self {
+        Self { device }
+    }
+}
+
+impl<D: AsyncDevice> Reporter for BlockingDeviceAdapter<D> {
+    fn create_report(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        futures::executor::block_on(self.device.create_report())
+    }
+}
+
+impl<D: AsyncDevice> Device for BlockingDeviceAdapter<D> {
+    fn turn_on(&mut self) {
+        futures::executor::block_on(self.device.turn_on())
+    }
+
+    fn turn_off(&mut self) {
+        futures::executor::block_on(self.device.turn_off())
+    }
+
+    fn is_on(&self) -> bool {
+        futures::executor::block_on(self.device.is_on())
+    }
+
+    fn is_off(&self) -> bool {
+        futures::executor::block_on(self.device.is_off())
+    }
+
+    fn get_device_name(&self) -> &str {
+        self.device.get_device_name()
+    }
+
+    fn set_device_name(&mut self, name: &str) {
+        self.device.set_device_name(name)
+    }
+}
+
+#[cfg(test)]
+mod adapters_tests {
+    use super::*;
+    use crate::smart_tools::smart_socket::{AsyncSmartSocket, SmartSocket};
+    use crate::smart_tools::thermomener::{AsyncThermometer, EnergyProvider};
+
+    struct FailingSmartSocketInfoProvider;
+
+    #[async_trait]
+    impl AsyncSmartSocketInfoProvider for FailingSmartSocketInfoProvider {
+        async fn get_current_power_consumption(
+            &self,
+        ) -> Result<f32, Box<dyn std::error::Error + Send + Sync>> {
+            Err("connection refused".into())
+        }
+    }
+
+    struct FailingThermometerInfoProvider;
+
+    #[async_trait]
+    impl AsyncThermometerInfoProvider for FailingThermometerInfoProvider {
+        async fn get_temperature(
+            &self,
+        ) -> Result<Temperature, Box<dyn std::error::Error + Send + Sync>> {
+            Err("timeout".into())
+        }
+    }
+
+    #[test]
+    fn test_async_provider_adapter() {
+        let provider = AsyncProviderAdapter::new(Arc::new(EnergyProvider { value: 10.0 }));
+        let power = futures::executor::block_on(provider.get_current_power_consumption());
+        assert_eq!(power.unwrap(), 10.0);
+    }
+
+    #[test]
+    fn test_blocking_provider_adapter_failure_is_nan() {
+        let provider = BlockingProviderAdapter::new(Arc::new(FailingSmartSocketInfoProvider));
+        assert!(provider.get_current_power_consumption().is_nan());
+    }
+
+    #[tokio::test]
+    async fn test_async_device_adapter() {
+        let device: Arc<RwLock<Box<dyn Device>>> = Arc::new(RwLock::new(Box::new(
+            SmartSocket::new("Socket", Arc::new(EnergyProvider { value: 10.0 })),
+        )));
+        let mut adapter = AsyncDeviceAdapter::new("Socket", device.clone());
+        assert_eq!(adapter.get_device_name(), "Socket");
+
+        adapter.toggle().await;
+        assert!(device.read().unwrap().is_off());
+        assert!(adapter.create_report().await.is_err());
+    }
+
+    #[test]
+    fn test_blocking_device_adapter() {
+        let provider = Arc::new(AsyncProviderAdapter::new(Arc::new(EnergyProvider {
+            value: 10.0,
+        })));
+        let mut device = BlockingDeviceAdapter::new(AsyncSmartSocket::new("Socket", provider));
+        assert!(device.create_report().is_ok());
+        device.turn_off();
+        assert!(device.is_off());
+        assert!(device.create_report().is_err());
+    }
+
+    #[test]
+    fn test_async_device_reports_provider_error() {
+        let thermometer =
+            AsyncThermometer::new("Thermometer", Arc::new(FailingThermometerInfoProvider));
+        assert!(futures::executor::block_on(thermometer.create_report()).is_err());
+    }
+}
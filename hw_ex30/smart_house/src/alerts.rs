@@ -0,0 +1,374 @@
+use std::{
+    fmt::Display,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{clock::Clock, SmartHouse};
+
+const MAX_HISTORY_LEN: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl FromStr for Severity {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "info" => Ok(Severity::Info),
+            "warning" => Ok(Severity::Warning),
+            "critical" => Ok(Severity::Critical),
+            _ => Err(format!("unknown severity {value}")),
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+/// Device reading an alert watches
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    /// Temperature in Celsius
+    Temperature,
+    /// Power consumption in watts
+    Power,
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "temperature" => Ok(Metric::Temperature),
+            "power" => Ok(Metric::Power),
+            _ => Err(format!("unknown metric {value}")),
+        }
+    }
+}
+
+impl Display for Metric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Metric::Temperature => write!(f, "temperature"),
+            Metric::Power => write!(f, "power"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    Above,
+    Below,
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "above" => Ok(Condition::Above),
+            "below" => Ok(Condition::Below),
+            _ => Err(format!("unknown condition {value}")),
+        }
+    }
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Condition::Above => write!(f, "above"),
+            Condition::Below => write!(f, "below"),
+        }
+    }
+}
+
+/// "Термометр3 below 10°C" or "Розетка1 above 2000 W for 30s".
+/// The alert is raised once the condition holds for `raise_after` and cleared
+/// once the value moves back past the threshold by more than `hysteresis`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertDefinition {
+    pub name: String,
+    pub room_name: String,
+    pub device_name: String,
+    pub metric: Metric,
+    pub condition: Condition,
+    pub threshold: f32,
+    pub hysteresis: f32,
+    pub raise_after: Duration,
+    pub severity: Severity,
+}
+
+impl AlertDefinition {
+    fn is_violated(&self, value: f32) -> bool {
+        match self.condition {
+            Condition::Above => value > self.threshold,
+            Condition::Below => value < self.threshold,
+        }
+    }
+
+    fn is_cleared(&self, value: f32) -> bool {
+        match self.condition {
+            Condition::Above => value < self.threshold - self.hysteresis,
+            Condition::Below => value > self.threshold + self.hysteresis,
+        }
+    }
+
+    fn read_value(&self, smart_house: &SmartHouse) -> Option<f32> {
+        let device = smart_house
+            .get_room(&self.room_name)?
+            .get_device(&self.device_name)?;
+        let device = device.read().unwrap();
+        let value = match self.metric {
+            Metric::Temperature => device.temperature()?.get_value(),
+            Metric::Power => device.power_consumption()?,
+        };
+        value.is_finite().then_some(value)
+    }
+}
+
+impl Display for AlertDefinition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} [{}] {}/{} {} {} {} for {}s",
+            self.name,
+            self.severity,
+            self.room_name,
+            self.device_name,
+            self.metric,
+            self.condition,
+            self.threshold,
+            self.raise_after.as_secs()
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AlertState {
+    Normal,
+    Pending { since: Instant },
+    Active { raised_value: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertEventKind {
+    Raised,
+    Cleared,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertEvent {
+    pub kind: AlertEventKind,
+    pub alert_name: String,
+    pub severity: Severity,
+    pub room_name: String,
+    pub device_name: String,
+    pub value: f32,
+}
+
+impl Display for AlertEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.kind {
+            AlertEventKind::Raised => "raised",
+            AlertEventKind::Cleared => "cleared",
+        };
+        write!(
+            f,
+            "alert {kind}: {} [{}] {}/{} value {}",
+            self.alert_name, self.severity, self.room_name, self.device_name, self.value
+        )
+    }
+}
+
+/// Evaluates alert definitions against the house on every polling cycle
+/// and keeps their state between cycles
+pub struct AlertManager {
+    alerts: Vec<(AlertDefinition, AlertState)>,
+    history: Vec<AlertEvent>,
+    clock: Arc<dyn Clock>,
+}
+
+impl AlertManager {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            alerts: vec![],
+            history: vec![],
+            clock,
+        }
+    }
+
+    /// Add a new alert definition
+    /// If an alert with the same name already exists, it will not be added
+    pub fn add_unique_alert(&mut self, definition: AlertDefinition) -> Option<usize> {
+        if self.contains(&definition.name) {
+            return None;
+        }
+        self.alerts.push((definition, AlertState::Normal));
+        Some(self.alerts.len() - 1)
+    }
+
+    pub fn remove_alert(&mut self, name: &str) -> Option<AlertDefinition> {
+        let remove_pos = self
+            .alerts
+            .iter()
+            .position(|(definition, _)| definition.name == name);
+        Some(self.alerts.remove(remove_pos?).0)
+    }
+
+    /// Remove the alerts of a device that left the house,
+    /// of every device of the room without `device_name`. Returns the removed definitions
+    pub fn remove_devices(
+        &mut self,
+        room_name: &str,
+        device_name: Option<&str>,
+    ) -> Vec<AlertDefinition> {
+        let (removed, kept) =
+            std::mem::take(&mut self.alerts)
+                .into_iter()
+                .partition(|(definition, _)| {
+                    definition.room_name == room_name
+                        && device_name
+                            .is_none_or(|device_name| definition.device_name == device_name)
+                });
+        self.alerts = kept;
+        removed
+            .into_iter()
+            .map(|(definition, _)| definition)
+            .collect()
+    }
+
+    /// Keep watching a renamed device, or every device of a renamed room without `device_name`
+    pub fn rename(&mut self, room_name: &str, device_name: Option<&str>, new_name: &str) {
+        for (definition, _) in &mut self.alerts {
+            if definition.room_name != room_name {
+                continue;
+            }
+            match device_name {
+                Some(device_name) if definition.device_name == device_name => {
+                    definition.device_name = new_name.to_string()
+                }
+                Some(_) => {}
+                None => definition.room_name = new_name.to_string(),
+            }
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.alerts
+            .iter()
+            .any(|(definition, _)| definition.name == name)
+    }
+
+    pub fn definitions(&self) -> Vec<&AlertDefinition> {
+        self.alerts
+            .iter()
+            .map(|(definition, _)| definition)
+            .collect()
+    }
+
+    pub fn active_alerts(&self) -> Vec<&AlertDefinition> {
+        self.alerts
+            .iter()
+            .filter(|(_, state)| matches!(state, AlertState::Active { .. }))
+            .map(|(definition, _)| definition)
+            .collect()
+    }
+
+    pub fn history(&self) -> &[AlertEvent] {
+        &self.history
+    }
+
+    /// Read the watched values and move every alert through its state machine.
+    /// Returns raised and cleared events of this cycle
+    pub fn evaluate(&mut self, smart_house: &SmartHouse) -> Vec<AlertEvent> {
+        let now = self.clock.now();
+        let mut events = vec![];
+
+        for (definition, state) in self.alerts.iter_mut() {
+            let Some(value) = definition.read_value(smart_house) else {
+                // No reading: a pending alert can't be confirmed, an active one stays active
+                if let AlertState::Pending { .. } = state {
+                    *state = AlertState::Normal;
+                }
+                continue;
+            };
+
+            let event_kind = match *state {
+                AlertState::Normal if definition.is_violated(value) => {
+                    *state = AlertState::Pending { since: now };
+                    None
+                }
+                AlertState::Pending { .. } if !definition.is_violated(value) => {
+                    *state = AlertState::Normal;
+                    None
+                }
+                AlertState::Active { .. } if definition.is_cleared(value) => {
+                    *state = AlertState::Normal;
+                    Some(AlertEventKind::Cleared)
+                }
+                _ => None,
+            };
+
+            let event_kind = match *state {
+                AlertState::Pending { since } if now - since >= definition.raise_after => {
+                    *state = AlertState::Active {
+                        raised_value: value,
+                    };
+                    Some(AlertEventKind::Raised)
+                }
+                _ => event_kind,
+            };
+
+            if let Some(kind) = event_kind {
+                events.push(AlertEvent {
+                    kind,
+                    alert_name: definition.name.clone(),
+                    severity: definition.severity,
+                    room_name: definition.room_name.clone(),
+                    device_name: definition.device_name.clone(),
+                    value,
+                });
+            }
+        }
+
+        self.history.extend(events.iter().cloned());
+        if self.history.len() > MAX_HISTORY_LEN {
+            let overflow = self.history.len() - MAX_HISTORY_LEN;
+            self.history.drain(..overflow);
+        }
+        events
+    }
+
+    pub fn create_active_report(&self) -> String {
+        let active: Vec<String> = self
+            .alerts
+            .iter()
+            .filter_map(|(definition, state)| match state {
+                AlertState::Active { raised_value } => {
+                    Some(format!("{definition} (raised at {raised_value})"))
+                }
+                _ => None,
+            })
+            .collect();
+        format!("[{}]", active.join(";"))
+    }
+
+    pub fn create_history_report(&self) -> String {
+        let history: Vec<String> = self.history.iter().map(|event| event.to_string()).collect();
+        format!("[{}]", history.join(";"))
+    }
+}
@@ -0,0 +1,26 @@
+use async_trait::async_trait;
+
+/// Async counterpart of [`crate::reporter::Reporter`] for devices whose
+/// readings come from I/O (network, serial port, ...)
+#[async_trait]
+pub trait AsyncReporter {
+    async fn create_report(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// Async counterpart of [`crate::device::Device`]
+#[async_trait]
+pub trait AsyncDevice: AsyncReporter + Sync + Send {
+    async fn turn_on(&mut self);
+    async fn turn_off(&mut self);
+    async fn toggle(&mut self) {
+        if self.is_on().await {
+            self.turn_off().await;
+        } else {
+            self.turn_on().await;
+        }
+    }
+    async fn is_on(&self) -> bool;
+    async fn is_off(&self) -> bool;
+    fn get_device_name(&self) -> &str;
+    fn set_device_name(&mut self, name: &str);
+}
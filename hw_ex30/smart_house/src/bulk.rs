@@ -0,0 +1,187 @@
+use std::{
+    fmt::Display,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use crate::{device::Device, SmartHouse};
+
+/// Device of a group together with the name of its room
+pub type GroupDevice<'a> = (&'a str, Arc<RwLock<Box<dyn Device>>>);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PowerAction {
+    On,
+    Off,
+    Toggle,
+}
+
+impl FromStr for PowerAction {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "true" => Ok(PowerAction::On),
+            "false" => Ok(PowerAction::Off),
+            "toggle" => Ok(PowerAction::Toggle),
+            _ => Err(format!("unknown power action {value}")),
+        }
+    }
+}
+
+/// Selects a group of devices: the whole house, one room, devices of one kind
+/// or devices of one kind in a room
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeviceGroup {
+    pub room_name: Option<String>,
+    pub kind: Option<String>,
+}
+
+impl DeviceGroup {
+    pub fn house() -> Self {
+        Self::default()
+    }
+
+    pub fn room(room_name: &str) -> Self {
+        Self {
+            room_name: Some(room_name.to_string()),
+            kind: None,
+        }
+    }
+
+    pub fn kind(kind: &str) -> Self {
+        Self {
+            room_name: None,
+            kind: Some(kind.to_string()),
+        }
+    }
+
+    pub fn with_kind(mut self, kind: &str) -> Self {
+        self.kind = Some(kind.to_string());
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceOperationError {
+    /// Another thread panicked while holding the device
+    LockPoisoned,
+    /// The device accepted the command but did not switch
+    StateNotChanged { is_on: bool },
+}
+
+impl Display for DeviceOperationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeviceOperationError::LockPoisoned => write!(f, "device lock poisoned"),
+            DeviceOperationError::StateNotChanged { is_on } => {
+                write!(f, "state not changed, is_on:{is_on}")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceOperationResult {
+    pub room_name: String,
+    pub device_name: String,
+    /// Power state after the operation
+    pub result: Result<bool, DeviceOperationError>,
+}
+
+impl Display for DeviceOperationResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "room_name:{},device_name:{},",
+            self.room_name, self.device_name
+        )?;
+        match &self.result {
+            Ok(is_on) => write!(f, "is_on:{is_on}"),
+            Err(e) => write!(f, "error:{e}"),
+        }
+    }
+}
+
+fn apply_power_action(
+    device: &RwLock<Box<dyn Device>>,
+    action: PowerAction,
+) -> Result<bool, DeviceOperationError> {
+    let mut device = device
+        .write()
+        .map_err(|_| DeviceOperationError::LockPoisoned)?;
+    let expected = match action {
+        PowerAction::On => true,
+        PowerAction::Off => false,
+        PowerAction::Toggle => !device.is_on(),
+    };
+    if expected {
+        device.turn_on();
+    } else {
+        device.turn_off();
+    }
+
+    let is_on = device.is_on();
+    if is_on != expected {
+        return Err(DeviceOperationError::StateNotChanged { is_on });
+    }
+    Ok(is_on)
+}
+
+impl SmartHouse {
+    /// Devices of the group with their room names.
+    /// Returns `None` if the group names a room that does not exist
+    pub fn group_devices(&self, group: &DeviceGroup) -> Option<Vec<GroupDevice<'_>>> {
+        let rooms: Vec<_> = match &group.room_name {
+            Some(room_name) => vec![self.get_room(room_name)?],
+            None => self.get_rooms().iter().collect(),
+        };
+
+        let mut devices = vec![];
+        for room in rooms {
+            for device in room.get_devices() {
+                let matches_kind = match &group.kind {
+                    Some(kind) => device
+                        .read()
+                        .is_ok_and(|device| device.kind() == kind.as_str()),
+                    None => true,
+                };
+                if matches_kind {
+                    devices.push((room.name(), device));
+                }
+            }
+        }
+        Some(devices)
+    }
+
+    /// Turn on, turn off or toggle every device of the group.
+    /// A failure of one device does not stop the others, each device gets its own result.
+    /// Returns `None` if the group names a room that does not exist
+    pub fn set_group_power_state(
+        &self,
+        group: &DeviceGroup,
+        action: PowerAction,
+    ) -> Option<Vec<DeviceOperationResult>> {
+        let results = self
+            .group_devices(group)?
+            .into_iter()
+            .map(|(room_name, device)| {
+                let device_name = match device.read() {
+                    Ok(device) => device.get_device_name().to_string(),
+                    Err(e) => e.into_inner().get_device_name().to_string(),
+                };
+                DeviceOperationResult {
+                    room_name: room_name.to_string(),
+                    device_name,
+                    result: apply_power_action(&device, action),
+                }
+            })
+            .collect();
+        Some(results)
+    }
+}
+
+pub fn create_results_report(results: &[DeviceOperationResult]) -> String {
+    let results: Vec<String> = results.iter().map(|result| result.to_string()).collect();
+    format!("[{}]", results.join(";"))
+}
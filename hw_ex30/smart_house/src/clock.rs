@@ -0,0 +1,14 @@
+use std::time::Instant;
+
+/// Source of the current time, so time dependent logic can be tested with a fake clock
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
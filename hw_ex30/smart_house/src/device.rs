@@ -1,4 +1,5 @@
 use crate::reporter::Reporter;
+use crate::temperature::Temperature;
 
 pub trait Device: Reporter + Sync + Send {
     fn turn_on(&mut self);
@@ -13,4 +14,18 @@ pub trait Device: Reporter + Sync + Send {
     fn is_on(&self) -> bool;
     fn is_off(&self) -> bool;
     fn get_device_name(&self) -> &str;
+    /// Rooms keep device names unique, rename through [`crate::Room::rename_device`]
+    fn set_device_name(&mut self, name: &str);
+    /// Device kind used to address groups of devices, e.g. `smart_socket`
+    fn kind(&self) -> &str {
+        "device"
+    }
+    /// Current power consumption in watts, `None` for devices that do not measure it
+    fn power_consumption(&self) -> Option<f32> {
+        None
+    }
+    /// Current temperature in Celsius, `None` for devices that do not measure it
+    fn temperature(&self) -> Option<Temperature> {
+        None
+    }
 }
@@ -1,6 +1,13 @@
+pub mod adapters;
+pub mod alerts;
+pub mod async_device;
+pub mod bulk;
+pub mod clock;
 pub mod device;
+pub mod power_budget;
 pub mod reporter;
 pub mod smart_tools;
+pub mod streams;
 pub mod temperature;
 
 use std::sync::{Arc, RwLock};
@@ -28,7 +35,7 @@ impl Room {
         Some(self.devices.len() - 1)
     }
 
-    pub fn remove_device(&mut self, device_name: &str) -> Option<Arc<RwLock<Box<(dyn Device)>>>> {
+    pub fn remove_device(&mut self, device_name: &str) -> Option<Arc<RwLock<Box<dyn Device>>>> {
         let remove_pos = self
             .devices
             .iter()
@@ -36,6 +43,16 @@ impl Room {
         Some(self.devices.swap_remove(remove_pos?))
     }
 
+    /// Rename a device, the new name must not be taken in the room
+    pub fn rename_device(&mut self, device_name: &str, new_name: &str) -> Option<()> {
+        if self.contains_device(new_name) {
+            return None;
+        }
+        let device = self.get_device(device_name)?;
+        device.write().unwrap().set_device_name(new_name);
+        Some(())
+    }
+
     pub fn contains_device(&self, device_name: &str) -> bool {
         self.devices
             .iter()
@@ -89,6 +106,15 @@ impl SmartHouse {
         Some(self.rooms.swap_remove(remove_pos?))
     }
 
+    /// Rename a room, the new name must not be taken
+    pub fn rename_room(&mut self, room_name: &str, new_name: &str) -> Option<()> {
+        if self.contains(new_name) {
+            return None;
+        }
+        self.get_room_mut(room_name)?.name = new_name.to_string();
+        Some(())
+    }
+
     pub fn get_rooms(&self) -> &Vec<Room> {
         &self.rooms
     }
@@ -116,16 +142,12 @@ impl SmartHouse {
         &self,
         room_name_device_name: Vec<(&str, &str)>,
     ) -> Result<String, Box<dyn std::error::Error>> {
-        let title = "===============Smart House Report===============";
-        let mut content = String::new();
+        let mut reports = Vec::new();
         for (room_name, device_name) in room_name_device_name {
             match self.get_room(room_name) {
                 Some(room) => match room.get_device(device_name) {
                     Some(device) => match device.read().unwrap().create_report() {
-                        Ok(report) => {
-                            content.push_str(report.as_str());
-                            content.push('\n');
-                        }
+                        Ok(report) => reports.push(report),
 
                         Err(err) => return Err(format!("Error: {}", err).into()),
                     },
@@ -134,8 +156,7 @@ impl SmartHouse {
                 None => return Err(format!("Room {} not found", room_name).into()),
             }
         }
-        let end = "===============Smart House Report end===========";
-        Ok(format!("{title}\n{content}{end}\n"))
+        Ok(frame_reports(&reports))
     }
 }
 
@@ -168,9 +189,23 @@ impl Reporter for SmartHouse {
     }
 }
 
+/// Device reports between the title and the end line, a report per line
+pub fn frame_reports(reports: &[String]) -> String {
+    let title = "===============Smart House Report===============";
+    let end = "===============Smart House Report end===========";
+    let mut content = String::new();
+    for report in reports {
+        content.push_str(report);
+        content.push('\n');
+    }
+    format!("{title}\n{content}{end}\n")
+}
+
 #[cfg(test)]
 mod lib_tests {
     use super::*;
+    use crate::smart_tools::smart_socket::SmartSocket;
+    use crate::smart_tools::thermomener::EnergyProvider;
 
     #[test]
     fn test_smart_house_creation() {
@@ -219,150 +254,35 @@ mod lib_tests {
     }
 
     #[test]
-    fn test_contains() {
-        let house = SmartHouse::new(vec![Room::new("Room 1".to_string(), vec![])]);
-        assert!(house.contains("Room 1"));
-    }
-
-    struct StubDevice {
-        name: &'static str,
-    }
-
-    impl Reporter for StubDevice {
-        fn create_report(&self) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
-            Ok("Dummy device".to_string())
-        }
-    }
-    impl Device for StubDevice {
-        fn turn_on(&mut self) {
-            todo!()
-        }
-
-        fn turn_off(&mut self) {
-            todo!()
-        }
-
-        fn is_on(&self) -> bool {
-            true
-        }
-
-        fn is_off(&self) -> bool {
-            todo!()
-        }
-
-        fn get_device_name(&self) -> &str {
-            self.name
-        }
-    }
-
-    #[test]
-    fn test_add_devices() {
-        let mut house = SmartHouse::new(vec![Room::new("Room 1".to_string(), vec![])]);
-        let devices_before_add_len = house.devices().len();
-        assert_eq!(devices_before_add_len, 0);
-
-        let room_opt = house.get_room_mut("Room 1");
-        assert!(room_opt.is_some());
-        if let Some(room) = room_opt {
-            room.add_unique_device(StubDevice {
-                name: "Dummy device",
-            });
-        }
-        let devices_after_add_len = house.devices().len();
-        assert_ne!(devices_after_add_len, devices_before_add_len);
-
-        let room_opt = house.get_room_mut("Room 1");
-        if let Some(room) = room_opt {
-            room.add_unique_device(StubDevice {
-                name: "Dummy device",
-            });
-        }
-        let devices_after_add_len = house.devices().len();
-        assert_eq!(devices_after_add_len, 1);
-    }
-
-    #[test]
-    fn test_create_report_by_devices() {
-        let mut house = SmartHouse::new(vec![Room::new("Room 1".to_string(), vec![])]);
-        let room_opt = house.get_room_mut("Room 1");
-        assert!(room_opt.is_some());
-        if let Some(room) = room_opt {
-            room.add_unique_device(StubDevice {
-                name: "Dummy device",
-            });
-            room.add_unique_device(StubDevice {
-                name: "Dummy device2",
-            });
-        }
-        let room_name_device_name = vec![("Room 1", "Dummy device"), ("Room 1", "Dummy device2")];
-        let report = house.create_report_by_devices(room_name_device_name);
-        assert!(report.is_ok());
+    fn test_rename_room_and_device() {
+        let socket = SmartSocket::new("Socket 1", Arc::new(EnergyProvider { value: 10.0 }));
+        let mut house = SmartHouse::new(vec![
+            Room::new("Room 1".to_string(), vec![]),
+            Room::new("Room 2".to_string(), vec![]),
+        ]);
+        let room = house.get_room_mut("Room 1").unwrap();
+        room.add_unique_device(socket);
+        room.add_unique_device(SmartSocket::new(
+            "Socket 2",
+            Arc::new(EnergyProvider { value: 20.0 }),
+        ));
+
+        assert_eq!(room.rename_device("Socket 1", "Socket 2"), None);
+        assert_eq!(room.rename_device("Socket 3", "Socket 4"), None);
+        assert_eq!(room.rename_device("Socket 1", "Lamp"), Some(()));
+        assert!(room.contains_device("Lamp"));
+        assert!(!room.contains_device("Socket 1"));
+
+        assert_eq!(house.rename_room("Room 1", "Room 2"), None);
+        assert_eq!(house.rename_room("Room 1", "Kitchen"), Some(()));
+        assert!(house.get_room("Kitchen").unwrap().contains_device("Lamp"));
+        assert!(!house.contains("Room 1"));
     }
 
     #[test]
-    fn test_create_report_by_devices_error() {
-        let mut house = SmartHouse::new(vec![Room::new("Room 1".to_string(), vec![])]);
-        let room_opt = house.get_room_mut("Room 1");
-        assert!(room_opt.is_some());
-        if let Some(room) = room_opt {
-            room.add_unique_device(StubDevice {
-                name: "Dummy device",
-            });
-            room.add_unique_device(StubDevice {
-                name: "Dummy device2",
-            });
-        }
-        let room_name_device_name = vec![("Room 1", "Dummy device"), ("Room 2", "Dummy device2")];
-        let report = house.create_report_by_devices(room_name_device_name);
-        assert!(report.is_err());
-    }
-
-    #[test]
-    fn test_create_report_by_devices_and_dynamic_rooms() {
-        let mut house = SmartHouse::new(vec![]);
-        house.add_unique_room(Room::new("Room 1".to_string(), vec![]));
-        let room_opt = house.get_room_mut("Room 1");
-        assert!(room_opt.is_some());
-        if let Some(room) = room_opt {
-            room.add_unique_device(StubDevice {
-                name: "Dummy device",
-            });
-            room.add_unique_device(StubDevice {
-                name: "Dummy device2",
-            });
-        }
-        let room_name_device_name = vec![("Room 1", "Dummy device"), ("Room 1", "Dummy device2")];
-        let report = house.create_report_by_devices(room_name_device_name);
-        assert!(report.is_ok());
-    }
-
-    #[test]
-    fn test_create_report_by_devices_and_dynamic_rooms2() {
-        let mut house = SmartHouse::new(vec![]);
-        house.add_unique_room(Room::new("Room 1".to_string(), vec![]));
-        let room_opt = house.get_room_mut("Room 1");
-        assert!(room_opt.is_some());
-        if let Some(room) = room_opt {
-            room.add_unique_device(StubDevice {
-                name: "Dummy device",
-            });
-            room.add_unique_device(StubDevice {
-                name: "Dummy device2",
-            });
-        }
-        house.add_unique_room(Room::new("Room 2".to_string(), vec![]));
-        let room_opt = house.get_room_mut("Room 2");
-        assert!(room_opt.is_some());
-        if let Some(room) = room_opt {
-            room.add_unique_device(StubDevice {
-                name: "Dummy device2",
-            });
-        }
-        house.add_unique_room(Room::new("Room 2".to_string(), vec![]));
-        house.remove_room("Room 3");
-        let room_name_device_name = vec![("Room 1", "Dummy device"), ("Room 2", "Dummy device2")];
-        let report = house.create_report_by_devices(room_name_device_name);
-        assert!(report.is_ok());
+    fn test_contains() {
+        let house = SmartHouse::new(vec![Room::new("Room 1".to_string(), vec![])]);
+        assert!(house.contains("Room 1"));
     }
 
     #[test]
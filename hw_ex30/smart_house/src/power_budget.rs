@@ -0,0 +1,337 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{clock::Clock, SmartHouse};
+
+pub const DEFAULT_PRIORITY: u32 = 0;
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+const MAX_HISTORY_LEN: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShedAction {
+    TurnedOff,
+    Restored,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShedEvent {
+    pub action: ShedAction,
+    pub room_name: String,
+    pub device_name: String,
+    pub priority: u32,
+    pub device_power: f32,
+    pub total_power: f32,
+}
+
+impl Display for ShedEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let action = match self.action {
+            ShedAction::TurnedOff => "turned off",
+            ShedAction::Restored => "restored",
+        };
+        write!(
+            f,
+            "{action} {}/{} (priority {}, {} Вт), house total {} Вт",
+            self.room_name, self.device_name, self.priority, self.device_power, self.total_power
+        )
+    }
+}
+
+/// Limit that is `NaN`, infinite or negative
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InvalidMaxPower(pub f32);
+
+impl Display for InvalidMaxPower {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "max power must be finite and not negative, got {}",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidMaxPower {}
+
+struct ShedDevice {
+    room_name: String,
+    device_name: String,
+    priority: u32,
+    power: f32,
+}
+
+/// Keeps total power consumption of the house under a limit by turning off
+/// the lowest priority devices and restoring them once there is headroom again.
+/// Devices are shed immediately, restores wait for `cooldown` after the last change
+pub struct PowerBudget {
+    max_power: Option<f32>,
+    cooldown: Duration,
+    priorities: HashMap<(String, String), u32>,
+    shed_devices: Vec<ShedDevice>,
+    last_change: Option<Instant>,
+    history: Vec<ShedEvent>,
+    clock: Arc<dyn Clock>,
+}
+
+impl PowerBudget {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            max_power: None,
+            cooldown: DEFAULT_COOLDOWN,
+            priorities: HashMap::new(),
+            shed_devices: vec![],
+            last_change: None,
+            history: vec![],
+            clock,
+        }
+    }
+
+    /// Set limit in watts. `None` disables the budget and restores shed devices on next enforce
+    pub fn set_max_power(&mut self, max_power: Option<f32>) -> Result<(), InvalidMaxPower> {
+        if let Some(max_power) = max_power.filter(|watts| !(watts.is_finite() && *watts >= 0.0)) {
+            return Err(InvalidMaxPower(max_power));
+        }
+        self.max_power = max_power;
+        Ok(())
+    }
+
+    pub fn max_power(&self) -> Option<f32> {
+        self.max_power
+    }
+
+    pub fn set_cooldown(&mut self, cooldown: Duration) {
+        self.cooldown = cooldown;
+    }
+
+    /// Devices with lower priority are turned off first
+    pub fn set_priority(&mut self, room_name: &str, device_name: &str, priority: u32) {
+        self.priorities
+            .insert((room_name.to_string(), device_name.to_string()), priority);
+    }
+
+    pub fn priority(&self, room_name: &str, device_name: &str) -> u32 {
+        self.priorities
+            .get(&(room_name.to_string(), device_name.to_string()))
+            .copied()
+            .unwrap_or(DEFAULT_PRIORITY)
+    }
+
+    /// Forget priorities and shed state of a device that left the house,
+    /// of every device of the room without `device_name`
+    pub fn remove_devices(&mut self, room_name: &str, device_name: Option<&str>) {
+        let matches = |room: &str, device: &str| {
+            room == room_name && device_name.is_none_or(|device_name| device == device_name)
+        };
+        self.priorities
+            .retain(|(room, device), _| !matches(room, device));
+        self.shed_devices
+            .retain(|shed| !matches(&shed.room_name, &shed.device_name));
+    }
+
+    /// Keep priority and shed state of a renamed device,
+    /// of every device of a renamed room without `device_name`
+    pub fn rename(&mut self, room_name: &str, device_name: Option<&str>, new_name: &str) {
+        let rename = |room: &mut String, device: &mut String| {
+            if room != room_name {
+                return;
+            }
+            match device_name {
+                Some(device_name) if device == device_name => *device = new_name.to_string(),
+                Some(_) => {}
+                None => *room = new_name.to_string(),
+            }
+        };
+        self.priorities = std::mem::take(&mut self.priorities)
+            .into_iter()
+            .map(|((mut room, mut device), priority)| {
+                rename(&mut room, &mut device);
+                ((room, device), priority)
+            })
+            .collect();
+        for shed in &mut self.shed_devices {
+            rename(&mut shed.room_name, &mut shed.device_name);
+        }
+    }
+
+    pub fn history(&self) -> &[ShedEvent] {
+        &self.history
+    }
+
+    pub fn shed_devices(&self) -> Vec<(&str, &str)> {
+        self.shed_devices
+            .iter()
+            .map(|device| (device.room_name.as_str(), device.device_name.as_str()))
+            .collect()
+    }
+
+    /// Total consumption of all devices that are currently on
+    pub fn total_power(smart_house: &SmartHouse) -> f32 {
+        Self::consumers(smart_house)
+            .iter()
+            .map(|(_, _, power)| power)
+            .sum()
+    }
+
+    fn consumers(smart_house: &SmartHouse) -> Vec<(String, String, f32)> {
+        let mut consumers = vec![];
+        for room in smart_house.get_rooms() {
+            for device in room.get_devices() {
+                let device = device.read().unwrap();
+                if let Some(power) = device.power_consumption().filter(|power| power.is_finite()) {
+                    consumers.push((
+                        room.name().to_string(),
+                        device.get_device_name().to_string(),
+                        power,
+                    ));
+                }
+            }
+        }
+        consumers
+    }
+
+    /// Shed or restore devices according to the current consumption.
+    /// Returns the decisions made during this call, they are also kept in the history
+    pub fn enforce(&mut self, smart_house: &SmartHouse) -> Vec<ShedEvent> {
+        self.forget_changed_devices(smart_house);
+
+        let mut events = self.shed(smart_house);
+        if events.is_empty() {
+            events = self.restore(smart_house);
+        }
+
+        if !events.is_empty() {
+            self.last_change = Some(self.clock.now());
+        }
+        self.history.extend(events.iter().cloned());
+        if self.history.len() > MAX_HISTORY_LEN {
+            let overflow = self.history.len() - MAX_HISTORY_LEN;
+            self.history.drain(..overflow);
+        }
+        events
+    }
+
+    /// Devices that were removed or turned back on by someone else are no longer ours to restore
+    fn forget_changed_devices(&mut self, smart_house: &SmartHouse) {
+        self.shed_devices.retain(|shed| {
+            smart_house
+                .get_room(&shed.room_name)
+                .and_then(|room| room.get_device(&shed.device_name))
+                .is_some_and(|device| device.read().unwrap().is_off())
+        });
+    }
+
+    fn shed(&mut self, smart_house: &SmartHouse) -> Vec<ShedEvent> {
+        let Some(max_power) = self.max_power else {
+            return vec![];
+        };
+
+        let mut consumers = Self::consumers(smart_house);
+        let mut total_power: f32 = consumers.iter().map(|(_, _, power)| power).sum();
+        if total_power <= max_power {
+            return vec![];
+        }
+
+        consumers.sort_by_key(|(room_name, device_name, _)| self.priority(room_name, device_name));
+
+        let mut events = vec![];
+        for (room_name, device_name, power) in consumers {
+            if total_power <= max_power {
+                break;
+            }
+            let Some(device) = smart_house
+                .get_room(&room_name)
+                .and_then(|room| room.get_device(&device_name))
+            else {
+                continue;
+            };
+            device.write().unwrap().turn_off();
+            total_power -= power;
+
+            let priority = self.priority(&room_name, &device_name);
+            events.push(ShedEvent {
+                action: ShedAction::TurnedOff,
+                room_name: room_name.clone(),
+                device_name: device_name.clone(),
+                priority,
+                device_power: power,
+                total_power,
+            });
+            self.shed_devices.push(ShedDevice {
+                room_name,
+                device_name,
+                priority,
+                power,
+            });
+        }
+        events
+    }
+
+    fn restore(&mut self, smart_house: &SmartHouse) -> Vec<ShedEvent> {
+        if self.shed_devices.is_empty() {
+            return vec![];
+        }
+        let cooldown_passed = self
+            .last_change
+            .is_none_or(|last_change| self.clock.now() - last_change >= self.cooldown);
+        if !cooldown_passed {
+            return vec![];
+        }
+
+        let mut total_power = Self::total_power(smart_house);
+        self.shed_devices.sort_by_key(|device| device.priority);
+
+        let mut events = vec![];
+        let mut index = self.shed_devices.len();
+        while index > 0 {
+            index -= 1;
+            let shed = &self.shed_devices[index];
+            let fits = self
+                .max_power
+                .is_none_or(|max_power| total_power + shed.power <= max_power);
+            if !fits {
+                continue;
+            }
+
+            let shed = self.shed_devices.remove(index);
+            if let Some(device) = smart_house
+                .get_room(&shed.room_name)
+                .and_then(|room| room.get_device(&shed.device_name))
+            {
+                device.write().unwrap().turn_on();
+                total_power += shed.power;
+                events.push(ShedEvent {
+                    action: ShedAction::Restored,
+                    room_name: shed.room_name,
+                    device_name: shed.device_name,
+                    priority: shed.priority,
+                    device_power: shed.power,
+                    total_power,
+                });
+            }
+        }
+        events
+    }
+
+    pub fn create_report(&self, smart_house: &SmartHouse) -> String {
+        let max_power = self.max_power.map_or_else(
+            || "unlimited".to_string(),
+            |max_power| format!("{max_power} Вт"),
+        );
+        let shed_devices: Vec<String> = self
+            .shed_devices
+            .iter()
+            .map(|device| format!("{}/{}", device.room_name, device.device_name))
+            .collect();
+        let history: Vec<String> = self.history.iter().map(|event| event.to_string()).collect();
+        format!(
+            "max_power:{max_power},total_power:{} Вт,shed:[{}],history:[{}]",
+            Self::total_power(smart_house),
+            shed_devices.join(","),
+            history.join(";")
+        )
+    }
+}
@@ -1,8 +1,10 @@
 use crate::{
+    async_device::{AsyncDevice, AsyncReporter},
     device::Device,
     reporter::Reporter,
     temperature::{Temperature, TemperatureMeasureUnits},
 };
+use async_trait::async_trait;
 use core::f32;
 use std::{str::FromStr, sync::Arc};
 
@@ -12,6 +14,22 @@ pub trait SmartSocketInfoProvider: Send + Sync {
     fn get_current_power_consumption(&self) -> f32;
 }
 
+/// Provider for smart sockets whose readings require I/O
+#[async_trait]
+pub trait AsyncSmartSocketInfoProvider: Send + Sync {
+    async fn get_current_power_consumption(
+        &self,
+    ) -> Result<f32, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+fn format_smart_socket_report(name: &str, power: f32) -> String {
+    let report_title = format!("---------{}---------", name);
+    format!(
+        "{report_title}\n Текущая потребляемая мощность: {power} Вт\n{}",
+        "-".repeat(report_title.chars().count())
+    )
+}
+
 pub struct SmartSocket {
     name: String,
     smart_socket_info_provider: Arc<dyn SmartSocketInfoProvider>,
@@ -61,6 +79,18 @@ impl Device for SmartSocket {
     fn get_device_name(&self) -> &str {
         &self.name
     }
+
+    fn set_device_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
+    fn kind(&self) -> &str {
+        "smart_socket"
+    }
+
+    fn power_consumption(&self) -> Option<f32> {
+        self.get_current_power_consumption()
+    }
 }
 
 impl Reporter for SmartSocket {
@@ -69,88 +99,92 @@ impl Reporter for SmartSocket {
             return Err("SmartSocket is off".into());
         }
 
-        match self.get_current_power_consumption() {
-            None => return Err("SmartSockerReporterError::PowerCannotBeParsed".into()),
-            Some(power) if power.is_finite() => {}
+        let result_power = match self.get_current_power_consumption() {
+            Some(power) if power.is_finite() => power,
             _ => return Err("SmartSockerReporterError::PowerCannotBeParsed".into()),
-        }
+        };
 
-        let report_title = format!("---------{}---------", self.name);
-        let result_power = self.get_current_power_consumption().unwrap_or(f32::NAN);
-        Ok(format!(
-            "{report_title}\n Текущая потребляемая мощность: {result_power} Вт\n{}",
-            "-".repeat(report_title.chars().count())
-        ))
+        Ok(format_smart_socket_report(&self.name, result_power))
     }
 }
 
-pub struct TemperatureProvider {
-    pub value: f32,
-    pub measure_units: TemperatureMeasureUnits,
+pub struct AsyncSmartSocket {
+    name: String,
+    smart_socket_info_provider: Arc<dyn AsyncSmartSocketInfoProvider>,
+    is_on: bool,
 }
 
-impl ThermometerInfoProvider for TemperatureProvider {
-    fn get_temperature(&self) -> Temperature {
-        Temperature::new(self.value, self.measure_units)
+impl AsyncSmartSocket {
+    pub fn new(
+        name: &str,
+        smart_socket_info_provider: Arc<dyn AsyncSmartSocketInfoProvider>,
+    ) -> AsyncSmartSocket {
+        AsyncSmartSocket {
+            name: name.to_string(),
+            smart_socket_info_provider,
+            is_on: true,
+        }
+    }
+
+    pub async fn get_current_power_consumption(
+        &self,
+    ) -> Result<f32, Box<dyn std::error::Error + Send + Sync>> {
+        if !self.is_on {
+            return Err("SmartSocket is off".into());
+        }
+        self.smart_socket_info_provider
+            .get_current_power_consumption()
+            .await
     }
 }
 
-#[cfg(test)]
-mod smart_socket_tests {
-    use super::*;
+#[async_trait]
+impl AsyncDevice for AsyncSmartSocket {
+    async fn turn_on(&mut self) {
+        self.is_on = true;
+    }
+
+    async fn turn_off(&mut self) {
+        self.is_on = false;
+    }
+
+    async fn is_on(&self) -> bool {
+        self.is_on
+    }
 
-    struct MockSmartSocketInfoProvider;
+    async fn is_off(&self) -> bool {
+        !self.is_on
+    }
 
-    impl SmartSocketInfoProvider for MockSmartSocketInfoProvider {
-        fn get_current_power_consumption(&self) -> f32 {
-            10.0
+    fn get_device_name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_device_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+}
+
+#[async_trait]
+impl AsyncReporter for AsyncSmartSocket {
+    async fn create_report(&self) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
+        let power = self.get_current_power_consumption().await?;
+        if !power.is_finite() {
+            return Err("SmartSockerReporterError::PowerCannotBeParsed".into());
         }
+        Ok(format_smart_socket_report(&self.name, power))
     }
+}
 
-    #[test]
-    fn test_smart_socket_creation() {
-        let info_provider = Arc::new(MockSmartSocketInfoProvider);
-        let socket = SmartSocket::new("Test Socket", info_provider);
-        assert_eq!(socket.name, "Test Socket");
-        assert!(socket.is_on);
-    }
-
-    #[test]
-    fn test_get_current_power_consumption() {
-        let info_provider = Arc::new(MockSmartSocketInfoProvider);
-        let socket = SmartSocket::new("Test Socket", info_provider);
-        assert_eq!(socket.get_current_power_consumption(), Some(10.0));
-    }
-
-    #[test]
-    fn test_turn_off_socket() {
-        let info_provider = Arc::new(MockSmartSocketInfoProvider);
-        let mut socket = SmartSocket::new("Test Socket", info_provider);
-        socket.turn_off();
-        assert!(socket.is_off());
-        socket.turn_off();
-        assert!(socket.is_off());
-    }
-
-    #[test]
-    fn test_change_toggle_socket() {
-        let info_provider = Arc::new(MockSmartSocketInfoProvider);
-        let mut socket = SmartSocket::new("Test Socket", info_provider);
-        assert!(socket.is_on());
-        socket.toggle();
-        assert!(socket.is_off());
-        socket.toggle();
-        assert!(socket.is_on());
-    }
-
-    #[test]
-    fn test_change_turn_on() {
-        let info_provider = Arc::new(MockSmartSocketInfoProvider);
-        let mut socket = SmartSocket::new("Test Socket", info_provider);
-        assert!(socket.is_on());
-        socket.turn_off();
-        assert!(socket.is_off());
-        socket.turn_off();
-        assert!(socket.is_off());
+/// Constant reading for the servers' demo house and devices added at runtime.
+/// Tests use the scripted providers of `smart_house_testkit`
+pub struct TemperatureProvider {
+    pub value: f32,
+    pub measure_units: TemperatureMeasureUnits,
+}
+
+impl ThermometerInfoProvider for TemperatureProvider {
+    fn get_temperature(&self) -> Temperature {
+        Temperature::new(self.value, self.measure_units)
     }
 }
@@ -1,6 +1,9 @@
 use std::str::FromStr;
 use std::sync::Arc;
 
+use async_trait::async_trait;
+
+use crate::async_device::{AsyncDevice, AsyncReporter};
 use crate::device::Device;
 use crate::reporter::Reporter;
 use crate::temperature::{Temperature, TemperatureMeasureUnits};
@@ -11,6 +14,22 @@ pub trait ThermometerInfoProvider: Send + Sync {
     fn get_temperature(&self) -> Temperature;
 }
 
+/// Provider for thermometers whose readings require I/O
+#[async_trait]
+pub trait AsyncThermometerInfoProvider: Send + Sync {
+    async fn get_temperature(
+        &self,
+    ) -> Result<Temperature, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+fn format_thermometer_report(name: &str, temperature: Temperature) -> String {
+    let report_title = format!("---------{}---------", name);
+    format!(
+        "{report_title}\n Температура: {temperature}\n{}",
+        "-".repeat(report_title.chars().count())
+    )
+}
+
 pub struct Thermometer {
     name: String,
     thermometer_info_provider: Arc<dyn ThermometerInfoProvider>,
@@ -60,6 +79,19 @@ impl Device for Thermometer {
     fn get_device_name(&self) -> &str {
         &self.name
     }
+
+    fn set_device_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
+    fn kind(&self) -> &str {
+        "thermometer"
+    }
+
+    fn temperature(&self) -> Option<Temperature> {
+        self.get_temperature(TemperatureMeasureUnits::Celsius)
+            .map(|temperature| temperature.convert_from_to(TemperatureMeasureUnits::Celsius))
+    }
 }
 
 impl Reporter for Thermometer {
@@ -68,96 +100,89 @@ impl Reporter for Thermometer {
             return Err("ThermometerIsOff".into());
         }
 
-        match self.get_temperature(TemperatureMeasureUnits::Celsius) {
-            None => return Err("TemperatureCannotBeParsed".into()),
-            Some(temp) if temp.get_value().is_finite() => {}
+        let result_temperature = match self.get_temperature(TemperatureMeasureUnits::Celsius) {
+            Some(temp) if temp.get_value().is_finite() => temp,
             _ => return Err("TemperatureCannotBeParsed".into()),
-        }
+        };
 
-        let report_title = format!("---------{}---------", self.name);
-        let result_temperature = self
-            .get_temperature(TemperatureMeasureUnits::Celsius)
-            .unwrap_or(Temperature::new(f32::NAN, TemperatureMeasureUnits::Celsius));
-        Ok(format!(
-            "{report_title}\n Температура: {result_temperature}\n{}",
-            "-".repeat(report_title.chars().count())
-        ))
+        Ok(format_thermometer_report(&self.name, result_temperature))
     }
 }
 
-pub struct EnergyProvider {
-    pub value: f32,
+pub struct AsyncThermometer {
+    name: String,
+    thermometer_info_provider: Arc<dyn AsyncThermometerInfoProvider>,
+    is_on: bool,
 }
 
-impl SmartSocketInfoProvider for EnergyProvider {
-    fn get_current_power_consumption(&self) -> f32 {
-        self.value
+impl AsyncThermometer {
+    pub fn new(
+        name: &str,
+        thermometer_info_provider: Arc<dyn AsyncThermometerInfoProvider>,
+    ) -> AsyncThermometer {
+        AsyncThermometer {
+            name: name.to_string(),
+            thermometer_info_provider,
+            is_on: true,
+        }
+    }
+
+    pub async fn get_temperature(
+        &self,
+    ) -> Result<Temperature, Box<dyn std::error::Error + Send + Sync>> {
+        if !self.is_on {
+            return Err("ThermometerIsOff".into());
+        }
+        self.thermometer_info_provider.get_temperature().await
     }
 }
 
-#[cfg(test)]
-mod smart_socket_tests {
-    use super::*;
-    use crate::temperature::{Temperature, TemperatureMeasureUnits};
+#[async_trait]
+impl AsyncDevice for AsyncThermometer {
+    async fn turn_on(&mut self) {
+        self.is_on = true;
+    }
+
+    async fn turn_off(&mut self) {
+        self.is_on = false;
+    }
+
+    async fn is_on(&self) -> bool {
+        self.is_on
+    }
 
-    struct MockThermometerInfoProvider {
-        temperature: Temperature,
+    async fn is_off(&self) -> bool {
+        !self.is_on
     }
 
-    impl ThermometerInfoProvider for MockThermometerInfoProvider {
-        fn get_temperature(&self) -> Temperature {
-            self.temperature
+    fn get_device_name(&self) -> &str {
+        &self.name
+    }
+
+    fn set_device_name(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+}
+
+#[async_trait]
+impl AsyncReporter for AsyncThermometer {
+    async fn create_report(&self) -> Result<String, Box<dyn std::error::Error + Sync + Send>> {
+        let temperature = self.get_temperature().await?;
+        if !temperature.get_value().is_finite() {
+            return Err("TemperatureCannotBeParsed".into());
         }
+        Ok(format_thermometer_report(&self.name, temperature))
     }
+}
 
-    #[test]
-    fn test_thermometer_creation() {
-        let thermometer_info_provider = Arc::new(MockThermometerInfoProvider {
-            temperature: Temperature::new(10.0, TemperatureMeasureUnits::Celsius),
-        });
-        let thermometer = Thermometer::new("Test Thermometer", thermometer_info_provider);
-        assert_eq!(thermometer.name, "Test Thermometer");
-        assert!(thermometer.is_on);
-    }
-
-    #[test]
-    fn test_thermometer_get_temperature() {
-        let thermometer_info_provider = Arc::new(MockThermometerInfoProvider {
-            temperature: Temperature::new(10.0, TemperatureMeasureUnits::Celsius),
-        });
-
-        let thermometer = Thermometer::new("Test Thermometer", thermometer_info_provider);
-        let temperature = thermometer.get_temperature(TemperatureMeasureUnits::Celsius);
-        assert!(temperature.is_some());
-
-        let temperature = temperature.unwrap();
-        assert_eq!(temperature.get_value(), 10.0);
-        assert_eq!(
-            temperature.get_measure_units(),
-            TemperatureMeasureUnits::Celsius
-        );
-    }
-
-    #[test]
-    fn test_thermometer_turn_on_and_off() {
-        let thermometer_info_provider = Arc::new(MockThermometerInfoProvider {
-            temperature: Temperature::new(10.0, TemperatureMeasureUnits::Celsius),
-        });
-        let mut thermometer = Thermometer::new("Test Thermometer", thermometer_info_provider);
-        thermometer.turn_off();
-        assert!(!thermometer.is_on);
-        thermometer.turn_on();
-        assert!(thermometer.is_on);
-    }
-
-    #[test]
-    fn test_thermometer_get_temperature_when_off() {
-        let thermometer_info_provider = Arc::new(MockThermometerInfoProvider {
-            temperature: Temperature::new(10.0, TemperatureMeasureUnits::Celsius),
-        });
-        let mut thermometer = Thermometer::new("Test Thermometer", thermometer_info_provider);
-        thermometer.turn_off();
-        let temperature = thermometer.get_temperature(TemperatureMeasureUnits::Celsius);
-        assert!(temperature.is_none());
+/// Constant reading for the servers' demo house and devices added at runtime.
+/// Tests use the scripted providers of `smart_house_testkit`
+pub struct EnergyProvider {
+    pub value: f32,
+}
+
+impl SmartSocketInfoProvider for EnergyProvider {
+    fn get_current_power_consumption(&self) -> f32 {
+        self.value
     }
 }
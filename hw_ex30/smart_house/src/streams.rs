@@ -0,0 +1,377 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Name of the report stream of a device, `room-device`.
+/// `-` and `\` in the names are escaped with `\`, so no two devices share a stream name:
+/// room `a-b` with device `c` gives `a\-b-c`, room `a` with device `b-c` gives `a-b\-c`
+pub fn stream_name(room_name: &str, device_name: &str) -> String {
+    let escape = |name: &str| name.replace('\\', "\\\\").replace('-', "\\-");
+    format!("{}-{}", escape(room_name), escape(device_name))
+}
+
+/// Thread or task sending the reports of a stream
+pub trait StreamHandle {
+    /// Tell it to stop, without waiting for it
+    fn cancel(&self);
+
+    fn is_finished(&self) -> bool;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamError {
+    CantFindStream,
+    NotStreamOwner,
+}
+
+impl Display for StreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamError::CantFindStream => write!(f, "cant find stream"),
+            StreamError::NotStreamOwner => write!(f, "stream belongs to another client"),
+        }
+    }
+}
+
+/// Who may renew and cancel a stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamOwner {
+    /// Authenticated client, from any of its sessions
+    Client(String),
+    /// Session of a client that has not authenticated
+    Session(u64),
+}
+
+impl Display for StreamOwner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamOwner::Client(identity) => write!(f, "client {identity}"),
+            StreamOwner::Session(id) => write!(f, "session {id}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Lease {
+    ttl: Option<Duration>,
+    expires_at: Option<Instant>,
+}
+
+/// Shared by the registry entry and the stream handle
+pub struct StreamState {
+    delivered: AtomicU64,
+    failed: AtomicU64,
+    lease: Mutex<Lease>,
+}
+
+impl StreamState {
+    /// Without `ttl` the stream runs until it is cancelled
+    pub fn new(ttl: Option<Duration>) -> Self {
+        Self {
+            delivered: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            lease: Mutex::new(Lease {
+                ttl,
+                expires_at: ttl.map(|ttl| Instant::now() + ttl),
+            }),
+        }
+    }
+
+    pub fn record(&self, delivered: bool) {
+        let counter = match delivered {
+            true => &self.delivered,
+            false => &self.failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn is_expired(&self) -> bool {
+        let expires_at = self.lease.lock().unwrap().expires_at;
+        expires_at.is_some_and(|expires_at| expires_at <= Instant::now())
+    }
+
+    /// How long the stream handle sleeps before the next report or the end of the lease
+    pub fn sleep_time(&self, next_report: Instant) -> Duration {
+        let wake_at = match self.lease.lock().unwrap().expires_at {
+            Some(expires_at) => expires_at.min(next_report),
+            None => next_report,
+        };
+        wake_at.saturating_duration_since(Instant::now())
+    }
+
+    /// Extend the lease by `ttl`, or by the TTL the stream has. `None` if it never expires
+    fn renew(&self, ttl: Option<Duration>) -> Option<Duration> {
+        let mut lease = self.lease.lock().unwrap();
+        let ttl = ttl.or(lease.ttl)?;
+        *lease = Lease {
+            ttl: Some(ttl),
+            expires_at: Some(Instant::now() + ttl),
+        };
+        Some(ttl)
+    }
+
+    fn expires_in(&self) -> Option<Duration> {
+        let expires_at = self.lease.lock().unwrap().expires_at;
+        expires_at.map(|expires_at| expires_at.saturating_duration_since(Instant::now()))
+    }
+}
+
+/// Report stream and the handle sending its reports
+pub struct StreamEntry<H> {
+    pub owner: StreamOwner,
+    /// `udp <addr>` or `push`
+    pub target: String,
+    pub interval: Duration,
+    pub created: SystemTime,
+    pub state: Arc<StreamState>,
+    pub handle: H,
+}
+
+impl<H> StreamEntry<H> {
+    fn info(&self, name: &str) -> String {
+        let created = self
+            .created
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let expires_in = self
+            .state
+            .expires_in()
+            .map_or_else(|| "never".to_string(), |left| left.as_secs().to_string());
+        format!(
+            "name:{name},owner:{},target:{},interval:{},created:{created},expires_in:{expires_in},delivered:{},failed:{}",
+            self.owner,
+            self.target,
+            self.interval.as_secs(),
+            self.state.delivered.load(Ordering::Relaxed),
+            self.state.failed.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Report streams by name. Only the owner of a stream can replace, renew or cancel it
+pub struct StreamRegistry<H> {
+    streams: BTreeMap<String, StreamEntry<H>>,
+    /// Cancelled handles, waited for on shutdown
+    stopped: Vec<H>,
+}
+
+impl<H> Default for StreamRegistry<H> {
+    fn default() -> Self {
+        Self {
+            streams: BTreeMap::new(),
+            stopped: vec![],
+        }
+    }
+}
+
+impl<H: StreamHandle> StreamRegistry<H> {
+    /// The handle is not waited for here: it may be waiting for the lock the caller holds
+    fn stop(&mut self, entry: StreamEntry<H>) {
+        entry.handle.cancel();
+        self.stopped.retain(|handle| !handle.is_finished());
+        self.stopped.push(entry.handle);
+    }
+
+    fn owned(
+        &self,
+        name: &str,
+        owner: &StreamOwner,
+    ) -> Result<Option<&StreamEntry<H>>, StreamError> {
+        match self.streams.get(name) {
+            Some(entry) if entry.owner != *owner => Err(StreamError::NotStreamOwner),
+            entry => Ok(entry),
+        }
+    }
+
+    /// Fails if a stream with the same name belongs to someone else
+    pub fn check_owner(&self, name: &str, owner: &StreamOwner) -> Result<(), StreamError> {
+        self.owned(name, owner).map(|_| ())
+    }
+
+    /// A stream of the same name is cancelled and replaced
+    pub fn insert(&mut self, name: String, entry: StreamEntry<H>) {
+        if let Some(replaced) = self.streams.insert(name, entry) {
+            self.stop(replaced);
+        }
+    }
+
+    /// `false` if there is no such stream
+    pub fn cancel(&mut self, name: &str, owner: &StreamOwner) -> Result<bool, StreamError> {
+        if self.owned(name, owner)?.is_none() {
+            return Ok(false);
+        }
+        if let Some(entry) = self.streams.remove(name) {
+            self.stop(entry);
+        }
+        Ok(true)
+    }
+
+    /// Returns the new TTL, `None` for a stream that never expires
+    pub fn renew(
+        &self,
+        name: &str,
+        owner: &StreamOwner,
+        ttl: Option<Duration>,
+    ) -> Result<Option<Duration>, StreamError> {
+        let entry = self
+            .owned(name, owner)?
+            .ok_or(StreamError::CantFindStream)?;
+        Ok(entry.state.renew(ttl))
+    }
+
+    /// Cancel a stream regardless of its owner, e.g. when its device is removed
+    pub fn remove(&mut self, name: &str) {
+        if let Some(entry) = self.streams.remove(name) {
+            self.stop(entry);
+        }
+    }
+
+    /// Called by a stream handle that stops by itself.
+    /// The entry is left alone if the stream has been replaced meanwhile
+    pub fn finish(&mut self, name: &str, state: &Arc<StreamState>) {
+        if let Some(entry) = self.streams.get(name) {
+            if Arc::ptr_eq(&entry.state, state) {
+                self.streams.remove(name);
+            }
+        }
+    }
+
+    pub fn info(&self, name: &str) -> Option<String> {
+        self.streams.get(name).map(|entry| entry.info(name))
+    }
+
+    /// `[stream;stream]` in the order of the names
+    pub fn list(&self) -> String {
+        let streams: Vec<_> = self
+            .streams
+            .iter()
+            .map(|(name, entry)| entry.info(name))
+            .collect();
+        format!("[{}]", streams.join(";"))
+    }
+
+    pub fn take_all(&mut self) -> Vec<StreamEntry<H>> {
+        std::mem::take(&mut self.streams).into_values().collect()
+    }
+
+    /// Handles of cancelled streams that may still be running
+    pub fn take_stopped(&mut self) -> Vec<H> {
+        std::mem::take(&mut self.stopped)
+    }
+}
+
+#[cfg(test)]
+mod streams_tests {
+    use std::sync::atomic::AtomicBool;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct StubHandle {
+        cancelled: AtomicBool,
+    }
+
+    impl StreamHandle for StubHandle {
+        fn cancel(&self) {
+            self.cancelled.store(true, Ordering::Relaxed);
+        }
+
+        fn is_finished(&self) -> bool {
+            self.cancelled.load(Ordering::Relaxed)
+        }
+    }
+
+    fn entry(owner: StreamOwner, ttl: Option<Duration>) -> StreamEntry<StubHandle> {
+        StreamEntry {
+            owner,
+            target: "push".to_string(),
+            interval: Duration::from_secs(1),
+            created: SystemTime::now(),
+            state: Arc::new(StreamState::new(ttl)),
+            handle: StubHandle::default(),
+        }
+    }
+
+    #[test]
+    fn only_the_owner_cancels_and_renews() {
+        let mut registry = StreamRegistry::default();
+        let owner = StreamOwner::Client("alice".to_string());
+        let other = StreamOwner::Session(1);
+        registry.insert("Кухня-Розетка1".to_string(), entry(owner.clone(), None));
+
+        assert!(matches!(
+            registry.check_owner("Кухня-Розетка1", &other),
+            Err(StreamError::NotStreamOwner)
+        ));
+        assert!(matches!(
+            registry.cancel("Кухня-Розетка1", &other),
+            Err(StreamError::NotStreamOwner)
+        ));
+        assert!(matches!(
+            registry.renew("Кухня-Розетка1", &other, None),
+            Err(StreamError::NotStreamOwner)
+        ));
+        assert_eq!(
+            registry.renew("Кухня-Розетка1", &owner, None).unwrap(),
+            None
+        );
+        assert!(registry.cancel("Кухня-Розетка1", &owner).unwrap());
+        assert!(!registry.cancel("Кухня-Розетка1", &owner).unwrap());
+    }
+
+    #[test]
+    fn stream_names_do_not_collide() {
+        assert_eq!(stream_name("Кухня", "Розетка1"), "Кухня-Розетка1");
+        assert_ne!(stream_name("a-b", "c"), stream_name("a", "b-c"));
+        assert_ne!(stream_name("a\\", "-b"), stream_name("a\\-", "b"));
+        assert_eq!(stream_name("a-b", "c\\"), "a\\-b-c\\\\");
+    }
+
+    #[test]
+    fn lease_expires_and_is_renewed() {
+        let state = StreamState::new(Some(Duration::ZERO));
+        assert!(state.is_expired());
+        assert_eq!(
+            state.renew(Some(Duration::from_secs(60))),
+            Some(Duration::from_secs(60))
+        );
+        assert!(!state.is_expired());
+        let next_report = Instant::now() + Duration::from_secs(1);
+        assert!(state.sleep_time(next_report) <= Duration::from_secs(1));
+        assert_eq!(StreamState::new(None).renew(None), None);
+    }
+
+    #[test]
+    fn finished_stream_does_not_remove_its_replacement() {
+        let mut registry = StreamRegistry::default();
+        let owner = StreamOwner::Session(1);
+        let first = entry(owner.clone(), None);
+        let first_state = first.state.clone();
+        registry.insert("stream".to_string(), first);
+        registry.insert("stream".to_string(), entry(owner, None));
+        registry.finish("stream", &first_state);
+        assert!(registry.info("stream").is_some());
+    }
+
+    #[test]
+    fn cancelled_handles_are_kept_until_they_finish() {
+        let mut registry = StreamRegistry::default();
+        let owner = StreamOwner::Session(1);
+        registry.insert("stream".to_string(), entry(owner.clone(), None));
+        registry.insert("stream".to_string(), entry(owner.clone(), None));
+        assert!(registry.cancel("stream", &owner).unwrap());
+
+        // The stub finishes as soon as it is cancelled, so only the last one is left
+        let stopped = registry.take_stopped();
+        assert_eq!(stopped.len(), 1);
+        assert!(stopped[0].cancelled.load(Ordering::Relaxed));
+        assert!(registry.take_all().is_empty());
+    }
+}
@@ -0,0 +1,45 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use smart_house::{adapters::AsyncDeviceAdapter, async_device::AsyncReporter, SmartHouse};
+use smart_house_testkit::{house_builder::HouseBuilder, providers::ScriptedPowerProvider};
+
+const SLOW_DEVICE_DELAY: Duration = Duration::from_millis(500);
+
+fn adapter(house: &SmartHouse, device_name: &str) -> AsyncDeviceAdapter {
+    AsyncDeviceAdapter::new(
+        device_name,
+        house
+            .get_room("Кухня")
+            .unwrap()
+            .get_device(device_name)
+            .unwrap(),
+    )
+}
+
+// One worker: a device call made inline would stall every other task until it answers
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn slow_device_does_not_stall_the_runtime() {
+    let house = HouseBuilder::new()
+        .room("Кухня", |room| {
+            room.socket_with(
+                "Чайник",
+                Arc::new(ScriptedPowerProvider::constant(2000.0).with_delay(SLOW_DEVICE_DELAY)),
+            )
+            .socket("Розетка1", 100.0)
+        })
+        .build();
+    let slow = adapter(&house, "Чайник");
+    let fast = adapter(&house, "Розетка1");
+
+    let started = Instant::now();
+    let slow_report = tokio::spawn(async move { slow.create_report().await.unwrap() });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let fast_report = tokio::spawn(async move { fast.create_report().await.unwrap() });
+
+    assert!(fast_report.await.unwrap().contains("Розетка1"));
+    assert!(started.elapsed() < SLOW_DEVICE_DELAY);
+    assert!(slow_report.await.unwrap().contains("Чайник"));
+}
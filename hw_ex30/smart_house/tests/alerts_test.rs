@@ -0,0 +1,142 @@
+use std::{sync::Arc, time::Duration};
+
+use smart_house::{
+    alerts::{AlertDefinition, AlertEventKind, AlertManager, Condition, Metric, Severity},
+    SmartHouse,
+};
+use smart_house_testkit::{
+    clock::FakeClock,
+    house_builder::HouseBuilder,
+    providers::{Reading, ScriptedPowerProvider, ScriptedTemperatureProvider},
+};
+
+fn cold_bedroom() -> AlertDefinition {
+    AlertDefinition {
+        name: "cold_bedroom".to_string(),
+        room_name: "Спальня".to_string(),
+        device_name: "Термометр3".to_string(),
+        metric: Metric::Temperature,
+        condition: Condition::Below,
+        threshold: 10.0,
+        hysteresis: 1.0,
+        raise_after: Duration::ZERO,
+        severity: Severity::Warning,
+    }
+}
+
+fn house_with_temperatures(values: &[f32]) -> SmartHouse {
+    let provider = Arc::new(ScriptedTemperatureProvider::from_values(values));
+    HouseBuilder::new()
+        .room("Спальня", |room| {
+            room.thermometer_with("Термометр3", provider)
+        })
+        .build()
+}
+
+#[test]
+fn raise_and_clear_with_hysteresis() {
+    let house = house_with_temperatures(&[12.0, 9.5, 10.5, 11.5]);
+    let mut alerts = AlertManager::new(Arc::new(FakeClock::new()));
+    alerts.add_unique_alert(cold_bedroom());
+
+    assert!(alerts.evaluate(&house).is_empty());
+
+    let events = alerts.evaluate(&house);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].kind, AlertEventKind::Raised);
+    assert_eq!(alerts.active_alerts().len(), 1);
+
+    // Above the threshold, but still inside the hysteresis band
+    assert!(alerts.evaluate(&house).is_empty());
+    assert_eq!(alerts.active_alerts().len(), 1);
+
+    let events = alerts.evaluate(&house);
+    assert_eq!(events[0].kind, AlertEventKind::Cleared);
+    assert!(alerts.active_alerts().is_empty());
+    assert_eq!(alerts.history().len(), 2);
+}
+
+#[test]
+fn raise_only_after_condition_holds() {
+    let provider = Arc::new(ScriptedPowerProvider::from_values(&[
+        2500.0, 2500.0, 2500.0,
+    ]));
+    let house = HouseBuilder::new()
+        .room("Кухня", |room| room.socket_with("Розетка1", provider))
+        .build();
+    let clock = Arc::new(FakeClock::new());
+    let mut alerts = AlertManager::new(clock.clone());
+    alerts.add_unique_alert(AlertDefinition {
+        name: "kettle".to_string(),
+        room_name: "Кухня".to_string(),
+        device_name: "Розетка1".to_string(),
+        metric: Metric::Power,
+        condition: Condition::Above,
+        threshold: 2000.0,
+        hysteresis: 100.0,
+        raise_after: Duration::from_secs(30),
+        severity: Severity::Critical,
+    });
+
+    assert!(alerts.evaluate(&house).is_empty());
+    clock.advance(Duration::from_secs(10));
+    assert!(alerts.evaluate(&house).is_empty());
+    clock.advance(Duration::from_secs(20));
+    let events = alerts.evaluate(&house);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].severity, Severity::Critical);
+}
+
+#[test]
+fn pending_alert_resets_when_reading_fails() {
+    let provider = Arc::new(ScriptedTemperatureProvider::new(vec![
+        Reading::Value(5.0),
+        Reading::Failure("sensor offline".to_string()),
+        Reading::Value(5.0),
+    ]));
+    let house = HouseBuilder::new()
+        .room("Спальня", |room| {
+            room.thermometer_with("Термометр3", provider)
+        })
+        .build();
+    let clock = Arc::new(FakeClock::new());
+    let mut alerts = AlertManager::new(clock.clone());
+    alerts.add_unique_alert(AlertDefinition {
+        raise_after: Duration::from_secs(30),
+        ..cold_bedroom()
+    });
+
+    alerts.evaluate(&house);
+    clock.advance(Duration::from_secs(30));
+    assert!(alerts.evaluate(&house).is_empty());
+    assert!(alerts.evaluate(&house).is_empty());
+    assert!(alerts.active_alerts().is_empty());
+}
+
+#[test]
+fn alert_names_are_unique() {
+    let mut alerts = AlertManager::new(Arc::new(FakeClock::new()));
+    assert_eq!(alerts.add_unique_alert(cold_bedroom()), Some(0));
+    assert_eq!(alerts.add_unique_alert(cold_bedroom()), None);
+    assert!(alerts.remove_alert("cold_bedroom").is_some());
+    assert!(alerts.definitions().is_empty());
+}
+
+#[test]
+fn alerts_follow_renames_and_are_removed_with_the_device() {
+    let mut alerts = AlertManager::new(Arc::new(FakeClock::new()));
+    alerts.add_unique_alert(cold_bedroom());
+
+    alerts.rename("Спальня", Some("Термометр3"), "Термометр4");
+    alerts.rename("Спальня", None, "Детская");
+    let definition = alerts.definitions()[0];
+    assert_eq!(definition.room_name, "Детская");
+    assert_eq!(definition.device_name, "Термометр4");
+
+    assert!(alerts
+        .remove_devices("Детская", Some("Термометр3"))
+        .is_empty());
+    let removed = alerts.remove_devices("Детская", None);
+    assert_eq!(removed[0].name, "cold_bedroom");
+    assert!(alerts.definitions().is_empty());
+}
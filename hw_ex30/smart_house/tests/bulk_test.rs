@@ -0,0 +1,84 @@
+use std::thread;
+
+use smart_house::{
+    bulk::{create_results_report, DeviceGroup, DeviceOperationError, PowerAction},
+    SmartHouse,
+};
+use smart_house_testkit::house_builder::HouseBuilder;
+
+fn create_house() -> SmartHouse {
+    HouseBuilder::new()
+        .room("Kitchen", |room| {
+            room.socket("Socket1", 100.0)
+                .thermometer("Thermometer1", 20.0)
+        })
+        .room("Bedroom", |room| room.socket("Socket2", 50.0))
+        .build()
+}
+
+#[test]
+fn room_off() {
+    let house = create_house();
+    let results = house
+        .set_group_power_state(&DeviceGroup::room("Kitchen"), PowerAction::Off)
+        .unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(results.iter().all(|result| result.result == Ok(false)));
+    assert!(house
+        .get_room("Bedroom")
+        .unwrap()
+        .get_device("Socket2")
+        .unwrap()
+        .read()
+        .unwrap()
+        .is_on());
+}
+
+#[test]
+fn kind_in_house() {
+    let house = create_house();
+    let results = house
+        .set_group_power_state(&DeviceGroup::kind("smart_socket"), PowerAction::Toggle)
+        .unwrap();
+    let names: Vec<_> = results
+        .iter()
+        .map(|result| result.device_name.as_str())
+        .collect();
+    assert_eq!(names, ["Socket1", "Socket2"]);
+    assert!(results.iter().all(|result| result.result == Ok(false)));
+}
+
+#[test]
+fn unknown_room() {
+    let house = create_house();
+    assert!(house
+        .set_group_power_state(&DeviceGroup::room("Garage"), PowerAction::On)
+        .is_none());
+}
+
+#[test]
+fn failures_are_reported_per_device() {
+    let house = create_house();
+    let socket = house
+        .get_room("Bedroom")
+        .unwrap()
+        .get_device("Socket2")
+        .unwrap();
+    let poisoned = thread::spawn(move || {
+        let _device = socket.write().unwrap();
+        panic!("device panicked");
+    })
+    .join();
+    assert!(poisoned.is_err());
+
+    let results = house
+        .set_group_power_state(&DeviceGroup::house(), PowerAction::Off)
+        .unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[2].result, Err(DeviceOperationError::LockPoisoned));
+    assert!(results[..2].iter().all(|result| result.result.is_ok()));
+    assert_eq!(
+        create_results_report(&results[1..]),
+        "[room_name:Kitchen,device_name:Thermometer1,is_on:false;room_name:Bedroom,device_name:Socket2,error:device lock poisoned]"
+    );
+}
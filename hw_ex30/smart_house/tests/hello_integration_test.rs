@@ -1,113 +1,59 @@
-use smart_house::{
-    smart_tools::{smart_socket::SmartSocketInfoProvider, thermomener::ThermometerInfoProvider},
-    temperature::{Temperature, TemperatureMeasureUnits},
+use smart_house::{device::Device, reporter::Reporter, smart_tools::thermomener::Thermometer};
+use smart_house_testkit::{
+    house_builder::HouseBuilder,
+    providers::{Reading, ScriptedPowerProvider, ScriptedTemperatureProvider},
 };
-
-struct TemperatureProvider {
-    value: f32,
-    measure_units: TemperatureMeasureUnits,
-}
-
-impl ThermometerInfoProvider for TemperatureProvider {
-    fn get_temperature(&self) -> Temperature {
-        Temperature::new(self.value, self.measure_units)
-    }
-}
-
-struct EnergyProvider {
-    value: f32,
-}
-
-impl SmartSocketInfoProvider for EnergyProvider {
-    fn get_current_power_consumption(&self) -> f32 {
-        self.value
-    }
-}
+use std::sync::Arc;
 
 #[cfg(test)]
 mod hello_integration_test {
+    use super::*;
+
     #[test]
     fn add_rooms_to_smart_house() -> Result<(), Box<dyn std::error::Error>> {
-        /*
-        let energy_provider1 = Arc::new(EnergyProvider { value: 100.0 });
-        let temperature_provider1 = Arc::new(TemperatureProvider {
-            value: 16.0,
-            measure_units: TemperatureMeasureUnits::Celsius,
-        });
-
-        let energy_provider2 = Arc::new(EnergyProvider { value: 50.0 });
-        let temperature_provider2 = Arc::new(TemperatureProvider {
-            value: 15.0,
-            measure_units: TemperatureMeasureUnits::Celsius,
-        });
-
-        let energy_provider3 = Arc::new(EnergyProvider { value: 30.0 });
-        let temperature_provider3 = Arc::new(TemperatureProvider {
-            value: 14.0,
-            measure_units: TemperatureMeasureUnits::Celsius,
-        });
-
-        let mut thermometer = Box::new(Thermometer::new(
+        let mut thermometer = Thermometer::new(
             "Термометр1",
-            Arc::clone(&temperature_provider1) as Arc<dyn ThermometerInfoProvider>,
-        ));
+            Arc::new(ScriptedTemperatureProvider::constant(16.0)),
+        );
         thermometer.turn_off();
-        let smart_house = smart_house::SmartHouse::new(vec![
-            smart_house::Room::new(
-                "Кухня".to_string(),
-                vec![
-                    thermometer,
-                    Box::new(Thermometer::new(
-                        "Термометр2",
-                        Arc::clone(&temperature_provider1) as Arc<dyn ThermometerInfoProvider>,
-                    )),
-                    Box::new(SmartSocket::new(
-                        "Розетка1",
-                        Arc::clone(&energy_provider1) as Arc<dyn SmartSocketInfoProvider>,
-                    )),
-                    Box::new(SmartSocket::new(
-                        "Розетка2",
-                        Arc::clone(&energy_provider1) as Arc<dyn SmartSocketInfoProvider>,
-                    )),
-                ],
-            ),
-            smart_house::Room::new(
-                "Спальня".to_string(),
-                vec![
-                    Box::new(Thermometer::new(
-                        "Термометр3",
-                        Arc::clone(&temperature_provider2) as Arc<dyn ThermometerInfoProvider>,
-                    )),
-                    Box::new(Thermometer::new(
-                        "Термометр4",
-                        Arc::clone(&temperature_provider2) as Arc<dyn ThermometerInfoProvider>,
-                    )),
-                    Box::new(SmartSocket::new(
-                        "Розетка3",
-                        Arc::clone(&energy_provider2) as Arc<dyn SmartSocketInfoProvider>,
-                    )),
-                ],
-            ),
-            smart_house::Room::new(
-                "Гостиная".to_string(),
-                vec![
-                    Box::new(Thermometer::new(
-                        "Термометр5",
-                        Arc::clone(&temperature_provider3) as Arc<dyn ThermometerInfoProvider>,
-                    )),
-                    Box::new(SmartSocket::new(
-                        "Розетка4",
-                        Arc::clone(&energy_provider3) as Arc<dyn SmartSocketInfoProvider>,
-                    )),
-                    Box::new(SmartSocket::new(
-                        "Розетка5",
-                        Arc::clone(&energy_provider3) as Arc<dyn SmartSocketInfoProvider>,
-                    )),
-                ],
-            ),
-        ]);
+        let smart_house = HouseBuilder::new()
+            .room("Кухня", |room| {
+                room.device(thermometer)
+                    .thermometer("Термометр2", 16.0)
+                    .socket("Розетка1", 100.0)
+                    .socket("Розетка2", 100.0)
+            })
+            .room("Спальня", |room| {
+                room.thermometer("Термометр3", 15.0)
+                    .thermometer("Термометр4", 15.0)
+                    .socket("Розетка3", 50.0)
+            })
+            .room("Гостиная", |room| {
+                room.thermometer("Термометр5", 14.0)
+                    .socket("Розетка4", 30.0)
+                    .socket("Розетка5", 30.0)
+            })
+            .build();
         assert!(smart_house.create_report().is_err());
-        */
         Ok(())
     }
+
+    #[test]
+    fn report_follows_provider_readings() {
+        let provider = Arc::new(ScriptedPowerProvider::new(vec![
+            Reading::Value(100.0),
+            Reading::Failure("socket offline".to_string()),
+            Reading::Value(50.0),
+        ]));
+        let smart_house = HouseBuilder::new()
+            .room("Кухня", |room| {
+                room.socket_with("Розетка1", provider.clone())
+            })
+            .build();
+        let report = || smart_house.create_report_by_devices(vec![("Кухня", "Розетка1")]);
+
+        assert!(report().unwrap().contains("100 Вт"));
+        assert!(report().is_err());
+        assert!(report().unwrap().contains("50 Вт"));
+    }
 }
@@ -0,0 +1,77 @@
+use std::sync::Arc;
+
+use smart_house::{smart_tools::smart_socket::SmartSocket, Room, SmartHouse};
+use smart_house_testkit::{house_builder::HouseBuilder, providers::ScriptedPowerProvider};
+
+fn socket(name: &str) -> SmartSocket {
+    SmartSocket::new(name, Arc::new(ScriptedPowerProvider::constant(10.0)))
+}
+
+#[test]
+fn devices_are_added_once_per_name() {
+    let mut house = SmartHouse::new(vec![Room::new("Room 1".to_string(), vec![])]);
+    assert!(house.devices().is_empty());
+
+    let room = house.get_room_mut("Room 1").unwrap();
+    assert_eq!(room.add_unique_device(socket("Dummy device")), Some(0));
+    assert_eq!(room.add_unique_device(socket("Dummy device")), None);
+    assert_eq!(house.devices().len(), 1);
+}
+
+#[test]
+fn renamed_device_knows_its_new_name() {
+    let mut room = Room::new("Room 1".to_string(), vec![]);
+    room.add_unique_device(socket("Dummy device"));
+    assert_eq!(room.rename_device("Dummy device", "Lamp"), Some(()));
+    let device = room.get_device("Lamp").unwrap();
+    assert_eq!(device.read().unwrap().get_device_name(), "Lamp");
+}
+
+#[test]
+fn report_by_devices() {
+    let house = HouseBuilder::new()
+        .room("Room 1", |room| {
+            room.socket("Dummy device", 10.0)
+                .socket("Dummy device2", 10.0)
+        })
+        .build();
+    let report = house.create_report_by_devices(vec![
+        ("Room 1", "Dummy device"),
+        ("Room 1", "Dummy device2"),
+    ]);
+    assert!(report.is_ok());
+
+    let report = house.create_report_by_devices(vec![
+        ("Room 1", "Dummy device"),
+        ("Room 2", "Dummy device2"),
+    ]);
+    assert!(report.is_err());
+}
+
+#[test]
+fn report_by_devices_of_rooms_added_later() {
+    let mut house = SmartHouse::new(vec![]);
+    house.add_unique_room(Room::new("Room 1".to_string(), vec![]));
+    let room = house.get_room_mut("Room 1").unwrap();
+    room.add_unique_device(socket("Dummy device"));
+    room.add_unique_device(socket("Dummy device2"));
+    let report = house.create_report_by_devices(vec![
+        ("Room 1", "Dummy device"),
+        ("Room 1", "Dummy device2"),
+    ]);
+    assert!(report.is_ok());
+
+    house.add_unique_room(Room::new("Room 2".to_string(), vec![]));
+    let room = house.get_room_mut("Room 2").unwrap();
+    room.add_unique_device(socket("Dummy device2"));
+    assert_eq!(
+        house.add_unique_room(Room::new("Room 2".to_string(), vec![])),
+        None
+    );
+    assert!(house.remove_room("Room 3").is_none());
+    let report = house.create_report_by_devices(vec![
+        ("Room 1", "Dummy device"),
+        ("Room 2", "Dummy device2"),
+    ]);
+    assert!(report.is_ok());
+}
@@ -0,0 +1,149 @@
+use std::{sync::Arc, time::Duration};
+
+use smart_house::{
+    power_budget::{PowerBudget, ShedAction, DEFAULT_PRIORITY},
+    SmartHouse,
+};
+use smart_house_testkit::{clock::FakeClock, house_builder::HouseBuilder};
+
+fn house() -> SmartHouse {
+    HouseBuilder::new()
+        .room("Кухня", |room| {
+            room.socket("Чайник", 2000.0)
+                .socket("Холодильник", 300.0)
+                .thermometer("Термометр1", 20.0)
+        })
+        .room("Спальня", |room| room.socket("Обогреватель", 1500.0))
+        .build()
+}
+
+fn budget(clock: Arc<FakeClock>) -> PowerBudget {
+    let mut budget = PowerBudget::new(clock);
+    budget.set_max_power(Some(3000.0)).unwrap();
+    budget.set_cooldown(Duration::from_secs(30));
+    budget.set_priority("Кухня", "Холодильник", 10);
+    budget.set_priority("Кухня", "Чайник", 5);
+    budget.set_priority("Спальня", "Обогреватель", 1);
+    budget
+}
+
+fn is_on(house: &SmartHouse, room_name: &str, device_name: &str) -> bool {
+    let room = house.get_room(room_name).unwrap();
+    let device = room.get_device(device_name).unwrap();
+    let is_on = device.read().unwrap().is_on();
+    is_on
+}
+
+#[test]
+fn total_power_counts_only_sockets_that_are_on() {
+    let house = house();
+    assert_eq!(PowerBudget::total_power(&house), 3800.0);
+}
+
+#[test]
+fn sheds_lowest_priority_first() {
+    let house = house();
+    let mut budget = budget(Arc::new(FakeClock::new()));
+
+    let events = budget.enforce(&house);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].action, ShedAction::TurnedOff);
+    assert_eq!(events[0].device_name, "Обогреватель");
+    assert_eq!(events[0].total_power, 2300.0);
+    assert!(!is_on(&house, "Спальня", "Обогреватель"));
+    assert!(is_on(&house, "Кухня", "Чайник"));
+}
+
+#[test]
+fn restores_after_cooldown_when_there_is_headroom() {
+    let house = house();
+    let clock = Arc::new(FakeClock::new());
+    let mut budget = budget(clock.clone());
+    budget.enforce(&house);
+
+    // The kettle is switched off, but the cooldown has not passed yet
+    let kettle = house
+        .get_room("Кухня")
+        .unwrap()
+        .get_device("Чайник")
+        .unwrap();
+    kettle.write().unwrap().turn_off();
+    assert!(budget.enforce(&house).is_empty());
+
+    clock.advance(Duration::from_secs(30));
+    let events = budget.enforce(&house);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].action, ShedAction::Restored);
+    assert!(is_on(&house, "Спальня", "Обогреватель"));
+    assert_eq!(budget.history().len(), 2);
+}
+
+#[test]
+fn does_not_restore_without_headroom() {
+    let house = house();
+    let clock = Arc::new(FakeClock::new());
+    let mut budget = budget(clock.clone());
+    budget.enforce(&house);
+
+    clock.advance(Duration::from_secs(60));
+    assert!(budget.enforce(&house).is_empty());
+    assert_eq!(budget.shed_devices(), vec![("Спальня", "Обогреватель")]);
+}
+
+#[test]
+fn manually_restored_device_is_forgotten() {
+    let house = house();
+    let mut budget = budget(Arc::new(FakeClock::new()));
+    budget.enforce(&house);
+
+    let heater = house
+        .get_room("Спальня")
+        .unwrap()
+        .get_device("Обогреватель")
+        .unwrap();
+    heater.write().unwrap().turn_on();
+    budget.set_max_power(None).unwrap();
+    assert!(budget.enforce(&house).is_empty());
+    assert!(budget.shed_devices().is_empty());
+}
+
+#[test]
+fn invalid_limits_are_rejected() {
+    let mut budget = budget(Arc::new(FakeClock::new()));
+    for max_power in [f32::NAN, f32::INFINITY, -5.0] {
+        assert!(budget.set_max_power(Some(max_power)).is_err());
+    }
+    assert_eq!(budget.max_power(), Some(3000.0));
+    budget.set_max_power(Some(0.0)).unwrap();
+    assert_eq!(budget.max_power(), Some(0.0));
+}
+
+#[test]
+fn priorities_follow_renames_and_are_forgotten_with_the_device() {
+    let mut budget = budget(Arc::new(FakeClock::new()));
+    budget.rename("Кухня", Some("Чайник"), "Самовар");
+    assert_eq!(budget.priority("Кухня", "Самовар"), 5);
+    assert_eq!(budget.priority("Кухня", "Чайник"), DEFAULT_PRIORITY);
+
+    budget.rename("Кухня", None, "Столовая");
+    assert_eq!(budget.priority("Столовая", "Холодильник"), 10);
+
+    budget.remove_devices("Столовая", Some("Самовар"));
+    assert_eq!(budget.priority("Столовая", "Самовар"), DEFAULT_PRIORITY);
+    assert_eq!(budget.priority("Столовая", "Холодильник"), 10);
+    budget.remove_devices("Столовая", None);
+    assert_eq!(budget.priority("Столовая", "Холодильник"), DEFAULT_PRIORITY);
+    assert_eq!(budget.priority("Спальня", "Обогреватель"), 1);
+}
+
+#[test]
+fn shed_device_is_renamed_with_its_room() {
+    let house = house();
+    let mut budget = budget(Arc::new(FakeClock::new()));
+    budget.enforce(&house);
+
+    budget.rename("Спальня", None, "Детская");
+    assert_eq!(budget.shed_devices(), vec![("Детская", "Обогреватель")]);
+    budget.remove_devices("Детская", Some("Обогреватель"));
+    assert!(budget.shed_devices().is_empty());
+}
@@ -0,0 +1,44 @@
+use std::sync::Arc;
+
+use smart_house::{device::Device, smart_tools::smart_socket::SmartSocket};
+use smart_house_testkit::providers::ScriptedPowerProvider;
+
+fn socket() -> SmartSocket {
+    SmartSocket::new(
+        "Test Socket",
+        Arc::new(ScriptedPowerProvider::constant(10.0)),
+    )
+}
+
+#[test]
+fn new_socket_is_on() {
+    let socket = socket();
+    assert_eq!(socket.get_device_name(), "Test Socket");
+    assert!(socket.is_on());
+}
+
+#[test]
+fn power_consumption_is_read_while_on() {
+    let mut socket = socket();
+    assert_eq!(socket.get_current_power_consumption(), Some(10.0));
+    socket.turn_off();
+    assert_eq!(socket.get_current_power_consumption(), None);
+}
+
+#[test]
+fn turn_off_twice() {
+    let mut socket = socket();
+    socket.turn_off();
+    assert!(socket.is_off());
+    socket.turn_off();
+    assert!(socket.is_off());
+}
+
+#[test]
+fn toggle() {
+    let mut socket = socket();
+    socket.toggle();
+    assert!(socket.is_off());
+    socket.toggle();
+    assert!(socket.is_on());
+}
@@ -0,0 +1,44 @@
+use std::sync::Arc;
+
+use smart_house::{
+    device::Device, smart_tools::thermomener::Thermometer, temperature::TemperatureMeasureUnits,
+};
+use smart_house_testkit::providers::ScriptedTemperatureProvider;
+
+fn thermometer() -> Thermometer {
+    Thermometer::new(
+        "Test Thermometer",
+        Arc::new(ScriptedTemperatureProvider::constant(10.0)),
+    )
+}
+
+#[test]
+fn new_thermometer_is_on() {
+    let thermometer = thermometer();
+    assert_eq!(thermometer.get_device_name(), "Test Thermometer");
+    assert!(thermometer.is_on());
+}
+
+#[test]
+fn temperature_is_read_in_celsius() {
+    let temperature = thermometer()
+        .get_temperature(TemperatureMeasureUnits::Celsius)
+        .unwrap();
+    assert_eq!(temperature.get_value(), 10.0);
+    assert_eq!(
+        temperature.get_measure_units(),
+        TemperatureMeasureUnits::Celsius
+    );
+}
+
+#[test]
+fn turn_off_and_on() {
+    let mut thermometer = thermometer();
+    thermometer.turn_off();
+    assert!(thermometer.is_off());
+    assert!(thermometer
+        .get_temperature(TemperatureMeasureUnits::Celsius)
+        .is_none());
+    thermometer.turn_on();
+    assert!(thermometer.is_on());
+}
@@ -1,25 +1,54 @@
+use my_stp_async::auth::Credentials;
+use my_stp_async::reconnect::ConnectionState;
+use smart_house_client_async::{NewDevice, ReportDelivery};
+
+/// Credentials for servers that require authentication
+const CLIENT_ID_VAR: &str = "SMART_HOUSE_CLIENT_ID";
+const CLIENT_KEY_VAR: &str = "SMART_HOUSE_CLIENT_KEY";
+
+fn print_connection_state(state: ConnectionState) {
+    match state {
+        ConnectionState::Connected => println!("connected"),
+        ConnectionState::Reconnecting { attempt } => println!("reconnecting… (attempt {attempt})"),
+        ConnectionState::Disconnected => println!("disconnected"),
+    }
+}
+
 #[tokio::main]
 async fn main() {
     let server_sddr = "127.0.0.1:8080";
     let mut client = smart_house_client_async::SmartHouseClient::new(server_sddr, "127.0.0.1:8081")
         .await
         .unwrap();
+    if let (Ok(client_id), Ok(key)) = (std::env::var(CLIENT_ID_VAR), std::env::var(CLIENT_KEY_VAR))
+    {
+        client
+            .set_credentials(Some(Credentials::new(&client_id, key.as_bytes())))
+            .await;
+    }
+    client.on_connection_state_change(print_connection_state);
     println!("write \"help\" for print available commands");
     loop {
         let mut input = String::new();
         std::io::stdin().read_line(&mut input).unwrap();
         let command = input.trim();
-        if command == "help" {
+        let params = match my_stp_async::custom_parser::parse_request_parameters(command) {
+            Ok(params) => params,
+            Err(e) => {
+                println!("Bad command : {e}");
+                continue;
+            }
+        };
+        if params.command() == "help" {
             println!("Available commands:");
-            println!("  help - print available commands");
-            println!("  hello");
-            println!("  rooms_list");
-            println!("  device_report room_name=<string> device_name=<string>");
-            println!("  set_device_power_state room_name=<string> device_name=<string> power_state=<true|false>");
-            println!("  devices_list room_name=<string>");
-            println!("  is_device_on room_name=<string> device_name=<string>");
-            println!("  get_device_report_stream room_name=<string> device_name=<string> request_delay=<seconds>");
-            println!("  cancel_device_report_stream stream_name=<string>");
+            println!("  help [command=<string>] - print available commands or describe one");
+            println!("Values with spaces are quoted: room_name=\"Детская комната\"");
+            println!("get_device_report_stream also takes delivery=<udp|push>,");
+            println!("the client fills in addr of the streams and alert subscriptions itself");
+            match client.help_request(params.get("command")).await {
+                Ok(help) => help.lines().for_each(|line| println!("  {line}")),
+                Err(e) => println!("Cant get commands from server : {e}"),
+            }
             continue;
         }
         if command == "hello" {
@@ -34,7 +63,6 @@ async fn main() {
             continue;
         }
         if command.starts_with("device_report") {
-            let params = my_stp_async::custom_parser::parse_request_parameters(command);
             let room_name = params.get("room_name");
             if room_name.is_none() {
                 println!("device_report command must have room_name parameter");
@@ -54,7 +82,6 @@ async fn main() {
             continue;
         }
         if command.starts_with("set_device_power_state") {
-            let params = my_stp_async::custom_parser::parse_request_parameters(command);
             let room_name = params.get("room_name");
             if room_name.is_none() {
                 println!("set_device_power_state command must have room_name parameter");
@@ -83,7 +110,6 @@ async fn main() {
             continue;
         }
         if command.starts_with("devices_list") {
-            let params = my_stp_async::custom_parser::parse_request_parameters(command);
             let room_name = params.get("room_name");
             if room_name.is_none() {
                 println!("devices_list command must have room_name parameter");
@@ -96,7 +122,6 @@ async fn main() {
             continue;
         }
         if command.starts_with("is_device_on") {
-            let params = my_stp_async::custom_parser::parse_request_parameters(command);
             let room_name = params.get("room_name");
             if room_name.is_none() {
                 println!("is_device_on command must have room_name parameter");
@@ -116,7 +141,6 @@ async fn main() {
             continue;
         }
         if command.starts_with("get_device_report_stream") {
-            let params = my_stp_async::custom_parser::parse_request_parameters(command);
             let room_name = params.get("room_name");
             if room_name.is_none() {
                 println!("get_device_report_stream command must have room_name parameter");
@@ -127,9 +151,22 @@ async fn main() {
                 println!("get_device_report_stream command must have device_name parameter");
                 continue;
             }
-            let request_delay = params.get("request_delay");
-            let request_delay: Option<u64> =
-                request_delay.map(|request_delay| request_delay.parse().unwrap());
+            let (request_delay, ttl) =
+                match (params.get_u64("request_delay"), params.get_u64("ttl")) {
+                    (Ok(request_delay), Ok(ttl)) => (request_delay, ttl),
+                    (Err(e), _) | (_, Err(e)) => {
+                        println!("{e}");
+                        continue;
+                    }
+                };
+            match params.get_enum::<ReportDelivery>("delivery") {
+                Ok(Some(delivery)) => client.set_report_delivery(delivery),
+                Ok(None) => {}
+                Err(e) => {
+                    println!("{e}");
+                    continue;
+                }
+            }
 
             println!(
                 "Response from server: {:?}",
@@ -137,14 +174,14 @@ async fn main() {
                     .get_device_report_stream_request(
                         room_name.unwrap(),
                         device_name.unwrap(),
-                        request_delay
+                        request_delay,
+                        ttl
                     )
                     .await
             );
             continue;
         }
         if command.starts_with("cancel_device_report_stream") {
-            let params = my_stp_async::custom_parser::parse_request_parameters(command);
             let stream_name = params.get("stream_name");
             if stream_name.is_none() {
                 println!("cancel_device_report_stream command must have stream_name parameter");
@@ -158,6 +195,315 @@ async fn main() {
             );
             continue;
         }
+        if command.starts_with("renew_device_report_stream") {
+            let stream_name = params.get("stream_name");
+            if stream_name.is_none() {
+                println!("renew_device_report_stream command must have stream_name parameter");
+                continue;
+            }
+            let ttl = match params.get_u64("ttl") {
+                Ok(ttl) => ttl,
+                Err(e) => {
+                    println!("{e}");
+                    continue;
+                }
+            };
+            println!(
+                "Response from server: {:?}",
+                client
+                    .renew_device_report_stream_request(stream_name.unwrap(), ttl)
+                    .await
+            );
+            continue;
+        }
+        if command.starts_with("list_streams") {
+            println!(
+                "Response from server: {:?}",
+                client.list_streams_request().await
+            );
+            continue;
+        }
+        if command.starts_with("stream_info") {
+            let stream_name = params.get("stream_name");
+            if stream_name.is_none() {
+                println!("stream_info command must have stream_name parameter");
+                continue;
+            }
+            println!(
+                "Response from server: {:?}",
+                client.stream_info_request(stream_name.unwrap()).await
+            );
+            continue;
+        }
+        if command.starts_with("set_power_budget") {
+            let max_power = params.get("max_power");
+            if max_power.is_none() {
+                println!("set_power_budget command must have max_power parameter");
+                continue;
+            }
+            let max_power = match max_power.unwrap() {
+                "none" => Ok(None),
+                _ => params.get_f32("max_power"),
+            };
+            let (max_power, cooldown) = match (max_power, params.get_u64("cooldown")) {
+                (Ok(max_power), Ok(cooldown)) => (max_power, cooldown),
+                (Err(e), _) | (_, Err(e)) => {
+                    println!("{e}");
+                    continue;
+                }
+            };
+            println!(
+                "Response from server: {:?}",
+                client.set_power_budget_request(max_power, cooldown).await
+            );
+            continue;
+        }
+        if command.starts_with("set_device_priority") {
+            let room_name = params.get("room_name");
+            if room_name.is_none() {
+                println!("set_device_priority command must have room_name parameter");
+                continue;
+            }
+            let device_name = params.get("device_name");
+            if device_name.is_none() {
+                println!("set_device_priority command must have device_name parameter");
+                continue;
+            }
+            let priority = match params.get_enum::<u32>("priority") {
+                Ok(Some(priority)) => priority,
+                Ok(None) => {
+                    println!("set_device_priority command must have priority parameter");
+                    continue;
+                }
+                Err(e) => {
+                    println!("{e}");
+                    continue;
+                }
+            };
+            println!(
+                "Response from server: {:?}",
+                client
+                    .set_device_priority_request(room_name.unwrap(), device_name.unwrap(), priority)
+                    .await
+            );
+            continue;
+        }
+        if command.starts_with("power_budget_report") {
+            println!(
+                "Response from server: {:?}",
+                client.power_budget_report_request().await
+            );
+            continue;
+        }
+        if command.starts_with("add_alert") {
+            let required = [
+                "name",
+                "room_name",
+                "device_name",
+                "metric",
+                "condition",
+                "threshold",
+            ];
+            if let Some(missing) = required.iter().find(|name| !params.contains_key(name)) {
+                println!("add_alert command must have {missing} parameter");
+                continue;
+            }
+            let typed_params = (
+                params.get_f32("threshold"),
+                params.get_f32("hysteresis"),
+                params.get_u64("for"),
+            );
+            let (threshold, hysteresis, for_seconds) = match typed_params {
+                (Ok(Some(threshold)), Ok(hysteresis), Ok(for_seconds)) => {
+                    (threshold, hysteresis, for_seconds)
+                }
+                (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                    println!("{e}");
+                    continue;
+                }
+                (Ok(None), _, _) => continue,
+            };
+            let alert = smart_house_client_async::AlertParams {
+                name: params.get("name").unwrap(),
+                room_name: params.get("room_name").unwrap(),
+                device_name: params.get("device_name").unwrap(),
+                metric: params.get("metric").unwrap(),
+                condition: params.get("condition").unwrap(),
+                threshold,
+                hysteresis,
+                for_seconds,
+                severity: params.get("severity"),
+            };
+            println!(
+                "Response from server: {:?}",
+                client.add_alert_request(&alert).await
+            );
+            continue;
+        }
+        if command.starts_with("remove_alert") {
+            let name = params.get("name");
+            if name.is_none() {
+                println!("remove_alert command must have name parameter");
+                continue;
+            }
+            println!(
+                "Response from server: {:?}",
+                client.remove_alert_request(name.unwrap()).await
+            );
+            continue;
+        }
+        if command.starts_with("active_alerts") {
+            println!(
+                "Response from server: {:?}",
+                client.active_alerts_request().await
+            );
+            continue;
+        }
+        if command.starts_with("alerts_history") {
+            println!(
+                "Response from server: {:?}",
+                client.alerts_history_request().await
+            );
+            continue;
+        }
+        if command.starts_with("subscribe_alerts") {
+            println!(
+                "Response from server: {:?}",
+                client.subscribe_alerts_request().await
+            );
+            continue;
+        }
+        if command.starts_with("unsubscribe_alerts") {
+            println!(
+                "Response from server: {:?}",
+                client.unsubscribe_alerts_request().await
+            );
+            continue;
+        }
+        if command.starts_with("set_group_power_state") {
+            let power_state = params.get("power_state");
+            if power_state.is_none() {
+                println!("set_group_power_state command must have power_state parameter");
+                continue;
+            }
+            println!(
+                "Response from server: {:?}",
+                client
+                    .set_group_power_state_request(
+                        params.get("room_name"),
+                        params.get("kind"),
+                        power_state.unwrap()
+                    )
+                    .await
+            );
+            continue;
+        }
+        if command.starts_with("add_room") {
+            let room_name = params.get("room_name");
+            if room_name.is_none() {
+                println!("add_room command must have room_name parameter");
+                continue;
+            }
+            println!(
+                "Response from server: {:?}",
+                client.add_room_request(room_name.unwrap()).await
+            );
+            continue;
+        }
+        if command.starts_with("remove_room") {
+            let room_name = params.get("room_name");
+            if room_name.is_none() {
+                println!("remove_room command must have room_name parameter");
+                continue;
+            }
+            println!(
+                "Response from server: {:?}",
+                client.remove_room_request(room_name.unwrap()).await
+            );
+            continue;
+        }
+        if command.starts_with("add_device") {
+            let room_name = params.get("room_name");
+            if room_name.is_none() {
+                println!("add_device command must have room_name parameter");
+                continue;
+            }
+            let device_name = params.get("device_name");
+            if device_name.is_none() {
+                println!("add_device command must have device_name parameter");
+                continue;
+            }
+            let device = match (
+                params.get("kind"),
+                params.get_f32("power"),
+                params.get_f32("temperature"),
+            ) {
+                (Some("smart_socket"), Ok(Some(power)), _) => NewDevice::SmartSocket { power },
+                (Some("thermometer"), _, Ok(Some(temperature))) => {
+                    NewDevice::Thermometer { temperature }
+                }
+                (_, Err(e), _) | (_, _, Err(e)) => {
+                    println!("{e}");
+                    continue;
+                }
+                _ => {
+                    println!("add_device command must have kind=smart_socket with power or kind=thermometer with temperature");
+                    continue;
+                }
+            };
+            println!(
+                "Response from server: {:?}",
+                client
+                    .add_device_request(room_name.unwrap(), device_name.unwrap(), device)
+                    .await
+            );
+            continue;
+        }
+        if command.starts_with("remove_device") {
+            let room_name = params.get("room_name");
+            if room_name.is_none() {
+                println!("remove_device command must have room_name parameter");
+                continue;
+            }
+            let device_name = params.get("device_name");
+            if device_name.is_none() {
+                println!("remove_device command must have device_name parameter");
+                continue;
+            }
+            println!(
+                "Response from server: {:?}",
+                client
+                    .remove_device_request(room_name.unwrap(), device_name.unwrap())
+                    .await
+            );
+            continue;
+        }
+        if command.starts_with("rename") {
+            let room_name = params.get("room_name");
+            if room_name.is_none() {
+                println!("rename command must have room_name parameter");
+                continue;
+            }
+            let new_name = params.get("new_name");
+            if new_name.is_none() {
+                println!("rename command must have new_name parameter");
+                continue;
+            }
+            let response = match params.get("device_name") {
+                Some(device_name) => {
+                    client
+                        .rename_device_request(room_name.unwrap(), device_name, new_name.unwrap())
+                        .await
+                }
+                None => {
+                    client
+                        .rename_room_request(room_name.unwrap(), new_name.unwrap())
+                        .await
+                }
+            };
+            println!("Response from server: {response:?}");
+            continue;
+        }
         println!("no command found");
     }
 }
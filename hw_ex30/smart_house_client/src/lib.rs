@@ -1,29 +1,152 @@
 use std::{
-    net::{ToSocketAddrs, UdpSocket},
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    str::FromStr,
+    sync::{Arc, Mutex},
     thread,
     time::Duration,
 };
 
+use my_stp::auth::Credentials;
+use my_stp::client::StpClient;
+use my_stp::custom_parser::quote;
 use my_stp::errors::RequestError;
+use my_stp::reconnect::{ConnectionState, ReconnectPolicy, ReconnectingClient};
 use thread_cancellation_token::Canceller;
 
+/// How often the push listener looks for new push messages
+const PUSH_POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// How long the push listener holds the session while waiting for a push
+const PUSH_WAIT: Duration = Duration::from_millis(20);
+/// Requests that only read the house, they are sent again after a reconnect
+const IDEMPOTENT_REQUESTS: [&str; 8] = [
+    "hello",
+    "rooms_list",
+    "devices_list",
+    "device_report",
+    "is_device_on",
+    "power_budget_report",
+    "active_alerts",
+    "alerts_history",
+];
+
+/// How the server delivers device report streams
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportDelivery {
+    /// Datagrams to the client UDP socket
+    #[default]
+    Udp,
+    /// Push messages on the client session, the stream stops when the session is closed
+    Push,
+}
+
+impl FromStr for ReportDelivery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "udp" => Ok(ReportDelivery::Udp),
+            "push" => Ok(ReportDelivery::Push),
+            _ => Err(format!("unknown report delivery {s}, expected udp or push")),
+        }
+    }
+}
+
+/// Parameters of the `add_alert` request.
+/// Optional fields fall back to server defaults
+pub struct AlertParams<'a> {
+    pub name: &'a str,
+    pub room_name: &'a str,
+    pub device_name: &'a str,
+    /// `temperature` or `power`
+    pub metric: &'a str,
+    /// `above` or `below`
+    pub condition: &'a str,
+    pub threshold: f32,
+    pub hysteresis: Option<f32>,
+    pub for_seconds: Option<u64>,
+    /// `info`, `warning` or `critical`
+    pub severity: Option<&'a str>,
+}
+
+impl AlertParams<'_> {
+    fn to_request_string(&self) -> String {
+        let mut request_string = format!(
+            "add_alert name={} room_name={} device_name={} metric={} condition={} threshold={}",
+            quote(self.name),
+            quote(self.room_name),
+            quote(self.device_name),
+            quote(self.metric),
+            quote(self.condition),
+            self.threshold
+        );
+        if let Some(hysteresis) = self.hysteresis {
+            request_string.push_str(&format!(" hysteresis={hysteresis}"));
+        }
+        if let Some(for_seconds) = self.for_seconds {
+            request_string.push_str(&format!(" for={for_seconds}"));
+        }
+        if let Some(severity) = self.severity {
+            request_string.push_str(&format!(" severity={}", quote(severity)));
+        }
+        request_string
+    }
+}
+
+/// Device the `add_device` request creates, the server reports the given constant value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NewDevice {
+    /// Power consumption in watts
+    SmartSocket { power: f32 },
+    /// Temperature in celsius
+    Thermometer { temperature: f32 },
+}
+
+impl NewDevice {
+    fn to_request_params(self) -> String {
+        match self {
+            NewDevice::SmartSocket { power } => format!("kind=smart_socket power={power}"),
+            NewDevice::Thermometer { temperature } => {
+                format!("kind=thermometer temperature={temperature}")
+            }
+        }
+    }
+}
+
 pub struct SmartHouseClient<Addrs>
 where
     Addrs: ToSocketAddrs + Clone + ToString,
 {
-    server_addr: Addrs,
     udp_socket_addr: Addrs,
     udp_thread: Canceller,
+    push_thread: Option<Canceller>,
+    session: Arc<ReconnectingClient>,
+    credentials: Arc<Mutex<Option<Credentials>>>,
+    report_delivery: ReportDelivery,
 }
 
 impl<Addrs> SmartHouseClient<Addrs>
 where
     Addrs: ToSocketAddrs + Clone + ToString,
 {
+    /// The session is opened on first use and kept alive with heartbeats,
+    /// a lost one is opened again in the background
     pub fn new(
         server_addr: Addrs,
         udp_socket_addr: Addrs,
     ) -> Result<Self, Box<dyn std::error::Error>> {
+        let server_addrs: Vec<SocketAddr> = server_addr.to_socket_addrs()?.collect();
+        let credentials = Arc::new(Mutex::new(None::<Credentials>));
+        let session_credentials = credentials.clone();
+        let session = ReconnectingClient::new(
+            move || match session_credentials.lock().unwrap().as_ref() {
+                Some(credentials) => {
+                    StpClient::connect_authenticated(server_addrs.as_slice(), credentials)
+                }
+                None => StpClient::connect(server_addrs.as_slice()),
+            },
+            ReconnectPolicy::default().idempotent(IDEMPOTENT_REQUESTS),
+        );
+
         let udp_socket = UdpSocket::bind(udp_socket_addr.clone())?;
         udp_socket.set_read_timeout(Some(Duration::from_secs(5)))?;
         let (canceller, cancellation_token) = thread_cancellation_token::cancellation_token();
@@ -40,17 +163,85 @@ where
         });
 
         Ok(Self {
-            server_addr,
             udp_socket_addr,
             udp_thread: canceller,
+            push_thread: None,
+            session: Arc::new(session),
+            credentials,
+            report_delivery: ReportDelivery::default(),
         })
     }
 
-    pub fn hello_request(&self) -> Result<String, RequestError> {
-        let mut connect = my_stp::client::StpClient::connect(self.server_addr.clone())?;
+    /// Report streams requested after this call use `report_delivery`.
+    /// Push messages are printed by a background listener like UDP reports
+    pub fn set_report_delivery(&mut self, report_delivery: ReportDelivery) {
+        self.report_delivery = report_delivery;
+        match report_delivery {
+            ReportDelivery::Push if self.push_thread.is_none() => {
+                self.push_thread = Some(self.start_push_listener());
+            }
+            ReportDelivery::Udp => {
+                if let Some(push_thread) = self.push_thread.take() {
+                    push_thread.cancel();
+                }
+            }
+            ReportDelivery::Push => {}
+        }
+    }
 
+    /// Requests have priority: the session is only held for a short wait between polls
+    fn start_push_listener(&self) -> Canceller {
+        let session = self.session.clone();
+        let (canceller, cancellation_token) = thread_cancellation_token::cancellation_token();
+        let _ = thread::spawn(move || loop {
+            if cancellation_token.should_cancel() {
+                break;
+            }
+            while let Ok(Some(push)) = session.recv_push(Some(PUSH_WAIT)) {
+                println!("{} #{} : {}", push.topic, push.seq, push.body);
+            }
+            thread::sleep(PUSH_POLL_INTERVAL);
+        });
+        canceller
+    }
+
+    /// Authenticate to servers that require it. The current session is closed,
+    /// the next request opens a new one with the new credentials
+    pub fn set_credentials(&mut self, credentials: Option<Credentials>) {
+        *self.credentials.lock().unwrap() = credentials;
+        self.session.disconnect();
+    }
+
+    /// `listener` sees the session being lost and opened again, e.g. to show it to the user.
+    /// Report streams pushed over a lost session are gone, they have to be requested again
+    pub fn on_connection_state_change<F>(&self, listener: F)
+    where
+        F: Fn(ConnectionState) + Send + Sync + 'static,
+    {
+        self.session.on_state_change(listener);
+    }
+
+    /// Send a request over the client session, opening the session on first use.
+    /// Reading requests are sent again if the session is lost before the response arrives
+    fn send_request<T>(&self, request: T) -> Result<String, RequestError>
+    where
+        T: ToString,
+    {
+        self.session.send_request(request)
+    }
+
+    pub fn hello_request(&self) -> Result<String, RequestError> {
         let request_string = "hello";
-        connect.send_request(request_string)
+        self.send_request(request_string)
+    }
+
+    /// Commands of the server with their parameters, or the description of `command`
+    pub fn help_request(&self, command: Option<&str>) -> Result<String, RequestError> {
+        let request_string = match command {
+            Some(command) => format!("help command={}", quote(command)),
+            None => "help".to_string(),
+        };
+        self.send_request(request_string)
     }
 
     pub fn device_report_request(
@@ -58,25 +249,22 @@ where
         room_name: &str,
         device_name: &str,
     ) -> Result<String, RequestError> {
-        let mut connect = my_stp::client::StpClient::connect(self.server_addr.clone())?;
-
-        let request_string =
-            format!("device_report room_name:{room_name} device_name:{device_name}");
-        connect.send_request(request_string)
+        let request_string = format!(
+            "device_report room_name={} device_name={}",
+            quote(room_name),
+            quote(device_name)
+        );
+        self.send_request(request_string)
     }
 
     pub fn rooms_list_request(&self) -> Result<String, RequestError> {
-        let mut connect = my_stp::client::StpClient::connect(self.server_addr.clone())?;
-
         let request_string = "rooms_list";
-        connect.send_request(request_string)
+        self.send_request(request_string)
     }
 
     pub fn devices_list_request(&self, room_name: &str) -> Result<String, RequestError> {
-        let mut connect = my_stp::client::StpClient::connect(self.server_addr.clone())?;
-
-        let request_string = format!("devices_list room_name:{room_name}");
-        connect.send_request(request_string)
+        let request_string = format!("devices_list room_name={}", quote(room_name));
+        self.send_request(request_string)
     }
 
     pub fn is_device_on_request(
@@ -84,11 +272,12 @@ where
         room_name: &str,
         device_name: &str,
     ) -> Result<String, RequestError> {
-        let mut connect = my_stp::client::StpClient::connect(self.server_addr.clone())?;
-
-        let request_string =
-            format!("is_device_on room_name:{room_name} device_name:{device_name}");
-        connect.send_request(request_string)
+        let request_string = format!(
+            "is_device_on room_name={} device_name={}",
+            quote(room_name),
+            quote(device_name)
+        );
+        self.send_request(request_string)
     }
 
     pub fn set_device_power_state_request(
@@ -97,10 +286,32 @@ where
         device_name: &str,
         power_state: bool,
     ) -> Result<String, RequestError> {
-        let mut connect = my_stp::client::StpClient::connect(self.server_addr.clone())?;
+        let request_string = format!(
+            "set_device_power_state room_name={} device_name={} power_state={}",
+            quote(room_name),
+            quote(device_name),
+            power_state
+        );
+        self.send_request(request_string)
+    }
 
-        let request_string = format!("set_device_power_state room_name:{room_name} device_name:{device_name} power_state:{power_state}");
-        connect.send_request(request_string)
+    /// Turn on (`true`), turn off (`false`) or `toggle` a group of devices.
+    /// Without room and kind the whole house is affected
+    pub fn set_group_power_state_request(
+        &self,
+        room_name: Option<&str>,
+        kind: Option<&str>,
+        power_state: &str,
+    ) -> Result<String, RequestError> {
+        let mut request_string =
+            format!("set_group_power_state power_state={}", quote(power_state));
+        if let Some(room_name) = room_name {
+            request_string.push_str(&format!(" room_name={}", quote(room_name)));
+        }
+        if let Some(kind) = kind {
+            request_string.push_str(&format!(" kind={}", quote(kind)));
+        }
+        self.send_request(request_string)
     }
 
     pub fn get_device_report_stream_request(
@@ -108,24 +319,197 @@ where
         room_name: &str,
         device_name: &str,
         request_delay_seconds: Option<u64>,
+        ttl_seconds: Option<u64>,
     ) -> Result<String, RequestError> {
         let result_request_delay = request_delay_seconds.unwrap_or(5);
-        let addr_as_string = self.udp_socket_addr.to_string();
-        let mut connect = my_stp::client::StpClient::connect(self.server_addr.clone())?;
-
-        let request_string = format!("get_device_report_stream room_name={room_name} device_name={device_name} request_delay={result_request_delay} addr={addr_as_string}");
+        let mut request_string = format!(
+            "get_device_report_stream room_name={} device_name={} request_delay={}",
+            quote(room_name),
+            quote(device_name),
+            result_request_delay,
+        );
+        // Without an address the server pushes reports over the session
+        if self.report_delivery == ReportDelivery::Udp {
+            let addr_as_string = self.udp_socket_addr.to_string();
+            request_string.push_str(&format!(" addr={addr_as_string}"));
+        }
+        // Without a TTL the stream runs until it is cancelled
+        if let Some(ttl) = ttl_seconds {
+            request_string.push_str(&format!(" ttl={ttl}"));
+        }
 
-        connect.send_request(request_string)
+        self.send_request(request_string)
     }
 
     pub fn cancel_device_report_stream_request(
         &mut self,
         stream_name: &str,
     ) -> Result<String, RequestError> {
-        let mut connect = my_stp::client::StpClient::connect(self.server_addr.clone())?;
+        let request_string = format!(
+            "cancel_device_report_stream stream_name={}",
+            quote(stream_name)
+        );
+        self.send_request(request_string)
+    }
+
+    /// Without `ttl_seconds` the stream keeps the TTL it was created with
+    pub fn renew_device_report_stream_request(
+        &mut self,
+        stream_name: &str,
+        ttl_seconds: Option<u64>,
+    ) -> Result<String, RequestError> {
+        let mut request_string = format!(
+            "renew_device_report_stream stream_name={}",
+            quote(stream_name)
+        );
+        if let Some(ttl) = ttl_seconds {
+            request_string.push_str(&format!(" ttl={ttl}"));
+        }
+        self.send_request(request_string)
+    }
+
+    pub fn list_streams_request(&self) -> Result<String, RequestError> {
+        self.send_request("list_streams".to_string())
+    }
+
+    pub fn stream_info_request(&self, stream_name: &str) -> Result<String, RequestError> {
+        let request_string = format!("stream_info stream_name={}", quote(stream_name));
+        self.send_request(request_string)
+    }
+
+    pub fn set_power_budget_request(
+        &self,
+        max_power: Option<f32>,
+        cooldown_seconds: Option<u64>,
+    ) -> Result<String, RequestError> {
+        let max_power = max_power.map_or_else(|| "none".to_string(), |value| value.to_string());
+        let mut request_string = format!("set_power_budget max_power={max_power}");
+        if let Some(cooldown) = cooldown_seconds {
+            request_string.push_str(&format!(" cooldown={cooldown}"));
+        }
+        self.send_request(request_string)
+    }
+
+    pub fn set_device_priority_request(
+        &self,
+        room_name: &str,
+        device_name: &str,
+        priority: u32,
+    ) -> Result<String, RequestError> {
+        let request_string = format!(
+            "set_device_priority room_name={} device_name={} priority={}",
+            quote(room_name),
+            quote(device_name),
+            priority
+        );
+        self.send_request(request_string)
+    }
 
-        let request_string = format!("cancel_device_report_stream stream_name={stream_name}");
-        connect.send_request(request_string)
+    pub fn power_budget_report_request(&self) -> Result<String, RequestError> {
+        let request_string = "power_budget_report";
+        self.send_request(request_string)
+    }
+
+    pub fn add_alert_request(&self, alert: &AlertParams<'_>) -> Result<String, RequestError> {
+        self.send_request(alert.to_request_string())
+    }
+
+    pub fn remove_alert_request(&self, name: &str) -> Result<String, RequestError> {
+        let request_string = format!("remove_alert name={}", quote(name));
+        self.send_request(request_string)
+    }
+
+    pub fn active_alerts_request(&self) -> Result<String, RequestError> {
+        let request_string = "active_alerts";
+        self.send_request(request_string)
+    }
+
+    pub fn alerts_history_request(&self) -> Result<String, RequestError> {
+        let request_string = "alerts_history";
+        self.send_request(request_string)
+    }
+
+    /// Alerts are delivered to the udp socket of this client
+    pub fn subscribe_alerts_request(&self) -> Result<String, RequestError> {
+        let addr_as_string = self.udp_socket_addr.to_string();
+        let request_string = format!("subscribe_alerts addr={addr_as_string}");
+        self.send_request(request_string)
+    }
+
+    pub fn unsubscribe_alerts_request(&self) -> Result<String, RequestError> {
+        let addr_as_string = self.udp_socket_addr.to_string();
+        let request_string = format!("unsubscribe_alerts addr={addr_as_string}");
+        self.send_request(request_string)
+    }
+
+    pub fn add_room_request(&self, room_name: &str) -> Result<String, RequestError> {
+        let request_string = format!("add_room room_name={}", quote(room_name));
+        self.send_request(request_string)
+    }
+
+    /// Report streams of the devices in the room are stopped
+    pub fn remove_room_request(&self, room_name: &str) -> Result<String, RequestError> {
+        let request_string = format!("remove_room room_name={}", quote(room_name));
+        self.send_request(request_string)
+    }
+
+    pub fn add_device_request(
+        &self,
+        room_name: &str,
+        device_name: &str,
+        device: NewDevice,
+    ) -> Result<String, RequestError> {
+        let request_string = format!(
+            "add_device room_name={} device_name={} {}",
+            quote(room_name),
+            quote(device_name),
+            device.to_request_params()
+        );
+        self.send_request(request_string)
+    }
+
+    /// The report stream of the device is stopped
+    pub fn remove_device_request(
+        &self,
+        room_name: &str,
+        device_name: &str,
+    ) -> Result<String, RequestError> {
+        let request_string = format!(
+            "remove_device room_name={} device_name={}",
+            quote(room_name),
+            quote(device_name)
+        );
+        self.send_request(request_string)
+    }
+
+    /// Report streams of the devices in the room are stopped
+    pub fn rename_room_request(
+        &self,
+        room_name: &str,
+        new_name: &str,
+    ) -> Result<String, RequestError> {
+        let request_string = format!(
+            "rename room_name={} new_name={}",
+            quote(room_name),
+            quote(new_name)
+        );
+        self.send_request(request_string)
+    }
+
+    /// The report stream of the device is stopped
+    pub fn rename_device_request(
+        &self,
+        room_name: &str,
+        device_name: &str,
+        new_name: &str,
+    ) -> Result<String, RequestError> {
+        let request_string = format!(
+            "rename room_name={} device_name={} new_name={}",
+            quote(room_name),
+            quote(device_name),
+            quote(new_name)
+        );
+        self.send_request(request_string)
     }
 }
 
@@ -135,5 +519,8 @@ where
 {
     fn drop(&mut self) {
         self.udp_thread.cancel();
+        if let Some(push_thread) = self.push_thread.take() {
+            push_thread.cancel();
+        }
     }
 }
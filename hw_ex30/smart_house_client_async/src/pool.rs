@@ -0,0 +1,190 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use tokio::{
+    net::{lookup_host, ToSocketAddrs},
+    sync::Mutex,
+};
+
+use my_stp_async::auth::Credentials;
+use my_stp_async::client::StpClient;
+use my_stp_async::custom_parser::quote;
+use my_stp_async::errors::{ConnectError, RequestError};
+use my_stp_async::pipeline::{CallOptions, PipelinedConnection};
+
+/// Sessions a pool keeps open by default
+pub const DEFAULT_POOL_SIZE: usize = 4;
+/// Calls without a deadline of their own fail after this long
+pub const DEFAULT_CALL_DEADLINE: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+pub struct PoolConfig {
+    /// Number of sessions, at least one is opened
+    pub size: usize,
+    /// Every session authenticates with these
+    pub credentials: Option<Credentials>,
+    /// Deadline of calls made without [`CallOptions`], `None` waits forever
+    pub deadline: Option<Duration>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            size: DEFAULT_POOL_SIZE,
+            credentials: None,
+            deadline: Some(DEFAULT_CALL_DEADLINE),
+        }
+    }
+}
+
+/// Client for dashboards that poll many devices at once. Keeps several sessions open,
+/// every call goes to the least busy one and is pipelined with the calls already waiting there.
+/// A lost session is opened again by the next call, calls that were waiting on it fail
+pub struct SmartHousePool {
+    server_addrs: Vec<SocketAddr>,
+    config: PoolConfig,
+    connections: Vec<Mutex<Option<Arc<PipelinedConnection>>>>,
+}
+
+impl SmartHousePool {
+    /// Open all sessions right away, so bad credentials are reported here
+    pub async fn connect<Addrs>(
+        server_addr: Addrs,
+        config: PoolConfig,
+    ) -> Result<Self, RequestError>
+    where
+        Addrs: ToSocketAddrs,
+    {
+        let server_addrs = lookup_host(server_addr)
+            .await
+            .map_err(ConnectError::Io)?
+            .collect();
+        let mut pool = Self {
+            server_addrs,
+            connections: Vec::new(),
+            config,
+        };
+        for _ in 0..pool.config.size.max(1) {
+            let connection = pool.open().await?;
+            pool.connections
+                .push(Mutex::new(Some(Arc::new(connection))));
+        }
+        Ok(pool)
+    }
+
+    /// Sessions that are open now
+    pub async fn open_connections(&self) -> usize {
+        let mut open = 0;
+        for slot in &self.connections {
+            if slot.lock().await.as_ref().is_some_and(|c| !c.is_closed()) {
+                open += 1;
+            }
+        }
+        open
+    }
+
+    /// Send a request with the default deadline of the pool
+    pub async fn send_request<T>(&self, request: T) -> Result<String, RequestError>
+    where
+        T: ToString,
+    {
+        self.send_request_with(
+            request,
+            CallOptions {
+                deadline: self.config.deadline,
+                cancel: None,
+            },
+        )
+        .await
+    }
+
+    /// Send a request bounded by `options` instead of the default deadline
+    pub async fn send_request_with<T>(
+        &self,
+        request: T,
+        options: CallOptions,
+    ) -> Result<String, RequestError>
+    where
+        T: ToString,
+    {
+        let connection = self.connection().await?;
+        connection.send_request_with(request, options).await
+    }
+
+    pub async fn rooms_list_request(&self) -> Result<String, RequestError> {
+        self.send_request("rooms_list").await
+    }
+
+    pub async fn devices_list_request(&self, room_name: &str) -> Result<String, RequestError> {
+        let request_string = format!("devices_list room_name={}", quote(room_name));
+        self.send_request(request_string).await
+    }
+
+    pub async fn device_report_request(
+        &self,
+        room_name: &str,
+        device_name: &str,
+    ) -> Result<String, RequestError> {
+        let request_string = format!(
+            "device_report room_name={} device_name={}",
+            quote(room_name),
+            quote(device_name)
+        );
+        self.send_request(request_string).await
+    }
+
+    pub async fn is_device_on_request(
+        &self,
+        room_name: &str,
+        device_name: &str,
+    ) -> Result<String, RequestError> {
+        let request_string = format!(
+            "is_device_on room_name={} device_name={}",
+            quote(room_name),
+            quote(device_name)
+        );
+        self.send_request(request_string).await
+    }
+
+    pub async fn power_budget_report_request(&self) -> Result<String, RequestError> {
+        self.send_request("power_budget_report").await
+    }
+
+    /// Least busy open session, lost ones are opened again on the way.
+    /// Fails only if no session could be opened
+    async fn connection(&self) -> Result<Arc<PipelinedConnection>, RequestError> {
+        let mut least_busy: Option<Arc<PipelinedConnection>> = None;
+        let mut error = None;
+        for slot in &self.connections {
+            let mut slot = slot.lock().await;
+            if slot.as_ref().is_none_or(|c| c.is_closed()) {
+                *slot = match self.open().await {
+                    Ok(connection) => Some(Arc::new(connection)),
+                    Err(e) => {
+                        error = Some(e);
+                        None
+                    }
+                };
+            }
+            let Some(connection) = slot.as_ref() else {
+                continue;
+            };
+            if least_busy
+                .as_ref()
+                .is_none_or(|best| connection.in_flight() < best.in_flight())
+            {
+                least_busy = Some(connection.clone());
+            }
+        }
+        least_busy.ok_or_else(|| error.unwrap_or(RequestError::Closed))
+    }
+
+    async fn open(&self) -> Result<PipelinedConnection, RequestError> {
+        let connection = match &self.config.credentials {
+            Some(credentials) => {
+                StpClient::connect_authenticated(self.server_addrs.as_slice(), credentials).await?
+            }
+            None => StpClient::connect(self.server_addrs.as_slice()).await?,
+        };
+        PipelinedConnection::new(connection)
+    }
+}
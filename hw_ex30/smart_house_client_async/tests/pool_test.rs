@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+use futures::future;
+use my_stp_async::auth::{Credentials, KeyStore};
+use my_stp_async::errors::{ConnectError, RequestError};
+use my_stp_async::pipeline::CallOptions;
+use smart_house_client_async::pool::{PoolConfig, SmartHousePool};
+use smart_house_testkit::{house_builder::HouseBuilder, stp::AsyncTestServer};
+use tokio::sync::watch;
+
+async fn start_server(key_store: Option<KeyStore>) -> AsyncTestServer {
+    AsyncTestServer::start_with_key_store(
+        HouseBuilder::new()
+            .room("Кухня", |room| {
+                room.thermometer("Термометр1", 16.0)
+                    .socket("Розетка1", 100.0)
+            })
+            .build(),
+        key_store,
+    )
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_polls_are_spread_over_the_pool() {
+    let server = start_server(None).await;
+    let pool = SmartHousePool::connect(
+        server.addr(),
+        PoolConfig {
+            size: 2,
+            ..PoolConfig::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(pool.open_connections().await, 2);
+    let reports =
+        future::join_all((0..40).map(|_| pool.device_report_request("Кухня", "Термометр1"))).await;
+    for report in reports {
+        assert!(report.unwrap().contains("Термометр1"));
+    }
+    assert_eq!(
+        pool.is_device_on_request("Кухня", "Розетка1")
+            .await
+            .unwrap(),
+        server
+            .request("is_device_on room_name=Кухня device_name=Розетка1")
+            .await
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pool_authenticates_every_session() {
+    let server = start_server(Some(KeyStore::parse("alice:secret").unwrap())).await;
+    let config = PoolConfig {
+        size: 2,
+        credentials: Some(Credentials::new("alice", b"secret")),
+        ..PoolConfig::default()
+    };
+    let pool = SmartHousePool::connect(server.addr(), config.clone())
+        .await
+        .unwrap();
+    assert_eq!(pool.rooms_list_request().await.unwrap(), "[Кухня]");
+
+    let rejected = SmartHousePool::connect(
+        server.addr(),
+        PoolConfig {
+            credentials: Some(Credentials::new("alice", b"wrong")),
+            ..config
+        },
+    )
+    .await;
+    assert!(matches!(
+        rejected,
+        Err(RequestError::Connect(ConnectError::AuthenticationRejected))
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn cancelled_call_fails_and_the_pool_stays_usable() {
+    let server = start_server(None).await;
+    let pool = SmartHousePool::connect(server.addr(), PoolConfig::default())
+        .await
+        .unwrap();
+    let (_cancel, cancelled) = watch::channel(true);
+    let options = CallOptions::default()
+        .deadline(Duration::from_secs(1))
+        .cancel_on(cancelled);
+    assert!(matches!(
+        pool.send_request_with("rooms_list", options).await,
+        Err(RequestError::Cancelled)
+    ));
+    assert_eq!(pool.rooms_list_request().await.unwrap(), "[Кухня]");
+}
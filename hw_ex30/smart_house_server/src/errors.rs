@@ -1,3 +1,9 @@
+use my_stp::errors::{ParseError, SchemaError};
+use my_stp::jsonrpc::{
+    JsonRpcError, INTERNAL_ERROR, INVALID_PARAMS, METHOD_NOT_FOUND, SERVER_ERROR,
+};
+use smart_house::streams::StreamError;
+
 #[derive(Debug, thiserror::Error)]
 pub enum SmartHouseInitError {
     #[error(transparent)]
@@ -12,6 +18,14 @@ pub enum CreateNewServerError {
     Io(#[from] std::io::Error),
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigureServerError {
+    #[error("Server is already listening")]
+    AlreadyListening,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ProccessRequestError {
     #[error("Cant read smart house")]
@@ -22,10 +36,58 @@ pub enum ProccessRequestError {
     ProccessorError(#[from] ProccessorError),
 }
 
+impl ProccessRequestError {
+    /// Name of the failure for typed responses, e.g. `CantFindRoom`
+    pub fn code(&self) -> String {
+        let name = match self {
+            // Which parameter is wrong stays in the message
+            ProccessRequestError::ProccessorError(ProccessorError::InvalidParams(e)) => {
+                format!("{e:?}")
+            }
+            ProccessRequestError::ProccessorError(e) => format!("{e:?}"),
+            e => format!("{e:?}"),
+        };
+        // Details like the position of a parse error stay in the message
+        name.split(['(', ' '])
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// Error object for JSON-RPC clients, the name of the failure goes into `data`
+    pub fn json_rpc_error(&self) -> JsonRpcError {
+        let code = match self {
+            ProccessRequestError::CantReadSmartHouse => INTERNAL_ERROR,
+            ProccessRequestError::CantProccessRequest => METHOD_NOT_FOUND,
+            ProccessRequestError::ProccessorError(e) => match e {
+                ProccessorError::MalformedRequest(_)
+                | ProccessorError::InvalidParams(_)
+                | ProccessorError::BadRequestParam => INVALID_PARAMS,
+                ProccessorError::CantGetReport => SERVER_ERROR,
+                ProccessorError::CantFindRoom => SERVER_ERROR - 1,
+                ProccessorError::CantFindDevice => SERVER_ERROR - 2,
+                ProccessorError::PushNotNegotiated => SERVER_ERROR - 3,
+                ProccessorError::RoomExists => SERVER_ERROR - 4,
+                ProccessorError::DeviceExists => SERVER_ERROR - 5,
+                ProccessorError::CantFindStream => SERVER_ERROR - 6,
+                ProccessorError::NotStreamOwner => SERVER_ERROR - 7,
+                ProccessorError::AlertExists => SERVER_ERROR - 8,
+                ProccessorError::CantFindAlert => SERVER_ERROR - 9,
+                ProccessorError::CantProccessRequest => METHOD_NOT_FOUND,
+            },
+        };
+        JsonRpcError::new(code, &self.to_string()).with_data(&self.code())
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ProccessorError {
     #[error("Cant proccess request")]
     CantProccessRequest,
+    #[error("Malformed request : {0}")]
+    MalformedRequest(#[from] ParseError),
+    #[error(transparent)]
+    InvalidParams(#[from] SchemaError),
     #[error("Bad request param")]
     BadRequestParam,
     #[error("Cant get report")]
@@ -34,4 +96,27 @@ pub enum ProccessorError {
     CantFindRoom,
     #[error("Cant find device")]
     CantFindDevice,
+    #[error("Room already exists")]
+    RoomExists,
+    #[error("Device already exists")]
+    DeviceExists,
+    #[error("Cant find stream")]
+    CantFindStream,
+    #[error("Stream belongs to another client")]
+    NotStreamOwner,
+    #[error("Alert already exists")]
+    AlertExists,
+    #[error("Cant find alert")]
+    CantFindAlert,
+    #[error("Push messages are not negotiated, give an addr for UDP reports")]
+    PushNotNegotiated,
+}
+
+impl From<StreamError> for ProccessorError {
+    fn from(e: StreamError) -> Self {
+        match e {
+            StreamError::CantFindStream => ProccessorError::CantFindStream,
+            StreamError::NotStreamOwner => ProccessorError::NotStreamOwner,
+        }
+    }
 }
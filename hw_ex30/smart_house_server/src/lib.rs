@@ -1,48 +1,141 @@
-use std::collections::HashMap;
-use std::net::{ToSocketAddrs, UdpSocket};
-use std::ops::DerefMut;
+use std::collections::HashSet;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::Path;
 use std::sync::{Arc, RwLock};
-use std::thread;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-use errors::{CreateNewServerError, ProccessRequestError, ProccessorError, SmartHouseInitError};
+use errors::{
+    ConfigureServerError, CreateNewServerError, ProccessRequestError, SmartHouseInitError,
+};
+use my_stp::auth::KeyStore;
+use my_stp::jsonrpc;
+use my_stp::message::{Request, Response};
+use my_stp::server::StpServer;
+#[cfg(unix)]
+use my_stp::socket_file::UnixSocketOptions;
 use processors::{
-    CancelDeviceReportStreamProcessor, DeviceListProcessor, DeviceReportProcessor,
-    GetDeviceReportStreamProcessor, HelloProcessor, IsDeviceOnProcessor, RequestProcessor,
-    RoomsListProcessor, SetDevicePowerStateProcessor,
+    ActiveAlertsProcessor, AddAlertProcessor, AddDeviceProcessor, AddRoomProcessor,
+    AlertsHistoryProcessor, CancelDeviceReportStreamProcessor, DeviceListProcessor,
+    DeviceReportProcessor, GetDeviceReportStreamProcessor, HelloProcessor, IsDeviceOnProcessor,
+    ListStreamsProcessor, PowerBudgetReportProcessor, Processor, RemoveAlertProcessor,
+    RemoveDeviceProcessor, RemoveRoomProcessor, RenameProcessor, RenewDeviceReportStreamProcessor,
+    RequestContext, RoomsListProcessor, SetDevicePowerStateProcessor, SetDevicePriorityProcessor,
+    SetGroupPowerStateProcessor, SetPowerBudgetProcessor, StreamInfoProcessor,
+    SubscribeAlertsProcessor, UnsubscribeAlertsProcessor,
 };
+use router::Router;
+use shutdown::{Sessions, ShutdownSummary, DEFAULT_SHUTDOWN_TIMEOUT};
+use smart_house::alerts::AlertManager;
+use smart_house::clock::SystemClock;
+use smart_house::power_budget::PowerBudget;
+use smart_house::streams::{StreamHandle, StreamRegistry};
 use smart_house::{smart_tools, temperature, SmartHouse};
 use smart_tools::smart_socket::{SmartSocket, SmartSocketInfoProvider, TemperatureProvider};
 use smart_tools::thermomener::{EnergyProvider, Thermometer, ThermometerInfoProvider};
 use temperature::TemperatureMeasureUnits;
-use thread_cancellation_token::Canceller;
+use thread_cancellation_token::{CancellationToken, Canceller};
+use worker_pool::WorkerPool;
 
 pub mod errors;
 mod processors;
+mod router;
+pub mod shutdown;
+mod worker_pool;
+
+const HOUSE_MONITOR_INTERVAL: Duration = Duration::from_secs(1);
+/// Connections served at the same time
+pub const DEFAULT_WORKER_THREADS: usize = 32;
+/// Accepted connections waiting for a free worker
+pub const DEFAULT_PENDING_CONNECTIONS: usize = 64;
+
+/// Thread that runs until it is cancelled
+struct ServerThread {
+    canceller: Canceller,
+    handle: JoinHandle<()>,
+}
+
+impl ServerThread {
+    fn spawn<F>(run: F) -> Self
+    where
+        F: FnOnce(CancellationToken) + Send + 'static,
+    {
+        let (canceller, cancellation_token) = thread_cancellation_token::cancellation_token();
+        let handle = thread::spawn(move || run(cancellation_token));
+        Self { canceller, handle }
+    }
+
+    fn cancel(&self) {
+        self.canceller.cancel();
+    }
+}
+
+impl StreamHandle for ServerThread {
+    fn cancel(&self) {
+        ServerThread::cancel(self);
+    }
+
+    fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+}
 
 struct ServerStore {
-    execution_threads: HashMap<String, Canceller>,
-    message_thread: Option<Canceller>,
+    streams: StreamRegistry<ServerThread>,
+    message_threads: Vec<ServerThread>,
+    monitor_thread: Option<ServerThread>,
+    power_budget: PowerBudget,
+    alerts: AlertManager,
+    alert_subscribers: HashSet<String>,
     udp_socket: UdpSocket,
 }
 
 pub struct SmartHouseServer {
     smart_house: Arc<RwLock<smart_house::SmartHouse>>,
-    stp: Arc<my_stp::server::StpServer>,
-    processors: Arc<Vec<Arc<dyn RequestProcessor>>>,
+    stp: Arc<StpServer>,
+    unix_stp: Option<Arc<StpServer>>,
+    key_store: Option<KeyStore>,
+    router: Arc<Router>,
+    worker_pool: Arc<WorkerPool>,
+    sessions: Arc<Sessions>,
     server_threads: Arc<RwLock<ServerStore>>,
+    shut_down: bool,
 }
 
 impl SmartHouseServer {
-    fn get_processors() -> Vec<Arc<dyn RequestProcessor>> {
-        let processors: Vec<Arc<dyn RequestProcessor>> = vec![
-            Arc::new(HelloProcessor),
-            Arc::new(RoomsListProcessor),
-            Arc::new(DeviceListProcessor),
-            Arc::new(DeviceReportProcessor),
-            Arc::new(SetDevicePowerStateProcessor),
-            Arc::new(IsDeviceOnProcessor),
-            Arc::new(GetDeviceReportStreamProcessor),
-            Arc::new(CancelDeviceReportStreamProcessor),
+    fn get_processors() -> Vec<Processor> {
+        // Shared processors may still change the house: device power state through
+        // the per-device locks, streams, budget and alerts through the ServerStore lock
+        let processors: Vec<Processor> = vec![
+            Processor::HouseShared(Arc::new(HelloProcessor)),
+            Processor::HouseShared(Arc::new(RoomsListProcessor)),
+            Processor::HouseShared(Arc::new(DeviceListProcessor)),
+            Processor::HouseShared(Arc::new(DeviceReportProcessor)),
+            Processor::HouseShared(Arc::new(SetDevicePowerStateProcessor)),
+            Processor::HouseShared(Arc::new(SetGroupPowerStateProcessor)),
+            Processor::HouseShared(Arc::new(IsDeviceOnProcessor)),
+            Processor::HouseShared(Arc::new(GetDeviceReportStreamProcessor)),
+            Processor::HouseShared(Arc::new(CancelDeviceReportStreamProcessor)),
+            Processor::HouseShared(Arc::new(RenewDeviceReportStreamProcessor)),
+            Processor::HouseShared(Arc::new(ListStreamsProcessor)),
+            Processor::HouseShared(Arc::new(StreamInfoProcessor)),
+            Processor::HouseShared(Arc::new(SetPowerBudgetProcessor)),
+            Processor::HouseShared(Arc::new(SetDevicePriorityProcessor)),
+            Processor::HouseShared(Arc::new(PowerBudgetReportProcessor)),
+            Processor::HouseShared(Arc::new(AddAlertProcessor)),
+            Processor::HouseShared(Arc::new(RemoveAlertProcessor)),
+            Processor::HouseShared(Arc::new(ActiveAlertsProcessor)),
+            Processor::HouseShared(Arc::new(AlertsHistoryProcessor)),
+            Processor::HouseShared(Arc::new(SubscribeAlertsProcessor)),
+            Processor::HouseShared(Arc::new(UnsubscribeAlertsProcessor)),
+            Processor::HouseExclusive(Arc::new(AddRoomProcessor)),
+            Processor::HouseExclusive(Arc::new(RemoveRoomProcessor)),
+            Processor::HouseExclusive(Arc::new(AddDeviceProcessor)),
+            Processor::HouseExclusive(Arc::new(RemoveDeviceProcessor)),
+            Processor::HouseExclusive(Arc::new(RenameProcessor)),
         ];
         processors
     }
@@ -112,119 +205,340 @@ impl SmartHouseServer {
     }
 
     pub fn new<Addrs>(tcp_addr: Addrs, udp_addr: Addrs) -> Result<Self, CreateNewServerError>
+    where
+        Addrs: ToSocketAddrs,
+    {
+        Self::with_smart_house(SmartHouseServer::init_smart_house()?, tcp_addr, udp_addr)
+    }
+
+    /// Create a server for an already built smart house
+    pub fn with_smart_house<Addrs>(
+        smart_house: SmartHouse,
+        tcp_addr: Addrs,
+        udp_addr: Addrs,
+    ) -> Result<Self, CreateNewServerError>
     where
         Addrs: ToSocketAddrs,
     {
         Ok(SmartHouseServer {
-            smart_house: Arc::new(RwLock::new(SmartHouseServer::init_smart_house()?)),
-            stp: Arc::new(my_stp::server::StpServer::bind(tcp_addr)?),
-            processors: Arc::new(SmartHouseServer::get_processors()),
+            smart_house: Arc::new(RwLock::new(smart_house)),
+            stp: Arc::new(StpServer::bind(tcp_addr)?),
+            unix_stp: None,
+            key_store: None,
+            router: Arc::new(Router::new(SmartHouseServer::get_processors())),
+            worker_pool: Arc::new(WorkerPool::new(
+                DEFAULT_WORKER_THREADS,
+                DEFAULT_PENDING_CONNECTIONS,
+            )),
+            sessions: Arc::new(Sessions::default()),
             server_threads: Arc::new(RwLock::new(ServerStore {
-                execution_threads: HashMap::new(),
-                message_thread: None,
+                streams: StreamRegistry::default(),
+                message_threads: Vec::new(),
+                monitor_thread: None,
+                power_budget: PowerBudget::new(Arc::new(SystemClock)),
+                alerts: AlertManager::new(Arc::new(SystemClock)),
+                alert_subscribers: HashSet::new(),
                 udp_socket: UdpSocket::bind(udp_addr)?,
             })),
+            shut_down: false,
         })
     }
 
+    /// Require clients to authenticate with one of the keys, on every listener.
+    /// Has to be called before [`SmartHouseServer::start_server_listening`]
+    pub fn set_key_store(
+        &mut self,
+        key_store: Option<KeyStore>,
+    ) -> Result<(), ConfigureServerError> {
+        let listeners = std::iter::once(&mut self.stp).chain(self.unix_stp.as_mut());
+        for stp in listeners {
+            let stp = Arc::get_mut(stp).ok_or(ConfigureServerError::AlreadyListening)?;
+            stp.set_key_store(key_store.clone());
+        }
+        self.key_store = key_store;
+        Ok(())
+    }
+
+    /// Serve connections with `workers` threads, `pending` more connections wait for a free one
+    /// and the ones beyond are closed right after the handshake.
+    /// Has to be called before [`SmartHouseServer::start_server_listening`]
+    pub fn set_worker_pool(
+        &mut self,
+        workers: usize,
+        pending: usize,
+    ) -> Result<(), ConfigureServerError> {
+        let worker_pool =
+            Arc::get_mut(&mut self.worker_pool).ok_or(ConfigureServerError::AlreadyListening)?;
+        *worker_pool = WorkerPool::new(workers, pending);
+        Ok(())
+    }
+
+    /// Also accept clients on a Unix domain socket, next to the TCP listener.
+    /// Has to be called before [`SmartHouseServer::start_server_listening`]
+    #[cfg(unix)]
+    pub fn listen_unix<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        options: UnixSocketOptions,
+    ) -> Result<(), ConfigureServerError> {
+        if Arc::get_mut(&mut self.stp).is_none() {
+            return Err(ConfigureServerError::AlreadyListening);
+        }
+        let mut stp = StpServer::bind_unix(path, options)?;
+        stp.set_key_store(self.key_store.clone());
+        self.unix_stp = Some(Arc::new(stp));
+        Ok(())
+    }
+
+    /// Address the STP listener is bound to, useful when binding to port 0
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.stp.local_addr()
+    }
+
+    /// Path of the Unix socket given to [`SmartHouseServer::listen_unix`]
+    #[cfg(unix)]
+    pub fn socket_path(&self) -> Option<&Path> {
+        self.unix_stp.as_ref().and_then(|stp| stp.socket_path())
+    }
+
     pub fn start_server_listening(&mut self) {
         println!("Starting server...");
 
+        let listeners: Vec<_> = std::iter::once(self.stp.clone())
+            .chain(self.unix_stp.clone())
+            .collect();
+        for stp in listeners {
+            let accept_thread = self.start_accepting(stp);
+            self.server_threads
+                .write()
+                .unwrap()
+                .message_threads
+                .push(accept_thread);
+        }
+        self.start_house_monitoring();
+    }
+
+    /// Every listener gets its own accept thread, handshakes and requests of all listeners
+    /// run on the worker pool, so the accept threads never wait for a client
+    fn start_accepting(&self, stp_atomic: Arc<StpServer>) -> ServerThread {
         let smart_house_ptr = self.smart_house.clone();
-        let processors_ptr = self.processors.clone();
-        let stp_atomic = self.stp.clone();
+        let router_ptr = self.router.clone();
+        let worker_pool = self.worker_pool.clone();
+        let sessions = self.sessions.clone();
 
         let server_threads_ptr = self.server_threads.clone();
 
-        let (canceler, cancellation_token) = thread_cancellation_token::cancellation_token();
-
-        let _: thread::JoinHandle<_> = thread::spawn(move || {
+        ServerThread::spawn(move |cancellation_token| {
             let smart_house_ptr = smart_house_ptr;
-            let processors_ptr = processors_ptr;
+            let router_ptr = router_ptr;
             let server_threads_ptr = server_threads_ptr;
 
             loop {
                 if cancellation_token.should_cancel() {
                     break;
                 }
-                let connection = stp_atomic.accept();
-                if connection.is_err() {
+                // The handshake runs on a worker, a silent client can't hold up the next ones
+                let Ok(pending) = stp_atomic.accept_pending() else {
                     continue;
-                }
-                let success_connection = connection.unwrap();
-
-                let proccess_result = success_connection.proccess_request(|reqest| {
-                    match Self::process_request(
-                        reqest,
-                        server_threads_ptr.clone(),
-                        &smart_house_ptr,
-                        &processors_ptr,
-                    ) {
-                        Ok(response) => response,
+                };
+
+                let stp = stp_atomic.clone();
+                let smart_house_ptr = smart_house_ptr.clone();
+                let router_ptr = router_ptr.clone();
+                let server_threads_ptr = server_threads_ptr.clone();
+                let sessions = sessions.clone();
+                let served = worker_pool.try_execute(move || {
+                    // Connections still queued when the server shuts down are closed unserved,
+                    // a shutdown also ends the handshake
+                    let Some(session_id) = pending
+                        .closer()
+                        .ok()
+                        .and_then(|closer| sessions.register(closer))
+                    else {
+                        return;
+                    };
+                    let mut success_connection = match stp.handshake(pending) {
+                        Ok(connection) => connection,
                         Err(e) => {
-                            eprintln!("Proccess request error : {:?}", e);
-                            format!("Proccess request error : {:?}", e)
+                            sessions.unregister(session_id);
+                            eprintln!("Handshake error : {e:?}");
+                            return;
                         }
+                    };
+                    let identity = success_connection.identity().map(str::to_string);
+                    let pusher = success_connection.pusher();
+                    let proccess_result = success_connection.serve_typed(|reqest| {
+                        let context = RequestContext {
+                            identity: identity.as_deref(),
+                            session_id,
+                            pusher: pusher.as_ref(),
+                        };
+                        Self::respond(reqest, |request| {
+                            Self::process_request(
+                                request,
+                                &context,
+                                server_threads_ptr.clone(),
+                                &smart_house_ptr,
+                                &router_ptr,
+                            )
+                        })
+                    });
+
+                    sessions.unregister(session_id);
+                    if let Err(request_error) = proccess_result {
+                        eprintln!("Request error : {:?}", request_error);
                     }
                 });
+                if !served {
+                    eprintln!("All workers are busy, connection closed");
+                }
+            }
+        })
+    }
+
+    fn start_house_monitoring(&mut self) {
+        let smart_house_ptr = self.smart_house.clone();
+        let server_threads_ptr = self.server_threads.clone();
 
-                if let Err(request_error) = proccess_result {
-                    eprintln!("Request error : {:?}", request_error);
+        let monitor_thread = ServerThread::spawn(move |cancellation_token| loop {
+            if cancellation_token.sleep(HOUSE_MONITOR_INTERVAL) {
+                break;
+            }
+            let smart_house = smart_house_ptr.read().unwrap();
+            let mut server = server_threads_ptr.write().unwrap();
+            for event in server.power_budget.enforce(&smart_house) {
+                println!("power budget: {event}");
+            }
+
+            for event in server.alerts.evaluate(&smart_house) {
+                let message = event.to_string();
+                println!("{message}");
+                for addr in server.alert_subscribers.iter() {
+                    if let Err(e) = server.udp_socket.send_to(message.as_bytes(), addr) {
+                        eprintln!("Cant send alert to {addr} : {e}");
+                    }
                 }
             }
         });
 
-        self.server_threads.write().unwrap().message_thread = Some(canceler);
+        self.server_threads.write().unwrap().monitor_thread = Some(monitor_thread);
+    }
+
+    /// Stop accepting connections, let requests in flight finish and close the sessions,
+    /// stop report streams and house monitoring, then join every server thread.
+    /// Threads still busy after `timeout` are left running and counted in the summary.
+    /// The server can't be started again
+    pub fn shutdown(&mut self, timeout: Duration) -> ShutdownSummary {
+        let started = Instant::now();
+        let deadline = started + timeout;
+        self.shut_down = true;
+        let accept_threads =
+            std::mem::take(&mut self.server_threads.write().unwrap().message_threads);
+        let mut summary = ShutdownSummary {
+            listeners: accept_threads.len(),
+            ..ShutdownSummary::default()
+        };
+
+        for thread in &accept_threads {
+            thread.cancel();
+        }
+        if !accept_threads.is_empty() {
+            self.wake_accepting();
+        }
+        let accept_threads = accept_threads.into_iter().map(|thread| thread.handle);
+        summary.unfinished_threads += shutdown::join_until(accept_threads.collect(), deadline);
+
+        // Requests in flight may still start streams, so streams are stopped after them
+        summary.closed_sessions = self.sessions.drain();
+        summary.unfinished_threads += self.worker_pool.shutdown(deadline);
+
+        let threads: Vec<_> = {
+            let mut server = self.server_threads.write().unwrap();
+            let streams = server.streams.take_all();
+            summary.stopped_streams = streams.len();
+            let streams = streams.into_iter().map(|stream| stream.handle);
+            let stopped = server.streams.take_stopped();
+            streams
+                .chain(stopped)
+                .chain(server.monitor_thread.take())
+                .collect()
+        };
+        for thread in &threads {
+            thread.cancel();
+        }
+        let threads = threads.into_iter().map(|thread| thread.handle).collect();
+        summary.unfinished_threads += shutdown::join_until(threads, deadline);
+
+        summary.elapsed = started.elapsed();
+        summary
+    }
+
+    /// Accept threads block in accept, a connection that is dropped right away
+    /// lets them see they are cancelled
+    fn wake_accepting(&self) {
+        if let Ok(addr) = self.stp.local_addr() {
+            let _ = TcpStream::connect(addr);
+        }
+        #[cfg(unix)]
+        if let Some(path) = self.socket_path() {
+            let _ = UnixStream::connect(path);
+        }
     }
 
     fn process_request(
         request: String,
+        context: &RequestContext,
         server: Arc<RwLock<ServerStore>>,
         smart_house_ptr: &RwLock<SmartHouse>,
-        processors: &[Arc<dyn RequestProcessor>],
+        router: &Router,
     ) -> Result<String, ProccessRequestError> {
-        let lock_guard = smart_house_ptr.write();
-        let mut lock_result = lock_guard.map_err(|_| ProccessRequestError::CantReadSmartHouse)?;
-        let smart_house_ref = lock_result.deref_mut();
-
-        for proccessor in processors.iter() {
-            let result = proccessor.try_process(&request, server.clone(), smart_house_ref);
-            match result {
-                Err(ProccessorError::CantProccessRequest) => continue,
-                Err(e) => return Err(ProccessRequestError::ProccessorError(e)),
-                _ => (),
-            }
+        if let Some(identity) = context.identity {
+            println!("{identity} : {request}");
+        }
+        router.process(&request, server, smart_house_ptr, context)
+    }
 
-            if let Ok(response) = result {
-                return Ok(response);
+    /// Command lines and typed requests are processed as they are,
+    /// a JSON-RPC frame call by call. Frames of notifications only get an empty response
+    fn respond<F>(request: Request, mut process: F) -> Response
+    where
+        F: FnMut(String) -> Result<String, ProccessRequestError>,
+    {
+        match request {
+            Request::Text(text) if jsonrpc::is_json_rpc(&text) => {
+                let response = jsonrpc::handle_frame(&text, |call| {
+                    process(call.into_command_line()).map_err(|e| {
+                        eprintln!("Proccess request error : {:?}", e);
+                        e.json_rpc_error()
+                    })
+                });
+                Response::Ok(response.unwrap_or_default())
             }
+            request => Self::create_response(process(request.into_command_line())),
         }
+    }
 
-        Err(ProccessRequestError::CantProccessRequest)
+    /// String clients get the error message, typed clients also get the error code
+    fn create_response(result: Result<String, ProccessRequestError>) -> Response {
+        match result {
+            Ok(response) => Response::Ok(response),
+            Err(e) => {
+                eprintln!("Proccess request error : {:?}", e);
+                Response::Error {
+                    code: e.code(),
+                    message: format!("Proccess request error : {:?}", e),
+                }
+            }
+        }
     }
 }
 
 impl Drop for SmartHouseServer {
     fn drop(&mut self) {
-        println!("Dropping server...");
-        println!("join all server threads");
-
-        let mut write_guard = self.server_threads.write().unwrap();
-
-        if let Some(thread) = write_guard.message_thread.take() {
-            thread.cancel();
-            println!("tcp message thread joined");
-        }
-
-        println!("join udp server threads");
-        let execution_threads = std::mem::take(&mut write_guard.execution_threads);
-
-        for (_, value) in execution_threads.into_iter() {
-            value.cancel();
+        if self.shut_down {
+            return;
         }
-
-        println!("udp server threads joined");
-
-        println!("server dropped");
+        println!("Dropping server...");
+        println!("{}", self.shutdown(DEFAULT_SHUTDOWN_TIMEOUT));
     }
 }
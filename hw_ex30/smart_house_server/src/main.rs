@@ -1,9 +1,36 @@
+use my_stp::auth::KeyStore;
+
+/// File with `client_id:key` lines. Without it every client is accepted
+const KEYS_FILE_VAR: &str = "SMART_HOUSE_KEYS_FILE";
+/// Unix socket path to listen on besides TCP
+const SOCKET_VAR: &str = "SMART_HOUSE_SOCKET";
+
 fn main() {
     let mut server =
         smart_house_server::SmartHouseServer::new("127.0.0.1:8080", "127.0.0.1:8082").unwrap();
-    server.start_server_listening();
-    loop {
-        std::thread::sleep(std::time::Duration::from_secs(1));
-        println!("server main thread tick")
+    if let Ok(keys_file) = std::env::var(KEYS_FILE_VAR) {
+        server
+            .set_key_store(Some(KeyStore::load(keys_file).unwrap()))
+            .unwrap();
+    }
+    #[cfg(unix)]
+    if let Ok(socket) = std::env::var(SOCKET_VAR) {
+        server
+            .listen_unix(socket, my_stp::socket_file::UnixSocketOptions::default())
+            .unwrap();
     }
+    server.start_server_listening();
+
+    let (stop_sender, stop) = std::sync::mpsc::channel();
+    ctrlc::set_handler(move || {
+        let _ = stop_sender.send(());
+    })
+    .unwrap();
+    // SIGINT or SIGTERM
+    let _ = stop.recv();
+    println!("Shutting down...");
+    println!(
+        "{}",
+        server.shutdown(smart_house_server::shutdown::DEFAULT_SHUTDOWN_TIMEOUT)
+    );
 }
@@ -1,62 +1,177 @@
 use std::{
+    str::FromStr,
     sync::{Arc, RwLock},
-    thread,
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
 
-use crate::{errors::ProccessorError, ServerStore};
+use smart_house::alerts::AlertDefinition;
+use smart_house::bulk::{self, DeviceGroup, PowerAction};
+use smart_house::smart_tools::smart_socket::{SmartSocket, TemperatureProvider};
+use smart_house::smart_tools::thermomener::{EnergyProvider, Thermometer};
+use smart_house::streams::{stream_name, StreamEntry, StreamOwner, StreamState};
+use smart_house::temperature::TemperatureMeasureUnits;
 
+use my_stp::command_schema::{CommandSchema, ParamSchema, ParamType};
+use my_stp::custom_parser::RequestParams;
+use my_stp::errors::SchemaError;
+use my_stp::{errors::PushError, push::Pusher};
+
+use crate::{errors::ProccessorError, ServerStore, ServerThread};
+
+/// What a processor knows about the connection the request came from
+pub(super) struct RequestContext<'a> {
+    /// Id of the authenticated client
+    pub identity: Option<&'a str>,
+    /// Unique among the sessions of the server
+    pub session_id: u64,
+    /// `None` if the client has not negotiated push messages
+    pub pusher: Option<&'a Pusher>,
+}
+
+impl RequestContext<'_> {
+    /// Streams of an authenticated client belong to all its sessions
+    pub fn stream_owner(&self) -> StreamOwner {
+        match self.identity {
+            Some(identity) => StreamOwner::Client(identity.to_string()),
+            None => StreamOwner::Session(self.session_id),
+        }
+    }
+}
+
+/// Processor that keeps the rooms and devices of the house as they are.
+/// It is served under the read lock of the house, in parallel with other such processors,
+/// and changes devices or server state only through their own locks
 pub(super) trait RequestProcessor: Sync + Send {
-    fn try_process(
+    /// Name and parameters of the command the processor serves
+    fn schema(&self) -> &'static CommandSchema;
+
+    /// Serve a request that matches the schema, defaults are already filled in
+    fn process(
         &self,
-        request: &str,
+        params: &RequestParams,
+        server: Arc<RwLock<ServerStore>>,
+        smart_house: &smart_house::SmartHouse,
+        context: &RequestContext,
+    ) -> Result<String, ProccessorError>;
+}
+
+/// Processor that adds, removes or renames rooms and devices.
+/// It is served under the write lock, alone
+pub(super) trait HouseEditProcessor: Sync + Send {
+    /// Name and parameters of the command the processor serves
+    fn schema(&self) -> &'static CommandSchema;
+
+    /// Serve a request that matches the schema, defaults are already filled in
+    fn process(
+        &self,
+        params: &RequestParams,
         server: Arc<RwLock<ServerStore>>,
         smart_house: &mut smart_house::SmartHouse,
+        context: &RequestContext,
     ) -> Result<String, ProccessorError>;
 }
 
+/// A processor with the lock of the house it needs
+#[derive(Clone)]
+pub(super) enum Processor {
+    /// Shares the house with other processors, see [`RequestProcessor`]
+    HouseShared(Arc<dyn RequestProcessor>),
+    /// Holds the house alone while it adds, removes or renames rooms and devices
+    HouseExclusive(Arc<dyn HouseEditProcessor>),
+}
+
+impl Processor {
+    pub fn schema(&self) -> &'static CommandSchema {
+        match self {
+            Processor::HouseShared(processor) => processor.schema(),
+            Processor::HouseExclusive(processor) => processor.schema(),
+        }
+    }
+}
+
+const ROOM_NAME: ParamSchema =
+    ParamSchema::required("room_name", ParamType::Text, "name of the room");
+const DEVICE_NAME: ParamSchema = ParamSchema::required(
+    "device_name",
+    ParamType::Text,
+    "name of the device in the room",
+);
+const MAX_POWER: ParamType = ParamType::Custom {
+    expected: "watts|none",
+    check: |value| {
+        value == "none"
+            || value
+                .parse::<f32>()
+                .is_ok_and(|watts| watts.is_finite() && watts >= 0.0)
+    },
+};
+
+/// Value of a parameter the schema requires or gives a default
+fn required<'a>(params: &'a RequestParams, name: &str) -> Result<&'a str, ProccessorError> {
+    params
+        .get(name)
+        .ok_or_else(|| SchemaError::MissingParam(name.to_string()).into())
+}
+
+/// Required value parsed, the schema has checked it already
+fn parsed<T: FromStr>(params: &RequestParams, name: &str) -> Result<T, ProccessorError> {
+    required(params, name)?
+        .parse()
+        .map_err(|_| ProccessorError::BadRequestParam)
+}
+
 pub(super) struct HelloProcessor;
 
 impl RequestProcessor for HelloProcessor {
-    fn try_process(
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema =
+            CommandSchema::new("hello", "Greeting, names the authenticated client", &[]);
+        &SCHEMA
+    }
+
+    fn process(
         &self,
-        request: &str,
+        params: &RequestParams,
         server: Arc<RwLock<ServerStore>>,
-        smart_house: &mut smart_house::SmartHouse,
+        smart_house: &smart_house::SmartHouse,
+        context: &RequestContext,
     ) -> Result<String, ProccessorError> {
+        let _ = params;
         let _ = server;
         let _ = smart_house;
 
-        if !request.starts_with("hello") {
-            return Err(ProccessorError::CantProccessRequest);
+        match context.identity {
+            Some(identity) => Ok(format!("Hello {identity} from server")),
+            None => Ok("Hello from server".to_string()),
         }
-        Ok("Hello from server".to_string())
     }
 }
 
 pub(super) struct DeviceReportProcessor;
 
 impl RequestProcessor for DeviceReportProcessor {
-    fn try_process(
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "device_report",
+            "Report of a device",
+            &[ROOM_NAME, DEVICE_NAME],
+        );
+        &SCHEMA
+    }
+
+    fn process(
         &self,
-        request: &str,
+        params: &RequestParams,
         server: Arc<RwLock<ServerStore>>,
-        smart_house: &mut smart_house::SmartHouse,
+        smart_house: &smart_house::SmartHouse,
+        context: &RequestContext,
     ) -> Result<String, ProccessorError> {
         let _ = server;
         let _ = smart_house;
+        let _ = context;
 
-        if !request.starts_with("device_report") {
-            return Err(ProccessorError::CantProccessRequest);
-        }
-
-        let params = my_stp::custom_parser::parse_request_parameters(request);
-        let room_name = params
-            .get("room_name")
-            .ok_or(ProccessorError::CantProccessRequest)?;
-        let device_name = params
-            .get("device_name")
-            .ok_or(ProccessorError::CantProccessRequest)?;
+        let room_name = required(params, "room_name")?;
+        let device_name = required(params, "device_name")?;
 
         let response = smart_house
             .create_report_by_devices(vec![(room_name, device_name)])
@@ -69,16 +184,21 @@ impl RequestProcessor for DeviceReportProcessor {
 pub(super) struct RoomsListProcessor;
 
 impl RequestProcessor for RoomsListProcessor {
-    fn try_process(
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new("rooms_list", "Names of the rooms", &[]);
+        &SCHEMA
+    }
+
+    fn process(
         &self,
-        request: &str,
+        params: &RequestParams,
         server: Arc<RwLock<ServerStore>>,
-        smart_house: &mut smart_house::SmartHouse,
+        smart_house: &smart_house::SmartHouse,
+        context: &RequestContext,
     ) -> Result<String, ProccessorError> {
+        let _ = params;
         let _ = server;
-        if !request.starts_with("rooms_list") {
-            return Err(ProccessorError::CantProccessRequest);
-        }
+        let _ = context;
 
         let rooms = smart_house.get_rooms();
         let room_names: Vec<&str> = rooms.iter().map(|value| value.name()).collect();
@@ -93,21 +213,26 @@ impl RequestProcessor for RoomsListProcessor {
 pub(super) struct DeviceListProcessor;
 
 impl RequestProcessor for DeviceListProcessor {
-    fn try_process(
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "devices_list",
+            "Names of the devices in a room",
+            &[ROOM_NAME],
+        );
+        &SCHEMA
+    }
+
+    fn process(
         &self,
-        request: &str,
+        params: &RequestParams,
         server: Arc<RwLock<ServerStore>>,
-        smart_house: &mut smart_house::SmartHouse,
+        smart_house: &smart_house::SmartHouse,
+        context: &RequestContext,
     ) -> Result<String, ProccessorError> {
         let _ = server;
-        if !request.starts_with("devices_list") {
-            return Err(ProccessorError::CantProccessRequest);
-        }
+        let _ = context;
 
-        let params = my_stp::custom_parser::parse_request_parameters(request);
-        let room_name = params
-            .get("room_name")
-            .ok_or(ProccessorError::CantProccessRequest)?;
+        let room_name = required(params, "room_name")?;
 
         let room = smart_house
             .get_room(room_name)
@@ -129,37 +254,42 @@ impl RequestProcessor for DeviceListProcessor {
 pub(super) struct SetDevicePowerStateProcessor;
 
 impl RequestProcessor for SetDevicePowerStateProcessor {
-    fn try_process(
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "set_device_power_state",
+            "Turn a device on or off",
+            &[
+                ROOM_NAME,
+                DEVICE_NAME,
+                ParamSchema::required("power_state", ParamType::Bool, "true turns the device on"),
+            ],
+        );
+        &SCHEMA
+    }
+
+    fn process(
         &self,
-        request: &str,
+        params: &RequestParams,
         server: Arc<RwLock<ServerStore>>,
-        smart_house: &mut smart_house::SmartHouse,
+        smart_house: &smart_house::SmartHouse,
+        context: &RequestContext,
     ) -> Result<String, ProccessorError> {
         let _ = server;
-        if !request.starts_with("set_device_power_state") {
-            return Err(ProccessorError::CantProccessRequest);
-        }
+        let _ = context;
 
-        let params = my_stp::custom_parser::parse_request_parameters(request);
-        let room_name = params
-            .get("room_name")
-            .ok_or(ProccessorError::CantProccessRequest)?;
-        let device_name = params
-            .get("device_name")
-            .ok_or(ProccessorError::CantProccessRequest)?;
-        let power_state = params
-            .get("power_state")
-            .ok_or(ProccessorError::CantProccessRequest)?;
+        let room_name = required(params, "room_name")?;
+        let device_name = required(params, "device_name")?;
+        let power_state = required(params, "power_state")?;
 
         let room = smart_house
-            .get_room_mut(room_name)
+            .get_room(room_name)
             .ok_or(ProccessorError::CantFindRoom)?;
         let device = room
             .get_device(device_name)
             .ok_or(ProccessorError::CantFindDevice)?;
 
         let mut device_write = device.write().unwrap();
-        match *power_state {
+        match power_state {
             "true" => device_write.turn_on(),
             "false" => device_write.turn_off(),
             _ => device_write.turn_off(),
@@ -169,27 +299,78 @@ impl RequestProcessor for SetDevicePowerStateProcessor {
     }
 }
 
+pub(super) struct SetGroupPowerStateProcessor;
+
+impl RequestProcessor for SetGroupPowerStateProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "set_group_power_state",
+            "Turn on, turn off or toggle a group of devices",
+            &[
+                ParamSchema::required(
+                    "power_state",
+                    ParamType::OneOf(&["true", "false", "toggle"]),
+                    "what to do with the devices",
+                ),
+                ParamSchema::optional("room_name", ParamType::Text, "only devices of the room"),
+                ParamSchema::optional(
+                    "kind",
+                    ParamType::Text,
+                    "only devices of the kind, e.g. smart_socket",
+                ),
+            ],
+        );
+        &SCHEMA
+    }
+
+    fn process(
+        &self,
+        params: &RequestParams,
+        _server: Arc<RwLock<ServerStore>>,
+        smart_house: &smart_house::SmartHouse,
+        _context: &RequestContext,
+    ) -> Result<String, ProccessorError> {
+        let power_state: PowerAction = required(params, "power_state")?
+            .parse()
+            .map_err(|_| ProccessorError::BadRequestParam)?;
+        let group = DeviceGroup {
+            room_name: params
+                .get("room_name")
+                .map(|room_name| room_name.to_string()),
+            kind: params.get("kind").map(|kind| kind.to_string()),
+        };
+
+        let results = smart_house
+            .set_group_power_state(&group, power_state)
+            .ok_or(ProccessorError::CantFindRoom)?;
+        Ok(bulk::create_results_report(&results))
+    }
+}
+
 pub(super) struct IsDeviceOnProcessor;
 
 impl RequestProcessor for IsDeviceOnProcessor {
-    fn try_process(
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "is_device_on",
+            "Power state of a device",
+            &[ROOM_NAME, DEVICE_NAME],
+        );
+        &SCHEMA
+    }
+
+    fn process(
         &self,
-        request: &str,
+        params: &RequestParams,
         server: Arc<RwLock<ServerStore>>,
-        smart_house: &mut smart_house::SmartHouse,
+        smart_house: &smart_house::SmartHouse,
+        context: &RequestContext,
     ) -> Result<String, ProccessorError> {
         let _ = server;
-        if !request.starts_with("is_device_on") {
-            return Err(ProccessorError::CantProccessRequest);
-        }
+        let _ = context;
 
-        let params = my_stp::custom_parser::parse_request_parameters(request);
-        let room_name = params
-            .get("room_name")
-            .ok_or(ProccessorError::CantProccessRequest)?;
-        let device_name = params
-            .get("device_name")
-            .ok_or(ProccessorError::CantProccessRequest)?;
+        let room_name = required(params, "room_name")?;
+        let device_name = required(params, "device_name")?;
 
         let room = smart_house
             .get_room(room_name)
@@ -208,86 +389,163 @@ impl RequestProcessor for IsDeviceOnProcessor {
 
 pub(super) struct GetDeviceReportStreamProcessor;
 
+/// Where a report stream goes: UDP datagrams to `addr` or push messages on the connection
+enum ReportTarget {
+    Udp(String),
+    Push(Pusher),
+}
+
+impl ReportTarget {
+    fn describe(&self) -> String {
+        match self {
+            ReportTarget::Udp(addr) => format!("udp {addr}"),
+            ReportTarget::Push(_) => "push".to_string(),
+        }
+    }
+}
+
+const STREAM_NAME: ParamSchema = ParamSchema::required(
+    "stream_name",
+    ParamType::Text,
+    "name the stream was created with",
+);
+
+const TTL: ParamSchema = ParamSchema::optional(
+    "ttl",
+    ParamType::UnsignedInteger,
+    "seconds the stream lives unless it is renewed",
+);
+
 impl RequestProcessor for GetDeviceReportStreamProcessor {
-    fn try_process(
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "get_device_report_stream",
+            "Send reports of a device periodically",
+            &[
+                ROOM_NAME,
+                DEVICE_NAME,
+                ParamSchema::optional(
+                    "request_delay",
+                    ParamType::UnsignedInteger,
+                    "seconds between reports",
+                )
+                .with_default("5"),
+                ParamSchema::optional(
+                    "addr",
+                    ParamType::Text,
+                    "UDP address for the reports, push messages are used without it",
+                ),
+                TTL,
+            ],
+        );
+        &SCHEMA
+    }
+
+    fn process(
         &self,
-        request: &str,
+        params: &RequestParams,
         server: Arc<RwLock<ServerStore>>,
-        smart_house: &mut smart_house::SmartHouse,
+        smart_house: &smart_house::SmartHouse,
+        context: &RequestContext,
     ) -> Result<String, ProccessorError> {
-        if !request.starts_with("get_device_report_stream") {
-            return Err(ProccessorError::CantProccessRequest);
-        }
         println!("get get_device_report_stream request");
-        const DEFAULT_REQUEST_DELAY: u64 = 5;
-
-        let params = my_stp::custom_parser::parse_request_parameters(request);
-        let room_name = params
-            .get("room_name")
-            .ok_or(ProccessorError::CantProccessRequest)?;
-        let device_name = params
-            .get("device_name")
-            .ok_or(ProccessorError::CantProccessRequest)?;
-        let request_delay: u64 = params.get("request_delay").map_or_else(
-            || DEFAULT_REQUEST_DELAY,
-            |request_delay_string| {
-                request_delay_string
-                    .parse()
-                    .unwrap_or(DEFAULT_REQUEST_DELAY)
-            },
-        );
-        let addr_for_send = params
-            .get("addr")
-            .ok_or(ProccessorError::CantProccessRequest)?
-            .to_string();
+        let room_name = required(params, "room_name")?;
+        let device_name = required(params, "device_name")?;
+        let interval = Duration::from_secs(parsed(params, "request_delay")?);
+        let ttl = match params.get("ttl") {
+            Some(_) => Some(Duration::from_secs(parsed(params, "ttl")?)),
+            None => None,
+        };
+        let target = match params.get("addr") {
+            Some(addr) => ReportTarget::Udp(addr.to_string()),
+            None => ReportTarget::Push(
+                context
+                    .pusher
+                    .cloned()
+                    .ok_or(ProccessorError::PushNotNegotiated)?,
+            ),
+        };
 
         let room = smart_house
-            .get_room_mut(room_name)
+            .get_room(room_name)
             .ok_or(ProccessorError::CantFindRoom)?;
         let device = room
             .get_device(device_name)
             .ok_or(ProccessorError::CantFindDevice)?;
         let server_thread = server.clone();
 
-        let thread_name = format!("{}-{}", room_name, device.read().unwrap().get_device_name());
-        let (canceler, cancellation_token) = thread_cancellation_token::cancellation_token();
-        let _ = thread::spawn(move || {
-            let address_for_reporting = addr_for_send;
+        let thread_name = stream_name(room_name, device_name);
+        let owner = context.stream_owner();
+        // Held until the stream is registered, so nobody else takes the name meanwhile
+        let mut server = server.write().unwrap();
+        server.streams.check_owner(&thread_name, &owner)?;
+
+        let state = Arc::new(StreamState::new(ttl));
+        let stream_state = state.clone();
+        let stream_name = thread_name.clone();
+        let description = target.describe();
+        let stream_thread = ServerThread::spawn(move |cancellation_token| {
+            let mut next_report = Instant::now() + interval;
             loop {
                 println!("thread loop");
-                if cancellation_token.should_cancel() {
+                if cancellation_token.sleep(stream_state.sleep_time(next_report)) {
                     println!("should thread canceled");
                     break;
                 }
-                thread::sleep(Duration::from_secs(request_delay));
-                if cancellation_token.should_cancel() {
-                    println!("should thread canceled");
+                if stream_state.is_expired() {
+                    let mut server = server_thread.write().unwrap();
+                    server.streams.finish(&stream_name, &stream_state);
+                    println!("stream {stream_name} expired");
                     break;
                 }
+                // Woken up by the lease, not by the interval
+                if Instant::now() < next_report {
+                    continue;
+                }
+                next_report += interval;
 
-                if let Ok(report) = { device.read().unwrap().create_report() } {
-                    println!(
-                        "send report to {} : {:?}",
-                        address_for_reporting.clone(),
-                        server_thread
+                let Ok(report) = device.read().unwrap().create_report() else {
+                    stream_state.record(false);
+                    continue;
+                };
+                match &target {
+                    ReportTarget::Udp(addr) => {
+                        let sent = server_thread
                             .read()
                             .unwrap()
                             .udp_socket
-                            .send_to(report.as_bytes(), address_for_reporting.clone())
-                    );
+                            .send_to(report.as_bytes(), addr);
+                        stream_state.record(sent.is_ok());
+                        println!("send report to {addr} : {sent:?}");
+                    }
+                    ReportTarget::Push(pusher) => match pusher.push(&stream_name, &report) {
+                        Err(PushError::Closed) => {
+                            // The client is gone, the entry is removed unless it was replaced
+                            let mut server = server_thread.write().unwrap();
+                            server.streams.finish(&stream_name, &stream_state);
+                            println!("stream {stream_name} stopped, connection closed");
+                            break;
+                        }
+                        result => {
+                            stream_state.record(result.is_ok());
+                            println!("push report {stream_name} : {result:?}");
+                        }
+                    },
                 }
             }
         });
 
-        let mut server = server.write().unwrap();
-        let entry = server.execution_threads.remove(&thread_name);
-        if let Some(cancellable_thread) = entry {
-            cancellable_thread.cancel();
-        }
-
-        server
-            .execution_threads
-            .insert(thread_name.clone(), canceler);
+        server.streams.insert(
+            thread_name.clone(),
+            StreamEntry {
+                owner,
+                target: description,
+                interval,
+                created: SystemTime::now(),
+                state,
+                handle: stream_thread,
+            },
+        );
 
         Ok(format!("create thread with name : {thread_name}"))
     }
@@ -296,30 +554,33 @@ impl RequestProcessor for GetDeviceReportStreamProcessor {
 pub(super) struct CancelDeviceReportStreamProcessor;
 
 impl RequestProcessor for CancelDeviceReportStreamProcessor {
-    fn try_process(
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "cancel_device_report_stream",
+            "Stop a report stream of this client",
+            &[STREAM_NAME],
+        );
+        &SCHEMA
+    }
+
+    fn process(
         &self,
-        request: &str,
+        params: &RequestParams,
         server: Arc<RwLock<ServerStore>>,
-        smart_house: &mut smart_house::SmartHouse,
+        smart_house: &smart_house::SmartHouse,
+        context: &RequestContext,
     ) -> Result<String, ProccessorError> {
         let _ = smart_house;
-        if !request.starts_with("cancel_device_report_stream") {
-            return Err(ProccessorError::CantProccessRequest);
-        }
 
-        let params = my_stp::custom_parser::parse_request_parameters(request);
-        let thread_name = params
-            .get("stream_name")
-            .ok_or(ProccessorError::CantProccessRequest)?;
+        let thread_name = required(params, "stream_name")?;
 
-        if let Some(ca) = server
+        let cancelled = server
             .write()
             .unwrap()
-            .execution_threads
-            .remove(*thread_name)
-        {
+            .streams
+            .cancel(thread_name, &context.stream_owner())?;
+        if cancelled {
             println!("Start joining thread with name : {thread_name}");
-            ca.cancel();
             return Ok(format!("Cancel thread with name : {thread_name}"));
         }
         Ok(format!(
@@ -327,3 +588,701 @@ impl RequestProcessor for CancelDeviceReportStreamProcessor {
         ))
     }
 }
+
+pub(super) struct RenewDeviceReportStreamProcessor;
+
+impl RequestProcessor for RenewDeviceReportStreamProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "renew_device_report_stream",
+            "Extend the lease of a report stream of this client",
+            &[STREAM_NAME, TTL],
+        );
+        &SCHEMA
+    }
+
+    fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<RwLock<ServerStore>>,
+        _smart_house: &smart_house::SmartHouse,
+        context: &RequestContext,
+    ) -> Result<String, ProccessorError> {
+        let stream_name = required(params, "stream_name")?;
+        let ttl = match params.get("ttl") {
+            Some(_) => Some(Duration::from_secs(parsed(params, "ttl")?)),
+            None => None,
+        };
+
+        let ttl =
+            server
+                .read()
+                .unwrap()
+                .streams
+                .renew(stream_name, &context.stream_owner(), ttl)?;
+        Ok(match ttl {
+            Some(ttl) => format!("stream {stream_name} expires in {}", ttl.as_secs()),
+            None => format!("stream {stream_name} never expires"),
+        })
+    }
+}
+
+pub(super) struct ListStreamsProcessor;
+
+impl RequestProcessor for ListStreamsProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema =
+            CommandSchema::new("list_streams", "Report streams of all clients", &[]);
+        &SCHEMA
+    }
+
+    fn process(
+        &self,
+        _params: &RequestParams,
+        server: Arc<RwLock<ServerStore>>,
+        _smart_house: &smart_house::SmartHouse,
+        _context: &RequestContext,
+    ) -> Result<String, ProccessorError> {
+        Ok(server.read().unwrap().streams.list())
+    }
+}
+
+pub(super) struct StreamInfoProcessor;
+
+impl RequestProcessor for StreamInfoProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "stream_info",
+            "Owner, target, interval, lease and delivery counters of a report stream",
+            &[STREAM_NAME],
+        );
+        &SCHEMA
+    }
+
+    fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<RwLock<ServerStore>>,
+        _smart_house: &smart_house::SmartHouse,
+        _context: &RequestContext,
+    ) -> Result<String, ProccessorError> {
+        let stream_name = required(params, "stream_name")?;
+        server
+            .read()
+            .unwrap()
+            .streams
+            .info(stream_name)
+            .ok_or(ProccessorError::CantFindStream)
+    }
+}
+
+pub(super) struct SetPowerBudgetProcessor;
+
+impl RequestProcessor for SetPowerBudgetProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "set_power_budget",
+            "Limit the total power of the house",
+            &[
+                ParamSchema::required("max_power", MAX_POWER, "watts, none removes the limit"),
+                ParamSchema::optional(
+                    "cooldown",
+                    ParamType::UnsignedInteger,
+                    "seconds restores wait after the last change",
+                ),
+            ],
+        );
+        &SCHEMA
+    }
+
+    fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<RwLock<ServerStore>>,
+        smart_house: &smart_house::SmartHouse,
+        _context: &RequestContext,
+    ) -> Result<String, ProccessorError> {
+        let max_power = required(params, "max_power")?;
+        let max_power: Option<f32> = match max_power {
+            "none" => None,
+            max_power => Some(
+                max_power
+                    .parse()
+                    .map_err(|_| ProccessorError::BadRequestParam)?,
+            ),
+        };
+        let cooldown = params
+            .get_u64("cooldown")
+            .map_err(|_| ProccessorError::BadRequestParam)?;
+
+        let mut server = server.write().unwrap();
+        server
+            .power_budget
+            .set_max_power(max_power)
+            .map_err(|_| ProccessorError::BadRequestParam)?;
+        if let Some(cooldown) = cooldown {
+            server
+                .power_budget
+                .set_cooldown(Duration::from_secs(cooldown));
+        }
+        for event in server.power_budget.enforce(smart_house) {
+            println!("power budget: {event}");
+        }
+
+        Ok(server.power_budget.create_report(smart_house))
+    }
+}
+
+pub(super) struct SetDevicePriorityProcessor;
+
+impl RequestProcessor for SetDevicePriorityProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "set_device_priority",
+            "Priority of a device under the power budget",
+            &[
+                ROOM_NAME,
+                DEVICE_NAME,
+                ParamSchema::required(
+                    "priority",
+                    ParamType::UnsignedInteger,
+                    "devices with lower priority are turned off first",
+                ),
+            ],
+        );
+        &SCHEMA
+    }
+
+    fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<RwLock<ServerStore>>,
+        smart_house: &smart_house::SmartHouse,
+        _context: &RequestContext,
+    ) -> Result<String, ProccessorError> {
+        let room_name = required(params, "room_name")?;
+        let device_name = required(params, "device_name")?;
+        let priority: u32 = required(params, "priority")?
+            .parse()
+            .map_err(|_| ProccessorError::BadRequestParam)?;
+
+        smart_house
+            .get_room(room_name)
+            .ok_or(ProccessorError::CantFindRoom)?
+            .get_device(device_name)
+            .ok_or(ProccessorError::CantFindDevice)?;
+
+        server
+            .write()
+            .unwrap()
+            .power_budget
+            .set_priority(room_name, device_name, priority);
+
+        Ok(format!(
+            "room_name:{room_name},device_name:{device_name},priority:{priority}"
+        ))
+    }
+}
+
+pub(super) struct PowerBudgetReportProcessor;
+
+impl RequestProcessor for PowerBudgetReportProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "power_budget_report",
+            "Power budget and devices it turned off",
+            &[],
+        );
+        &SCHEMA
+    }
+
+    fn process(
+        &self,
+        _params: &RequestParams,
+        server: Arc<RwLock<ServerStore>>,
+        smart_house: &smart_house::SmartHouse,
+        _context: &RequestContext,
+    ) -> Result<String, ProccessorError> {
+        Ok(server
+            .read()
+            .unwrap()
+            .power_budget
+            .create_report(smart_house))
+    }
+}
+
+fn parse_alert_definition(params: &RequestParams) -> Result<AlertDefinition, ProccessorError> {
+    Ok(AlertDefinition {
+        name: required(params, "name")?.to_string(),
+        room_name: required(params, "room_name")?.to_string(),
+        device_name: required(params, "device_name")?.to_string(),
+        metric: parsed(params, "metric")?,
+        condition: parsed(params, "condition")?,
+        threshold: parsed(params, "threshold")?,
+        hysteresis: parsed(params, "hysteresis")?,
+        raise_after: Duration::from_secs(parsed(params, "for")?),
+        severity: parsed(params, "severity")?,
+    })
+}
+
+pub(super) struct AddAlertProcessor;
+
+impl RequestProcessor for AddAlertProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "add_alert",
+            "Raise an alert when a device metric crosses a threshold",
+            &[
+                ParamSchema::required("name", ParamType::Text, "unique name of the alert"),
+                ROOM_NAME,
+                DEVICE_NAME,
+                ParamSchema::required(
+                    "metric",
+                    ParamType::OneOf(&["temperature", "power"]),
+                    "what is watched",
+                ),
+                ParamSchema::required(
+                    "condition",
+                    ParamType::OneOf(&["above", "below"]),
+                    "which side of the threshold raises the alert",
+                ),
+                ParamSchema::required(
+                    "threshold",
+                    ParamType::Number,
+                    "value the metric is compared with",
+                ),
+                ParamSchema::optional(
+                    "hysteresis",
+                    ParamType::Number,
+                    "how far back the metric has to go to clear the alert",
+                )
+                .with_default("0"),
+                ParamSchema::optional(
+                    "for",
+                    ParamType::UnsignedInteger,
+                    "seconds the condition has to hold",
+                )
+                .with_default("0"),
+                ParamSchema::optional(
+                    "severity",
+                    ParamType::OneOf(&["info", "warning", "critical"]),
+                    "severity of the alert",
+                )
+                .with_default("warning"),
+            ],
+        );
+        &SCHEMA
+    }
+
+    fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<RwLock<ServerStore>>,
+        smart_house: &smart_house::SmartHouse,
+        _context: &RequestContext,
+    ) -> Result<String, ProccessorError> {
+        let definition = parse_alert_definition(params)?;
+        smart_house
+            .get_room(&definition.room_name)
+            .ok_or(ProccessorError::CantFindRoom)?
+            .get_device(&definition.device_name)
+            .ok_or(ProccessorError::CantFindDevice)?;
+
+        let response = format!("add alert : {definition}");
+        server
+            .write()
+            .unwrap()
+            .alerts
+            .add_unique_alert(definition)
+            .ok_or(ProccessorError::AlertExists)?;
+        Ok(response)
+    }
+}
+
+pub(super) struct RemoveAlertProcessor;
+
+impl RequestProcessor for RemoveAlertProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "remove_alert",
+            "Remove an alert",
+            &[ParamSchema::required(
+                "name",
+                ParamType::Text,
+                "name of the alert",
+            )],
+        );
+        &SCHEMA
+    }
+
+    fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<RwLock<ServerStore>>,
+        _smart_house: &smart_house::SmartHouse,
+        _context: &RequestContext,
+    ) -> Result<String, ProccessorError> {
+        let name = required(params, "name")?;
+
+        server
+            .write()
+            .unwrap()
+            .alerts
+            .remove_alert(name)
+            .ok_or(ProccessorError::CantFindAlert)?;
+        Ok(format!("remove alert : {name}"))
+    }
+}
+
+pub(super) struct ActiveAlertsProcessor;
+
+impl RequestProcessor for ActiveAlertsProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema =
+            CommandSchema::new("active_alerts", "Alerts that are raised now", &[]);
+        &SCHEMA
+    }
+
+    fn process(
+        &self,
+        _params: &RequestParams,
+        server: Arc<RwLock<ServerStore>>,
+        _smart_house: &smart_house::SmartHouse,
+        _context: &RequestContext,
+    ) -> Result<String, ProccessorError> {
+        Ok(server.read().unwrap().alerts.create_active_report())
+    }
+}
+
+pub(super) struct AlertsHistoryProcessor;
+
+impl RequestProcessor for AlertsHistoryProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema =
+            CommandSchema::new("alerts_history", "Alerts raised and cleared so far", &[]);
+        &SCHEMA
+    }
+
+    fn process(
+        &self,
+        _params: &RequestParams,
+        server: Arc<RwLock<ServerStore>>,
+        _smart_house: &smart_house::SmartHouse,
+        _context: &RequestContext,
+    ) -> Result<String, ProccessorError> {
+        Ok(server.read().unwrap().alerts.create_history_report())
+    }
+}
+
+pub(super) struct SubscribeAlertsProcessor;
+
+impl RequestProcessor for SubscribeAlertsProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "subscribe_alerts",
+            "Send alert events to a UDP address",
+            &[ParamSchema::required(
+                "addr",
+                ParamType::Text,
+                "UDP address for the events",
+            )],
+        );
+        &SCHEMA
+    }
+
+    fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<RwLock<ServerStore>>,
+        _smart_house: &smart_house::SmartHouse,
+        _context: &RequestContext,
+    ) -> Result<String, ProccessorError> {
+        let addr = required(params, "addr")?.to_string();
+
+        let response = format!("subscribe alerts to {addr}");
+        server.write().unwrap().alert_subscribers.insert(addr);
+        Ok(response)
+    }
+}
+
+pub(super) struct UnsubscribeAlertsProcessor;
+
+impl RequestProcessor for UnsubscribeAlertsProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "unsubscribe_alerts",
+            "Stop sending alert events to a UDP address",
+            &[ParamSchema::required(
+                "addr",
+                ParamType::Text,
+                "UDP address given to subscribe_alerts",
+            )],
+        );
+        &SCHEMA
+    }
+
+    fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<RwLock<ServerStore>>,
+        _smart_house: &smart_house::SmartHouse,
+        _context: &RequestContext,
+    ) -> Result<String, ProccessorError> {
+        let addr = required(params, "addr")?;
+
+        server.write().unwrap().alert_subscribers.remove(addr);
+        Ok(format!("unsubscribe alerts from {addr}"))
+    }
+}
+
+/// Stop the report streams of the devices
+fn cancel_device_streams(
+    server: &Arc<RwLock<ServerStore>>,
+    room_name: &str,
+    device_names: &[String],
+) {
+    let mut server = server.write().unwrap();
+    for device_name in device_names {
+        server.streams.remove(&stream_name(room_name, device_name));
+    }
+}
+
+/// Priorities and alerts of devices that left the house would be inherited
+/// by devices added later under the same name
+fn forget_devices(server: &Arc<RwLock<ServerStore>>, room_name: &str, device_name: Option<&str>) {
+    let mut server = server.write().unwrap();
+    server.power_budget.remove_devices(room_name, device_name);
+    server.alerts.remove_devices(room_name, device_name);
+}
+
+/// Priorities and alerts follow a renamed room or device
+fn rename_devices(
+    server: &Arc<RwLock<ServerStore>>,
+    room_name: &str,
+    device_name: Option<&str>,
+    new_name: &str,
+) {
+    let mut server = server.write().unwrap();
+    server.power_budget.rename(room_name, device_name, new_name);
+    server.alerts.rename(room_name, device_name, new_name);
+}
+
+fn device_names(room: &smart_house::Room) -> Vec<String> {
+    room.get_devices()
+        .iter()
+        .map(|device| device.read().unwrap().get_device_name().to_string())
+        .collect()
+}
+
+pub(super) struct AddRoomProcessor;
+
+impl HouseEditProcessor for AddRoomProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema =
+            CommandSchema::new("add_room", "Add an empty room", &[ROOM_NAME]);
+        &SCHEMA
+    }
+
+    fn process(
+        &self,
+        params: &RequestParams,
+        _server: Arc<RwLock<ServerStore>>,
+        smart_house: &mut smart_house::SmartHouse,
+        _context: &RequestContext,
+    ) -> Result<String, ProccessorError> {
+        let room_name = required(params, "room_name")?;
+
+        smart_house
+            .add_unique_room(smart_house::Room::new(room_name.to_string(), vec![]))
+            .ok_or(ProccessorError::RoomExists)?;
+        Ok(format!("add room : {room_name}"))
+    }
+}
+
+pub(super) struct RemoveRoomProcessor;
+
+impl HouseEditProcessor for RemoveRoomProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "remove_room",
+            "Remove a room with its devices, their report streams, priorities and alerts",
+            &[ROOM_NAME],
+        );
+        &SCHEMA
+    }
+
+    fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<RwLock<ServerStore>>,
+        smart_house: &mut smart_house::SmartHouse,
+        _context: &RequestContext,
+    ) -> Result<String, ProccessorError> {
+        let room_name = required(params, "room_name")?;
+
+        let room = smart_house
+            .remove_room(room_name)
+            .ok_or(ProccessorError::CantFindRoom)?;
+        cancel_device_streams(&server, room_name, &device_names(&room));
+        forget_devices(&server, room_name, None);
+        Ok(format!("remove room : {room_name}"))
+    }
+}
+
+pub(super) struct AddDeviceProcessor;
+
+impl HouseEditProcessor for AddDeviceProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "add_device",
+            "Add a device to a room",
+            &[
+                ROOM_NAME,
+                ParamSchema::required("device_name", ParamType::Text, "unique name in the room"),
+                ParamSchema::required(
+                    "kind",
+                    ParamType::OneOf(&["smart_socket", "thermometer"]),
+                    "kind of the device",
+                ),
+                ParamSchema::optional(
+                    "power",
+                    ParamType::Number,
+                    "watts a smart socket reports, required for smart_socket",
+                ),
+                ParamSchema::optional(
+                    "temperature",
+                    ParamType::Number,
+                    "celsius a thermometer reports, required for thermometer",
+                ),
+            ],
+        );
+        &SCHEMA
+    }
+
+    fn process(
+        &self,
+        params: &RequestParams,
+        _server: Arc<RwLock<ServerStore>>,
+        smart_house: &mut smart_house::SmartHouse,
+        _context: &RequestContext,
+    ) -> Result<String, ProccessorError> {
+        let room_name = required(params, "room_name")?;
+        let device_name = required(params, "device_name")?;
+        let kind = required(params, "kind")?;
+
+        let room = smart_house
+            .get_room_mut(room_name)
+            .ok_or(ProccessorError::CantFindRoom)?;
+        let added = match kind {
+            "smart_socket" => room.add_unique_device(SmartSocket::new(
+                device_name,
+                Arc::new(EnergyProvider {
+                    value: parsed(params, "power")?,
+                }),
+            )),
+            "thermometer" => room.add_unique_device(Thermometer::new(
+                device_name,
+                Arc::new(TemperatureProvider {
+                    value: parsed(params, "temperature")?,
+                    measure_units: TemperatureMeasureUnits::Celsius,
+                }),
+            )),
+            _ => return Err(ProccessorError::BadRequestParam),
+        };
+        added.ok_or(ProccessorError::DeviceExists)?;
+        Ok(format!("add {kind} : {room_name}/{device_name}"))
+    }
+}
+
+pub(super) struct RemoveDeviceProcessor;
+
+impl HouseEditProcessor for RemoveDeviceProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "remove_device",
+            "Remove a device with its report stream, priority and alerts",
+            &[ROOM_NAME, DEVICE_NAME],
+        );
+        &SCHEMA
+    }
+
+    fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<RwLock<ServerStore>>,
+        smart_house: &mut smart_house::SmartHouse,
+        _context: &RequestContext,
+    ) -> Result<String, ProccessorError> {
+        let room_name = required(params, "room_name")?;
+        let device_name = required(params, "device_name")?;
+
+        smart_house
+            .get_room_mut(room_name)
+            .ok_or(ProccessorError::CantFindRoom)?
+            .remove_device(device_name)
+            .ok_or(ProccessorError::CantFindDevice)?;
+        cancel_device_streams(&server, room_name, &[device_name.to_string()]);
+        forget_devices(&server, room_name, Some(device_name));
+        Ok(format!("remove device : {room_name}/{device_name}"))
+    }
+}
+
+pub(super) struct RenameProcessor;
+
+impl HouseEditProcessor for RenameProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "rename",
+            "Rename a room or a device, its report streams are stopped, priorities and alerts are kept",
+            &[
+                ROOM_NAME,
+                ParamSchema::optional(
+                    "device_name",
+                    ParamType::Text,
+                    "device to rename, the room is renamed without it",
+                ),
+                ParamSchema::required("new_name", ParamType::Text, "name that is not taken yet"),
+            ],
+        );
+        &SCHEMA
+    }
+
+    fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<RwLock<ServerStore>>,
+        smart_house: &mut smart_house::SmartHouse,
+        _context: &RequestContext,
+    ) -> Result<String, ProccessorError> {
+        let room_name = required(params, "room_name")?;
+        let new_name = required(params, "new_name")?;
+
+        let room = smart_house
+            .get_room_mut(room_name)
+            .ok_or(ProccessorError::CantFindRoom)?;
+        // Streams keep the name they were created with, so they would no longer be found by it
+        match params.get("device_name") {
+            Some(device_name) => {
+                if !room.contains_device(device_name) {
+                    return Err(ProccessorError::CantFindDevice);
+                }
+                room.rename_device(device_name, new_name)
+                    .ok_or(ProccessorError::DeviceExists)?;
+                cancel_device_streams(&server, room_name, &[device_name.to_string()]);
+                rename_devices(&server, room_name, Some(device_name), new_name);
+                Ok(format!(
+                    "rename device : {room_name}/{device_name} -> {new_name}"
+                ))
+            }
+            None => {
+                let device_names = device_names(room);
+                smart_house
+                    .rename_room(room_name, new_name)
+                    .ok_or(ProccessorError::RoomExists)?;
+                cancel_device_streams(&server, room_name, &device_names);
+                rename_devices(&server, room_name, None, new_name);
+                Ok(format!("rename room : {room_name} -> {new_name}"))
+            }
+        }
+    }
+}
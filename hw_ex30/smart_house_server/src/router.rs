@@ -0,0 +1,106 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use my_stp::command_schema::{self, CommandSchema, ParamSchema, ParamType};
+use my_stp::custom_parser::parse_request_parameters;
+use smart_house::SmartHouse;
+
+use crate::{
+    errors::{ProccessRequestError, ProccessorError},
+    processors::{Processor, RequestContext},
+    ServerStore,
+};
+
+/// Served by the router itself, it needs the schemas of all commands
+const HELP: CommandSchema = CommandSchema::new(
+    "help",
+    "Commands and their parameters",
+    &[ParamSchema::optional(
+        "command",
+        ParamType::Text,
+        "describe only this command",
+    )],
+);
+
+/// Finds the processor of a request by the exact command name,
+/// checks the request against the schema of the processor
+/// and serves it under the lock of the house the processor needs
+pub(super) struct Router {
+    /// In the order they are listed by help
+    processors: Vec<Processor>,
+    by_name: HashMap<&'static str, Processor>,
+}
+
+impl Router {
+    /// Panics if two processors serve the same command
+    pub fn new(processors: Vec<Processor>) -> Self {
+        let mut by_name = HashMap::new();
+        for processor in &processors {
+            let name = processor.schema().name;
+            let duplicate = by_name.insert(name, processor.clone()).is_some();
+            assert!(
+                !duplicate && name != HELP.name,
+                "command {name} is served twice"
+            );
+        }
+        Self {
+            processors,
+            by_name,
+        }
+    }
+
+    pub fn schemas(&self) -> impl Iterator<Item = &'static CommandSchema> + '_ {
+        std::iter::once(&HELP).chain(self.processors.iter().map(Processor::schema))
+    }
+
+    pub fn process(
+        &self,
+        request: &str,
+        server: Arc<RwLock<ServerStore>>,
+        smart_house: &RwLock<SmartHouse>,
+        context: &RequestContext,
+    ) -> Result<String, ProccessRequestError> {
+        let params = parse_request_parameters(request).map_err(ProccessorError::from)?;
+        if params.command() == HELP.name {
+            let params = HELP.validate(params).map_err(ProccessorError::from)?;
+            return self.help(params.get("command"));
+        }
+        let processor = self
+            .by_name
+            .get(params.command())
+            .ok_or(ProccessRequestError::CantProccessRequest)?;
+        let params = processor
+            .schema()
+            .validate(params)
+            .map_err(ProccessorError::from)?;
+        let response = match processor {
+            Processor::HouseShared(processor) => {
+                let smart_house = smart_house
+                    .read()
+                    .map_err(|_| ProccessRequestError::CantReadSmartHouse)?;
+                processor.process(&params, server, &smart_house, context)
+            }
+            Processor::HouseExclusive(processor) => {
+                let mut smart_house = smart_house
+                    .write()
+                    .map_err(|_| ProccessRequestError::CantReadSmartHouse)?;
+                processor.process(&params, server, &mut smart_house, context)
+            }
+        };
+        Ok(response?)
+    }
+
+    /// Usage of every command or the description of one
+    fn help(&self, command: Option<&str>) -> Result<String, ProccessRequestError> {
+        match command {
+            None => Ok(command_schema::help(self.schemas())),
+            Some(command) => self
+                .schemas()
+                .find(|schema| schema.name == command)
+                .map(CommandSchema::describe)
+                .ok_or(ProccessRequestError::CantProccessRequest),
+        }
+    }
+}
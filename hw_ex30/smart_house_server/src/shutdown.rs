@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use my_stp::server::SessionCloser;
+
+/// How long [`crate::SmartHouseServer`] waits for requests in flight when it is dropped
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+const JOIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What [`crate::SmartHouseServer::shutdown`] has stopped
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ShutdownSummary {
+    /// Listeners that stopped accepting connections
+    pub listeners: usize,
+    /// Sessions that were open when the shutdown started
+    pub closed_sessions: usize,
+    pub stopped_streams: usize,
+    /// Threads still busy when the deadline passed, they are left running
+    pub unfinished_threads: usize,
+    pub elapsed: Duration,
+}
+
+impl ShutdownSummary {
+    /// Every thread was joined before the deadline
+    pub fn is_clean(&self) -> bool {
+        self.unfinished_threads == 0
+    }
+}
+
+impl Display for ShutdownSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "server stopped in {:.2}s : {} listeners, {} sessions closed, {} streams stopped, {} threads unfinished",
+            self.elapsed.as_secs_f64(),
+            self.listeners,
+            self.closed_sessions,
+            self.stopped_streams,
+            self.unfinished_threads
+        )
+    }
+}
+
+/// Join the threads that finish before `deadline`, returns how many did not
+pub(super) fn join_until(handles: Vec<JoinHandle<()>>, deadline: Instant) -> usize {
+    let mut handles = handles;
+    loop {
+        let (finished, running): (Vec<_>, Vec<_>) =
+            handles.into_iter().partition(JoinHandle::is_finished);
+        for handle in finished {
+            if handle.join().is_err() {
+                eprintln!("server thread panicked");
+            }
+        }
+        if running.is_empty() || Instant::now() >= deadline {
+            return running.len();
+        }
+        handles = running;
+        thread::sleep(JOIN_POLL_INTERVAL);
+    }
+}
+
+#[derive(Default)]
+struct SessionsState {
+    draining: bool,
+    next_id: u64,
+    closers: HashMap<u64, SessionCloser>,
+}
+
+/// Sessions being served. Once draining, new sessions are refused
+/// and open ones end after their request in flight
+#[derive(Default)]
+pub(super) struct Sessions {
+    state: Mutex<SessionsState>,
+}
+
+impl Sessions {
+    /// `None` if the server is shutting down
+    pub fn register(&self, closer: SessionCloser) -> Option<u64> {
+        let mut state = self.state.lock().unwrap();
+        if state.draining {
+            return None;
+        }
+        state.next_id += 1;
+        let id = state.next_id;
+        state.closers.insert(id, closer);
+        Some(id)
+    }
+
+    pub fn unregister(&self, id: u64) {
+        self.state.lock().unwrap().closers.remove(&id);
+    }
+
+    /// Close every open session, returns how many there were
+    pub fn drain(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.draining = true;
+        for closer in state.closers.values() {
+            closer.close();
+        }
+        state.closers.len()
+    }
+}
+
+#[cfg(test)]
+mod shutdown_tests {
+    use super::*;
+
+    #[test]
+    fn busy_threads_are_left_after_the_deadline() {
+        let (release_sender, release) = std::sync::mpsc::channel::<()>();
+        let handles = vec![
+            thread::spawn(|| {}),
+            thread::spawn(move || {
+                let _ = release.recv();
+            }),
+        ];
+        let unfinished = join_until(handles, Instant::now() + Duration::from_millis(50));
+        assert_eq!(unfinished, 1);
+        drop(release_sender);
+    }
+}
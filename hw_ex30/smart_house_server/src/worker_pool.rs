@@ -0,0 +1,136 @@
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use crate::shutdown;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Fixed number of threads serving jobs from a bounded queue.
+/// Workers finish the queued jobs and stop once the pool is shut down or dropped
+pub(super) struct WorkerPool {
+    sender: Mutex<Option<SyncSender<Job>>>,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl WorkerPool {
+    /// `queue_len` jobs wait for a free worker, more are rejected
+    pub fn new(workers: usize, queue_len: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(queue_len);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..workers.max(1))
+            .map(|index| {
+                let receiver = receiver.clone();
+                thread::Builder::new()
+                    .name(format!("worker-{index}"))
+                    .spawn(move || Self::work(&receiver))
+                    .expect("cant spawn worker thread")
+            })
+            .collect();
+        Self {
+            sender: Mutex::new(Some(sender)),
+            workers: Mutex::new(workers),
+        }
+    }
+
+    fn work(receiver: &Mutex<Receiver<Job>>) {
+        loop {
+            // The lock is released before the job runs, so other workers can take the next one
+            let job = match receiver.lock() {
+                Ok(receiver) => receiver.recv(),
+                Err(_) => break,
+            };
+            match job {
+                Ok(job) => job(),
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Never blocks. Returns false if all workers are busy and the queue is full
+    pub fn try_execute<F>(&self, job: F) -> bool
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let sender = self.sender.lock().unwrap();
+        let Some(sender) = sender.as_ref() else {
+            return false;
+        };
+        match sender.try_send(Box::new(job)) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    /// Stop taking jobs, the queued ones still run.
+    /// Returns the number of workers still busy at `deadline`
+    pub fn shutdown(&self, deadline: Instant) -> usize {
+        self.sender.lock().unwrap().take();
+        let workers = std::mem::take(&mut *self.workers.lock().unwrap());
+        shutdown::join_until(workers, deadline)
+    }
+}
+
+#[cfg(test)]
+mod worker_pool_tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn jobs_run_in_parallel() {
+        let pool = WorkerPool::new(2, 0);
+        let released = Arc::new(AtomicBool::new(false));
+        let (started_sender, started) = mpsc::channel();
+        for _ in 0..2 {
+            let released = released.clone();
+            let started_sender = started_sender.clone();
+            let job = move || {
+                started_sender.send(()).unwrap();
+                while !released.load(Ordering::Acquire) {
+                    thread::sleep(Duration::from_millis(1));
+                }
+            };
+            // Without a queue a job is only taken by a free worker
+            while !pool.try_execute(job.clone()) {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        for _ in 0..2 {
+            started.recv_timeout(Duration::from_secs(1)).unwrap();
+        }
+        released.store(true, Ordering::Release);
+    }
+
+    #[test]
+    fn full_queue_rejects_jobs() {
+        let pool = WorkerPool::new(1, 1);
+        let (release_sender, release) = mpsc::channel::<()>();
+        let (started_sender, started) = mpsc::channel();
+        assert!(pool.try_execute(move || {
+            started_sender.send(()).unwrap();
+            let _ = release.recv();
+        }));
+        started.recv_timeout(Duration::from_secs(1)).unwrap();
+
+        assert!(pool.try_execute(|| {}));
+        assert!(!pool.try_execute(|| {}));
+        drop(release_sender);
+    }
+
+    #[test]
+    fn shutdown_runs_queued_jobs_and_joins_workers() {
+        let pool = WorkerPool::new(1, 2);
+        let (done_sender, done) = mpsc::channel();
+        for _ in 0..2 {
+            let done_sender = done_sender.clone();
+            assert!(pool.try_execute(move || done_sender.send(()).unwrap()));
+        }
+        assert_eq!(pool.shutdown(Instant::now() + Duration::from_secs(1)), 0);
+        assert_eq!(done.try_iter().count(), 2);
+        assert!(!pool.try_execute(|| {}));
+    }
+}
@@ -0,0 +1,791 @@
+use std::{
+    net::UdpSocket,
+    thread,
+    time::{Duration, Instant},
+};
+
+use my_stp::auth::{Credentials, KeyStore};
+use my_stp::client::StpClient;
+use my_stp::errors::ConnectError;
+use my_stp::message::{Request, Response};
+use my_stp::protocol::{Features, Protocol, V2};
+use smart_house_testkit::{house_builder::HouseBuilder, stp::TestServer};
+
+fn start_server() -> TestServer {
+    TestServer::start(
+        HouseBuilder::new()
+            .room("Кухня", |room| {
+                room.thermometer("Термометр1", 16.0)
+                    .socket("Розетка1", 100.0)
+            })
+            .room("Спальня", |room| room.socket("Розетка3", 50.0))
+            .build(),
+    )
+}
+
+#[test]
+fn hello() {
+    let server = start_server();
+    assert_eq!(server.request("hello"), "Hello from server");
+}
+
+#[test]
+fn rooms_list() {
+    let server = start_server();
+    assert_eq!(server.request("rooms_list"), "[Кухня,Спальня]");
+}
+
+#[test]
+fn devices_list() {
+    let server = start_server();
+    assert_eq!(
+        server.request("devices_list room_name=Кухня"),
+        "Кухня:[Термометр1,Розетка1]"
+    );
+}
+
+#[test]
+fn device_report() {
+    let server = start_server();
+    let response = server.request("device_report room_name=Кухня device_name=Термометр1");
+    assert!(response.contains("Температура: 16°C"), "{response}");
+}
+
+#[test]
+fn device_report_unknown_room() {
+    let server = start_server();
+    let response = server.request("device_report room_name=Чердак device_name=Термометр1");
+    assert!(response.contains("CantGetReport"), "{response}");
+}
+
+#[test]
+fn set_device_power_state_and_is_device_on() {
+    let server = start_server();
+    server
+        .request("set_device_power_state room_name=Спальня device_name=Розетка3 power_state=false");
+    assert_eq!(
+        server.request("is_device_on room_name=Спальня device_name=Розетка3"),
+        "room_name:Спальня,device_name:Розетка3,is_on:false"
+    );
+}
+
+#[test]
+fn report_stream_create_and_cancel() {
+    let server = start_server();
+    let mut session = server.connect();
+    let response = session
+        .send_request(
+            "get_device_report_stream room_name=Кухня device_name=Розетка1 request_delay=1 addr=127.0.0.1:9",
+        )
+        .unwrap();
+    assert_eq!(response, "create thread with name : Кухня-Розетка1");
+    assert_eq!(
+        session
+            .send_request("cancel_device_report_stream stream_name=Кухня-Розетка1")
+            .unwrap(),
+        "Cancel thread with name : Кухня-Розетка1"
+    );
+}
+
+#[test]
+fn streams_are_listed_and_cancelled_only_by_their_owner() {
+    let server = start_server();
+    let mut owner = server.connect();
+    owner
+        .send_request(
+            "get_device_report_stream room_name=Кухня device_name=Розетка1 request_delay=1 addr=127.0.0.1:9",
+        )
+        .unwrap();
+
+    let list = server.request("list_streams");
+    assert!(
+        list.starts_with("[name:Кухня-Розетка1,owner:session "),
+        "{list}"
+    );
+    assert!(
+        list.contains("target:udp 127.0.0.1:9,interval:1,"),
+        "{list}"
+    );
+    assert!(list.contains("expires_in:never"), "{list}");
+    let info = server.request("stream_info stream_name=Кухня-Розетка1");
+    assert_eq!(format!("[{info}]"), list);
+    assert!(server
+        .request("stream_info stream_name=Кухня-Термометр1")
+        .contains("CantFindStream"));
+
+    for request in [
+        "cancel_device_report_stream stream_name=Кухня-Розетка1",
+        "renew_device_report_stream stream_name=Кухня-Розетка1 ttl=10",
+        "get_device_report_stream room_name=Кухня device_name=Розетка1 addr=127.0.0.1:9",
+    ] {
+        let response = server.request(request);
+        assert!(
+            response.contains("NotStreamOwner"),
+            "{request} : {response}"
+        );
+    }
+    assert_eq!(
+        owner
+            .send_request("cancel_device_report_stream stream_name=Кухня-Розетка1")
+            .unwrap(),
+        "Cancel thread with name : Кухня-Розетка1"
+    );
+    assert_eq!(server.request("list_streams"), "[]");
+}
+
+#[test]
+fn stream_expires_unless_renewed_and_counts_deliveries() {
+    let server = start_server();
+    let reports = UdpSocket::bind("127.0.0.1:0").unwrap();
+    reports
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut owner = server.connect();
+    owner
+        .send_request(format!(
+            "get_device_report_stream room_name=Кухня device_name=Розетка1 request_delay=1 ttl=1 addr={}",
+            reports.local_addr().unwrap()
+        ))
+        .unwrap();
+    thread::sleep(Duration::from_millis(600));
+    assert_eq!(
+        owner
+            .send_request("renew_device_report_stream stream_name=Кухня-Розетка1 ttl=2")
+            .unwrap(),
+        "stream Кухня-Розетка1 expires in 2"
+    );
+    reports.recv_from(&mut [0; 1024]).unwrap();
+    let delivered = (0..100).any(|_| {
+        thread::sleep(Duration::from_millis(10));
+        !server
+            .request("stream_info stream_name=Кухня-Розетка1")
+            .contains("delivered:0")
+    });
+    assert!(delivered);
+
+    thread::sleep(Duration::from_millis(2500));
+    assert_eq!(server.request("list_streams"), "[]");
+}
+
+#[test]
+fn add_and_remove_rooms_and_devices() {
+    let server = start_server();
+    assert_eq!(
+        server.request("add_room room_name=Чердак"),
+        "add room : Чердак"
+    );
+    assert!(server
+        .request("add_room room_name=Чердак")
+        .contains("RoomExists"));
+    assert_eq!(
+        server.request("add_device room_name=Чердак device_name=Лампа kind=smart_socket power=60"),
+        "add smart_socket : Чердак/Лампа"
+    );
+    server.request(
+        "add_device room_name=Чердак device_name=Термометр kind=thermometer temperature=5",
+    );
+    let response =
+        server.request("add_device room_name=Чердак device_name=Лампа kind=smart_socket power=1");
+    assert!(response.contains("DeviceExists"), "{response}");
+    let response =
+        server.request("add_device room_name=Чердак device_name=Обогреватель kind=smart_socket");
+    assert!(response.contains(r#"MissingParam("power")"#), "{response}");
+    assert_eq!(
+        server.request("devices_list room_name=Чердак"),
+        "Чердак:[Лампа,Термометр]"
+    );
+    let response = server.request("device_report room_name=Чердак device_name=Термометр");
+    assert!(response.contains("Температура: 5°C"), "{response}");
+
+    assert_eq!(
+        server.request("remove_device room_name=Чердак device_name=Лампа"),
+        "remove device : Чердак/Лампа"
+    );
+    assert!(server
+        .request("remove_device room_name=Чердак device_name=Лампа")
+        .contains("CantFindDevice"));
+    assert_eq!(
+        server.request("remove_room room_name=Чердак"),
+        "remove room : Чердак"
+    );
+    assert_eq!(server.request("rooms_list"), "[Кухня,Спальня]");
+}
+
+#[test]
+fn removing_a_device_cancels_its_stream() {
+    let server = start_server();
+    server.request(
+        "get_device_report_stream room_name=Кухня device_name=Розетка1 request_delay=1 addr=127.0.0.1:9",
+    );
+    server.request("get_device_report_stream room_name=Кухня device_name=Термометр1 request_delay=1 addr=127.0.0.1:9");
+    server.request("remove_device room_name=Кухня device_name=Розетка1");
+    assert_eq!(
+        server.request("cancel_device_report_stream stream_name=Кухня-Розетка1"),
+        "Cancel thread with name : Кухня-Розетка1 - no thread to cancel"
+    );
+    server.request("remove_room room_name=Кухня");
+    assert_eq!(
+        server.request("cancel_device_report_stream stream_name=Кухня-Термометр1"),
+        "Cancel thread with name : Кухня-Термометр1 - no thread to cancel"
+    );
+}
+
+#[test]
+fn rename_rooms_and_devices() {
+    let server = start_server();
+    assert_eq!(
+        server.request("rename room_name=Кухня device_name=Розетка1 new_name=Чайник"),
+        "rename device : Кухня/Розетка1 -> Чайник"
+    );
+    let response = server.request("rename room_name=Кухня device_name=Чайник new_name=Термометр1");
+    assert!(response.contains("DeviceExists"), "{response}");
+    let response = server.request("rename room_name=Кухня device_name=Розетка1 new_name=Лампа");
+    assert!(response.contains("CantFindDevice"), "{response}");
+    assert_eq!(
+        server.request("rename room_name=Кухня new_name=Столовая"),
+        "rename room : Кухня -> Столовая"
+    );
+    assert!(server
+        .request("rename room_name=Столовая new_name=Спальня")
+        .contains("RoomExists"));
+    assert_eq!(
+        server.request("devices_list room_name=Столовая"),
+        "Столовая:[Термометр1,Чайник]"
+    );
+}
+
+#[test]
+fn removed_device_leaves_no_priority_or_alerts_behind() {
+    let server = start_server();
+    server.request("set_device_priority room_name=Кухня device_name=Розетка1 priority=10");
+    server.request("set_device_priority room_name=Спальня device_name=Розетка3 priority=20");
+    server.request(
+        "add_alert name=watts room_name=Спальня device_name=Розетка3 metric=power condition=above threshold=1000",
+    );
+    server.request("remove_device room_name=Спальня device_name=Розетка3");
+    server.request("add_device room_name=Спальня device_name=Розетка3 kind=smart_socket power=50");
+
+    // The new device under the old name starts with the default priority and no alerts
+    assert!(server
+        .request("remove_alert name=watts")
+        .contains("CantFindAlert"));
+    let response = server.request("set_power_budget max_power=120 cooldown=60");
+    assert!(response.contains("shed:[Спальня/Розетка3]"), "{response}");
+}
+
+#[test]
+fn removed_device_stops_only_its_own_stream() {
+    let server = start_server();
+    server.request("add_room room_name=Дом-Сад");
+    server.request("add_device room_name=Дом-Сад device_name=Лампа kind=smart_socket power=60");
+    server.request("add_room room_name=Дом");
+    server.request("add_device room_name=Дом device_name=Сад-Лампа kind=smart_socket power=60");
+    for (room_name, device_name) in [("Дом-Сад", "Лампа"), ("Дом", "Сад-Лампа")]
+    {
+        server.request(&format!(
+            "get_device_report_stream room_name={room_name} device_name={device_name} addr=127.0.0.1:9"
+        ));
+    }
+
+    server.request("remove_device room_name=Дом device_name=Сад-Лампа");
+    let streams = server.request("list_streams");
+    assert!(streams.contains(r"name:Дом\-Сад-Лампа,"), "{streams}");
+    assert!(!streams.contains(r"name:Дом-Сад\-Лампа,"), "{streams}");
+}
+
+#[test]
+fn renamed_device_keeps_priority_and_alerts() {
+    let server = start_server();
+    server.request("set_device_priority room_name=Кухня device_name=Розетка1 priority=10");
+    server.request("set_device_priority room_name=Спальня device_name=Розетка3 priority=5");
+    server.request(
+        "add_alert name=hot room_name=Кухня device_name=Термометр1 metric=temperature condition=above threshold=10",
+    );
+    server.request("rename room_name=Кухня device_name=Розетка1 new_name=Чайник");
+    server.request("rename room_name=Кухня new_name=Столовая");
+
+    let response = server.request("set_power_budget max_power=120 cooldown=60");
+    assert!(response.contains("shed:[Спальня/Розетка3]"), "{response}");
+    let raised = (0..50).any(|_| {
+        thread::sleep(Duration::from_millis(100));
+        server
+            .request("active_alerts")
+            .contains("hot [warning] Столовая/Термометр1")
+    });
+    assert!(raised, "{}", server.request("active_alerts"));
+}
+
+#[test]
+fn report_stream_is_pushed_and_stops_with_the_session() {
+    let server = start_server();
+    let mut connection = server.connect();
+    assert_eq!(
+        connection
+            .send_request(
+                "get_device_report_stream room_name=Кухня device_name=Розетка1 request_delay=1"
+            )
+            .unwrap(),
+        "create thread with name : Кухня-Розетка1"
+    );
+    let push = connection
+        .recv_push(Some(Duration::from_secs(5)))
+        .unwrap()
+        .unwrap();
+    assert_eq!((push.seq, push.topic.as_str()), (1, "Кухня-Розетка1"));
+    assert!(!push.body.is_empty());
+
+    connection.close().unwrap();
+    thread::sleep(Duration::from_millis(2500));
+    assert_eq!(
+        server.request("cancel_device_report_stream stream_name=Кухня-Розетка1"),
+        "Cancel thread with name : Кухня-Розетка1 - no thread to cancel"
+    );
+}
+
+#[test]
+fn report_stream_without_addr_needs_push() {
+    let server = start_server();
+    let mut connection = StpClient::connect_with(
+        server.addr(),
+        Protocol {
+            version: V2,
+            features: Features::JSON,
+        },
+    )
+    .unwrap();
+    let response = connection
+        .send_request("get_device_report_stream room_name=Кухня device_name=Розетка1")
+        .unwrap();
+    assert!(response.contains("PushNotNegotiated"), "{response}");
+}
+
+#[test]
+fn unknown_command() {
+    let server = start_server();
+    assert!(server.request("reboot").contains("CantProccessRequest"));
+}
+
+#[test]
+fn commands_are_matched_by_exact_name() {
+    let server = start_server();
+    assert!(server
+        .request("hello_world")
+        .contains("CantProccessRequest"));
+    assert!(server
+        .request("rooms_list_all")
+        .contains("CantProccessRequest"));
+}
+
+#[test]
+fn power_budget_takes_only_finite_non_negative_limits() {
+    let server = start_server();
+    for max_power in ["NaN", "inf", "-5"] {
+        let response = server.request(&format!("set_power_budget max_power={max_power}"));
+        assert!(
+            response.contains(r#"InvalidParam { name: "max_power""#),
+            "{response}"
+        );
+    }
+    let response = server.request("set_power_budget max_power=0");
+    assert!(response.contains("max_power:0 Вт"), "{response}");
+}
+
+#[test]
+fn missing_and_invalid_params_are_named() {
+    let server = start_server();
+    let response = server.request("device_report room_name=Кухня");
+    assert!(
+        response.contains(r#"MissingParam("device_name")"#),
+        "{response}"
+    );
+    let response = server
+        .request("set_device_power_state room_name=Кухня device_name=Розетка1 power_state=maybe");
+    assert!(
+        response.contains(r#"InvalidParam { name: "power_state", value: "maybe""#),
+        "{response}"
+    );
+    let mut connection = server.connect();
+    let response = connection
+        .send_typed(&Request::command("devices_list"))
+        .unwrap();
+    assert!(
+        matches!(&response, Response::Error { code, .. } if code == "MissingParam"),
+        "{response:?}"
+    );
+}
+
+#[test]
+fn help_is_generated_from_schemas() {
+    let server = start_server();
+    let help = server.request("help");
+    assert!(
+        help.contains(
+            "device_report room_name=<string> device_name=<string>\n  Report of a device"
+        ),
+        "{help}"
+    );
+    assert!(
+        help.contains("[request_delay=<unsigned integer>]"),
+        "{help}"
+    );
+    let help = server.request("help command=get_device_report_stream");
+    assert!(
+        help.contains("request_delay: seconds between reports, default 5"),
+        "{help}"
+    );
+    assert!(server
+        .request("help command=reboot")
+        .contains("CantProccessRequest"));
+}
+
+#[test]
+fn power_budget_sheds_lowest_priority() {
+    let server = start_server();
+    server.request("set_device_priority room_name=Кухня device_name=Розетка1 priority=10");
+    let response = server.request("set_power_budget max_power=120 cooldown=60");
+    assert!(response.contains("shed:[Спальня/Розетка3]"), "{response}");
+    assert_eq!(
+        server.request("is_device_on room_name=Спальня device_name=Розетка3"),
+        "room_name:Спальня,device_name:Розетка3,is_on:false"
+    );
+    assert!(server
+        .request("power_budget_report")
+        .contains("turned off Спальня/Розетка3"));
+}
+
+#[test]
+fn alert_is_raised_and_pushed_to_subscribers() {
+    let server = start_server();
+    let subscriber = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    subscriber
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+    let addr = subscriber.local_addr().unwrap();
+
+    server.request(&format!("subscribe_alerts addr={addr}"));
+    let response = server.request(
+        "add_alert name=hot room_name=Кухня device_name=Термометр1 metric=temperature condition=above threshold=10 severity=critical",
+    );
+    assert!(
+        response.starts_with("add alert : hot [critical]"),
+        "{response}"
+    );
+
+    let mut buf = [0u8; 1024];
+    let size = subscriber.recv(&mut buf).unwrap();
+    let message = String::from_utf8_lossy(&buf[..size]);
+    assert_eq!(
+        message,
+        "alert raised: hot [critical] Кухня/Термометр1 value 16"
+    );
+
+    assert!(server.request("active_alerts").contains("hot [critical]"));
+    assert!(server
+        .request("alerts_history")
+        .contains("alert raised: hot"));
+}
+
+#[test]
+fn add_alert_validates_params() {
+    let server = start_server();
+    let response = server.request(
+        "add_alert name=hot room_name=Кухня device_name=Нет metric=temperature condition=above threshold=10",
+    );
+    assert!(response.contains("CantFindDevice"), "{response}");
+    let response = server.request(
+        "add_alert name=hot room_name=Кухня device_name=Термометр1 metric=humidity condition=above threshold=10",
+    );
+    assert!(
+        response.contains(r#"InvalidParam { name: "metric", value: "humidity""#),
+        "{response}"
+    );
+    assert!(server
+        .request("remove_alert name=hot")
+        .contains("CantFindAlert"));
+
+    let alert = "add_alert name=hot room_name=Кухня device_name=Термометр1 metric=temperature condition=above threshold=10";
+    assert!(server.request(alert).starts_with("add alert : hot"));
+    assert!(server.request(alert).contains("AlertExists"));
+}
+
+#[test]
+fn set_group_power_state_by_room_and_kind() {
+    let server = start_server();
+    assert_eq!(
+        server.request("set_group_power_state room_name=Кухня power_state=false"),
+        "[room_name:Кухня,device_name:Термометр1,is_on:false;room_name:Кухня,device_name:Розетка1,is_on:false]"
+    );
+    assert_eq!(
+        server.request("set_group_power_state kind=smart_socket power_state=toggle"),
+        "[room_name:Кухня,device_name:Розетка1,is_on:true;room_name:Спальня,device_name:Розетка3,is_on:false]"
+    );
+    assert!(server
+        .request("set_group_power_state room_name=Гараж power_state=true")
+        .contains("CantFindRoom"));
+}
+
+#[test]
+fn many_requests_over_one_session() {
+    let server = start_server();
+    let mut connection = server.connect();
+    assert_eq!(
+        connection.send_request("hello").unwrap(),
+        "Hello from server"
+    );
+    assert_eq!(
+        connection.send_request("rooms_list").unwrap(),
+        "[Кухня,Спальня]"
+    );
+    // Another client is served while the first session stays open
+    assert_eq!(server.request("hello"), "Hello from server");
+    connection.close().unwrap();
+}
+
+#[test]
+fn sessions_are_served_in_parallel() {
+    let server = start_server();
+    let mut sessions: Vec<_> = (0..4).map(|_| server.connect()).collect();
+    thread::scope(|scope| {
+        for session in &mut sessions {
+            scope.spawn(|| {
+                for _ in 0..20 {
+                    assert_eq!(
+                        session.send_request("rooms_list").unwrap(),
+                        "[Кухня,Спальня]"
+                    );
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn silent_client_does_not_block_new_connections() {
+    let mut server = start_server();
+    // Connects but never starts the handshake
+    let _silent = std::net::TcpStream::connect(server.addr()).unwrap();
+    let started = Instant::now();
+    assert_eq!(server.request("hello"), "Hello from server");
+    assert!(started.elapsed() < Duration::from_secs(2));
+    // Its handshake is ended by the shutdown instead of running into the timeout
+    assert!(server.shutdown(Duration::from_secs(2)).is_clean());
+}
+
+#[test]
+fn connections_beyond_the_worker_pool_are_closed() {
+    use smart_house_server::{errors::ConfigureServerError, SmartHouseServer};
+
+    let mut server = SmartHouseServer::with_smart_house(
+        HouseBuilder::new().build(),
+        "127.0.0.1:0",
+        "127.0.0.1:0",
+    )
+    .unwrap();
+    server.set_worker_pool(1, 0).unwrap();
+    server.start_server_listening();
+    assert!(matches!(
+        server.set_worker_pool(2, 0),
+        Err(ConfigureServerError::AlreadyListening)
+    ));
+    let addr = server.local_addr().unwrap();
+
+    let mut session = StpClient::connect(addr).unwrap();
+    assert_eq!(session.send_request("hello").unwrap(), "Hello from server");
+    // The only worker is busy with the open session, so not even the handshake is run
+    assert!(StpClient::connect(addr)
+        .ok()
+        .and_then(|mut connection| connection.send_request("hello").ok())
+        .is_none());
+    session.close().unwrap();
+
+    let response = (0..100).find_map(|_| {
+        thread::sleep(Duration::from_millis(10));
+        StpClient::connect(addr).ok()?.send_request("hello").ok()
+    });
+    assert_eq!(response.as_deref(), Some("Hello from server"));
+}
+
+#[test]
+fn shutdown_closes_sessions_and_stops_streams() {
+    let mut server = start_server();
+    let mut session = server.connect();
+    assert!(session
+        .send_request(
+            "get_device_report_stream room_name=Кухня device_name=Розетка1 request_delay=60 addr=127.0.0.1:9"
+        )
+        .unwrap()
+        .contains("Кухня-Розетка1"));
+
+    let summary = server.shutdown(Duration::from_secs(5));
+    assert_eq!(summary.listeners, 1);
+    assert_eq!(summary.closed_sessions, 1);
+    assert_eq!(summary.stopped_streams, 1);
+    assert!(summary.is_clean(), "{summary}");
+    // The stream thread is woken up, not waited for
+    assert!(summary.elapsed < Duration::from_secs(5), "{summary}");
+    assert!(session.send_request("hello").is_err());
+    assert_eq!(server.shutdown(Duration::ZERO).listeners, 0);
+}
+
+#[test]
+fn typed_request_gets_error_code() {
+    let server = start_server();
+    let mut connection = server.connect();
+    let response = connection
+        .send_typed(
+            &Request::command("is_device_on")
+                .param("room_name", "Гараж")
+                .param("device_name", "Розетка1"),
+        )
+        .unwrap();
+    assert!(
+        matches!(&response, Response::Error { code, .. } if code == "CantFindRoom"),
+        "{response:?}"
+    );
+    assert_eq!(
+        connection.send_typed(&Request::command("hello")).unwrap(),
+        Response::Ok("Hello from server".to_string())
+    );
+}
+
+#[test]
+fn authenticated_client_is_greeted_by_identity() {
+    let server = TestServer::start_with_key_store(
+        HouseBuilder::new().build(),
+        Some(KeyStore::parse("alice:secret").unwrap()),
+    );
+    let mut connection =
+        StpClient::connect_authenticated(server.addr(), &Credentials::new("alice", b"secret"))
+            .unwrap();
+    assert_eq!(
+        connection.send_request("hello").unwrap(),
+        "Hello alice from server"
+    );
+    assert!(matches!(
+        StpClient::connect(server.addr()),
+        Err(ConnectError::AuthenticationRequired)
+    ));
+}
+
+#[test]
+fn quoted_room_name_with_spaces() {
+    let server = TestServer::start(
+        HouseBuilder::new()
+            .room("Детская комната", |room| {
+                room.socket("Розетка 2", 20.0)
+            })
+            .build(),
+    );
+    assert_eq!(
+        server.request(r#"devices_list room_name="Детская комната""#),
+        "Детская комната:[Розетка 2]"
+    );
+    let mut connection = server.connect();
+    let response = connection
+        .send_typed(
+            &Request::command("is_device_on")
+                .param("room_name", "Детская комната")
+                .param("device_name", "Розетка 2"),
+        )
+        .unwrap();
+    assert!(matches!(response, Response::Ok(_)), "{response:?}");
+}
+
+#[test]
+fn malformed_request_is_reported_with_position() {
+    let server = start_server();
+    let response = server.request(r#"devices_list room_name="Кухня"#);
+    assert!(
+        response.contains("MalformedRequest(UnterminatedQuote { position: 23 })"),
+        "{response}"
+    );
+    let response = server.request("devices_list room_name=a=b");
+    assert!(response.contains("CantFindRoom"), "{response}");
+    let mut connection = server.connect();
+    let response = connection
+        .send_typed(&Request::Text("is_device_on room_name".to_string()))
+        .unwrap();
+    assert!(
+        matches!(&response, Response::Error { code, .. } if code == "MalformedRequest"),
+        "{response:?}"
+    );
+    assert_eq!(server.request("hello"), "Hello from server");
+}
+
+#[test]
+fn json_rpc_and_command_lines_share_a_session() {
+    let server = start_server();
+    let mut connection = server.connect();
+    let rooms = r#"{"jsonrpc":"2.0","method":"rooms_list","id":1}"#;
+    assert_eq!(
+        connection.send_request(rooms).unwrap(),
+        r#"{"id":1,"jsonrpc":"2.0","result":"[Кухня,Спальня]"}"#
+    );
+    assert_eq!(
+        connection
+            .send_request("devices_list room_name=Спальня")
+            .unwrap(),
+        "Спальня:[Розетка3]"
+    );
+    let batch = r#"[
+        {"jsonrpc":"2.0","method":"set_device_power_state",
+         "params":{"room_name":"Кухня","device_name":"Розетка1","power_state":false}},
+        {"jsonrpc":"2.0","method":"is_device_on",
+         "params":{"room_name":"Кухня","device_name":"Розетка1"},"id":"on"},
+        {"jsonrpc":"2.0","method":"devices_list","params":{"room_name":"Гараж"},"id":2},
+        {"jsonrpc":"2.0","method":"open_garage","id":3}
+    ]"#;
+    assert_eq!(
+        connection.send_request(batch).unwrap(),
+        concat!(
+            r#"[{"id":"on","jsonrpc":"2.0","result":"room_name:Кухня,device_name:Розетка1,is_on:false"},"#,
+            r#"{"error":{"code":-32001,"data":"CantFindRoom","message":"Cant find room"},"id":2,"jsonrpc":"2.0"},"#,
+            r#"{"error":{"code":-32601,"data":"CantProccessRequest","message":"Cant proccess request"},"id":3,"jsonrpc":"2.0"}]"#
+        )
+    );
+    let notification = r#"{"jsonrpc":"2.0","method":"hello"}"#;
+    assert_eq!(connection.send_request(notification).unwrap(), "");
+}
+
+#[cfg(unix)]
+#[test]
+fn same_house_over_tcp_and_unix_socket() {
+    use my_stp::socket_file::UnixSocketOptions;
+    use smart_house_server::SmartHouseServer;
+
+    let path = std::env::temp_dir().join(format!("smart-house-{}.sock", std::process::id()));
+    let mut server = SmartHouseServer::with_smart_house(
+        HouseBuilder::new()
+            .room("Кухня", |room| room.socket("Розетка1", 100.0))
+            .build(),
+        "127.0.0.1:0",
+        "127.0.0.1:0",
+    )
+    .unwrap();
+    server
+        .set_key_store(Some(KeyStore::parse("alice:secret").unwrap()))
+        .unwrap();
+    server
+        .listen_unix(&path, UnixSocketOptions::default())
+        .unwrap();
+    assert_eq!(server.socket_path(), Some(path.as_path()));
+    server.start_server_listening();
+
+    let credentials = Credentials::new("alice", b"secret");
+    let mut tcp =
+        StpClient::connect_authenticated(server.local_addr().unwrap(), &credentials).unwrap();
+    let mut unix = StpClient::connect_unix_authenticated(&path, &credentials).unwrap();
+    unix.send_request(
+        "set_device_power_state room_name=Кухня device_name=Розетка1 power_state=false",
+    )
+    .unwrap();
+    assert_eq!(
+        tcp.send_request("is_device_on room_name=Кухня device_name=Розетка1")
+            .unwrap(),
+        "room_name:Кухня,device_name:Розетка1,is_on:false"
+    );
+    assert!(matches!(
+        StpClient::connect_unix(&path),
+        Err(ConnectError::AuthenticationRequired)
+    ));
+}
@@ -1,35 +1,143 @@
-use std::collections::HashMap;
-use std::ops::DerefMut;
-use std::sync::{Arc, RwLock};
+use std::collections::HashSet;
+use std::future::Future;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
-use errors::{CreateNewServerError, ProccessRequestError, ProccessorError, SmartHouseInitError};
+use errors::{
+    ConfigureServerError, CreateNewServerError, ProccessRequestError, SmartHouseInitError,
+};
+use my_stp_async::auth::KeyStore;
+use my_stp_async::jsonrpc;
+use my_stp_async::message::{Request, Response};
+use my_stp_async::server::StpServer;
+#[cfg(unix)]
+use my_stp_async::socket_file::UnixSocketOptions;
 use processors::{
-    CancelDeviceReportStreamProcessor, DeviceListProcessor, DeviceReportProcessor,
-    GetDeviceReportStreamProcessor, HelloProcessor, IsDeviceOnProcessor, RequestProcessor,
-    RoomsListProcessor, SetDevicePowerStateProcessor,
+    ActiveAlertsProcessor, AddAlertProcessor, AddDeviceProcessor, AddRoomProcessor,
+    AlertsHistoryProcessor, CancelDeviceReportStreamProcessor, DeviceListProcessor,
+    DeviceReportProcessor, GetDeviceReportStreamProcessor, HelloProcessor, IsDeviceOnProcessor,
+    ListStreamsProcessor, PowerBudgetReportProcessor, RemoveAlertProcessor, RemoveDeviceProcessor,
+    RemoveRoomProcessor, RenameProcessor, RenewDeviceReportStreamProcessor, RequestContext,
+    RequestProcessor, RoomsListProcessor, SetDevicePowerStateProcessor, SetDevicePriorityProcessor,
+    SetGroupPowerStateProcessor, SetPowerBudgetProcessor, StreamInfoProcessor,
+    SubscribeAlertsProcessor, UnsubscribeAlertsProcessor,
 };
+use router::Router;
+use shutdown::{Sessions, ShutdownSummary};
+use smart_house::alerts::AlertManager;
+use smart_house::clock::SystemClock;
+use smart_house::power_budget::PowerBudget;
+use smart_house::streams::{StreamHandle, StreamRegistry};
 use smart_house::{smart_tools, temperature, SmartHouse};
 use smart_tools::smart_socket::{SmartSocket, SmartSocketInfoProvider, TemperatureProvider};
 use smart_tools::thermomener::{EnergyProvider, Thermometer, ThermometerInfoProvider};
 use temperature::TemperatureMeasureUnits;
 use tokio::net::{ToSocketAddrs, UdpSocket};
-use tokio::sync::watch::{self, Sender};
-use tokio::sync::Mutex;
+use tokio::sync::watch::{self, Receiver, Sender};
+use tokio::task::{self, JoinHandle};
+use tokio::time::{self, Instant};
 
 pub mod errors;
 mod processors;
+mod router;
+pub mod shutdown;
+
+const HOUSE_MONITOR_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Task that runs until it is cancelled
+struct ServerTask {
+    canceller: Sender<bool>,
+    handle: JoinHandle<()>,
+}
+
+impl ServerTask {
+    fn spawn<F, Fut>(run: F) -> Self
+    where
+        F: FnOnce(Receiver<bool>) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (canceller, cancellation_token) = watch::channel(false);
+        let handle = tokio::spawn(run(cancellation_token));
+        Self { canceller, handle }
+    }
+
+    fn cancel(&self) {
+        let _ = self.canceller.send(true);
+    }
+}
 
+impl StreamHandle for ServerTask {
+    fn cancel(&self) {
+        ServerTask::cancel(self);
+    }
+
+    fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+}
+
+/// Resolves once `cancellation_token` is set or its task is gone
+async fn cancelled(cancellation_token: &mut Receiver<bool>) {
+    let _ = cancellation_token.wait_for(|cancelled| *cancelled).await;
+}
+
+/// The house lock is only taken on the blocking pool. Work on the whole house calls
+/// every device it touches, and a writer queued behind it would otherwise park
+/// a runtime worker together with every reader queued behind the writer
+async fn read_house_blocking<R, F>(smart_house: &Arc<RwLock<SmartHouse>>, work: F) -> R
+where
+    R: Send + 'static,
+    F: FnOnce(&SmartHouse) -> R + Send + 'static,
+{
+    let smart_house = smart_house.clone();
+    match task::spawn_blocking(move || work(&smart_house.read().unwrap())).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => panic!("house work was cancelled : {e}"),
+    }
+}
+
+/// Changes of the rooms and devices, see [`read_house_blocking`]
+async fn write_house_blocking<R, F>(smart_house: &Arc<RwLock<SmartHouse>>, work: F) -> R
+where
+    R: Send + 'static,
+    F: FnOnce(&mut SmartHouse) -> R + Send + 'static,
+{
+    let smart_house = smart_house.clone();
+    match task::spawn_blocking(move || work(&mut smart_house.write().unwrap())).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => panic!("house work was cancelled : {e}"),
+    }
+}
+
+/// State shared by the connections. Every part has its own lock,
+/// locks are never held across an await
 struct ServerStore {
-    execution_threads: HashMap<String, Sender<bool>>,
-    message_thread: Option<Sender<bool>>,
+    streams: Mutex<StreamRegistry<ServerTask>>,
+    message_threads: Mutex<Vec<ServerTask>>,
+    monitor_thread: Mutex<Option<ServerTask>>,
+    power_budget: Mutex<PowerBudget>,
+    alerts: Mutex<AlertManager>,
+    alert_subscribers: Mutex<HashSet<String>>,
     udp_socket: UdpSocket,
 }
 
 pub struct SmartHouseServer {
-    smart_house: Arc<tokio::sync::Mutex<SmartHouse>>,
-    stp: Arc<Mutex<my_stp_async::server::StpServer>>,
-    processors: Arc<Vec<Box<dyn RequestProcessor>>>,
-    server_threads: Arc<tokio::sync::Mutex<ServerStore>>,
+    /// Rooms and devices change under the write lock, devices have their own locks
+    smart_house: Arc<RwLock<SmartHouse>>,
+    stp: Arc<StpServer>,
+    unix_stp: Option<Arc<StpServer>>,
+    local_addr: SocketAddr,
+    #[cfg(unix)]
+    socket_path: Option<PathBuf>,
+    key_store: Option<KeyStore>,
+    router: Arc<Router>,
+    sessions: Arc<Sessions>,
+    server_threads: Arc<ServerStore>,
 }
 
 impl SmartHouseServer {
@@ -38,11 +146,29 @@ impl SmartHouseServer {
             Box::new(HelloProcessor),
             Box::new(RoomsListProcessor),
             Box::new(DeviceListProcessor),
-            Box::new(IsDeviceOnProcessor),
             Box::new(DeviceReportProcessor),
-            Box::new(GetDeviceReportStreamProcessor),
             Box::new(SetDevicePowerStateProcessor),
+            Box::new(SetGroupPowerStateProcessor),
+            Box::new(IsDeviceOnProcessor),
+            Box::new(GetDeviceReportStreamProcessor),
             Box::new(CancelDeviceReportStreamProcessor),
+            Box::new(RenewDeviceReportStreamProcessor),
+            Box::new(ListStreamsProcessor),
+            Box::new(StreamInfoProcessor),
+            Box::new(SetPowerBudgetProcessor),
+            Box::new(SetDevicePriorityProcessor),
+            Box::new(PowerBudgetReportProcessor),
+            Box::new(AddAlertProcessor),
+            Box::new(RemoveAlertProcessor),
+            Box::new(ActiveAlertsProcessor),
+            Box::new(AlertsHistoryProcessor),
+            Box::new(SubscribeAlertsProcessor),
+            Box::new(UnsubscribeAlertsProcessor),
+            Box::new(AddRoomProcessor),
+            Box::new(RemoveRoomProcessor),
+            Box::new(AddDeviceProcessor),
+            Box::new(RemoveDeviceProcessor),
+            Box::new(RenameProcessor),
         ];
         processors
     }
@@ -114,126 +240,362 @@ impl SmartHouseServer {
     where
         Addrs: ToSocketAddrs,
     {
+        Self::with_smart_house(SmartHouseServer::init_smart_house()?, tcp_addr, udp_addr).await
+    }
+
+    /// Create a server for an already built smart house
+    pub async fn with_smart_house<Addrs>(
+        smart_house: SmartHouse,
+        tcp_addr: Addrs,
+        udp_addr: Addrs,
+    ) -> Result<Self, CreateNewServerError>
+    where
+        Addrs: ToSocketAddrs,
+    {
+        let stp = StpServer::bind(tcp_addr).await?;
         Ok(SmartHouseServer {
-            smart_house: Arc::new(tokio::sync::Mutex::new(
-                SmartHouseServer::init_smart_house()?
-            )),
-            stp: Arc::new(tokio::sync::Mutex::new(
-                my_stp_async::server::StpServer::bind(tcp_addr).await?,
-            )),
-            processors: Arc::new(SmartHouseServer::create_processors()),
-            server_threads: Arc::new(Mutex::new(ServerStore {
-                execution_threads: HashMap::new(),
-                message_thread: None,
+            smart_house: Arc::new(RwLock::new(smart_house)),
+            local_addr: stp.local_addr()?,
+            stp: Arc::new(stp),
+            unix_stp: None,
+            #[cfg(unix)]
+            socket_path: None,
+            key_store: None,
+            router: Arc::new(Router::new(SmartHouseServer::create_processors())),
+            sessions: Arc::new(Sessions::default()),
+            server_threads: Arc::new(ServerStore {
+                streams: Mutex::new(StreamRegistry::default()),
+                message_threads: Mutex::new(Vec::new()),
+                monitor_thread: Mutex::new(None),
+                power_budget: Mutex::new(PowerBudget::new(Arc::new(SystemClock))),
+                alerts: Mutex::new(AlertManager::new(Arc::new(SystemClock))),
+                alert_subscribers: Mutex::new(HashSet::new()),
                 udp_socket: UdpSocket::bind(udp_addr).await?,
-            })),
+            }),
         })
     }
 
+    /// Require clients to authenticate with one of the keys, on every listener.
+    /// Has to be called before [`SmartHouseServer::start_server_listening`]
+    pub fn set_key_store(
+        &mut self,
+        key_store: Option<KeyStore>,
+    ) -> Result<(), ConfigureServerError> {
+        let listeners = std::iter::once(&mut self.stp).chain(self.unix_stp.as_mut());
+        for stp in listeners {
+            let stp = Arc::get_mut(stp).ok_or(ConfigureServerError::AlreadyListening)?;
+            stp.set_key_store(key_store.clone());
+        }
+        self.key_store = key_store;
+        Ok(())
+    }
+
+    /// Also accept clients on a Unix domain socket, next to the TCP listener.
+    /// Has to be called before [`SmartHouseServer::start_server_listening`]
+    #[cfg(unix)]
+    pub async fn listen_unix<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        options: UnixSocketOptions,
+    ) -> Result<(), ConfigureServerError> {
+        if Arc::get_mut(&mut self.stp).is_none() {
+            return Err(ConfigureServerError::AlreadyListening);
+        }
+        let mut stp = StpServer::bind_unix(path, options).await?;
+        stp.set_key_store(self.key_store.clone());
+        self.socket_path = stp.socket_path().map(Path::to_path_buf);
+        self.unix_stp = Some(Arc::new(stp));
+        Ok(())
+    }
+
+    /// Address the STP listener is bound to, useful when binding to port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Path of the Unix socket given to [`SmartHouseServer::listen_unix`]
+    #[cfg(unix)]
+    pub fn socket_path(&self) -> Option<&Path> {
+        self.socket_path.as_deref()
+    }
+
     pub fn start_server_listening(&mut self) {
         println!("Starting server...");
 
+        let accept_tasks: Vec<_> = std::iter::once(self.stp.clone())
+            .chain(self.unix_stp.clone())
+            .map(|stp| self.start_accepting(stp))
+            .collect();
+        let monitor_task = self.start_house_monitoring();
+        *self.server_threads.message_threads.lock().unwrap() = accept_tasks;
+        *self.server_threads.monitor_thread.lock().unwrap() = Some(monitor_task);
+    }
+
+    /// Every listener gets its own accept task and every connection its own task,
+    /// so clients are served in parallel
+    fn start_accepting(&self, stp_atomic: Arc<StpServer>) -> ServerTask {
         let server_threads_ptr = self.server_threads.clone();
         let smart_house_ptr = self.smart_house.clone();
-        let processors_ptr = self.processors.clone();
-        let stp_atomic = self.stp.clone();
+        let router_ptr = self.router.clone();
+        let sessions = self.sessions.clone();
 
-        let (canceller, cancellation_token) = watch::channel(false);
+        ServerTask::spawn(|mut cancellation_token| async move {
+            loop {
+                // The handshake runs in the connection task, a silent client can't hold up the next ones
+                let pending = tokio::select! {
+                    pending = stp_atomic.accept_pending() => pending,
+                    _ = cancelled(&mut cancellation_token) => break,
+                };
+                let Ok(pending) = pending else {
+                    continue;
+                };
 
-        tokio::spawn(async move {
-            let server_threads_ptr = server_threads_ptr;
-            let smart_house_ptr = smart_house_ptr;
-            let processors_ptr = processors_ptr;
-            let stp_atomic = stp_atomic;
+                let stp = stp_atomic.clone();
+                let mut handshake_cancellation = cancellation_token.clone();
+                let server_threads_ptr = server_threads_ptr.clone();
+                let smart_house_ptr = smart_house_ptr.clone();
+                let router_ptr = router_ptr.clone();
+                let sessions_ptr = sessions.clone();
+                let task = tokio::spawn(async move {
+                    // A shutdown ends handshakes in progress
+                    let connection = tokio::select! {
+                        connection = stp.handshake(pending) => connection,
+                        _ = cancelled(&mut handshake_cancellation) => return,
+                    };
+                    let mut success_connection = match connection {
+                        Ok(connection) => connection,
+                        Err(e) => {
+                            eprintln!("Handshake error : {e:?}");
+                            return;
+                        }
+                    };
+                    // Connections accepted while the server shuts down are closed unserved
+                    let Some(session_id) = sessions_ptr.register(success_connection.closer())
+                    else {
+                        return;
+                    };
+                    let identity = success_connection.identity().map(str::to_string);
+                    let pusher = success_connection.pusher();
+                    let proccess_result = success_connection
+                        .serve_typed(|reqest| {
+                            let identity = identity.clone();
+                            let pusher = pusher.clone();
+                            let server_threads = server_threads_ptr.clone();
+                            let smart_house_ptr = smart_house_ptr.clone();
+                            let router_ptr = router_ptr.clone();
+                            async move {
+                                let context = RequestContext {
+                                    identity: identity.as_deref(),
+                                    session_id,
+                                    pusher: pusher.as_ref(),
+                                };
+                                Self::respond(
+                                    reqest,
+                                    &context,
+                                    &server_threads,
+                                    &smart_house_ptr,
+                                    &router_ptr,
+                                )
+                                .await
+                            }
+                        })
+                        .await;
 
-            loop {
-                if *cancellation_token.borrow() {
-                    break;
-                }
+                    sessions_ptr.unregister(session_id);
+                    if let Err(request_error) = proccess_result {
+                        eprintln!("Request error : {:?}", request_error);
+                    }
+                });
+                sessions.track(task);
+            }
+        })
+    }
 
-                let connection = stp_atomic.lock().await.accept().await;
-                if connection.is_err() {
-                    continue;
+    fn start_house_monitoring(&mut self) -> ServerTask {
+        let server_threads_ptr = self.server_threads.clone();
+        let smart_house_ptr = self.smart_house.clone();
+
+        ServerTask::spawn(|mut cancellation_token| async move {
+            let mut interval = time::interval(HOUSE_MONITOR_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = cancelled(&mut cancellation_token) => break,
                 }
-                let success_connection = connection.unwrap();
-
-                let server_threads = server_threads_ptr.clone();
-                let mut smart_house = smart_house_ptr.lock().await;
-
-                let processors = processors_ptr.as_ref();
-
-                let proccess_result = success_connection
-                    .proccess_request(move |reqest| {
-                        match Self::process_request_by_processors(
-                            reqest,
-                            server_threads,
-                            smart_house.deref_mut(),
-                            processors,
-                        ) {
-                            Ok(response) => response,
-                            Err(e) => {
-                                eprintln!("Proccess request error : {:?}", e);
-                                format!("Proccess request error : {:?}", e)
-                            }
-                        }
+                let server = server_threads_ptr.clone();
+                let (shed_events, events) =
+                    read_house_blocking(&smart_house_ptr, move |smart_house| {
+                        let shed_events = server.power_budget.lock().unwrap().enforce(smart_house);
+                        (
+                            shed_events,
+                            server.alerts.lock().unwrap().evaluate(smart_house),
+                        )
                     })
                     .await;
+                for event in shed_events {
+                    println!("power budget: {event}");
+                }
+                if events.is_empty() {
+                    continue;
+                }
+                let subscribers: Vec<String> = server_threads_ptr
+                    .alert_subscribers
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .cloned()
+                    .collect();
 
-                if let Err(request_error) = proccess_result {
-                    eprintln!("Request error : {:?}", request_error);
+                for event in events {
+                    let message = event.to_string();
+                    println!("{message}");
+                    for addr in subscribers.iter() {
+                        let sent = server_threads_ptr
+                            .udp_socket
+                            .send_to(message.as_bytes(), addr)
+                            .await;
+                        if let Err(e) = sent {
+                            eprintln!("Cant send alert to {addr} : {e}");
+                        }
+                    }
                 }
             }
-        });
-        let server_threads = self.server_threads.clone();
-        tokio::task::spawn_blocking(move || {
-            server_threads.blocking_lock().message_thread = Some(canceller);
-        });
+        })
     }
 
-    fn process_request_by_processors(
+    /// Stop accepting connections, let requests in flight finish and close the sessions,
+    /// stop report streams and house monitoring, then join every server task.
+    /// Tasks still busy after `timeout` are aborted and counted in the summary.
+    /// The server can't be started again
+    pub async fn shutdown(&mut self, timeout: Duration) -> ShutdownSummary {
+        let started = Instant::now();
+        let deadline = started + timeout;
+        let accept_tasks =
+            std::mem::take(&mut *self.server_threads.message_threads.lock().unwrap());
+        let mut summary = ShutdownSummary {
+            listeners: accept_tasks.len(),
+            ..ShutdownSummary::default()
+        };
+
+        for task in &accept_tasks {
+            task.cancel();
+        }
+        let accept_tasks = accept_tasks.into_iter().map(|task| task.handle).collect();
+        summary.aborted_tasks += shutdown::join_until(accept_tasks, deadline).await;
+
+        // Requests in flight may still start streams, so streams are stopped after them
+        let (closed_sessions, connection_tasks) = self.sessions.drain();
+        summary.closed_sessions = closed_sessions;
+        summary.aborted_tasks += shutdown::join_until(connection_tasks, deadline).await;
+
+        let tasks: Vec<_> = {
+            let (streams, stopped) = {
+                let mut streams = self.server_threads.streams.lock().unwrap();
+                (streams.take_all(), streams.take_stopped())
+            };
+            summary.stopped_streams = streams.len();
+            let monitor_task = self.server_threads.monitor_thread.lock().unwrap().take();
+            streams
+                .into_iter()
+                .map(|stream| stream.handle)
+                .chain(stopped)
+                .chain(monitor_task)
+                .collect()
+        };
+        for task in &tasks {
+            task.cancel();
+        }
+        let tasks = tasks.into_iter().map(|task| task.handle).collect();
+        summary.aborted_tasks += shutdown::join_until(tasks, deadline).await;
+
+        summary.elapsed = started.elapsed();
+        summary
+    }
+
+    async fn process_request(
         request: String,
-        server: Arc<Mutex<ServerStore>>,
-        smart_house: &mut SmartHouse,
-        processors: &Vec<Box<dyn RequestProcessor>>,
+        context: &RequestContext<'_>,
+        server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        router: &Router,
     ) -> Result<String, ProccessRequestError> {
-        for proccessor in processors.iter() {
-            let result = proccessor.try_process(&request, server.clone(), smart_house);
-            match result {
-                Err(ProccessorError::CantProccessRequest) => continue,
-                Err(e) => return Err(ProccessRequestError::ProccessorError(e)),
-                _ => (),
-            }
+        if let Some(identity) = context.identity {
+            println!("{identity} : {request}");
+        }
+        router.process(&request, server, smart_house, context).await
+    }
 
-            if let Ok(response) = result {
-                return Ok(response);
+    /// Command lines and typed requests are processed as they are,
+    /// a JSON-RPC frame call by call. Frames of notifications only get an empty response
+    async fn respond(
+        request: Request,
+        context: &RequestContext<'_>,
+        server: &Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        router: &Router,
+    ) -> Response {
+        match request {
+            Request::Text(text) if jsonrpc::is_json_rpc(&text) => {
+                let response = jsonrpc::handle_frame_async(&text, |call| async move {
+                    let request = call.into_command_line();
+                    Self::process_request(request, context, server.clone(), smart_house, router)
+                        .await
+                        .map_err(|e| {
+                            eprintln!("Proccess request error : {:?}", e);
+                            e.json_rpc_error()
+                        })
+                })
+                .await;
+                Response::Ok(response.unwrap_or_default())
+            }
+            request => {
+                let request = request.into_command_line();
+                Self::create_response(
+                    Self::process_request(request, context, server.clone(), smart_house, router)
+                        .await,
+                )
             }
         }
+    }
 
-        Err(ProccessRequestError::CantProccessRequest)
+    /// String clients get the error message, typed clients also get the error code
+    fn create_response(result: Result<String, ProccessRequestError>) -> Response {
+        match result {
+            Ok(response) => Response::Ok(response),
+            Err(e) => {
+                eprintln!("Proccess request error : {:?}", e);
+                Response::Error {
+                    code: e.code(),
+                    message: format!("Proccess request error : {:?}", e),
+                }
+            }
+        }
     }
 }
 
 impl Drop for SmartHouseServer {
+    /// Tasks can't be awaited here, they are only told to stop.
+    /// [`SmartHouseServer::shutdown`] waits for them
     fn drop(&mut self) {
-        println!("Dropping server...");
-        println!("join all server threads");
-
-        let mut write_guard = self.server_threads.blocking_lock();
-
-        if let Some(thread) = write_guard.message_thread.take() {
-            thread.send(true).unwrap();
-            println!("tcp message thread joined");
+        let server_threads = &self.server_threads;
+        let accept_tasks = std::mem::take(&mut *server_threads.message_threads.lock().unwrap());
+        if accept_tasks.is_empty() {
+            return;
         }
-
-        println!("join udp server threads");
-        let execution_threads = std::mem::take(&mut write_guard.execution_threads);
-
-        for (_, value) in execution_threads.into_iter() {
-            value.send(true).unwrap();
+        println!("Dropping server without shutdown...");
+        for task in accept_tasks {
+            task.cancel();
+        }
+        let _ = self.sessions.drain();
+        let streams = server_threads.streams.lock().unwrap().take_all();
+        let monitor_task = server_threads.monitor_thread.lock().unwrap().take();
+        for task in streams
+            .into_iter()
+            .map(|stream| stream.handle)
+            .chain(monitor_task)
+        {
+            task.cancel();
         }
-
-        println!("udp server threads joined");
-
         println!("server dropped");
     }
 }
@@ -1,12 +1,55 @@
+use my_stp_async::auth::KeyStore;
+
+/// File with `client_id:key` lines. Without it every client is accepted
+const KEYS_FILE_VAR: &str = "SMART_HOUSE_KEYS_FILE";
+/// Unix socket path to listen on besides TCP
+const SOCKET_VAR: &str = "SMART_HOUSE_SOCKET";
+
 #[tokio::main]
 async fn main() {
     let mut server =
         smart_house_server_async::SmartHouseServer::new("127.0.0.1:8080", "127.0.0.1:8082")
             .await
             .unwrap();
+    if let Ok(keys_file) = std::env::var(KEYS_FILE_VAR) {
+        server
+            .set_key_store(Some(KeyStore::load(keys_file).unwrap()))
+            .unwrap();
+    }
+    #[cfg(unix)]
+    if let Ok(socket) = std::env::var(SOCKET_VAR) {
+        server
+            .listen_unix(
+                socket,
+                my_stp_async::socket_file::UnixSocketOptions::default(),
+            )
+            .await
+            .unwrap();
+    }
     server.start_server_listening();
-    loop {
-        std::thread::sleep(std::time::Duration::from_secs(1));
-        println!("server main thread tick")
+
+    wait_for_stop_signal().await;
+    println!("Shutting down...");
+    println!(
+        "{}",
+        server
+            .shutdown(smart_house_server_async::shutdown::DEFAULT_SHUTDOWN_TIMEOUT)
+            .await
+    );
+}
+
+/// SIGINT or SIGTERM, only Ctrl-C off Unix
+async fn wait_for_stop_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).unwrap();
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
     }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
 }
@@ -1,89 +1,212 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use async_trait::async_trait;
+use smart_house::adapters::AsyncDeviceAdapter;
+use smart_house::async_device::{AsyncDevice, AsyncReporter};
+use smart_house::SmartHouse;
+use tokio::time;
+
+use smart_house::alerts::AlertDefinition;
+use smart_house::bulk::{self, DeviceGroup, PowerAction};
+use smart_house::smart_tools::smart_socket::{SmartSocket, TemperatureProvider};
+use smart_house::smart_tools::thermomener::{EnergyProvider, Thermometer};
+use smart_house::streams::{stream_name, StreamEntry, StreamOwner, StreamState};
+use smart_house::temperature::TemperatureMeasureUnits;
 
-use tokio::{
-    sync::{watch, Mutex},
-    time,
+use my_stp_async::command_schema::{CommandSchema, ParamSchema, ParamType};
+use my_stp_async::custom_parser::RequestParams;
+use my_stp_async::errors::SchemaError;
+use my_stp_async::{errors::PushError, push::Pusher};
+
+use crate::{
+    cancelled, errors::ProccessorError, read_house_blocking, write_house_blocking, ServerStore,
+    ServerTask,
 };
 
-use crate::{errors::ProccessorError, ServerStore};
+/// What a processor knows about the connection the request came from
+pub struct RequestContext<'a> {
+    /// Id of the authenticated client
+    pub identity: Option<&'a str>,
+    /// Unique among the sessions of the server
+    pub session_id: u64,
+    /// `None` if the client has not negotiated push messages
+    pub pusher: Option<&'a Pusher>,
+}
+
+impl RequestContext<'_> {
+    /// Streams of an authenticated client belong to all its sessions
+    pub(super) fn stream_owner(&self) -> StreamOwner {
+        match self.identity {
+            Some(identity) => StreamOwner::Client(identity.to_string()),
+            None => StreamOwner::Session(self.session_id),
+        }
+    }
+}
 
+#[async_trait]
 pub trait RequestProcessor: Send + Sync {
-    fn try_process(
+    /// Name and parameters of the command the processor serves
+    fn schema(&self) -> &'static CommandSchema;
+
+    /// Serve a request that matches the schema, defaults are already filled in.
+    /// Locks are held only while the state is read or changed, never across an await,
+    /// so a slow device does not stall the other connections
+    async fn process(
         &self,
-        request: &str,
-        server: Arc<Mutex<ServerStore>>,
-        smart_house: &mut smart_house::SmartHouse,
+        params: &RequestParams,
+        server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        context: &RequestContext<'_>,
     ) -> Result<String, ProccessorError>;
 }
 
+const ROOM_NAME: ParamSchema =
+    ParamSchema::required("room_name", ParamType::Text, "name of the room");
+const DEVICE_NAME: ParamSchema = ParamSchema::required(
+    "device_name",
+    ParamType::Text,
+    "name of the device in the room",
+);
+const MAX_POWER: ParamType = ParamType::Custom {
+    expected: "watts|none",
+    check: |value| {
+        value == "none"
+            || value
+                .parse::<f32>()
+                .is_ok_and(|watts| watts.is_finite() && watts >= 0.0)
+    },
+};
+
+/// Value of a parameter the schema requires or gives a default
+fn required<'a>(params: &'a RequestParams, name: &str) -> Result<&'a str, ProccessorError> {
+    params
+        .get(name)
+        .ok_or_else(|| SchemaError::MissingParam(name.to_string()).into())
+}
+
+/// Required value parsed, the schema has checked it already
+fn parsed<T: FromStr>(params: &RequestParams, name: &str) -> Result<T, ProccessorError> {
+    required(params, name)?
+        .parse()
+        .map_err(|_| ProccessorError::BadRequestParam)
+}
+
+/// Device of a room. The house is unlocked before the device is used and device calls
+/// run on the blocking pool, so a slow device only holds up the request that waits for it
+async fn find_device(
+    smart_house: &Arc<RwLock<SmartHouse>>,
+    room_name: &str,
+    device_name: &str,
+) -> Result<AsyncDeviceAdapter, ProccessorError> {
+    let room_name = room_name.to_string();
+    let device_name = device_name.to_string();
+    read_house_blocking(smart_house, move |smart_house| {
+        let device = smart_house
+            .get_room(&room_name)
+            .ok_or(ProccessorError::CantFindRoom)?
+            .get_device(&device_name)
+            .ok_or(ProccessorError::CantFindDevice)?;
+        Ok(AsyncDeviceAdapter::new(&device_name, device))
+    })
+    .await
+}
+
 pub(super) struct HelloProcessor;
 
+#[async_trait]
 impl RequestProcessor for HelloProcessor {
-    fn try_process(
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema =
+            CommandSchema::new("hello", "Greeting, names the authenticated client", &[]);
+        &SCHEMA
+    }
+
+    async fn process(
         &self,
-        request: &str,
-        server: Arc<Mutex<ServerStore>>,
-        smart_house: &mut smart_house::SmartHouse,
+        params: &RequestParams,
+        server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        context: &RequestContext<'_>,
     ) -> Result<String, ProccessorError> {
+        let _ = params;
         let _ = server;
         let _ = smart_house;
 
-        if !request.starts_with("hello") {
-            return Err(ProccessorError::CantProccessRequest);
+        match context.identity {
+            Some(identity) => Ok(format!("Hello {identity} from server")),
+            None => Ok("Hello from server".to_string()),
         }
-        Ok("Hello from server".to_string())
     }
 }
 
 pub(super) struct DeviceReportProcessor;
 
+#[async_trait]
 impl RequestProcessor for DeviceReportProcessor {
-    fn try_process(
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "device_report",
+            "Report of a device",
+            &[ROOM_NAME, DEVICE_NAME],
+        );
+        &SCHEMA
+    }
+
+    async fn process(
         &self,
-        request: &str,
-        server: Arc<Mutex<ServerStore>>,
-        smart_house: &mut smart_house::SmartHouse,
+        params: &RequestParams,
+        server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        context: &RequestContext<'_>,
     ) -> Result<String, ProccessorError> {
         let _ = server;
-        let _ = smart_house;
-
-        if !request.starts_with("device_report") {
-            return Err(ProccessorError::CantProccessRequest);
-        }
+        let _ = context;
 
-        let params = my_stp_async::custom_parser::parse_request_parameters(request);
-        let room_name = params
-            .get("room_name")
-            .ok_or(ProccessorError::CantProccessRequest)?;
-        let device_name = params
-            .get("device_name")
-            .ok_or(ProccessorError::CantProccessRequest)?;
+        let room_name = required(params, "room_name")?;
+        let device_name = required(params, "device_name")?;
 
-        let response = smart_house
-            .create_report_by_devices(vec![(room_name, device_name)])
+        let device = find_device(smart_house, room_name, device_name)
+            .await
+            .map_err(|_| ProccessorError::CantGetReport)?;
+        let report = device
+            .create_report()
+            .await
             .map_err(|_| ProccessorError::CantGetReport)?;
 
-        Ok(response)
+        Ok(smart_house::frame_reports(&[report]))
     }
 }
 
 pub(super) struct RoomsListProcessor;
 
+#[async_trait]
 impl RequestProcessor for RoomsListProcessor {
-    fn try_process(
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new("rooms_list", "Names of the rooms", &[]);
+        &SCHEMA
+    }
+
+    async fn process(
         &self,
-        request: &str,
-        server: Arc<Mutex<ServerStore>>,
-        smart_house: &mut smart_house::SmartHouse,
+        params: &RequestParams,
+        server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        context: &RequestContext<'_>,
     ) -> Result<String, ProccessorError> {
+        let _ = params;
         let _ = server;
-        if !request.starts_with("rooms_list") {
-            return Err(ProccessorError::CantProccessRequest);
-        }
+        let _ = context;
 
-        let rooms = smart_house.get_rooms();
-        let room_names: Vec<&str> = rooms.iter().map(|value| value.name()).collect();
-        let room_names_string = room_names.join(",");
+        let room_names_string = read_house_blocking(smart_house, |smart_house| {
+            let rooms = smart_house.get_rooms();
+            let room_names: Vec<&str> = rooms.iter().map(|value| value.name()).collect();
+            room_names.join(",")
+        })
+        .await;
 
         let response = format!("[{room_names_string}]");
 
@@ -93,32 +216,35 @@ impl RequestProcessor for RoomsListProcessor {
 
 pub(super) struct DeviceListProcessor;
 
+#[async_trait]
 impl RequestProcessor for DeviceListProcessor {
-    fn try_process(
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "devices_list",
+            "Names of the devices in a room",
+            &[ROOM_NAME],
+        );
+        &SCHEMA
+    }
+
+    async fn process(
         &self,
-        request: &str,
-        server: Arc<Mutex<ServerStore>>,
-        smart_house: &mut smart_house::SmartHouse,
+        params: &RequestParams,
+        server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        context: &RequestContext<'_>,
     ) -> Result<String, ProccessorError> {
         let _ = server;
-        if !request.starts_with("devices_list") {
-            return Err(ProccessorError::CantProccessRequest);
-        }
-
-        let params = my_stp_async::custom_parser::parse_request_parameters(request);
-        let room_name = params
-            .get("room_name")
-            .ok_or(ProccessorError::CantProccessRequest)?;
+        let _ = context;
 
-        let room = smart_house
-            .get_room(room_name)
-            .ok_or(ProccessorError::CantFindRoom)?;
+        let room_name = required(params, "room_name")?;
 
-        let device_names: Vec<String> = room
-            .get_devices()
-            .iter()
-            .map(|device| device.read().unwrap().get_device_name().to_string())
-            .collect();
+        let room = room_name.to_string();
+        let device_names = read_house_blocking(smart_house, move |smart_house| {
+            smart_house.get_room(&room).map(device_names)
+        })
+        .await
+        .ok_or(ProccessorError::CantFindRoom)?;
         let device_names_string = device_names.join(",");
 
         let response = format!("{room_name}:[{device_names_string}]");
@@ -129,221 +255,1088 @@ impl RequestProcessor for DeviceListProcessor {
 
 pub(super) struct SetDevicePowerStateProcessor;
 
+#[async_trait]
 impl RequestProcessor for SetDevicePowerStateProcessor {
-    fn try_process(
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "set_device_power_state",
+            "Turn a device on or off",
+            &[
+                ROOM_NAME,
+                DEVICE_NAME,
+                ParamSchema::required("power_state", ParamType::Bool, "true turns the device on"),
+            ],
+        );
+        &SCHEMA
+    }
+
+    async fn process(
         &self,
-        request: &str,
-        server: Arc<Mutex<ServerStore>>,
-        smart_house: &mut smart_house::SmartHouse,
+        params: &RequestParams,
+        server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        context: &RequestContext<'_>,
     ) -> Result<String, ProccessorError> {
         let _ = server;
-        if !request.starts_with("set_device_power_state") {
-            return Err(ProccessorError::CantProccessRequest);
-        }
+        let _ = context;
 
-        let params = my_stp_async::custom_parser::parse_request_parameters(request);
-        let room_name = params
-            .get("room_name")
-            .ok_or(ProccessorError::CantProccessRequest)?;
-        let device_name = params
-            .get("device_name")
-            .ok_or(ProccessorError::CantProccessRequest)?;
-        let power_state = params
-            .get("power_state")
-            .ok_or(ProccessorError::CantProccessRequest)?;
-
-        let room = smart_house
-            .get_room_mut(room_name)
-            .ok_or(ProccessorError::CantFindRoom)?;
-        let device = room
-            .get_device(device_name)
-            .ok_or(ProccessorError::CantFindDevice)?;
+        let room_name = required(params, "room_name")?;
+        let device_name = required(params, "device_name")?;
+        let power_state = required(params, "power_state")?;
 
-        let mut device_write = device.write().unwrap();
-        match *power_state {
-            "true" => device_write.turn_on(),
-            "false" => device_write.turn_off(),
-            _ => device_write.turn_off(),
+        let mut device = find_device(smart_house, room_name, device_name).await?;
+        match power_state {
+            "true" => device.turn_on().await,
+            "false" => device.turn_off().await,
+            _ => device.turn_off().await,
         };
 
         Ok("".to_string())
     }
 }
 
+pub(super) struct SetGroupPowerStateProcessor;
+
+#[async_trait]
+impl RequestProcessor for SetGroupPowerStateProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "set_group_power_state",
+            "Turn on, turn off or toggle a group of devices",
+            &[
+                ParamSchema::required(
+                    "power_state",
+                    ParamType::OneOf(&["true", "false", "toggle"]),
+                    "what to do with the devices",
+                ),
+                ParamSchema::optional("room_name", ParamType::Text, "only devices of the room"),
+                ParamSchema::optional(
+                    "kind",
+                    ParamType::Text,
+                    "only devices of the kind, e.g. smart_socket",
+                ),
+            ],
+        );
+        &SCHEMA
+    }
+
+    async fn process(
+        &self,
+        params: &RequestParams,
+        _server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        _context: &RequestContext<'_>,
+    ) -> Result<String, ProccessorError> {
+        let power_state: PowerAction = required(params, "power_state")?
+            .parse()
+            .map_err(|_| ProccessorError::BadRequestParam)?;
+        let group = DeviceGroup {
+            room_name: params
+                .get("room_name")
+                .map(|room_name| room_name.to_string()),
+            kind: params.get("kind").map(|kind| kind.to_string()),
+        };
+
+        let results = read_house_blocking(smart_house, move |smart_house| {
+            smart_house.set_group_power_state(&group, power_state)
+        })
+        .await
+        .ok_or(ProccessorError::CantFindRoom)?;
+        Ok(bulk::create_results_report(&results))
+    }
+}
+
 pub(super) struct IsDeviceOnProcessor;
 
+#[async_trait]
 impl RequestProcessor for IsDeviceOnProcessor {
-    fn try_process(
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "is_device_on",
+            "Power state of a device",
+            &[ROOM_NAME, DEVICE_NAME],
+        );
+        &SCHEMA
+    }
+
+    async fn process(
         &self,
-        request: &str,
-        server: Arc<Mutex<ServerStore>>,
-        smart_house: &mut smart_house::SmartHouse,
+        params: &RequestParams,
+        server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        context: &RequestContext<'_>,
     ) -> Result<String, ProccessorError> {
         let _ = server;
-        if !request.starts_with("is_device_on") {
-            return Err(ProccessorError::CantProccessRequest);
-        }
+        let _ = context;
 
-        let params = my_stp_async::custom_parser::parse_request_parameters(request);
-        let room_name = params
-            .get("room_name")
-            .ok_or(ProccessorError::CantProccessRequest)?;
-        let device_name = params
-            .get("device_name")
-            .ok_or(ProccessorError::CantProccessRequest)?;
+        let room_name = required(params, "room_name")?;
+        let device_name = required(params, "device_name")?;
 
-        let room = smart_house
-            .get_room(room_name)
-            .ok_or(ProccessorError::CantFindRoom)?;
-
-        let device = room
-            .get_device(device_name)
-            .ok_or(ProccessorError::CantProccessRequest)?;
+        let device = find_device(smart_house, room_name, device_name)
+            .await
+            .map_err(|e| match e {
+                ProccessorError::CantFindDevice => ProccessorError::CantProccessRequest,
+                e => e,
+            })?;
 
         Ok(format!(
             "room_name:{room_name},device_name:{device_name},is_on:{}",
-            device.read().unwrap().is_on()
+            device.is_on().await
         ))
     }
 }
 
 pub(super) struct GetDeviceReportStreamProcessor;
 
+/// Where a report stream goes: UDP datagrams to `addr` or push messages on the connection
+enum ReportTarget {
+    Udp(String),
+    Push(Pusher),
+}
+
+impl ReportTarget {
+    fn describe(&self) -> String {
+        match self {
+            ReportTarget::Udp(addr) => format!("udp {addr}"),
+            ReportTarget::Push(_) => "push".to_string(),
+        }
+    }
+}
+
+const STREAM_NAME: ParamSchema = ParamSchema::required(
+    "stream_name",
+    ParamType::Text,
+    "name the stream was created with",
+);
+
+const TTL: ParamSchema = ParamSchema::optional(
+    "ttl",
+    ParamType::UnsignedInteger,
+    "seconds the stream lives unless it is renewed",
+);
+
+#[async_trait]
 impl RequestProcessor for GetDeviceReportStreamProcessor {
-    fn try_process(
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "get_device_report_stream",
+            "Send reports of a device periodically",
+            &[
+                ROOM_NAME,
+                DEVICE_NAME,
+                ParamSchema::optional(
+                    "request_delay",
+                    ParamType::UnsignedInteger,
+                    "seconds between reports",
+                )
+                .with_default("5"),
+                ParamSchema::optional(
+                    "addr",
+                    ParamType::Text,
+                    "UDP address for the reports, push messages are used without it",
+                ),
+                TTL,
+            ],
+        );
+        &SCHEMA
+    }
+
+    async fn process(
         &self,
-        request: &str,
-        server: Arc<Mutex<ServerStore>>,
-        smart_house: &mut smart_house::SmartHouse,
+        params: &RequestParams,
+        server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        context: &RequestContext<'_>,
     ) -> Result<String, ProccessorError> {
-        if !request.starts_with("get_device_report_stream") {
-            return Err(ProccessorError::CantProccessRequest);
-        }
         println!("get get_device_report_stream request");
-        const DEFAULT_REQUEST_DELAY: u64 = 5;
-
-        let params = my_stp_async::custom_parser::parse_request_parameters(request);
-        let room_name = params
-            .get("room_name")
-            .ok_or(ProccessorError::CantProccessRequest)?;
-        let device_name = params
-            .get("device_name")
-            .ok_or(ProccessorError::CantProccessRequest)?;
-        let request_delay: u64 = params.get("request_delay").map_or_else(
-            || DEFAULT_REQUEST_DELAY,
-            |request_delay_string| {
-                request_delay_string
-                    .parse()
-                    .unwrap_or(DEFAULT_REQUEST_DELAY)
-            },
-        );
-        let addr_for_send = params
-            .get("addr")
-            .ok_or(ProccessorError::CantProccessRequest)?
-            .to_string();
-
-        let room = smart_house
-            .get_room_mut(room_name)
-            .ok_or(ProccessorError::CantFindRoom)?;
-        let device = room
-            .get_device(device_name)
-            .ok_or(ProccessorError::CantFindDevice)?;
-
-        let thread_name = format!("{}-{}", room_name, device.read().unwrap().get_device_name());
-        let (canceller, cancellation_token) = watch::channel(false);
+        let room_name = required(params, "room_name")?;
+        let device_name = required(params, "device_name")?;
+        let interval = Duration::from_secs(parsed(params, "request_delay")?);
+        let ttl = match params.get("ttl") {
+            Some(_) => Some(Duration::from_secs(parsed(params, "ttl")?)),
+            None => None,
+        };
+        let target = match params.get("addr") {
+            Some(addr) => ReportTarget::Udp(addr.to_string()),
+            None => ReportTarget::Push(
+                context
+                    .pusher
+                    .cloned()
+                    .ok_or(ProccessorError::PushNotNegotiated)?,
+            ),
+        };
 
-        let server_ptr = server.clone();
-        let thread_name_copy = thread_name.clone();
-        tokio::task::spawn_blocking(move || {
-            let entry = server_ptr
-                .blocking_lock()
-                .execution_threads
-                .remove(&thread_name_copy);
-            if let Some(cancellable_thread) = entry {
-                cancellable_thread.send(true).unwrap();
-            }
-            server_ptr
-                .blocking_lock()
-                .execution_threads
-                .insert(thread_name_copy, canceller);
-        });
+        let device = find_device(smart_house, room_name, device_name).await?;
 
-        tokio::task::spawn(async move {
-            let server = server;
-            let device = device;
+        let thread_name = stream_name(room_name, device_name);
+        let owner = context.stream_owner();
+        // Held until the stream is registered, so nobody else takes the name meanwhile
+        let mut streams = server.streams.lock().unwrap();
+        streams.check_owner(&thread_name, &owner)?;
 
+        let state = Arc::new(StreamState::new(ttl));
+        let stream_state = state.clone();
+        let stream_name = thread_name.clone();
+        let description = target.describe();
+        let server_ptr = server.clone();
+        let stream_task = ServerTask::spawn(|mut cancellation_token| async move {
+            let server = server_ptr;
+            let mut next_report = Instant::now() + interval;
             loop {
-                println!("cancellation_token.borrow() 1");
-                if *cancellation_token.borrow() {
+                let sleep_time = stream_state.sleep_time(next_report);
+                if time::timeout(sleep_time, cancelled(&mut cancellation_token))
+                    .await
+                    .is_ok()
+                {
                     break;
                 }
-
-                let device_ptr_for_send = device.clone();
-                let report = tokio::task::spawn_blocking(move || {
-                    device_ptr_for_send.read().unwrap().create_report().unwrap()
-                })
-                .await
-                .unwrap();
-                println!("cancellation_token.borrow() 2");
-                if *cancellation_token.borrow() {
+                if stream_state.is_expired() {
+                    let mut streams = server.streams.lock().unwrap();
+                    streams.finish(&stream_name, &stream_state);
+                    println!("stream {stream_name} expired");
                     break;
                 }
-                let send_result = {
-                    let server_lock = server.lock().await;
-                    server_lock
-                        .udp_socket
-                        .send_to(report.as_bytes(), addr_for_send.clone())
-                        .await
+                // Woken up by the lease, not by the interval
+                if Instant::now() < next_report {
+                    continue;
+                }
+                next_report += interval;
+
+                // Runs on the blocking pool, a slow device delays only this stream
+                let Ok(report) = device.create_report().await else {
+                    stream_state.record(false);
+                    continue;
                 };
-                println!(
-                    "sended report to {} : {:?}",
-                    addr_for_send.clone(),
-                    send_result
-                );
-                println!("cancellation_token.borrow() 2");
                 if *cancellation_token.borrow() {
                     break;
                 }
-                time::sleep(Duration::from_secs(request_delay)).await;
+                match &target {
+                    ReportTarget::Udp(addr) => {
+                        let send_result = server.udp_socket.send_to(report.as_bytes(), addr).await;
+                        stream_state.record(send_result.is_ok());
+                        println!("sended report to {addr} : {send_result:?}");
+                    }
+                    ReportTarget::Push(pusher) => match pusher.push(&stream_name, &report) {
+                        Err(PushError::Closed) => {
+                            // The client is gone, the entry is removed unless it was replaced
+                            let mut streams = server.streams.lock().unwrap();
+                            streams.finish(&stream_name, &stream_state);
+                            println!("stream {stream_name} stopped, connection closed");
+                            break;
+                        }
+                        result => {
+                            stream_state.record(result.is_ok());
+                            println!("push report {stream_name} : {result:?}");
+                        }
+                    },
+                }
             }
         });
 
+        streams.insert(
+            thread_name.clone(),
+            StreamEntry {
+                owner,
+                target: description,
+                interval,
+                created: SystemTime::now(),
+                state,
+                handle: stream_task,
+            },
+        );
+
         Ok(format!("create thread with name : {thread_name}"))
     }
 }
 
 pub(super) struct CancelDeviceReportStreamProcessor;
 
+#[async_trait]
 impl RequestProcessor for CancelDeviceReportStreamProcessor {
-    fn try_process(
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "cancel_device_report_stream",
+            "Stop a report stream of this client",
+            &[STREAM_NAME],
+        );
+        &SCHEMA
+    }
+
+    async fn process(
         &self,
-        request: &str,
-        server: Arc<Mutex<ServerStore>>,
-        smart_house: &mut smart_house::SmartHouse,
+        params: &RequestParams,
+        server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        context: &RequestContext<'_>,
     ) -> Result<String, ProccessorError> {
         let _ = smart_house;
-        if !request.starts_with("cancel_device_report_stream") {
-            return Err(ProccessorError::CantProccessRequest);
+
+        let thread_name = required(params, "stream_name")?;
+
+        let cancelled = server
+            .streams
+            .lock()
+            .unwrap()
+            .cancel(thread_name, &context.stream_owner())?;
+        if cancelled {
+            println!("Start joining thread with name : {thread_name}");
+            return Ok(format!("Cancel thread with name : {thread_name}"));
         }
+        Ok(format!(
+            "Cancel thread with name : {thread_name} - no thread to cancel"
+        ))
+    }
+}
+
+pub(super) struct RenewDeviceReportStreamProcessor;
+
+#[async_trait]
+impl RequestProcessor for RenewDeviceReportStreamProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "renew_device_report_stream",
+            "Extend the lease of a report stream of this client",
+            &[STREAM_NAME, TTL],
+        );
+        &SCHEMA
+    }
+
+    async fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<ServerStore>,
+        _smart_house: &Arc<RwLock<SmartHouse>>,
+        context: &RequestContext<'_>,
+    ) -> Result<String, ProccessorError> {
+        let stream_name = required(params, "stream_name")?;
+        let ttl = match params.get("ttl") {
+            Some(_) => Some(Duration::from_secs(parsed(params, "ttl")?)),
+            None => None,
+        };
+
+        let ttl =
+            server
+                .streams
+                .lock()
+                .unwrap()
+                .renew(stream_name, &context.stream_owner(), ttl)?;
+        Ok(match ttl {
+            Some(ttl) => format!("stream {stream_name} expires in {}", ttl.as_secs()),
+            None => format!("stream {stream_name} never expires"),
+        })
+    }
+}
+
+pub(super) struct ListStreamsProcessor;
+
+#[async_trait]
+impl RequestProcessor for ListStreamsProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema =
+            CommandSchema::new("list_streams", "Report streams of all clients", &[]);
+        &SCHEMA
+    }
+
+    async fn process(
+        &self,
+        _params: &RequestParams,
+        server: Arc<ServerStore>,
+        _smart_house: &Arc<RwLock<SmartHouse>>,
+        _context: &RequestContext<'_>,
+    ) -> Result<String, ProccessorError> {
+        Ok(server.streams.lock().unwrap().list())
+    }
+}
+
+pub(super) struct StreamInfoProcessor;
+
+#[async_trait]
+impl RequestProcessor for StreamInfoProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "stream_info",
+            "Owner, target, interval, lease and delivery counters of a report stream",
+            &[STREAM_NAME],
+        );
+        &SCHEMA
+    }
+
+    async fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<ServerStore>,
+        _smart_house: &Arc<RwLock<SmartHouse>>,
+        _context: &RequestContext<'_>,
+    ) -> Result<String, ProccessorError> {
+        let stream_name = required(params, "stream_name")?;
+        server
+            .streams
+            .lock()
+            .unwrap()
+            .info(stream_name)
+            .ok_or(ProccessorError::CantFindStream)
+    }
+}
+
+pub(super) struct SetPowerBudgetProcessor;
+
+#[async_trait]
+impl RequestProcessor for SetPowerBudgetProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "set_power_budget",
+            "Limit the total power of the house",
+            &[
+                ParamSchema::required("max_power", MAX_POWER, "watts, none removes the limit"),
+                ParamSchema::optional(
+                    "cooldown",
+                    ParamType::UnsignedInteger,
+                    "seconds restores wait after the last change",
+                ),
+            ],
+        );
+        &SCHEMA
+    }
+
+    async fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        _context: &RequestContext<'_>,
+    ) -> Result<String, ProccessorError> {
+        let max_power = required(params, "max_power")?;
+        let max_power: Option<f32> = match max_power {
+            "none" => None,
+            max_power => Some(
+                max_power
+                    .parse()
+                    .map_err(|_| ProccessorError::BadRequestParam)?,
+            ),
+        };
+        let cooldown = params
+            .get_u64("cooldown")
+            .map_err(|_| ProccessorError::BadRequestParam)?;
+
+        read_house_blocking(smart_house, move |smart_house| {
+            let mut power_budget = server.power_budget.lock().unwrap();
+            power_budget
+                .set_max_power(max_power)
+                .map_err(|_| ProccessorError::BadRequestParam)?;
+            if let Some(cooldown) = cooldown {
+                power_budget.set_cooldown(Duration::from_secs(cooldown));
+            }
+            for event in power_budget.enforce(smart_house) {
+                println!("power budget: {event}");
+            }
+            Ok(power_budget.create_report(smart_house))
+        })
+        .await
+    }
+}
+
+pub(super) struct SetDevicePriorityProcessor;
+
+#[async_trait]
+impl RequestProcessor for SetDevicePriorityProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "set_device_priority",
+            "Priority of a device under the power budget",
+            &[
+                ROOM_NAME,
+                DEVICE_NAME,
+                ParamSchema::required(
+                    "priority",
+                    ParamType::UnsignedInteger,
+                    "devices with lower priority are turned off first",
+                ),
+            ],
+        );
+        &SCHEMA
+    }
+
+    async fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        _context: &RequestContext<'_>,
+    ) -> Result<String, ProccessorError> {
+        let room_name = required(params, "room_name")?;
+        let device_name = required(params, "device_name")?;
+        let priority: u32 = required(params, "priority")?
+            .parse()
+            .map_err(|_| ProccessorError::BadRequestParam)?;
+
+        find_device(smart_house, room_name, device_name).await?;
+
+        server
+            .power_budget
+            .lock()
+            .unwrap()
+            .set_priority(room_name, device_name, priority);
+
+        Ok(format!(
+            "room_name:{room_name},device_name:{device_name},priority:{priority}"
+        ))
+    }
+}
+
+pub(super) struct PowerBudgetReportProcessor;
+
+#[async_trait]
+impl RequestProcessor for PowerBudgetReportProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "power_budget_report",
+            "Power budget and devices it turned off",
+            &[],
+        );
+        &SCHEMA
+    }
+
+    async fn process(
+        &self,
+        _params: &RequestParams,
+        server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        _context: &RequestContext<'_>,
+    ) -> Result<String, ProccessorError> {
+        Ok(read_house_blocking(smart_house, move |smart_house| {
+            server
+                .power_budget
+                .lock()
+                .unwrap()
+                .create_report(smart_house)
+        })
+        .await)
+    }
+}
+
+fn parse_alert_definition(params: &RequestParams) -> Result<AlertDefinition, ProccessorError> {
+    Ok(AlertDefinition {
+        name: required(params, "name")?.to_string(),
+        room_name: required(params, "room_name")?.to_string(),
+        device_name: required(params, "device_name")?.to_string(),
+        metric: parsed(params, "metric")?,
+        condition: parsed(params, "condition")?,
+        threshold: parsed(params, "threshold")?,
+        hysteresis: parsed(params, "hysteresis")?,
+        raise_after: Duration::from_secs(parsed(params, "for")?),
+        severity: parsed(params, "severity")?,
+    })
+}
 
-        let params = my_stp_async::custom_parser::parse_request_parameters(request);
-        let thread_name = params
-            .get("stream_name")
-            .ok_or(ProccessorError::CantProccessRequest)?;
-
-        tokio::task::block_in_place(move || {
-            if let Some(ca) = server
-                .blocking_lock()
-                .execution_threads
-                .remove(*thread_name)
-            {
-                println!("Start joining thread with name : {thread_name}");
-                ca.send(true).unwrap();
-                return Ok(format!("Cancel thread with name : {thread_name}"));
+pub(super) struct AddAlertProcessor;
+
+#[async_trait]
+impl RequestProcessor for AddAlertProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "add_alert",
+            "Raise an alert when a device metric crosses a threshold",
+            &[
+                ParamSchema::required("name", ParamType::Text, "unique name of the alert"),
+                ROOM_NAME,
+                DEVICE_NAME,
+                ParamSchema::required(
+                    "metric",
+                    ParamType::OneOf(&["temperature", "power"]),
+                    "what is watched",
+                ),
+                ParamSchema::required(
+                    "condition",
+                    ParamType::OneOf(&["above", "below"]),
+                    "which side of the threshold raises the alert",
+                ),
+                ParamSchema::required(
+                    "threshold",
+                    ParamType::Number,
+                    "value the metric is compared with",
+                ),
+                ParamSchema::optional(
+                    "hysteresis",
+                    ParamType::Number,
+                    "how far back the metric has to go to clear the alert",
+                )
+                .with_default("0"),
+                ParamSchema::optional(
+                    "for",
+                    ParamType::UnsignedInteger,
+                    "seconds the condition has to hold",
+                )
+                .with_default("0"),
+                ParamSchema::optional(
+                    "severity",
+                    ParamType::OneOf(&["info", "warning", "critical"]),
+                    "severity of the alert",
+                )
+                .with_default("warning"),
+            ],
+        );
+        &SCHEMA
+    }
+
+    async fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        _context: &RequestContext<'_>,
+    ) -> Result<String, ProccessorError> {
+        let definition = parse_alert_definition(params)?;
+        find_device(smart_house, &definition.room_name, &definition.device_name).await?;
+
+        let response = format!("add alert : {definition}");
+        server
+            .alerts
+            .lock()
+            .unwrap()
+            .add_unique_alert(definition)
+            .ok_or(ProccessorError::AlertExists)?;
+        Ok(response)
+    }
+}
+
+pub(super) struct RemoveAlertProcessor;
+
+#[async_trait]
+impl RequestProcessor for RemoveAlertProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "remove_alert",
+            "Remove an alert",
+            &[ParamSchema::required(
+                "name",
+                ParamType::Text,
+                "name of the alert",
+            )],
+        );
+        &SCHEMA
+    }
+
+    async fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<ServerStore>,
+        _smart_house: &Arc<RwLock<SmartHouse>>,
+        _context: &RequestContext<'_>,
+    ) -> Result<String, ProccessorError> {
+        let name = required(params, "name")?;
+
+        server
+            .alerts
+            .lock()
+            .unwrap()
+            .remove_alert(name)
+            .ok_or(ProccessorError::CantFindAlert)?;
+        Ok(format!("remove alert : {name}"))
+    }
+}
+
+pub(super) struct ActiveAlertsProcessor;
+
+#[async_trait]
+impl RequestProcessor for ActiveAlertsProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema =
+            CommandSchema::new("active_alerts", "Alerts that are raised now", &[]);
+        &SCHEMA
+    }
+
+    async fn process(
+        &self,
+        _params: &RequestParams,
+        server: Arc<ServerStore>,
+        _smart_house: &Arc<RwLock<SmartHouse>>,
+        _context: &RequestContext<'_>,
+    ) -> Result<String, ProccessorError> {
+        Ok(server.alerts.lock().unwrap().create_active_report())
+    }
+}
+
+pub(super) struct AlertsHistoryProcessor;
+
+#[async_trait]
+impl RequestProcessor for AlertsHistoryProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema =
+            CommandSchema::new("alerts_history", "Alerts raised and cleared so far", &[]);
+        &SCHEMA
+    }
+
+    async fn process(
+        &self,
+        _params: &RequestParams,
+        server: Arc<ServerStore>,
+        _smart_house: &Arc<RwLock<SmartHouse>>,
+        _context: &RequestContext<'_>,
+    ) -> Result<String, ProccessorError> {
+        Ok(server.alerts.lock().unwrap().create_history_report())
+    }
+}
+
+pub(super) struct SubscribeAlertsProcessor;
+
+#[async_trait]
+impl RequestProcessor for SubscribeAlertsProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "subscribe_alerts",
+            "Send alert events to a UDP address",
+            &[ParamSchema::required(
+                "addr",
+                ParamType::Text,
+                "UDP address for the events",
+            )],
+        );
+        &SCHEMA
+    }
+
+    async fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<ServerStore>,
+        _smart_house: &Arc<RwLock<SmartHouse>>,
+        _context: &RequestContext<'_>,
+    ) -> Result<String, ProccessorError> {
+        let addr = required(params, "addr")?.to_string();
+
+        let response = format!("subscribe alerts to {addr}");
+        server.alert_subscribers.lock().unwrap().insert(addr);
+        Ok(response)
+    }
+}
+
+pub(super) struct UnsubscribeAlertsProcessor;
+
+#[async_trait]
+impl RequestProcessor for UnsubscribeAlertsProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "unsubscribe_alerts",
+            "Stop sending alert events to a UDP address",
+            &[ParamSchema::required(
+                "addr",
+                ParamType::Text,
+                "UDP address given to subscribe_alerts",
+            )],
+        );
+        &SCHEMA
+    }
+
+    async fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<ServerStore>,
+        _smart_house: &Arc<RwLock<SmartHouse>>,
+        _context: &RequestContext<'_>,
+    ) -> Result<String, ProccessorError> {
+        let addr = required(params, "addr")?;
+
+        server.alert_subscribers.lock().unwrap().remove(addr);
+        Ok(format!("unsubscribe alerts from {addr}"))
+    }
+}
+
+/// Stop the report streams of the devices
+fn cancel_device_streams(server: &ServerStore, room_name: &str, device_names: &[String]) {
+    let mut streams = server.streams.lock().unwrap();
+    for device_name in device_names {
+        streams.remove(&stream_name(room_name, device_name));
+    }
+}
+
+/// Priorities and alerts of devices that left the house would be inherited
+/// by devices added later under the same name
+fn forget_devices(server: &ServerStore, room_name: &str, device_name: Option<&str>) {
+    server
+        .power_budget
+        .lock()
+        .unwrap()
+        .remove_devices(room_name, device_name);
+    server
+        .alerts
+        .lock()
+        .unwrap()
+        .remove_devices(room_name, device_name);
+}
+
+/// Priorities and alerts follow a renamed room or device
+fn rename_devices(
+    server: &ServerStore,
+    room_name: &str,
+    device_name: Option<&str>,
+    new_name: &str,
+) {
+    server
+        .power_budget
+        .lock()
+        .unwrap()
+        .rename(room_name, device_name, new_name);
+    server
+        .alerts
+        .lock()
+        .unwrap()
+        .rename(room_name, device_name, new_name);
+}
+
+fn device_names(room: &smart_house::Room) -> Vec<String> {
+    room.get_devices()
+        .iter()
+        .map(|device| device.read().unwrap().get_device_name().to_string())
+        .collect()
+}
+
+pub(super) struct AddRoomProcessor;
+
+#[async_trait]
+impl RequestProcessor for AddRoomProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema =
+            CommandSchema::new("add_room", "Add an empty room", &[ROOM_NAME]);
+        &SCHEMA
+    }
+
+    async fn process(
+        &self,
+        params: &RequestParams,
+        _server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        _context: &RequestContext<'_>,
+    ) -> Result<String, ProccessorError> {
+        let room_name = required(params, "room_name")?;
+
+        let room = smart_house::Room::new(room_name.to_string(), vec![]);
+        write_house_blocking(smart_house, |smart_house| smart_house.add_unique_room(room))
+            .await
+            .ok_or(ProccessorError::RoomExists)?;
+        Ok(format!("add room : {room_name}"))
+    }
+}
+
+pub(super) struct RemoveRoomProcessor;
+
+#[async_trait]
+impl RequestProcessor for RemoveRoomProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "remove_room",
+            "Remove a room with its devices, their report streams, priorities and alerts",
+            &[ROOM_NAME],
+        );
+        &SCHEMA
+    }
+
+    async fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        _context: &RequestContext<'_>,
+    ) -> Result<String, ProccessorError> {
+        let room_name = required(params, "room_name")?;
+
+        let room = room_name.to_string();
+        write_house_blocking(smart_house, move |smart_house| {
+            let removed = smart_house
+                .remove_room(&room)
+                .ok_or(ProccessorError::CantFindRoom)?;
+            cancel_device_streams(&server, &room, &device_names(&removed));
+            forget_devices(&server, &room, None);
+            Ok::<_, ProccessorError>(())
+        })
+        .await?;
+        Ok(format!("remove room : {room_name}"))
+    }
+}
+
+pub(super) struct AddDeviceProcessor;
+
+#[async_trait]
+impl RequestProcessor for AddDeviceProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "add_device",
+            "Add a device to a room",
+            &[
+                ROOM_NAME,
+                ParamSchema::required("device_name", ParamType::Text, "unique name in the room"),
+                ParamSchema::required(
+                    "kind",
+                    ParamType::OneOf(&["smart_socket", "thermometer"]),
+                    "kind of the device",
+                ),
+                ParamSchema::optional(
+                    "power",
+                    ParamType::Number,
+                    "watts a smart socket reports, required for smart_socket",
+                ),
+                ParamSchema::optional(
+                    "temperature",
+                    ParamType::Number,
+                    "celsius a thermometer reports, required for thermometer",
+                ),
+            ],
+        );
+        &SCHEMA
+    }
+
+    async fn process(
+        &self,
+        params: &RequestParams,
+        _server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        _context: &RequestContext<'_>,
+    ) -> Result<String, ProccessorError> {
+        let room_name = required(params, "room_name")?;
+        let device_name = required(params, "device_name")?;
+        let kind = required(params, "kind")?;
+
+        type AddDevice = Box<dyn FnOnce(&mut smart_house::Room) -> Option<usize> + Send>;
+        let add: AddDevice = match kind {
+            "smart_socket" => {
+                let socket = SmartSocket::new(
+                    device_name,
+                    Arc::new(EnergyProvider {
+                        value: parsed(params, "power")?,
+                    }),
+                );
+                Box::new(move |room| room.add_unique_device(socket))
             }
-            Ok(format!(
-                "Cancel thread with name : {thread_name} - no thread to cancel"
-            ))
+            "thermometer" => {
+                let thermometer = Thermometer::new(
+                    device_name,
+                    Arc::new(TemperatureProvider {
+                        value: parsed(params, "temperature")?,
+                        measure_units: TemperatureMeasureUnits::Celsius,
+                    }),
+                );
+                Box::new(move |room| room.add_unique_device(thermometer))
+            }
+            _ => return Err(ProccessorError::BadRequestParam),
+        };
+        let room = room_name.to_string();
+        write_house_blocking(smart_house, move |smart_house| {
+            let room = smart_house
+                .get_room_mut(&room)
+                .ok_or(ProccessorError::CantFindRoom)?;
+            add(room).ok_or(ProccessorError::DeviceExists)
         })
+        .await?;
+        Ok(format!("add {kind} : {room_name}/{device_name}"))
+    }
+}
+
+pub(super) struct RemoveDeviceProcessor;
+
+#[async_trait]
+impl RequestProcessor for RemoveDeviceProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "remove_device",
+            "Remove a device with its report stream, priority and alerts",
+            &[ROOM_NAME, DEVICE_NAME],
+        );
+        &SCHEMA
+    }
+
+    async fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        _context: &RequestContext<'_>,
+    ) -> Result<String, ProccessorError> {
+        let room_name = required(params, "room_name")?;
+        let device_name = required(params, "device_name")?;
+
+        let room = room_name.to_string();
+        let device = device_name.to_string();
+        write_house_blocking(smart_house, move |smart_house| {
+            smart_house
+                .get_room_mut(&room)
+                .ok_or(ProccessorError::CantFindRoom)?
+                .remove_device(&device)
+                .ok_or(ProccessorError::CantFindDevice)?;
+            cancel_device_streams(&server, &room, std::slice::from_ref(&device));
+            forget_devices(&server, &room, Some(&device));
+            Ok::<_, ProccessorError>(())
+        })
+        .await?;
+        Ok(format!("remove device : {room_name}/{device_name}"))
+    }
+}
+
+pub(super) struct RenameProcessor;
+
+#[async_trait]
+impl RequestProcessor for RenameProcessor {
+    fn schema(&self) -> &'static CommandSchema {
+        const SCHEMA: CommandSchema = CommandSchema::new(
+            "rename",
+            "Rename a room or a device, its report streams are stopped, priorities and alerts are kept",
+            &[
+                ROOM_NAME,
+                ParamSchema::optional(
+                    "device_name",
+                    ParamType::Text,
+                    "device to rename, the room is renamed without it",
+                ),
+                ParamSchema::required("new_name", ParamType::Text, "name that is not taken yet"),
+            ],
+        );
+        &SCHEMA
+    }
+
+    async fn process(
+        &self,
+        params: &RequestParams,
+        server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        _context: &RequestContext<'_>,
+    ) -> Result<String, ProccessorError> {
+        let room_name = required(params, "room_name")?;
+        let new_name = required(params, "new_name")?;
+
+        let device_name = params.get("device_name");
+
+        let (room, new) = (room_name.to_string(), new_name.to_string());
+        let renamed = device_name.map(str::to_string);
+        write_house_blocking(smart_house, move |smart_house| {
+            let room_name = room.as_str();
+            let room = smart_house
+                .get_room_mut(room_name)
+                .ok_or(ProccessorError::CantFindRoom)?;
+            // Streams keep the name they were created with, so they would no longer be found by it
+            match renamed {
+                Some(device_name) => {
+                    if !room.contains_device(&device_name) {
+                        return Err(ProccessorError::CantFindDevice);
+                    }
+                    room.rename_device(&device_name, &new)
+                        .ok_or(ProccessorError::DeviceExists)?;
+                    cancel_device_streams(&server, room_name, std::slice::from_ref(&device_name));
+                    rename_devices(&server, room_name, Some(&device_name), &new);
+                }
+                None => {
+                    let device_names = device_names(room);
+                    smart_house
+                        .rename_room(room_name, &new)
+                        .ok_or(ProccessorError::RoomExists)?;
+                    cancel_device_streams(&server, room_name, &device_names);
+                    rename_devices(&server, room_name, None, &new);
+                }
+            }
+            Ok(())
+        })
+        .await?;
+        match device_name {
+            Some(device_name) => Ok(format!(
+                "rename device : {room_name}/{device_name} -> {new_name}"
+            )),
+            None => Ok(format!("rename room : {room_name} -> {new_name}")),
+        }
     }
 }
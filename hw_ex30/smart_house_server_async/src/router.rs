@@ -0,0 +1,95 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use my_stp_async::command_schema::{self, CommandSchema, ParamSchema, ParamType};
+use my_stp_async::custom_parser::parse_request_parameters;
+use smart_house::SmartHouse;
+
+use crate::{
+    errors::{ProccessRequestError, ProccessorError},
+    processors::{RequestContext, RequestProcessor},
+    ServerStore,
+};
+
+/// Served by the router itself, it needs the schemas of all commands
+const HELP: CommandSchema = CommandSchema::new(
+    "help",
+    "Commands and their parameters",
+    &[ParamSchema::optional(
+        "command",
+        ParamType::Text,
+        "describe only this command",
+    )],
+);
+
+/// Finds the processor of a request by the exact command name
+/// and checks the request against the schema of the processor
+pub struct Router {
+    /// In the order they are listed by help
+    processors: Vec<Box<dyn RequestProcessor>>,
+    /// Index of the processor of a command
+    by_name: HashMap<&'static str, usize>,
+}
+
+impl Router {
+    /// Panics if two processors serve the same command
+    pub fn new(processors: Vec<Box<dyn RequestProcessor>>) -> Self {
+        let mut by_name = HashMap::new();
+        for (index, processor) in processors.iter().enumerate() {
+            let name = processor.schema().name;
+            let duplicate = by_name.insert(name, index).is_some();
+            assert!(
+                !duplicate && name != HELP.name,
+                "command {name} is served twice"
+            );
+        }
+        Self {
+            processors,
+            by_name,
+        }
+    }
+
+    pub fn schemas(&self) -> impl Iterator<Item = &'static CommandSchema> + '_ {
+        std::iter::once(&HELP).chain(self.processors.iter().map(|processor| processor.schema()))
+    }
+
+    pub async fn process(
+        &self,
+        request: &str,
+        server: Arc<ServerStore>,
+        smart_house: &Arc<RwLock<SmartHouse>>,
+        context: &RequestContext<'_>,
+    ) -> Result<String, ProccessRequestError> {
+        let params = parse_request_parameters(request).map_err(ProccessorError::from)?;
+        if params.command() == HELP.name {
+            let params = HELP.validate(params).map_err(ProccessorError::from)?;
+            return self.help(params.get("command"));
+        }
+        let processor = self
+            .by_name
+            .get(params.command())
+            .map(|&index| &self.processors[index])
+            .ok_or(ProccessRequestError::CantProccessRequest)?;
+        let params = processor
+            .schema()
+            .validate(params)
+            .map_err(ProccessorError::from)?;
+        Ok(processor
+            .process(&params, server, smart_house, context)
+            .await?)
+    }
+
+    /// Usage of every command or the description of one
+    fn help(&self, command: Option<&str>) -> Result<String, ProccessRequestError> {
+        match command {
+            None => Ok(command_schema::help(self.schemas())),
+            Some(command) => self
+                .schemas()
+                .find(|schema| schema.name == command)
+                .map(CommandSchema::describe)
+                .ok_or(ProccessRequestError::CantProccessRequest),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use my_stp_async::server::SessionCloser;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant};
+
+/// How long the server binary waits for requests in flight on SIGINT or SIGTERM
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What [`crate::SmartHouseServer::shutdown`] has stopped
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ShutdownSummary {
+    /// Listeners that stopped accepting connections
+    pub listeners: usize,
+    /// Sessions that were open when the shutdown started
+    pub closed_sessions: usize,
+    pub stopped_streams: usize,
+    /// Tasks still busy when the deadline passed, they are aborted
+    pub aborted_tasks: usize,
+    pub elapsed: Duration,
+}
+
+impl ShutdownSummary {
+    /// Every task finished before the deadline
+    pub fn is_clean(&self) -> bool {
+        self.aborted_tasks == 0
+    }
+}
+
+impl Display for ShutdownSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "server stopped in {:.2}s : {} listeners, {} sessions closed, {} streams stopped, {} tasks aborted",
+            self.elapsed.as_secs_f64(),
+            self.listeners,
+            self.closed_sessions,
+            self.stopped_streams,
+            self.aborted_tasks
+        )
+    }
+}
+
+/// Join the tasks that finish before `deadline` and abort the rest,
+/// returns how many were aborted
+pub(super) async fn join_until(handles: Vec<JoinHandle<()>>, deadline: Instant) -> usize {
+    let mut aborted = 0;
+    for mut handle in handles {
+        match time::timeout_at(deadline, &mut handle).await {
+            Ok(Err(e)) if e.is_panic() => eprintln!("server task panicked"),
+            Ok(_) => {}
+            Err(_) => {
+                handle.abort();
+                aborted += 1;
+            }
+        }
+    }
+    aborted
+}
+
+#[derive(Default)]
+struct SessionsState {
+    draining: bool,
+    next_id: u64,
+    closers: HashMap<u64, SessionCloser>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+/// Sessions being served and the tasks serving them. Once draining,
+/// new sessions are refused and open ones end after their request in flight
+#[derive(Default)]
+pub(super) struct Sessions {
+    state: Mutex<SessionsState>,
+}
+
+impl Sessions {
+    /// `None` if the server is shutting down
+    pub fn register(&self, closer: SessionCloser) -> Option<u64> {
+        let mut state = self.state.lock().unwrap();
+        if state.draining {
+            return None;
+        }
+        state.next_id += 1;
+        let id = state.next_id;
+        state.closers.insert(id, closer);
+        Some(id)
+    }
+
+    pub fn unregister(&self, id: u64) {
+        self.state.lock().unwrap().closers.remove(&id);
+    }
+
+    /// Keep the task serving a connection, finished ones are forgotten
+    pub fn track(&self, task: JoinHandle<()>) {
+        let mut state = self.state.lock().unwrap();
+        state.tasks.retain(|task| !task.is_finished());
+        state.tasks.push(task);
+    }
+
+    /// Close every open session, returns how many there were and the tasks serving them
+    pub fn drain(&self) -> (usize, Vec<JoinHandle<()>>) {
+        let mut state = self.state.lock().unwrap();
+        state.draining = true;
+        for closer in state.closers.values() {
+            closer.close();
+        }
+        (state.closers.len(), std::mem::take(&mut state.tasks))
+    }
+}
+
+#[cfg(test)]
+mod shutdown_tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn busy_tasks_are_aborted_after_the_deadline() {
+        let busy = tokio::spawn(std::future::pending::<()>());
+        let handles = vec![tokio::spawn(async {}), busy];
+        let aborted = join_until(handles, Instant::now() + Duration::from_millis(50)).await;
+        assert_eq!(aborted, 1);
+    }
+}
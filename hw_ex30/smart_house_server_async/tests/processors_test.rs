@@ -0,0 +1,952 @@
+use std::time::Duration;
+
+use my_stp_async::auth::{Credentials, KeyStore};
+use my_stp_async::client::StpClient;
+use my_stp_async::errors::ConnectError;
+use my_stp_async::message::{Request, Response};
+use my_stp_async::protocol::{Features, Protocol, V2};
+
+use smart_house_testkit::{house_builder::HouseBuilder, stp::AsyncTestServer};
+
+async fn start_server() -> AsyncTestServer {
+    AsyncTestServer::start(
+        HouseBuilder::new()
+            .room("Кухня", |room| {
+                room.thermometer("Термометр1", 16.0)
+                    .socket("Розетка1", 100.0)
+            })
+            .room("Спальня", |room| room.socket("Розетка3", 50.0))
+            .build(),
+    )
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn hello() {
+    let server = start_server().await;
+    assert_eq!(server.request("hello").await, "Hello from server");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rooms_list() {
+    let server = start_server().await;
+    assert_eq!(server.request("rooms_list").await, "[Кухня,Спальня]");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn devices_list() {
+    let server = start_server().await;
+    assert_eq!(
+        server.request("devices_list room_name=Кухня").await,
+        "Кухня:[Термометр1,Розетка1]"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn device_report() {
+    let server = start_server().await;
+    let response = server
+        .request("device_report room_name=Кухня device_name=Термометр1")
+        .await;
+    assert!(response.contains("Температура: 16°C"), "{response}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn device_report_unknown_room() {
+    let server = start_server().await;
+    let response = server
+        .request("device_report room_name=Чердак device_name=Термометр1")
+        .await;
+    assert!(response.contains("CantGetReport"), "{response}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn set_device_power_state_and_is_device_on() {
+    let server = start_server().await;
+    server
+        .request("set_device_power_state room_name=Спальня device_name=Розетка3 power_state=false")
+        .await;
+    assert_eq!(
+        server
+            .request("is_device_on room_name=Спальня device_name=Розетка3")
+            .await,
+        "room_name:Спальня,device_name:Розетка3,is_on:false"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn report_stream_create_and_cancel() {
+    let server = start_server().await;
+    let mut session = server.connect().await;
+    let response = session
+        .send_request(
+            "get_device_report_stream room_name=Кухня device_name=Розетка1 request_delay=1 addr=127.0.0.1:9",
+        )
+        .await
+        .unwrap();
+    assert_eq!(response, "create thread with name : Кухня-Розетка1");
+    assert_eq!(
+        session
+            .send_request("cancel_device_report_stream stream_name=Кухня-Розетка1")
+            .await
+            .unwrap(),
+        "Cancel thread with name : Кухня-Розетка1"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn streams_are_listed_and_cancelled_only_by_their_owner() {
+    let server = start_server().await;
+    let mut owner = server.connect().await;
+    owner
+        .send_request(
+            "get_device_report_stream room_name=Кухня device_name=Розетка1 request_delay=1 addr=127.0.0.1:9",
+        )
+        .await
+        .unwrap();
+
+    let list = server.request("list_streams").await;
+    assert!(
+        list.starts_with("[name:Кухня-Розетка1,owner:session "),
+        "{list}"
+    );
+    assert!(
+        list.contains("target:udp 127.0.0.1:9,interval:1,"),
+        "{list}"
+    );
+    assert!(list.contains("expires_in:never"), "{list}");
+    assert!(server
+        .request("stream_info stream_name=Кухня-Термометр1")
+        .await
+        .contains("CantFindStream"));
+
+    for request in [
+        "cancel_device_report_stream stream_name=Кухня-Розетка1",
+        "renew_device_report_stream stream_name=Кухня-Розетка1 ttl=10",
+        "get_device_report_stream room_name=Кухня device_name=Розетка1 addr=127.0.0.1:9",
+    ] {
+        let response = server.request(request).await;
+        assert!(
+            response.contains("NotStreamOwner"),
+            "{request} : {response}"
+        );
+    }
+    assert_eq!(
+        owner
+            .send_request("cancel_device_report_stream stream_name=Кухня-Розетка1")
+            .await
+            .unwrap(),
+        "Cancel thread with name : Кухня-Розетка1"
+    );
+    assert_eq!(server.request("list_streams").await, "[]");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stream_expires_unless_renewed_and_counts_deliveries() {
+    let server = start_server().await;
+    let reports = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut owner = server.connect().await;
+    owner
+        .send_request(&format!(
+            "get_device_report_stream room_name=Кухня device_name=Розетка1 request_delay=1 ttl=1 addr={}",
+            reports.local_addr().unwrap()
+        ))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert_eq!(
+        owner
+            .send_request("renew_device_report_stream stream_name=Кухня-Розетка1 ttl=2")
+            .await
+            .unwrap(),
+        "stream Кухня-Розетка1 expires in 2"
+    );
+    tokio::time::timeout(Duration::from_secs(5), reports.recv_from(&mut [0; 1024]))
+        .await
+        .unwrap()
+        .unwrap();
+    let mut delivered = false;
+    for _ in 0..100 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let info = server
+            .request("stream_info stream_name=Кухня-Розетка1")
+            .await;
+        if !info.contains("delivered:0") {
+            delivered = true;
+            break;
+        }
+    }
+    assert!(delivered);
+
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    assert_eq!(server.request("list_streams").await, "[]");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failed_reports_are_counted_and_not_sent() {
+    let server = start_server().await;
+    server
+        .request("set_device_power_state room_name=Кухня device_name=Розетка1 power_state=false")
+        .await;
+    let reports = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    server
+        .request(&format!(
+            "get_device_report_stream room_name=Кухня device_name=Розетка1 request_delay=1 addr={}",
+            reports.local_addr().unwrap()
+        ))
+        .await;
+    // The first report is due after one interval
+    let info = server
+        .request("stream_info stream_name=Кухня-Розетка1")
+        .await;
+    assert!(info.contains("delivered:0,failed:0"), "{info}");
+
+    let received = tokio::time::timeout(
+        Duration::from_millis(1500),
+        reports.recv_from(&mut [0; 1024]),
+    )
+    .await;
+    assert!(received.is_err());
+    let info = server
+        .request("stream_info stream_name=Кухня-Розетка1")
+        .await;
+    assert!(info.contains("delivered:0,failed:1"), "{info}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn add_and_remove_rooms_and_devices() {
+    let server = start_server().await;
+    assert_eq!(
+        server.request("add_room room_name=Чердак").await,
+        "add room : Чердак"
+    );
+    assert!(server
+        .request("add_room room_name=Чердак")
+        .await
+        .contains("RoomExists"));
+    assert_eq!(
+        server
+            .request("add_device room_name=Чердак device_name=Лампа kind=smart_socket power=60")
+            .await,
+        "add smart_socket : Чердак/Лампа"
+    );
+    server
+        .request("add_device room_name=Чердак device_name=Термометр kind=thermometer temperature=5")
+        .await;
+    let response = server
+        .request("add_device room_name=Чердак device_name=Лампа kind=smart_socket power=1")
+        .await;
+    assert!(response.contains("DeviceExists"), "{response}");
+    let response = server
+        .request("add_device room_name=Чердак device_name=Обогреватель kind=smart_socket")
+        .await;
+    assert!(response.contains(r#"MissingParam("power")"#), "{response}");
+    assert_eq!(
+        server.request("devices_list room_name=Чердак").await,
+        "Чердак:[Лампа,Термометр]"
+    );
+    let response = server
+        .request("device_report room_name=Чердак device_name=Термометр")
+        .await;
+    assert!(response.contains("Температура: 5°C"), "{response}");
+
+    assert_eq!(
+        server
+            .request("remove_device room_name=Чердак device_name=Лампа")
+            .await,
+        "remove device : Чердак/Лампа"
+    );
+    assert!(server
+        .request("remove_device room_name=Чердак device_name=Лампа")
+        .await
+        .contains("CantFindDevice"));
+    assert_eq!(
+        server.request("remove_room room_name=Чердак").await,
+        "remove room : Чердак"
+    );
+    assert_eq!(server.request("rooms_list").await, "[Кухня,Спальня]");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn removing_a_device_cancels_its_stream() {
+    let server = start_server().await;
+    server.request(
+        "get_device_report_stream room_name=Кухня device_name=Розетка1 request_delay=1 addr=127.0.0.1:9",
+    ).await;
+    server.request("get_device_report_stream room_name=Кухня device_name=Термометр1 request_delay=1 addr=127.0.0.1:9").await;
+    server
+        .request("remove_device room_name=Кухня device_name=Розетка1")
+        .await;
+    assert_eq!(
+        server
+            .request("cancel_device_report_stream stream_name=Кухня-Розетка1")
+            .await,
+        "Cancel thread with name : Кухня-Розетка1 - no thread to cancel"
+    );
+    server.request("remove_room room_name=Кухня").await;
+    assert_eq!(
+        server
+            .request("cancel_device_report_stream stream_name=Кухня-Термометр1")
+            .await,
+        "Cancel thread with name : Кухня-Термометр1 - no thread to cancel"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rename_rooms_and_devices() {
+    let server = start_server().await;
+    assert_eq!(
+        server
+            .request("rename room_name=Кухня device_name=Розетка1 new_name=Чайник")
+            .await,
+        "rename device : Кухня/Розетка1 -> Чайник"
+    );
+    let response = server
+        .request("rename room_name=Кухня device_name=Чайник new_name=Термометр1")
+        .await;
+    assert!(response.contains("DeviceExists"), "{response}");
+    let response = server
+        .request("rename room_name=Кухня device_name=Розетка1 new_name=Лампа")
+        .await;
+    assert!(response.contains("CantFindDevice"), "{response}");
+    assert_eq!(
+        server
+            .request("rename room_name=Кухня new_name=Столовая")
+            .await,
+        "rename room : Кухня -> Столовая"
+    );
+    assert!(server
+        .request("rename room_name=Столовая new_name=Спальня")
+        .await
+        .contains("RoomExists"));
+    assert_eq!(
+        server.request("devices_list room_name=Столовая").await,
+        "Столовая:[Термометр1,Чайник]"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn removed_device_leaves_no_priority_or_alerts_behind() {
+    let server = start_server().await;
+    server
+        .request("set_device_priority room_name=Кухня device_name=Розетка1 priority=10")
+        .await;
+    server
+        .request("set_device_priority room_name=Спальня device_name=Розетка3 priority=20")
+        .await;
+    server.request(
+        "add_alert name=watts room_name=Спальня device_name=Розетка3 metric=power condition=above threshold=1000",
+    ).await;
+    server
+        .request("remove_device room_name=Спальня device_name=Розетка3")
+        .await;
+    server
+        .request("add_device room_name=Спальня device_name=Розетка3 kind=smart_socket power=50")
+        .await;
+
+    // The new device under the old name starts with the default priority and no alerts
+    assert!(server
+        .request("remove_alert name=watts")
+        .await
+        .contains("CantFindAlert"));
+    let response = server
+        .request("set_power_budget max_power=120 cooldown=60")
+        .await;
+    assert!(response.contains("shed:[Спальня/Розетка3]"), "{response}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn removed_device_stops_only_its_own_stream() {
+    let server = start_server().await;
+    server.request("add_room room_name=Дом-Сад").await;
+    server
+        .request("add_device room_name=Дом-Сад device_name=Лампа kind=smart_socket power=60")
+        .await;
+    server.request("add_room room_name=Дом").await;
+    server
+        .request("add_device room_name=Дом device_name=Сад-Лампа kind=smart_socket power=60")
+        .await;
+    for (room_name, device_name) in [("Дом-Сад", "Лампа"), ("Дом", "Сад-Лампа")]
+    {
+        server
+            .request(&format!(
+                "get_device_report_stream room_name={room_name} device_name={device_name} addr=127.0.0.1:9"
+            ))
+            .await;
+    }
+
+    server
+        .request("remove_device room_name=Дом device_name=Сад-Лампа")
+        .await;
+    let streams = server.request("list_streams").await;
+    assert!(streams.contains(r"name:Дом\-Сад-Лампа,"), "{streams}");
+    assert!(!streams.contains(r"name:Дом-Сад\-Лампа,"), "{streams}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn renamed_device_keeps_priority_and_alerts() {
+    let server = start_server().await;
+    server
+        .request("set_device_priority room_name=Кухня device_name=Розетка1 priority=10")
+        .await;
+    server
+        .request("set_device_priority room_name=Спальня device_name=Розетка3 priority=5")
+        .await;
+    server.request(
+        "add_alert name=hot room_name=Кухня device_name=Термометр1 metric=temperature condition=above threshold=10",
+    ).await;
+    server
+        .request("rename room_name=Кухня device_name=Розетка1 new_name=Чайник")
+        .await;
+    server
+        .request("rename room_name=Кухня new_name=Столовая")
+        .await;
+
+    let response = server
+        .request("set_power_budget max_power=120 cooldown=60")
+        .await;
+    assert!(response.contains("shed:[Спальня/Розетка3]"), "{response}");
+    let mut raised = false;
+    for _ in 0..50 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        if server
+            .request("active_alerts")
+            .await
+            .contains("hot [warning] Столовая/Термометр1")
+        {
+            raised = true;
+            break;
+        }
+    }
+    assert!(raised, "{}", server.request("active_alerts").await);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn report_stream_is_pushed_and_stops_with_the_session() {
+    let server = start_server().await;
+    let mut connection = server.connect().await;
+    assert_eq!(
+        connection
+            .send_request(
+                "get_device_report_stream room_name=Кухня device_name=Розетка1 request_delay=1"
+            )
+            .await
+            .unwrap(),
+        "create thread with name : Кухня-Розетка1"
+    );
+    let push = connection
+        .recv_push(Some(Duration::from_secs(5)))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((push.seq, push.topic.as_str()), (1, "Кухня-Розетка1"));
+    assert!(!push.body.is_empty());
+
+    connection.close().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    assert_eq!(
+        server
+            .request("cancel_device_report_stream stream_name=Кухня-Розетка1")
+            .await,
+        "Cancel thread with name : Кухня-Розетка1 - no thread to cancel"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn report_stream_without_addr_needs_push() {
+    let server = start_server().await;
+    let mut connection = StpClient::connect_with(
+        server.addr(),
+        Protocol {
+            version: V2,
+            features: Features::JSON,
+        },
+    )
+    .await
+    .unwrap();
+    let response = connection
+        .send_request("get_device_report_stream room_name=Кухня device_name=Розетка1")
+        .await
+        .unwrap();
+    assert!(response.contains("PushNotNegotiated"), "{response}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unknown_command() {
+    let server = start_server().await;
+    assert!(server
+        .request("reboot")
+        .await
+        .contains("CantProccessRequest"));
+    assert!(server
+        .request("hello_world")
+        .await
+        .contains("CantProccessRequest"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn power_budget_takes_only_finite_non_negative_limits() {
+    let server = start_server().await;
+    for max_power in ["NaN", "inf", "-5"] {
+        let response = server
+            .request(&format!("set_power_budget max_power={max_power}"))
+            .await;
+        assert!(
+            response.contains(r#"InvalidParam { name: "max_power""#),
+            "{response}"
+        );
+    }
+    let response = server.request("set_power_budget max_power=0").await;
+    assert!(response.contains("max_power:0 Вт"), "{response}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn missing_and_invalid_params_are_named() {
+    let server = start_server().await;
+    let response = server.request("device_report room_name=Кухня").await;
+    assert!(
+        response.contains(r#"MissingParam("device_name")"#),
+        "{response}"
+    );
+    let response = server
+        .request("get_device_report_stream room_name=Кухня device_name=Розетка1 request_delay=soon")
+        .await;
+    assert!(
+        response.contains(r#"InvalidParam { name: "request_delay", value: "soon""#),
+        "{response}"
+    );
+    let mut connection = server.connect().await;
+    let response = connection
+        .send_typed(&Request::command("devices_list"))
+        .await
+        .unwrap();
+    assert!(
+        matches!(&response, Response::Error { code, .. } if code == "MissingParam"),
+        "{response:?}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn help_is_generated_from_schemas() {
+    let server = start_server().await;
+    let help = server.request("help").await;
+    assert!(
+        help.contains("devices_list room_name=<string>\n  Names of the devices in a room"),
+        "{help}"
+    );
+    let help = server.request("help command=add_alert").await;
+    assert!(
+        help.contains("severity: severity of the alert, default warning"),
+        "{help}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn power_budget_sheds_lowest_priority() {
+    let server = start_server().await;
+    server
+        .request("set_device_priority room_name=Кухня device_name=Розетка1 priority=10")
+        .await;
+    let response = server
+        .request("set_power_budget max_power=120 cooldown=60")
+        .await;
+    assert!(response.contains("shed:[Спальня/Розетка3]"), "{response}");
+    assert_eq!(
+        server
+            .request("is_device_on room_name=Спальня device_name=Розетка3")
+            .await,
+        "room_name:Спальня,device_name:Розетка3,is_on:false"
+    );
+    assert!(server
+        .request("power_budget_report")
+        .await
+        .contains("turned off Спальня/Розетка3"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn alert_is_raised_and_pushed_to_subscribers() {
+    let server = start_server().await;
+    let subscriber = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = subscriber.local_addr().unwrap();
+
+    server
+        .request(&format!("subscribe_alerts addr={addr}"))
+        .await;
+    let response = server
+        .request("add_alert name=hot room_name=Кухня device_name=Термометр1 metric=temperature condition=above threshold=10 severity=critical")
+        .await;
+    assert!(
+        response.starts_with("add alert : hot [critical]"),
+        "{response}"
+    );
+
+    let mut buf = [0u8; 1024];
+    let size = tokio::time::timeout(Duration::from_secs(5), subscriber.recv(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    let message = String::from_utf8_lossy(&buf[..size]);
+    assert_eq!(
+        message,
+        "alert raised: hot [critical] Кухня/Термометр1 value 16"
+    );
+
+    assert!(server
+        .request("active_alerts")
+        .await
+        .contains("hot [critical]"));
+    assert!(server
+        .request("alerts_history")
+        .await
+        .contains("alert raised: hot"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn add_alert_validates_params() {
+    let server = start_server().await;
+    let response = server
+        .request("add_alert name=hot room_name=Кухня device_name=Нет metric=temperature condition=above threshold=10")
+        .await;
+    assert!(response.contains("CantFindDevice"), "{response}");
+    let response = server
+        .request("add_alert name=hot room_name=Кухня device_name=Термометр1 metric=humidity condition=above threshold=10")
+        .await;
+    assert!(
+        response.contains(r#"InvalidParam { name: "metric", value: "humidity""#),
+        "{response}"
+    );
+    assert!(server
+        .request("remove_alert name=hot")
+        .await
+        .contains("CantFindAlert"));
+
+    let alert = "add_alert name=hot room_name=Кухня device_name=Термометр1 metric=temperature condition=above threshold=10";
+    assert!(server.request(alert).await.starts_with("add alert : hot"));
+    assert!(server.request(alert).await.contains("AlertExists"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn set_group_power_state_by_room_and_kind() {
+    let server = start_server().await;
+    assert_eq!(
+        server
+            .request("set_group_power_state room_name=Кухня power_state=false")
+            .await,
+        "[room_name:Кухня,device_name:Термометр1,is_on:false;room_name:Кухня,device_name:Розетка1,is_on:false]"
+    );
+    assert_eq!(
+        server
+            .request("set_group_power_state kind=smart_socket power_state=toggle")
+            .await,
+        "[room_name:Кухня,device_name:Розетка1,is_on:true;room_name:Спальня,device_name:Розетка3,is_on:false]"
+    );
+    assert!(server
+        .request("set_group_power_state room_name=Гараж power_state=true")
+        .await
+        .contains("CantFindRoom"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn many_requests_over_one_session() {
+    let server = start_server().await;
+    let mut connection = server.connect().await;
+    assert_eq!(
+        connection.send_request("hello").await.unwrap(),
+        "Hello from server"
+    );
+    assert_eq!(
+        connection.send_request("rooms_list").await.unwrap(),
+        "[Кухня,Спальня]"
+    );
+    // Another client is served while the first session stays open
+    assert_eq!(server.request("hello").await, "Hello from server");
+    connection.close().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sessions_are_served_in_parallel() {
+    let server = start_server().await;
+    let mut sessions = Vec::new();
+    for _ in 0..4 {
+        sessions.push(server.connect().await);
+    }
+    let tasks = sessions.into_iter().map(|mut session| {
+        tokio::spawn(async move {
+            for _ in 0..20 {
+                assert_eq!(
+                    session.send_request("rooms_list").await.unwrap(),
+                    "[Кухня,Спальня]"
+                );
+            }
+            session.close().await.unwrap();
+        })
+    });
+    for task in tasks.collect::<Vec<_>>() {
+        task.await.unwrap();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn silent_client_does_not_block_new_connections() {
+    let mut server = start_server().await;
+    // Connects but never starts the handshake
+    let _silent = tokio::net::TcpStream::connect(server.addr()).await.unwrap();
+    let started = std::time::Instant::now();
+    assert_eq!(server.request("hello").await, "Hello from server");
+    assert!(started.elapsed() < Duration::from_secs(2));
+    // Its handshake is ended by the shutdown instead of running into the timeout
+    assert!(server.shutdown(Duration::from_secs(2)).await.is_clean());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutdown_closes_sessions_and_stops_streams() {
+    let mut server = start_server().await;
+    let mut session = server.connect().await;
+    assert!(session
+        .send_request(
+            "get_device_report_stream room_name=Кухня device_name=Розетка1 request_delay=60 addr=127.0.0.1:9"
+        )
+        .await
+        .unwrap()
+        .contains("Кухня-Розетка1"));
+
+    let summary = server.shutdown(Duration::from_secs(5)).await;
+    assert_eq!(summary.listeners, 1);
+    assert_eq!(summary.closed_sessions, 1);
+    assert_eq!(summary.stopped_streams, 1);
+    assert!(summary.is_clean(), "{summary}");
+    // The stream task is woken up, not waited for
+    assert!(summary.elapsed < Duration::from_secs(5), "{summary}");
+    assert!(session.send_request("hello").await.is_err());
+    assert_eq!(server.shutdown(Duration::ZERO).await.listeners, 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn typed_request_gets_error_code() {
+    let server = start_server().await;
+    let mut connection = server.connect().await;
+    let response = connection
+        .send_typed(
+            &Request::command("is_device_on")
+                .param("room_name", "Гараж")
+                .param("device_name", "Розетка1"),
+        )
+        .await
+        .unwrap();
+    assert!(
+        matches!(&response, Response::Error { code, .. } if code == "CantFindRoom"),
+        "{response:?}"
+    );
+    assert_eq!(
+        connection
+            .send_typed(&Request::command("hello"))
+            .await
+            .unwrap(),
+        Response::Ok("Hello from server".to_string())
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn authenticated_client_is_greeted_by_identity() {
+    let server = AsyncTestServer::start_with_key_store(
+        HouseBuilder::new().build(),
+        Some(KeyStore::parse("alice:secret").unwrap()),
+    )
+    .await;
+    let mut connection =
+        StpClient::connect_authenticated(server.addr(), &Credentials::new("alice", b"secret"))
+            .await
+            .unwrap();
+    assert_eq!(
+        connection.send_request("hello").await.unwrap(),
+        "Hello alice from server"
+    );
+    assert!(matches!(
+        StpClient::connect(server.addr()).await,
+        Err(ConnectError::AuthenticationRequired)
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn quoted_room_name_with_spaces() {
+    let server = AsyncTestServer::start(
+        HouseBuilder::new()
+            .room("Детская комната", |room| {
+                room.socket("Розетка 2", 20.0)
+            })
+            .build(),
+    )
+    .await;
+    assert_eq!(
+        server
+            .request(r#"devices_list room_name="Детская комната""#)
+            .await,
+        "Детская комната:[Розетка 2]"
+    );
+    let mut connection = server.connect().await;
+    let response = connection
+        .send_typed(
+            &Request::command("is_device_on")
+                .param("room_name", "Детская комната")
+                .param("device_name", "Розетка 2"),
+        )
+        .await
+        .unwrap();
+    assert!(matches!(response, Response::Ok(_)), "{response:?}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn malformed_request_is_reported_with_position() {
+    let server = start_server().await;
+    let response = server.request(r#"devices_list room_name="Кухня"#).await;
+    assert!(
+        response.contains("MalformedRequest(UnterminatedQuote { position: 23 })"),
+        "{response}"
+    );
+    let response = server.request("devices_list room_name=a=b").await;
+    assert!(response.contains("CantFindRoom"), "{response}");
+    let mut connection = server.connect().await;
+    let response = connection
+        .send_typed(&Request::Text("is_device_on room_name".to_string()))
+        .await
+        .unwrap();
+    assert!(
+        matches!(&response, Response::Error { code, .. } if code == "MalformedRequest"),
+        "{response:?}"
+    );
+    assert_eq!(server.request("hello").await, "Hello from server");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn json_rpc_and_command_lines_share_a_session() {
+    let server = start_server().await;
+    let mut connection = server.connect().await;
+    let rooms = r#"{"jsonrpc":"2.0","method":"rooms_list","id":1}"#;
+    assert_eq!(
+        connection.send_request(rooms).await.unwrap(),
+        r#"{"id":1,"jsonrpc":"2.0","result":"[Кухня,Спальня]"}"#
+    );
+    assert_eq!(
+        connection
+            .send_request("devices_list room_name=Спальня")
+            .await
+            .unwrap(),
+        "Спальня:[Розетка3]"
+    );
+    let batch = r#"[
+        {"jsonrpc":"2.0","method":"set_device_power_state",
+         "params":{"room_name":"Кухня","device_name":"Розетка1","power_state":false}},
+        {"jsonrpc":"2.0","method":"is_device_on",
+         "params":{"room_name":"Кухня","device_name":"Розетка1"},"id":"on"},
+        {"jsonrpc":"2.0","method":"devices_list","params":{"room_name":"Гараж"},"id":2},
+        {"jsonrpc":"2.0","method":"open_garage","id":3}
+    ]"#;
+    assert_eq!(
+        connection.send_request(batch).await.unwrap(),
+        concat!(
+            r#"[{"id":"on","jsonrpc":"2.0","result":"room_name:Кухня,device_name:Розетка1,is_on:false"},"#,
+            r#"{"error":{"code":-32001,"data":"CantFindRoom","message":"Cant find room"},"id":2,"jsonrpc":"2.0"},"#,
+            r#"{"error":{"code":-32601,"data":"CantProccessRequest","message":"Cant proccess request"},"id":3,"jsonrpc":"2.0"}]"#
+        )
+    );
+    let notification = r#"{"jsonrpc":"2.0","method":"hello"}"#;
+    assert_eq!(connection.send_request(notification).await.unwrap(), "");
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn same_house_over_tcp_and_unix_socket() {
+    use my_stp_async::socket_file::UnixSocketOptions;
+    use smart_house_server_async::SmartHouseServer;
+
+    let path = std::env::temp_dir().join(format!("smart-house-async-{}.sock", std::process::id()));
+    let mut server = SmartHouseServer::with_smart_house(
+        HouseBuilder::new()
+            .room("Кухня", |room| room.socket("Розетка1", 100.0))
+            .build(),
+        "127.0.0.1:0",
+        "127.0.0.1:0",
+    )
+    .await
+    .unwrap();
+    server
+        .set_key_store(Some(KeyStore::parse("alice:secret").unwrap()))
+        .unwrap();
+    server
+        .listen_unix(&path, UnixSocketOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(server.socket_path(), Some(path.as_path()));
+    server.start_server_listening();
+
+    let credentials = Credentials::new("alice", b"secret");
+    let mut tcp = StpClient::connect_authenticated(server.local_addr(), &credentials)
+        .await
+        .unwrap();
+    let mut unix = StpClient::connect_unix_authenticated(&path, &credentials)
+        .await
+        .unwrap();
+    unix.send_request(
+        "set_device_power_state room_name=Кухня device_name=Розетка1 power_state=false",
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        tcp.send_request("is_device_on room_name=Кухня device_name=Розетка1")
+            .await
+            .unwrap(),
+        "room_name:Кухня,device_name:Розетка1,is_on:false"
+    );
+    assert!(matches!(
+        StpClient::connect_unix(&path).await,
+        Err(ConnectError::AuthenticationRequired)
+    ));
+    tokio::task::block_in_place(move || drop(server));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+async fn house_edit_behind_a_slow_device_does_not_stall_other_clients() {
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use smart_house_testkit::providers::ScriptedPowerProvider;
+
+    let delay = Duration::from_millis(600);
+    let server = AsyncTestServer::start(
+        HouseBuilder::new()
+            .room("Кухня", |room| {
+                room.socket_with(
+                    "Розетка1",
+                    Arc::new(ScriptedPowerProvider::constant(100.0).with_delay(delay)),
+                )
+            })
+            .build(),
+    )
+    .await;
+
+    let mut report_client = server.connect().await;
+    let report = tokio::spawn(async move {
+        report_client
+            .send_request("power_budget_report")
+            .await
+            .unwrap()
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    // Waits for the report to release the house
+    let mut edit_client = server.connect().await;
+    let edit = tokio::spawn(async move {
+        edit_client
+            .send_request("add_room room_name=Чердак")
+            .await
+            .unwrap()
+    });
+    let started = Instant::now();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(server.request("hello").await, "Hello from server");
+    assert!(started.elapsed() < Duration::from_millis(400));
+
+    eprintln!("DBG hello {:?}", started.elapsed());
+    eprintln!("DBG report {:?}", report.await.unwrap());
+    eprintln!("DBG after {:?}", started.elapsed());
+    assert_eq!(edit.await.unwrap(), "add room : Чердак");
+}
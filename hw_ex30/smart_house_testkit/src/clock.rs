@@ -0,0 +1,56 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use smart_house::clock::Clock;
+
+/// Clock that only moves when the test tells it to
+pub struct FakeClock {
+    start: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+impl FakeClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Default for FakeClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+}
+
+#[cfg(test)]
+mod clock_tests {
+    use super::*;
+
+    #[test]
+    fn test_fake_clock_advance() {
+        let clock = FakeClock::new();
+        let before = clock.now();
+        assert_eq!(clock.now(), before);
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(clock.now() - before, Duration::from_secs(30));
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::{Arc, RwLock};
+
+use smart_house::{
+    device::Device,
+    smart_tools::{
+        smart_socket::{SmartSocket, SmartSocketInfoProvider},
+        thermomener::{Thermometer, ThermometerInfoProvider},
+    },
+    Room, SmartHouse,
+};
+
+use crate::providers::{ScriptedPowerProvider, ScriptedTemperatureProvider};
+
+/// Builds a [`SmartHouse`] for tests
+///
+/// ```
+/// use smart_house_testkit::house_builder::HouseBuilder;
+///
+/// let house = HouseBuilder::new()
+///     .room("Кухня", |room| {
+///         room.thermometer("Термометр1", 16.0)
+///             .socket("Розетка1", 100.0)
+///     })
+///     .build();
+/// assert_eq!(house.devices().len(), 2);
+/// ```
+#[derive(Default)]
+pub struct HouseBuilder {
+    rooms: Vec<Room>,
+}
+
+impl HouseBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn room<F>(mut self, name: &str, fill: F) -> Self
+    where
+        F: FnOnce(RoomBuilder) -> RoomBuilder,
+    {
+        self.rooms.push(fill(RoomBuilder::new(name)).build());
+        self
+    }
+
+    pub fn build(self) -> SmartHouse {
+        SmartHouse::new(self.rooms)
+    }
+}
+
+pub struct RoomBuilder {
+    name: String,
+    devices: Vec<Arc<RwLock<Box<dyn Device>>>>,
+}
+
+impl RoomBuilder {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            devices: vec![],
+        }
+    }
+
+    pub fn device(mut self, device: impl Device + 'static) -> Self {
+        self.devices.push(Arc::new(RwLock::new(Box::new(device))));
+        self
+    }
+
+    /// Thermometer with a constant temperature in Celsius
+    pub fn thermometer(self, name: &str, value: f32) -> Self {
+        self.thermometer_with(name, Arc::new(ScriptedTemperatureProvider::constant(value)))
+    }
+
+    pub fn thermometer_with(self, name: &str, provider: Arc<dyn ThermometerInfoProvider>) -> Self {
+        self.device(Thermometer::new(name, provider))
+    }
+
+    /// Smart socket with a constant power consumption in watts
+    pub fn socket(self, name: &str, power: f32) -> Self {
+        self.socket_with(name, Arc::new(ScriptedPowerProvider::constant(power)))
+    }
+
+    pub fn socket_with(self, name: &str, provider: Arc<dyn SmartSocketInfoProvider>) -> Self {
+        self.device(SmartSocket::new(name, provider))
+    }
+
+    pub fn build(self) -> Room {
+        Room::new(self.name, self.devices)
+    }
+}
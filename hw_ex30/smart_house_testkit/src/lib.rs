@@ -0,0 +1,6 @@
+//! Test doubles and helpers shared by the smart house crates
+
+pub mod clock;
+pub mod house_builder;
+pub mod providers;
+pub mod stp;
@@ -0,0 +1,231 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use smart_house::{
+    smart_tools::{
+        smart_socket::{AsyncSmartSocketInfoProvider, SmartSocketInfoProvider},
+        thermomener::{AsyncThermometerInfoProvider, ThermometerInfoProvider},
+    },
+    temperature::{Temperature, TemperatureMeasureUnits},
+};
+
+/// One scripted answer of a mock provider
+#[derive(Debug, Clone)]
+pub enum Reading<T> {
+    Value(T),
+    Failure(String),
+}
+
+/// Replays readings in order. The last reading is repeated once the script is exhausted
+struct Script<T: Clone> {
+    readings: Mutex<VecDeque<Reading<T>>>,
+    calls: AtomicUsize,
+    delay: Duration,
+}
+
+impl<T: Clone> Script<T> {
+    fn new(readings: Vec<Reading<T>>) -> Self {
+        assert!(
+            !readings.is_empty(),
+            "script must contain at least one reading"
+        );
+        Self {
+            readings: Mutex::new(readings.into()),
+            calls: AtomicUsize::new(0),
+            delay: Duration::ZERO,
+        }
+    }
+
+    fn next(&self) -> Reading<T> {
+        self.calls.fetch_add(1, Ordering::AcqRel);
+        if !self.delay.is_zero() {
+            thread::sleep(self.delay);
+        }
+        let mut readings = self.readings.lock().unwrap();
+        if readings.len() > 1 {
+            return readings.pop_front().unwrap();
+        }
+        readings.front().cloned().unwrap()
+    }
+
+    fn push(&self, reading: Reading<T>) {
+        self.readings.lock().unwrap().push_back(reading);
+    }
+
+    fn calls(&self) -> usize {
+        self.calls.load(Ordering::Acquire)
+    }
+}
+
+/// Thermometer provider answering with a scripted sequence of readings.
+/// Failures become errors for async callers and NaN for sync callers
+pub struct ScriptedTemperatureProvider {
+    script: Script<f32>,
+    measure_units: TemperatureMeasureUnits,
+}
+
+impl ScriptedTemperatureProvider {
+    pub fn new(readings: Vec<Reading<f32>>) -> Self {
+        Self {
+            script: Script::new(readings),
+            measure_units: TemperatureMeasureUnits::Celsius,
+        }
+    }
+
+    pub fn constant(value: f32) -> Self {
+        Self::new(vec![Reading::Value(value)])
+    }
+
+    pub fn from_values(values: &[f32]) -> Self {
+        Self::new(values.iter().copied().map(Reading::Value).collect())
+    }
+
+    /// Every reading blocks the calling thread for `delay`, like a slow device
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.script.delay = delay;
+        self
+    }
+
+    pub fn push(&self, reading: Reading<f32>) {
+        self.script.push(reading);
+    }
+
+    /// How many times the provider was asked for a reading
+    pub fn calls(&self) -> usize {
+        self.script.calls()
+    }
+
+    fn next_temperature(&self) -> Result<Temperature, String> {
+        match self.script.next() {
+            Reading::Value(value) => Ok(Temperature::new(value, self.measure_units)),
+            Reading::Failure(message) => Err(message),
+        }
+    }
+}
+
+impl ThermometerInfoProvider for ScriptedTemperatureProvider {
+    fn get_temperature(&self) -> Temperature {
+        self.next_temperature()
+            .unwrap_or(Temperature::new(f32::NAN, self.measure_units))
+    }
+}
+
+#[async_trait]
+impl AsyncThermometerInfoProvider for ScriptedTemperatureProvider {
+    async fn get_temperature(
+        &self,
+    ) -> Result<Temperature, Box<dyn std::error::Error + Send + Sync>> {
+        self.next_temperature().map_err(|message| message.into())
+    }
+}
+
+/// Smart socket provider answering with a scripted sequence of readings.
+/// Failures become errors for async callers and NaN for sync callers
+pub struct ScriptedPowerProvider {
+    script: Script<f32>,
+}
+
+impl ScriptedPowerProvider {
+    pub fn new(readings: Vec<Reading<f32>>) -> Self {
+        Self {
+            script: Script::new(readings),
+        }
+    }
+
+    pub fn constant(value: f32) -> Self {
+        Self::new(vec![Reading::Value(value)])
+    }
+
+    pub fn from_values(values: &[f32]) -> Self {
+        Self::new(values.iter().copied().map(Reading::Value).collect())
+    }
+
+    /// Every reading blocks the calling thread for `delay`, like a slow device
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.script.delay = delay;
+        self
+    }
+
+    pub fn push(&self, reading: Reading<f32>) {
+        self.script.push(reading);
+    }
+
+    /// How many times the provider was asked for a reading
+    pub fn calls(&self) -> usize {
+        self.script.calls()
+    }
+
+    fn next_power(&self) -> Result<f32, String> {
+        match self.script.next() {
+            Reading::Value(value) => Ok(value),
+            Reading::Failure(message) => Err(message),
+        }
+    }
+}
+
+impl SmartSocketInfoProvider for ScriptedPowerProvider {
+    fn get_current_power_consumption(&self) -> f32 {
+        self.next_power().unwrap_or(f32::NAN)
+    }
+}
+
+#[async_trait]
+impl AsyncSmartSocketInfoProvider for ScriptedPowerProvider {
+    async fn get_current_power_consumption(
+        &self,
+    ) -> Result<f32, Box<dyn std::error::Error + Send + Sync>> {
+        self.next_power().map_err(|message| message.into())
+    }
+}
+
+#[cfg(test)]
+mod providers_tests {
+    use super::*;
+
+    #[test]
+    fn test_scripted_readings_repeat_last() {
+        let provider = ScriptedPowerProvider::from_values(&[1.0, 2.0]);
+        assert_eq!(
+            SmartSocketInfoProvider::get_current_power_consumption(&provider),
+            1.0
+        );
+        assert_eq!(
+            SmartSocketInfoProvider::get_current_power_consumption(&provider),
+            2.0
+        );
+        assert_eq!(
+            SmartSocketInfoProvider::get_current_power_consumption(&provider),
+            2.0
+        );
+        assert_eq!(provider.calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_scripted_failure() {
+        let provider = ScriptedTemperatureProvider::new(vec![
+            Reading::Failure("sensor offline".to_string()),
+            Reading::Value(20.0),
+        ]);
+        let result = AsyncThermometerInfoProvider::get_temperature(&provider).await;
+        assert_eq!(result.unwrap_err().to_string(), "sensor offline");
+        let result = AsyncThermometerInfoProvider::get_temperature(&provider).await;
+        assert_eq!(result.unwrap().get_value(), 20.0);
+    }
+
+    #[test]
+    fn test_scripted_failure_is_nan_for_sync_callers() {
+        let provider =
+            ScriptedTemperatureProvider::new(vec![Reading::Failure("sensor offline".to_string())]);
+        assert!(ThermometerInfoProvider::get_temperature(&provider)
+            .get_value()
+            .is_nan());
+    }
+}
@@ -0,0 +1,174 @@
+use std::{net::SocketAddr, thread};
+
+use smart_house::SmartHouse;
+
+const EPHEMERAL_ADDR: &str = "127.0.0.1:0";
+
+/// Start an STP server on an ephemeral port that answers every request with `handler`,
+/// serving connections one after another. Returns its address and a connected client
+pub fn start_stp_server<F>(handler: F) -> (SocketAddr, my_stp::client::StpConnection)
+where
+    F: Fn(String) -> String + Send + 'static,
+{
+    let server = my_stp::server::StpServer::bind(EPHEMERAL_ADDR).unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || loop {
+        if let Ok(connection) = server.accept() {
+            let _ = connection.serve(&handler);
+        }
+    });
+    let client = my_stp::client::StpClient::connect(addr).unwrap();
+    (addr, client)
+}
+
+/// Async counterpart of [`start_stp_server`]
+pub async fn start_async_stp_server<F>(
+    handler: F,
+) -> (SocketAddr, my_stp_async::client::StpConnection)
+where
+    F: Fn(String) -> String + Send + Sync + 'static,
+{
+    let server = my_stp_async::server::StpServer::bind(EPHEMERAL_ADDR)
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            if let Ok(connection) = server.accept().await {
+                let _ = connection
+                    .serve(|request| std::future::ready(handler(request)))
+                    .await;
+            }
+        }
+    });
+    let client = my_stp_async::client::StpClient::connect(addr)
+        .await
+        .unwrap();
+    (addr, client)
+}
+
+/// Running [`smart_house_server::SmartHouseServer`] bound to ephemeral ports
+pub struct TestServer {
+    server: smart_house_server::SmartHouseServer,
+    addr: SocketAddr,
+}
+
+impl TestServer {
+    pub fn start(smart_house: SmartHouse) -> Self {
+        Self::start_with_key_store(smart_house, None)
+    }
+
+    /// Server that requires clients to authenticate with one of the keys
+    pub fn start_with_key_store(
+        smart_house: SmartHouse,
+        key_store: Option<my_stp::auth::KeyStore>,
+    ) -> Self {
+        let mut server = smart_house_server::SmartHouseServer::with_smart_house(
+            smart_house,
+            EPHEMERAL_ADDR,
+            EPHEMERAL_ADDR,
+        )
+        .unwrap();
+        server.set_key_store(key_store).unwrap();
+        let addr = server.local_addr().unwrap();
+        server.start_server_listening();
+        Self { server, addr }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn server(&self) -> &smart_house_server::SmartHouseServer {
+        &self.server
+    }
+
+    pub fn connect(&self) -> my_stp::client::StpConnection {
+        my_stp::client::StpClient::connect(self.addr).unwrap()
+    }
+
+    /// Send a single request over a fresh connection
+    pub fn request(&self, request: &str) -> String {
+        self.connect().send_request(request).unwrap()
+    }
+
+    pub fn shutdown(
+        &mut self,
+        timeout: std::time::Duration,
+    ) -> smart_house_server::shutdown::ShutdownSummary {
+        self.server.shutdown(timeout)
+    }
+}
+
+/// Running [`smart_house_server_async::SmartHouseServer`] bound to ephemeral ports
+pub struct AsyncTestServer {
+    server: smart_house_server_async::SmartHouseServer,
+    addr: SocketAddr,
+}
+
+impl AsyncTestServer {
+    pub async fn start(smart_house: SmartHouse) -> Self {
+        Self::start_with_key_store(smart_house, None).await
+    }
+
+    /// Server that requires clients to authenticate with one of the keys
+    pub async fn start_with_key_store(
+        smart_house: SmartHouse,
+        key_store: Option<my_stp_async::auth::KeyStore>,
+    ) -> Self {
+        let mut server = smart_house_server_async::SmartHouseServer::with_smart_house(
+            smart_house,
+            EPHEMERAL_ADDR,
+            EPHEMERAL_ADDR,
+        )
+        .await
+        .unwrap();
+        server.set_key_store(key_store).unwrap();
+        let addr = server.local_addr();
+        server.start_server_listening();
+        Self { server, addr }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn server(&self) -> &smart_house_server_async::SmartHouseServer {
+        &self.server
+    }
+
+    pub async fn connect(&self) -> my_stp_async::client::StpConnection {
+        my_stp_async::client::StpClient::connect(self.addr)
+            .await
+            .unwrap()
+    }
+
+    /// Send a single request over a fresh connection
+    pub async fn request(&self, request: &str) -> String {
+        self.connect().await.send_request(request).await.unwrap()
+    }
+
+    pub async fn shutdown(
+        &mut self,
+        timeout: std::time::Duration,
+    ) -> smart_house_server_async::shutdown::ShutdownSummary {
+        self.server.shutdown(timeout).await
+    }
+}
+
+#[cfg(test)]
+mod stp_tests {
+    use super::*;
+
+    #[test]
+    fn test_start_stp_server() {
+        let (_, mut client) = start_stp_server(|request| format!("echo {request}"));
+        assert_eq!(client.send_request("ping").unwrap(), "echo ping");
+    }
+
+    #[tokio::test]
+    async fn test_start_async_stp_server() {
+        let (_, mut client) = start_async_stp_server(|request| format!("echo {request}")).await;
+        assert_eq!(client.send_request("ping").await.unwrap(), "echo ping");
+    }
+}
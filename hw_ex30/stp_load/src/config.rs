@@ -0,0 +1,228 @@
+use std::{net::SocketAddr, time::Duration};
+
+use my_stp_async::auth::Credentials;
+
+use crate::errors::ConfigError;
+
+pub const DEFAULT_CONNECTIONS: usize = 8;
+pub const DEFAULT_DURATION: Duration = Duration::from_secs(10);
+pub const DEFAULT_ROOM: &str = "Кухня";
+pub const DEFAULT_DEVICE: &str = "Розетка1";
+
+/// Operation a connection performs on its turn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadCommand {
+    RoomsList,
+    DeviceReport,
+    /// Turns the device on and off in turns
+    SetPowerState,
+    /// Subscribes to pushed reports of the device and cancels the stream right away
+    Stream,
+}
+
+impl LoadCommand {
+    fn parse(name: &str) -> Result<Self, ConfigError> {
+        match name {
+            "rooms_list" => Ok(LoadCommand::RoomsList),
+            "device_report" => Ok(LoadCommand::DeviceReport),
+            "set_device_power_state" => Ok(LoadCommand::SetPowerState),
+            "stream" => Ok(LoadCommand::Stream),
+            _ => Err(ConfigError::UnknownCommand(name.to_string())),
+        }
+    }
+
+    /// Requests of one turn, `turn` is the number of turns the connection had before
+    pub fn requests(&self, room: &str, device: &str, turn: u64) -> Vec<String> {
+        let target = format!("room_name=\"{room}\" device_name=\"{device}\"");
+        match self {
+            LoadCommand::RoomsList => vec!["rooms_list".to_string()],
+            LoadCommand::DeviceReport => vec![format!("device_report {target}")],
+            LoadCommand::SetPowerState => vec![format!(
+                "set_device_power_state {target} power_state={}",
+                turn % 2 == 1
+            )],
+            LoadCommand::Stream => vec![
+                format!("get_device_report_stream {target} request_delay=1"),
+                format!("cancel_device_report_stream stream_name=\"{room}-{device}\""),
+            ],
+        }
+    }
+}
+
+/// Commands with their weights, parsed from `rooms_list=4,device_report=2`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandMix(Vec<(LoadCommand, u32)>);
+
+impl CommandMix {
+    pub fn parse(mix: &str) -> Result<Self, ConfigError> {
+        let mut weights = Vec::new();
+        for entry in mix.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (name, weight) = entry.split_once('=').unwrap_or((entry, "1"));
+            let weight = weight
+                .parse()
+                .map_err(|_| ConfigError::BadValue("mix", entry.to_string()))?;
+            weights.push((LoadCommand::parse(name)?, weight));
+        }
+        if weights.iter().all(|(_, weight)| *weight == 0) {
+            return Err(ConfigError::EmptyMix);
+        }
+        Ok(Self(weights))
+    }
+
+    /// Commands in the order they are sent, repeated over and over.
+    /// Commands are interleaved, so a short run still gets the whole mix
+    pub fn schedule(&self) -> Vec<LoadCommand> {
+        let total: u32 = self.0.iter().map(|(_, weight)| weight).sum();
+        let mut credits = vec![0i64; self.0.len()];
+        let mut schedule = Vec::with_capacity(total as usize);
+        for _ in 0..total {
+            for (credit, (_, weight)) in credits.iter_mut().zip(&self.0) {
+                *credit += *weight as i64;
+            }
+            let (next, _) = credits
+                .iter()
+                .enumerate()
+                .max_by_key(|(index, credit)| (**credit, std::cmp::Reverse(*index)))
+                .unwrap();
+            credits[next] -= total as i64;
+            schedule.push(self.0[next].0);
+        }
+        schedule
+    }
+}
+
+impl Default for CommandMix {
+    fn default() -> Self {
+        Self(vec![
+            (LoadCommand::RoomsList, 4),
+            (LoadCommand::DeviceReport, 4),
+            (LoadCommand::SetPowerState, 1),
+            (LoadCommand::Stream, 1),
+        ])
+    }
+}
+
+pub struct LoadConfig {
+    pub addr: SocketAddr,
+    pub connections: usize,
+    /// Turns per second over all connections, `None` sends as fast as the server answers
+    pub rate: Option<f64>,
+    pub duration: Duration,
+    pub mix: CommandMix,
+    pub room: String,
+    pub device: String,
+    pub credentials: Option<Credentials>,
+}
+
+impl LoadConfig {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            connections: DEFAULT_CONNECTIONS,
+            rate: None,
+            duration: DEFAULT_DURATION,
+            mix: CommandMix::default(),
+            room: DEFAULT_ROOM.to_string(),
+            device: DEFAULT_DEVICE.to_string(),
+            credentials: None,
+        }
+    }
+
+    /// Parse `<addr> [--connections N] [--rate N] [--duration SECONDS] [--mix MIX]
+    /// [--room NAME] [--device NAME]`
+    pub fn from_args<I>(args: I) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut args = args.into_iter();
+        let addr = args.next().ok_or(ConfigError::MissingAddr)?;
+        let addr = addr
+            .parse()
+            .map_err(|_| ConfigError::BadValue("addr", addr))?;
+        let mut config = Self::new(addr);
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| ConfigError::MissingValue(flag.clone()))?;
+            match flag.as_str() {
+                "--connections" => {
+                    config.connections = match value.parse() {
+                        Ok(connections) if connections > 0 => connections,
+                        _ => return Err(ConfigError::BadValue("connections", value)),
+                    }
+                }
+                "--rate" => {
+                    config.rate = match value.parse::<f64>() {
+                        Ok(rate) if rate > 0.0 => Some(rate),
+                        _ => return Err(ConfigError::BadValue("rate", value)),
+                    }
+                }
+                "--duration" => {
+                    config.duration = value
+                        .parse()
+                        .map(Duration::from_secs_f64)
+                        .map_err(|_| ConfigError::BadValue("duration", value))?
+                }
+                "--mix" => config.mix = CommandMix::parse(&value)?,
+                "--room" => config.room = value,
+                "--device" => config.device = value,
+                _ => return Err(ConfigError::UnknownFlag(flag)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn schedule_follows_weights() {
+        let mix = CommandMix::parse("rooms_list=2,stream=1,device_report=0").unwrap();
+        assert_eq!(
+            mix.schedule(),
+            vec![
+                LoadCommand::RoomsList,
+                LoadCommand::Stream,
+                LoadCommand::RoomsList
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_mix() {
+        assert!(matches!(
+            CommandMix::parse("rooms_list=1,reboot=1"),
+            Err(ConfigError::UnknownCommand(name)) if name == "reboot"
+        ));
+        assert!(matches!(
+            CommandMix::parse("rooms_list=0"),
+            Err(ConfigError::EmptyMix)
+        ));
+    }
+
+    #[test]
+    fn from_args() {
+        let config = LoadConfig::from_args(args(
+            "127.0.0.1:8080 --connections 3 --rate 50 --duration 0.5 --mix stream",
+        ))
+        .unwrap();
+        assert_eq!(config.connections, 3);
+        assert_eq!(config.rate, Some(50.0));
+        assert_eq!(config.duration, Duration::from_millis(500));
+        assert_eq!(config.mix.schedule(), vec![LoadCommand::Stream]);
+        assert!(matches!(
+            LoadConfig::from_args(args("127.0.0.1:8080 --connections 0")),
+            Err(ConfigError::BadValue("connections", _))
+        ));
+        assert!(matches!(
+            LoadConfig::from_args(args("127.0.0.1:8080 --rate")),
+            Err(ConfigError::MissingValue(_))
+        ));
+    }
+}
@@ -0,0 +1,17 @@
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("server address is required")]
+    MissingAddr,
+    #[error("{0} needs a value")]
+    MissingValue(String),
+    #[error("unknown flag {0}")]
+    UnknownFlag(String),
+    #[error("bad {0}: {1}")]
+    BadValue(&'static str, String),
+    #[error(
+        "unknown command {0}, expected rooms_list, device_report, set_device_power_state or stream"
+    )]
+    UnknownCommand(String),
+    #[error("command mix has no commands")]
+    EmptyMix,
+}
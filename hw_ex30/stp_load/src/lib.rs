@@ -0,0 +1,7 @@
+//! Load generator for the smart house STP server: many concurrent connections send
+//! a weighted mix of commands at a target rate, latencies and errors are collected per command
+
+pub mod config;
+pub mod errors;
+pub mod runner;
+pub mod stats;
@@ -0,0 +1,36 @@
+use std::process::ExitCode;
+
+use my_stp_async::auth::Credentials;
+use stp_load::{config::LoadConfig, runner};
+
+/// Credentials for servers that require authentication
+const CLIENT_ID_VAR: &str = "SMART_HOUSE_CLIENT_ID";
+const CLIENT_KEY_VAR: &str = "SMART_HOUSE_CLIENT_KEY";
+
+const USAGE: &str = "usage: stp_load <server addr> [--connections N] [--rate REQUESTS_PER_SECOND]
+    [--duration SECONDS] [--mix rooms_list=4,device_report=4,set_device_power_state=1,stream=1]
+    [--room NAME] [--device NAME]";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut config = match LoadConfig::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if let (Ok(client_id), Ok(key)) = (std::env::var(CLIENT_ID_VAR), std::env::var(CLIENT_KEY_VAR))
+    {
+        config.credentials = Some(Credentials::new(&client_id, key.as_bytes()));
+    }
+    println!(
+        "{} connections to {} for {:.1}s",
+        config.connections,
+        config.addr,
+        config.duration.as_secs_f64()
+    );
+    let report = runner::run(config).await;
+    print!("{report}");
+    ExitCode::SUCCESS
+}
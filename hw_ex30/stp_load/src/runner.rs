@@ -0,0 +1,149 @@
+use std::{sync::Arc, time::Duration};
+
+use my_stp_async::client::{StpClient, StpConnection};
+use my_stp_async::errors::RequestError;
+use tokio::time::{self, Instant};
+
+use crate::{
+    config::{LoadCommand, LoadConfig},
+    stats::{LoadReport, Stats},
+};
+
+/// A request that takes longer is counted as [`RequestError::DeadlineExceeded`]
+/// and its connection is replaced
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Pause after a failed connect, so a server that is down is not hammered
+const RECONNECT_DELAY: Duration = Duration::from_millis(100);
+/// Name connect failures are recorded under
+pub const CONNECT: &str = "connect";
+
+/// Open the configured connections and send the command mix over them until the duration is over.
+/// Connections that fail are reopened on their next turn
+pub async fn run(config: LoadConfig) -> LoadReport {
+    let config = Arc::new(config);
+    let started = Instant::now();
+    let end = started + config.duration;
+    let workers: Vec<_> = (0..config.connections)
+        .map(|index| tokio::spawn(worker(config.clone(), index, started, end)))
+        .collect();
+
+    let mut stats = Stats::default();
+    let mut missed_turns = 0;
+    for worker in workers {
+        let (worker_stats, missed) = worker.await.expect("load worker panicked");
+        stats.merge(worker_stats);
+        missed_turns += missed;
+    }
+    LoadReport {
+        elapsed: started.elapsed(),
+        missed_turns,
+        stats,
+    }
+}
+
+/// One connection. Returns its stats and the number of turns it missed
+async fn worker(
+    config: Arc<LoadConfig>,
+    index: usize,
+    started: Instant,
+    end: Instant,
+) -> (Stats, u64) {
+    let schedule = config.mix.schedule();
+    // Every connection gets an equal share of the rate, starts are spread over one period
+    let period = config
+        .rate
+        .map(|rate| Duration::from_secs_f64(config.connections as f64 / rate));
+    let mut next_turn =
+        started + period.unwrap_or_default() * index as u32 / config.connections as u32;
+
+    let mut stats = Stats::default();
+    let mut missed = 0;
+    let mut connection = None;
+    let mut turn = 0u64;
+    loop {
+        if let Some(period) = period {
+            let now = Instant::now();
+            if now > next_turn + period {
+                let behind = ((now - next_turn).as_secs_f64() / period.as_secs_f64()) as u32;
+                missed += behind as u64;
+                next_turn += period * behind;
+            }
+            time::sleep_until(next_turn).await;
+            next_turn += period;
+        }
+        if Instant::now() >= end {
+            break;
+        }
+        let command = schedule[(index + turn as usize) % schedule.len()];
+        let Some(open) = connect(&config, &mut connection, &mut stats).await else {
+            turn += 1;
+            continue;
+        };
+        if !send_turn(open, command, &config, turn, &mut stats).await {
+            connection = None;
+        }
+        turn += 1;
+    }
+    if let Some(connection) = connection {
+        let _ = connection.close().await;
+    }
+    (stats, missed)
+}
+
+async fn connect<'a>(
+    config: &LoadConfig,
+    connection: &'a mut Option<StpConnection>,
+    stats: &mut Stats,
+) -> Option<&'a mut StpConnection> {
+    if connection.is_none() {
+        let started = Instant::now();
+        let opened = match &config.credentials {
+            Some(credentials) => StpClient::connect_authenticated(config.addr, credentials).await,
+            None => StpClient::connect(config.addr).await,
+        };
+        match opened {
+            Ok(opened) => {
+                stats.record(CONNECT, Ok(started.elapsed()));
+                *connection = Some(opened);
+            }
+            Err(e) => {
+                stats.record(CONNECT, Err(&e.into()));
+                time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+    connection.as_mut()
+}
+
+/// Send the requests of one turn. Returns false if the connection has to be replaced
+async fn send_turn(
+    connection: &mut StpConnection,
+    command: LoadCommand,
+    config: &LoadConfig,
+    turn: u64,
+    stats: &mut Stats,
+) -> bool {
+    for request in command.requests(&config.room, &config.device, turn) {
+        let name = request
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let started = Instant::now();
+        let result = match time::timeout(REQUEST_TIMEOUT, connection.send_request(request)).await {
+            Ok(result) => result.map(|_| started.elapsed()),
+            Err(_) => Err(RequestError::DeadlineExceeded),
+        };
+        stats.record(&name, result.as_ref().map(|latency| *latency));
+        match result {
+            Err(RequestError::DeadlineExceeded) => return false,
+            Err(e) if e.is_connection_lost() => return false,
+            _ => {}
+        }
+    }
+    if command == LoadCommand::Stream {
+        // Reports pushed before the cancel arrived are of no interest
+        while let Ok(Some(_)) = connection.recv_push(Some(Duration::ZERO)).await {}
+    }
+    true
+}
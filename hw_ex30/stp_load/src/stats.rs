@@ -0,0 +1,203 @@
+use std::{collections::BTreeMap, fmt::Display, time::Duration};
+
+use my_stp_async::errors::RequestError;
+
+/// Name of the [`RequestError`] variant, errors are counted by it
+pub fn error_kind(error: &RequestError) -> &'static str {
+    match error {
+        RequestError::Connect(_) => "Connect",
+        RequestError::Send(_) => "Send",
+        RequestError::Recv(_) => "Recv",
+        RequestError::Closed => "Closed",
+        RequestError::EncodingNotNegotiated => "EncodingNotNegotiated",
+        RequestError::UnexpectedResponseId { .. } => "UnexpectedResponseId",
+        RequestError::StreamFailed(_) => "StreamFailed",
+        RequestError::HeartbeatNotNegotiated => "HeartbeatNotNegotiated",
+        RequestError::DeadlineExceeded => "DeadlineExceeded",
+        RequestError::Cancelled => "Cancelled",
+    }
+}
+
+/// Latencies and errors of one command
+#[derive(Debug, Default, Clone)]
+pub struct CommandStats {
+    latencies: Vec<Duration>,
+    errors: BTreeMap<&'static str, usize>,
+}
+
+impl CommandStats {
+    pub fn succeeded(&self) -> usize {
+        self.latencies.len()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.errors.values().sum()
+    }
+
+    pub fn errors(&self) -> &BTreeMap<&'static str, usize> {
+        &self.errors
+    }
+
+    /// Nearest-rank percentile of the successful requests, `p` is in 0..=100
+    pub fn percentile(&mut self, p: f64) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        self.latencies.sort_unstable();
+        let rank = (p / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        Some(self.latencies[rank.clamp(1, self.latencies.len()) - 1])
+    }
+}
+
+/// Results of a run by command name. Every connection collects its own and they are merged at the end
+#[derive(Debug, Default, Clone)]
+pub struct Stats {
+    commands: BTreeMap<String, CommandStats>,
+}
+
+impl Stats {
+    pub fn record(&mut self, command: &str, result: Result<Duration, &RequestError>) {
+        let stats = self.commands.entry(command.to_string()).or_default();
+        match result {
+            Ok(latency) => stats.latencies.push(latency),
+            Err(e) => *stats.errors.entry(error_kind(e)).or_default() += 1,
+        }
+    }
+
+    pub fn merge(&mut self, other: Stats) {
+        for (command, other) in other.commands {
+            let stats = self.commands.entry(command).or_default();
+            stats.latencies.extend(other.latencies);
+            for (kind, count) in other.errors {
+                *stats.errors.entry(kind).or_default() += count;
+            }
+        }
+    }
+
+    pub fn command(&self, command: &str) -> Option<&CommandStats> {
+        self.commands.get(command)
+    }
+
+    pub fn succeeded(&self) -> usize {
+        self.commands.values().map(CommandStats::succeeded).sum()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.commands.values().map(CommandStats::failed).sum()
+    }
+
+    /// Errors of all commands by variant
+    pub fn errors(&self) -> BTreeMap<&'static str, usize> {
+        let mut errors = BTreeMap::new();
+        for stats in self.commands.values() {
+            for (kind, count) in &stats.errors {
+                *errors.entry(*kind).or_default() += count;
+            }
+        }
+        errors
+    }
+}
+
+/// What a run measured
+pub struct LoadReport {
+    pub elapsed: Duration,
+    /// Turns that were due but not started because the connection was still busy
+    pub missed_turns: u64,
+    pub stats: Stats,
+}
+
+impl LoadReport {
+    /// Answered requests per second, failed ones included
+    pub fn throughput(&self) -> f64 {
+        let requests = self.stats.succeeded() + self.stats.failed();
+        requests as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+fn millis(latency: Option<Duration>) -> String {
+    match latency {
+        Some(latency) => format!("{:.2}", latency.as_secs_f64() * 1000.0),
+        None => "-".to_string(),
+    }
+}
+
+impl Display for LoadReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} requests in {:.2}s, {:.1} req/s, {} failed, {} turns missed",
+            self.stats.succeeded() + self.stats.failed(),
+            self.elapsed.as_secs_f64(),
+            self.throughput(),
+            self.stats.failed(),
+            self.missed_turns
+        )?;
+        writeln!(
+            f,
+            "{:<30} {:>8} {:>8} {:>9} {:>9} {:>9} {:>9}",
+            "command", "ok", "failed", "p50 ms", "p90 ms", "p99 ms", "max ms"
+        )?;
+        for (command, stats) in &self.stats.commands {
+            let mut stats = stats.clone();
+            writeln!(
+                f,
+                "{:<30} {:>8} {:>8} {:>9} {:>9} {:>9} {:>9}",
+                command,
+                stats.succeeded(),
+                stats.failed(),
+                millis(stats.percentile(50.0)),
+                millis(stats.percentile(90.0)),
+                millis(stats.percentile(99.0)),
+                millis(stats.percentile(100.0)),
+            )?;
+        }
+        let errors = self.stats.errors();
+        if !errors.is_empty() {
+            writeln!(f, "errors:")?;
+            for (kind, count) in errors {
+                writeln!(f, "  {kind}: {count}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod stats_tests {
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        let mut stats = Stats::default();
+        for millis in (1..=100).rev() {
+            stats.record("rooms_list", Ok(Duration::from_millis(millis)));
+        }
+        let mut rooms_list = stats.command("rooms_list").unwrap().clone();
+        assert_eq!(rooms_list.percentile(50.0), Some(Duration::from_millis(50)));
+        assert_eq!(rooms_list.percentile(99.0), Some(Duration::from_millis(99)));
+        assert_eq!(
+            rooms_list.percentile(100.0),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(rooms_list.percentile(0.0), Some(Duration::from_millis(1)));
+        assert_eq!(CommandStats::default().percentile(50.0), None);
+    }
+
+    #[test]
+    fn merge_counts_errors_by_variant() {
+        let mut first = Stats::default();
+        first.record("rooms_list", Err(&RequestError::Closed));
+        first.record("rooms_list", Ok(Duration::from_millis(1)));
+        let mut second = Stats::default();
+        second.record("rooms_list", Err(&RequestError::Closed));
+        second.record("device_report", Err(&RequestError::DeadlineExceeded));
+        first.merge(second);
+
+        assert_eq!(first.succeeded(), 1);
+        assert_eq!(first.failed(), 3);
+        assert_eq!(
+            first.errors(),
+            BTreeMap::from([("Closed", 2), ("DeadlineExceeded", 1)])
+        );
+    }
+}
@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use smart_house_testkit::{house_builder::HouseBuilder, stp::AsyncTestServer};
+use stp_load::{
+    config::{CommandMix, LoadConfig},
+    runner::{self, CONNECT},
+};
+
+async fn start_server() -> AsyncTestServer {
+    AsyncTestServer::start(
+        HouseBuilder::new()
+            .room("Кухня", |room| {
+                room.thermometer("Термометр1", 16.0)
+                    .socket("Розетка1", 100.0)
+            })
+            .build(),
+    )
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mix_runs_against_server() {
+    let server = start_server().await;
+    let mut config = LoadConfig::new(server.addr());
+    config.connections = 2;
+    config.rate = Some(100.0);
+    config.duration = Duration::from_millis(500);
+    let report = runner::run(config).await;
+
+    let stats = &report.stats;
+    assert_eq!(stats.failed(), 0, "{report}");
+    assert_eq!(stats.command(CONNECT).unwrap().succeeded(), 2);
+    for command in [
+        "rooms_list",
+        "device_report",
+        "set_device_power_state",
+        "get_device_report_stream",
+        "cancel_device_report_stream",
+    ] {
+        assert!(stats.command(command).is_some(), "{command} : {report}");
+    }
+    assert!(report.throughput() > 0.0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn connect_errors_are_counted() {
+    // Nothing listens on a port that was just released
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let mut config = LoadConfig::new(addr);
+    config.connections = 1;
+    config.duration = Duration::from_millis(300);
+    config.mix = CommandMix::parse("rooms_list").unwrap();
+    let report = runner::run(config).await;
+
+    assert_eq!(report.stats.succeeded(), 0);
+    assert!(report.stats.errors()["Connect"] > 0, "{report}");
+}
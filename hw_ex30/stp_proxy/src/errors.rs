@@ -0,0 +1,18 @@
+use my_stp::errors::RequestError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RecordingError {
+    #[error("line {line} is not a recorded frame: {source}")]
+    BadLine {
+        line: usize,
+        source: serde_json::Error,
+    },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReplayError {
+    #[error("session {session} failed: {source}")]
+    Session { session: u64, source: RequestError },
+}
@@ -0,0 +1,7 @@
+//! Recording STP proxy: relays client sessions to a smart house server, logs every frame
+//! and replays recorded sessions against a server to find responses that changed
+
+pub mod errors;
+pub mod proxy;
+pub mod recording;
+pub mod replay;
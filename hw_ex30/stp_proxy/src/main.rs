@@ -0,0 +1,74 @@
+use std::process::ExitCode;
+
+use my_stp::auth::Credentials;
+use stp_proxy::{
+    proxy::RecordingProxy,
+    recording::{read_recording, Recorder},
+    replay::replay,
+};
+
+/// Credentials for servers that require authentication
+const CLIENT_ID_VAR: &str = "SMART_HOUSE_CLIENT_ID";
+const CLIENT_KEY_VAR: &str = "SMART_HOUSE_CLIENT_KEY";
+
+const USAGE: &str = "usage:
+    stp_proxy record <listen addr> <server addr> <recording file>
+    stp_proxy replay <recording file> <server addr>";
+
+fn credentials() -> Option<Credentials> {
+    match (std::env::var(CLIENT_ID_VAR), std::env::var(CLIENT_KEY_VAR)) {
+        (Ok(client_id), Ok(key)) => Some(Credentials::new(&client_id, key.as_bytes())),
+        _ => None,
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["record", listen, server, recording] => {
+            let recorder = Recorder::create(recording).unwrap();
+            let mut proxy = RecordingProxy::bind(*listen, *server, recorder).unwrap();
+            proxy.set_credentials(credentials());
+            println!(
+                "recording sessions to {recording}, clients connect to {}",
+                proxy.local_addr().unwrap()
+            );
+            proxy.run();
+            ExitCode::SUCCESS
+        }
+        ["replay", recording, server] => {
+            let frames = match read_recording(recording) {
+                Ok(frames) => frames,
+                Err(e) => {
+                    eprintln!("Cant read {recording} : {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            let report = match replay(&frames, *server, credentials().as_ref()) {
+                Ok(report) => report,
+                Err(e) => {
+                    eprintln!("Replay failed : {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+            for mismatch in &report.mismatches {
+                println!("{mismatch}");
+            }
+            println!(
+                "{} responses compared, {} differ",
+                report.compared,
+                report.mismatches.len()
+            );
+            if report.is_clean() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        _ => {
+            eprintln!("{USAGE}");
+            ExitCode::FAILURE
+        }
+    }
+}
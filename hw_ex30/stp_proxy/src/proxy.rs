@@ -0,0 +1,149 @@
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use my_stp::auth::Credentials;
+use my_stp::client::{self, StpClient};
+use my_stp::errors::{ConnectError, RequestError};
+use my_stp::message::{Request, Response};
+use my_stp::push::Pusher;
+use my_stp::server::{self, StpServer};
+
+use crate::recording::{Frame, Recorder};
+
+/// Code of the error response a client gets when the proxy could not reach the server
+pub const PROXY_ERROR_CODE: &str = "ProxyError";
+
+/// Sits between clients and a server and records every frame it relays.
+/// Each client session gets its own session with the server, opened with the latest
+/// protocol version, so a client that negotiated less still sees the same responses.
+/// Streamed responses are relayed and recorded joined, pushes are relayed after each response
+pub struct RecordingProxy {
+    server: StpServer,
+    upstream: Vec<SocketAddr>,
+    credentials: Option<Credentials>,
+    recorder: Arc<Recorder>,
+}
+
+impl RecordingProxy {
+    /// Listen on `listen` for clients of the server at `upstream`
+    pub fn bind<Listen, Upstream>(
+        listen: Listen,
+        upstream: Upstream,
+        recorder: Recorder,
+    ) -> io::Result<Self>
+    where
+        Listen: ToSocketAddrs,
+        Upstream: ToSocketAddrs,
+    {
+        Ok(Self {
+            server: StpServer::bind(listen)?,
+            upstream: upstream.to_socket_addrs()?.collect(),
+            credentials: None,
+            recorder: Arc::new(recorder),
+        })
+    }
+
+    /// Authenticate to a server that requires it. Clients of the proxy are not authenticated
+    pub fn set_credentials(&mut self, credentials: Option<Credentials>) {
+        self.credentials = credentials;
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.server.local_addr()
+    }
+
+    /// Accept clients until the process ends, every session is relayed by its own thread
+    pub fn run(self) {
+        loop {
+            let connection = match self.server.accept() {
+                Ok(connection) => connection,
+                Err(e) => {
+                    eprintln!("Cant accept client : {e}");
+                    continue;
+                }
+            };
+            let upstream = match self.connect_upstream() {
+                Ok(upstream) => upstream,
+                Err(e) => {
+                    eprintln!("Cant connect to server : {e}");
+                    continue;
+                }
+            };
+            let recorder = self.recorder.clone();
+            thread::spawn(move || {
+                let session = recorder.new_session();
+                if let Err(e) = relay(connection, upstream, &recorder, session) {
+                    eprintln!("Session {session} error : {e:?}");
+                }
+            });
+        }
+    }
+
+    fn connect_upstream(&self) -> Result<client::StpConnection, ConnectError> {
+        match &self.credentials {
+            Some(credentials) => {
+                StpClient::connect_authenticated(self.upstream.as_slice(), credentials)
+            }
+            None => StpClient::connect(self.upstream.as_slice()),
+        }
+    }
+}
+
+fn relay(
+    mut connection: server::StpConnection,
+    mut upstream: client::StpConnection,
+    recorder: &Recorder,
+    session: u64,
+) -> Result<(), RequestError> {
+    let pusher = connection.pusher();
+    connection.serve_typed(|request| {
+        record(recorder, session, Frame::Request(request.clone()));
+        let response = forward(&mut upstream, request);
+        record(recorder, session, Frame::Response(response.clone()));
+        relay_pushes(&mut upstream, pusher.as_ref(), recorder, session);
+        response
+    })
+}
+
+/// Text requests get text responses like they would from the server itself
+fn forward(upstream: &mut client::StpConnection, request: Request) -> Response {
+    let response = match request {
+        Request::Text(text) => upstream.send_request(text).map(Response::Ok),
+        request => upstream.send_typed(&request),
+    };
+    response.unwrap_or_else(|e| Response::Error {
+        code: PROXY_ERROR_CODE.to_string(),
+        message: format!("Proxy error : {e}"),
+    })
+}
+
+/// Pushes that arrived with the response, clients without push messages lose them
+fn relay_pushes(
+    upstream: &mut client::StpConnection,
+    pusher: Option<&Pusher>,
+    recorder: &Recorder,
+    session: u64,
+) {
+    while let Ok(Some(push)) = upstream.recv_push(Some(Duration::ZERO)) {
+        if let Some(pusher) = pusher {
+            let _ = pusher.push(&push.topic, &push.body);
+        }
+        let frame = Frame::Push {
+            topic: push.topic,
+            body: push.body,
+        };
+        record(recorder, session, frame);
+    }
+}
+
+/// A full disk must not break the session being recorded
+fn record(recorder: &Recorder, session: u64, frame: Frame) {
+    if let Err(e) = recorder.record(session, frame) {
+        eprintln!("Cant record session {session} : {e}");
+    }
+}
@@ -0,0 +1,87 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, LineWriter, Write},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use serde::{Deserialize, Serialize};
+
+use my_stp::message::{Request, Response};
+
+use crate::errors::RecordingError;
+
+/// Frame that went through the proxy
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Frame {
+    Request(Request),
+    Response(Response),
+    Push { topic: String, body: String },
+}
+
+/// One line of a recording
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    /// Client sessions are numbered from 1 in the order they were accepted
+    pub session: u64,
+    /// Milliseconds since the recording started
+    pub at_ms: u64,
+    pub frame: Frame,
+}
+
+/// Writes frames of all sessions to one file, a JSON object per line.
+/// Every line is written out right away, so a killed proxy leaves a usable recording
+pub struct Recorder {
+    started: Instant,
+    next_session: AtomicU64,
+    file: Mutex<LineWriter<File>>,
+}
+
+impl Recorder {
+    /// Start a recording, an existing file is overwritten
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        Ok(Self {
+            started: Instant::now(),
+            next_session: AtomicU64::new(1),
+            file: Mutex::new(LineWriter::new(File::create(path)?)),
+        })
+    }
+
+    /// Number of the next client session
+    pub fn new_session(&self) -> u64 {
+        self.next_session.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn record(&self, session: u64, frame: Frame) -> std::io::Result<()> {
+        let recorded = RecordedFrame {
+            session,
+            at_ms: self.started.elapsed().as_millis() as u64,
+            frame,
+        };
+        let mut line = serde_json::to_string(&recorded)?;
+        line.push('\n');
+        self.file.lock().unwrap().write_all(line.as_bytes())
+    }
+}
+
+/// Frames of a recording in the order they were written
+pub fn read_recording<P: AsRef<Path>>(path: P) -> Result<Vec<RecordedFrame>, RecordingError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut frames = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let frame = serde_json::from_str(&line).map_err(|source| RecordingError::BadLine {
+            line: index + 1,
+            source,
+        })?;
+        frames.push(frame);
+    }
+    Ok(frames)
+}
@@ -0,0 +1,107 @@
+use std::{collections::HashMap, fmt::Display, net::ToSocketAddrs};
+
+use my_stp::auth::Credentials;
+use my_stp::client::{StpClient, StpConnection};
+use my_stp::errors::RequestError;
+use my_stp::message::{Request, Response};
+
+use crate::{
+    errors::ReplayError,
+    recording::{Frame, RecordedFrame},
+};
+
+/// Recorded response that the server answers differently now
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub session: u64,
+    pub request: Request,
+    pub expected: Response,
+    pub actual: Response,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "session {} : {:?}", self.session, self.request)?;
+        writeln!(f, "- {}", self.expected)?;
+        write!(f, "+ {}", self.actual)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    /// Requests that had a recorded response
+    pub compared: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl ReplayReport {
+    pub fn is_clean(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Send the recorded requests to the server at `target` in the order they were recorded,
+/// every recorded session over a session of its own, and compare the responses.
+/// Pushes and timing are not compared, a request without a recorded response is still sent
+pub fn replay<Addrs>(
+    frames: &[RecordedFrame],
+    target: Addrs,
+    credentials: Option<&Credentials>,
+) -> Result<ReplayReport, ReplayError>
+where
+    Addrs: ToSocketAddrs + Clone,
+{
+    let mut connections: HashMap<u64, StpConnection> = HashMap::new();
+    let mut report = ReplayReport::default();
+    for (index, recorded) in frames.iter().enumerate() {
+        let Frame::Request(request) = &recorded.frame else {
+            continue;
+        };
+        let session = recorded.session;
+        let fail = |source: RequestError| ReplayError::Session { session, source };
+        let connection = match connections.get_mut(&session) {
+            Some(connection) => connection,
+            None => {
+                let connection = match credentials {
+                    Some(credentials) => {
+                        StpClient::connect_authenticated(target.clone(), credentials)
+                    }
+                    None => StpClient::connect(target.clone()),
+                }
+                .map_err(|e| fail(e.into()))?;
+                connections.entry(session).or_insert(connection)
+            }
+        };
+        let actual = match request {
+            Request::Text(text) => connection.send_request(text).map(Response::Ok),
+            request => connection.send_typed(request),
+        }
+        .map_err(fail)?;
+        let Some(expected) = recorded_response(&frames[index + 1..], session) else {
+            continue;
+        };
+        report.compared += 1;
+        if *expected != actual {
+            report.mismatches.push(Mismatch {
+                session,
+                request: request.clone(),
+                expected: expected.clone(),
+                actual,
+            });
+        }
+    }
+    Ok(report)
+}
+
+/// Response to the request of `session` that `frames` follow
+fn recorded_response(frames: &[RecordedFrame], session: u64) -> Option<&Response> {
+    frames
+        .iter()
+        .filter(|recorded| recorded.session == session)
+        .find_map(|recorded| match &recorded.frame {
+            Frame::Response(response) => Some(Some(response)),
+            Frame::Request(_) => Some(None),
+            Frame::Push { .. } => None,
+        })
+        .flatten()
+}
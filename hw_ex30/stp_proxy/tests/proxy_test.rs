@@ -0,0 +1,103 @@
+use std::{net::SocketAddr, path::PathBuf, thread};
+
+use my_stp::client::StpClient;
+use my_stp::message::{Request, Response};
+use smart_house_testkit::{house_builder::HouseBuilder, stp::TestServer};
+use stp_proxy::{
+    errors::RecordingError,
+    proxy::RecordingProxy,
+    recording::{read_recording, Frame, Recorder},
+    replay::replay,
+};
+
+fn start_server() -> TestServer {
+    TestServer::start(
+        HouseBuilder::new()
+            .room("Спальня", |room| room.socket("Розетка3", 50.0))
+            .build(),
+    )
+}
+
+fn recording_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("stp-proxy-{}-{name}", std::process::id()))
+}
+
+fn start_proxy(server: &TestServer, recording: &PathBuf) -> SocketAddr {
+    let recorder = Recorder::create(recording).unwrap();
+    let proxy = RecordingProxy::bind("127.0.0.1:0", server.addr(), recorder).unwrap();
+    let addr = proxy.local_addr().unwrap();
+    thread::spawn(move || proxy.run());
+    addr
+}
+
+const IS_ON: &str = "is_device_on room_name=Спальня device_name=Розетка3";
+
+fn record_session(proxy: SocketAddr) {
+    let mut connection = StpClient::connect(proxy).unwrap();
+    assert_eq!(
+        connection.send_request(IS_ON).unwrap(),
+        "room_name:Спальня,device_name:Розетка3,is_on:true"
+    );
+    assert_eq!(
+        connection.send_typed(&Request::command("hello")).unwrap(),
+        Response::Ok("Hello from server".to_string())
+    );
+}
+
+#[test]
+fn proxy_relays_and_records_frames() {
+    let server = start_server();
+    let recording = recording_path("relay");
+    let proxy = start_proxy(&server, &recording);
+    record_session(proxy);
+
+    let frames = read_recording(&recording).unwrap();
+    let frames: Vec<_> = frames.into_iter().map(|recorded| recorded.frame).collect();
+    assert_eq!(
+        frames,
+        vec![
+            Frame::Request(Request::Text(IS_ON.to_string())),
+            Frame::Response(Response::Ok(
+                "room_name:Спальня,device_name:Розетка3,is_on:true".to_string()
+            )),
+            Frame::Request(Request::command("hello")),
+            Frame::Response(Response::Ok("Hello from server".to_string())),
+        ]
+    );
+    let _ = std::fs::remove_file(recording);
+}
+
+#[test]
+fn replay_reports_changed_responses() {
+    let server = start_server();
+    let recording = recording_path("replay");
+    let proxy = start_proxy(&server, &recording);
+    record_session(proxy);
+    let frames = read_recording(&recording).unwrap();
+
+    let report = replay(&frames, server.addr(), None).unwrap();
+    assert_eq!(report.compared, 2);
+    assert!(report.is_clean(), "{:?}", report.mismatches);
+
+    server
+        .request("set_device_power_state room_name=Спальня device_name=Розетка3 power_state=false");
+    let report = replay(&frames, server.addr(), None).unwrap();
+    assert_eq!(report.mismatches.len(), 1);
+    assert_eq!(
+        report.mismatches[0].actual,
+        Response::Ok("room_name:Спальня,device_name:Розетка3,is_on:false".to_string())
+    );
+    let _ = std::fs::remove_file(recording);
+}
+
+#[test]
+fn bad_recording_line() {
+    let recording = recording_path("bad");
+    std::fs::write(&recording, "not a frame\n").unwrap();
+    let error = read_recording(&recording).unwrap_err();
+    assert!(
+        matches!(error, RecordingError::BadLine { line: 1, .. }),
+        "{error:?}"
+    );
+    let _ = std::fs::remove_file(recording);
+}
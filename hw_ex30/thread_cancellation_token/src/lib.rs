@@ -1,37 +1,87 @@
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+struct State {
+    cancelled: AtomicBool,
+    /// Wakes threads sleeping on the token
+    wakeup: Condvar,
+    lock: Mutex<()>,
+}
 
 #[derive(Clone)]
 pub struct CancellationToken {
-    cancelled: Arc<AtomicBool>,
+    state: Arc<State>,
 }
 
 impl CancellationToken {
     #[inline]
     pub fn should_cancel(&self) -> bool {
-        self.cancelled.load(Ordering::Acquire)
+        self.state.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Sleep for `duration` or until cancelled. Returns true if cancelled
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let guard = self.state.lock.lock().unwrap();
+        let _ = self
+            .state
+            .wakeup
+            .wait_timeout_while(guard, duration, |_| !self.should_cancel())
+            .unwrap();
+        self.should_cancel()
     }
 }
 
 #[derive(Clone)]
 pub struct Canceller {
-    cancelled: Arc<AtomicBool>,
+    state: Arc<State>,
 }
 
 impl Canceller {
     #[inline]
     pub fn cancel(&self) {
-        self.cancelled.store(true, Ordering::Release);
+        self.state.cancelled.store(true, Ordering::Release);
+        let _guard = self.state.lock.lock().unwrap();
+        self.state.wakeup.notify_all();
     }
 }
 
 #[inline]
 pub fn cancellation_token() -> (Canceller, CancellationToken) {
-    let cancelled = Arc::new(AtomicBool::new(false));
+    let state = Arc::new(State {
+        cancelled: AtomicBool::new(false),
+        wakeup: Condvar::new(),
+        lock: Mutex::new(()),
+    });
     (
         Canceller {
-            cancelled: Arc::clone(&cancelled),
+            state: Arc::clone(&state),
         },
-        CancellationToken { cancelled },
+        CancellationToken { state },
     )
 }
+
+#[cfg(test)]
+mod cancellation_token_tests {
+    use std::thread;
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn cancel_wakes_sleeping_thread() {
+        let (canceller, token) = cancellation_token();
+        let started = Instant::now();
+        let sleeper = thread::spawn(move || token.sleep(Duration::from_secs(10)));
+        thread::sleep(Duration::from_millis(50));
+        canceller.cancel();
+        assert!(sleeper.join().unwrap());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn sleep_times_out_without_cancel() {
+        let (_canceller, token) = cancellation_token();
+        assert!(!token.sleep(Duration::from_millis(10)));
+    }
+}